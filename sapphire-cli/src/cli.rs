@@ -6,15 +6,35 @@ use sapphire_core::utils::error::Result;
 use sapphire_core::utils::Cache;
 use sapphire_core::Config;
 
+use self::audit::Audit;
+use self::bottle::Bottle;
+use self::cleanup::Cleanup;
+use self::config::ConfigCommand;
 use self::info::Info;
 use self::install::Install;
+use self::list::List;
+use self::outdated::Outdated;
+use self::owner::Owner;
+use self::pin::{Pin, Unpin};
+use self::relink::Relink;
 use self::search::Search;
+use self::test::Test;
 use self::uninstall::Uninstall;
 use self::update::Update;
 
+pub mod audit;
+pub mod bottle;
+pub mod cleanup;
+pub mod config;
 pub mod info;
 pub mod install;
+pub mod list;
+pub mod outdated;
+pub mod owner;
+pub mod pin;
+pub mod relink;
 pub mod search;
+pub mod test;
 pub mod uninstall;
 pub mod update;
 
@@ -42,10 +62,43 @@ pub enum Command {
     Update(Update),
 
     /// Install a formula or cask
-    Install(Install),
+    Install(Box<Install>),
 
     /// Uninstall one or more formulas or casks
     Uninstall(Uninstall),
+
+    /// List installed kegs
+    List(List),
+
+    /// Remove old versions of installed formulas
+    Cleanup(Cleanup),
+
+    /// List installed formulas that have a newer version available
+    Outdated(Outdated),
+
+    /// Show which installed formula owns a file
+    Owner(Owner),
+
+    /// Pin formulas so upgrades leave them alone, or list pinned formulas
+    Pin(Pin),
+
+    /// Unpin previously pinned formulas
+    Unpin(Unpin),
+
+    /// Recreate missing or broken links for every installed formula
+    Relink(Relink),
+
+    /// Query or set persisted default install options
+    Config(ConfigCommand),
+
+    /// Run the smoke tests of installed formulas
+    Test(Test),
+
+    /// Package installed formulas as bottles that can be poured under another prefix
+    Bottle(Bottle),
+
+    /// Check that the patches formulas carry still apply to their current source
+    Audit(Audit),
 }
 
 impl Command {
@@ -56,6 +109,17 @@ impl Command {
             Self::Update(command) => command.run(config, cache).await,
             Self::Install(command) => command.run(config, cache).await,
             Self::Uninstall(command) => command.run(config, cache).await,
+            Self::List(command) => command.run(config, cache).await,
+            Self::Cleanup(command) => command.run(config, cache).await,
+            Self::Outdated(command) => command.run(config, cache).await,
+            Self::Owner(command) => command.run(config, cache).await,
+            Self::Pin(command) => command.run(config, cache).await,
+            Self::Unpin(command) => command.run(config, cache).await,
+            Self::Relink(command) => command.run(config, cache).await,
+            Self::Config(command) => command.run(config, cache).await,
+            Self::Test(command) => command.run(config, cache).await,
+            Self::Bottle(command) => command.run(config, cache).await,
+            Self::Audit(command) => command.run(config, cache).await,
         }
     }
 }
@@ -0,0 +1,49 @@
+//! Contains the logic for the `audit` command.
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sapphire_core::audit::audit_patches;
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::Config;
+use sapphire_core::utils::error::{Result, SapphireError};
+
+use crate::cli::info;
+
+#[derive(Args, Debug)]
+pub struct Audit {
+    /// The formulae to audit
+    #[arg(required = true)]
+    pub names: Vec<String>,
+}
+
+impl Audit {
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let mut failed = 0;
+        for name in &self.names {
+            let formula = info::get_formula_info(name, config, Arc::clone(&cache)).await?;
+            match audit_patches(&formula, config).await {
+                Ok(findings) if findings.is_empty() => println!("{} {}", "Passed:".green(), name),
+                Ok(findings) => {
+                    for finding in findings {
+                        println!("{} {}: {}", "Failed:".red(), name, finding);
+                    }
+                    failed += 1;
+                }
+                Err(e) => {
+                    println!("{} {}: {}", "Failed:".red(), name, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed == 0 {
+            Ok(())
+        } else {
+            Err(SapphireError::Generic(format!(
+                "{} formula(e) failed the audit",
+                failed
+            )))
+        }
+    }
+}
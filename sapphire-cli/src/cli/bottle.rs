@@ -0,0 +1,70 @@
+//! Contains the logic for the `bottle` command.
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sapphire_core::build::formula::{create_bottle, Platform};
+use sapphire_core::keg::KegRegistry;
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::Config;
+use sapphire_core::utils::error::{Result, SapphireError};
+
+#[derive(Args, Debug)]
+pub struct Bottle {
+    /// The installed formulae to package
+    #[arg(required = true)]
+    pub names: Vec<String>,
+
+    /// Directory to write the bottles to (defaults to the current directory)
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+}
+
+impl Bottle {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let platform = Platform::current().ok_or_else(|| {
+            SapphireError::Generic("Bottles can't be made on this platform".to_string())
+        })?;
+        let output_dir = match &self.output_dir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir()?,
+        };
+        let registry = KegRegistry::new(config.clone());
+        let mut failed = 0;
+        for name in &self.names {
+            let Some(keg) = registry.get_installed_keg(name)? else {
+                println!("{} {} is not installed", "Failed:".red(), name);
+                failed += 1;
+                continue;
+            };
+            let file_name = format!(
+                "{}--{}.{}.bottle.tar.gz",
+                keg.name,
+                keg.path.file_name().unwrap_or_default().to_string_lossy(),
+                platform.tag()
+            );
+            match create_bottle(&keg.path, &output_dir.join(file_name)) {
+                Ok(bottle) => println!(
+                    "{} {} (sha256 {})",
+                    "Bottled:".green(),
+                    bottle.path.display(),
+                    bottle.sha256
+                ),
+                Err(e) => {
+                    println!("{} {}: {}", "Failed:".red(), name, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed == 0 {
+            Ok(())
+        } else {
+            Err(SapphireError::Generic(format!(
+                "{} formula(e) could not be bottled",
+                failed
+            )))
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Contains the logic for the `cleanup` command.
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sapphire_core::keg::{parse_time_cutoff, CleanupOptions, InstallTimeFilter, KegRegistry};
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::Config;
+use sapphire_core::utils::error::Result;
+
+#[derive(Args, Debug)]
+pub struct Cleanup {
+    /// Only remove kegs installed before this date, timestamp or age (e.g. 2024-05-01, 90d)
+    #[arg(long, value_parser = parse_time_cutoff)]
+    pub older_than: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Only remove kegs installed after this date, timestamp or age
+    #[arg(long, value_parser = parse_time_cutoff)]
+    pub newer_than: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Number of newest versions of each formula to keep
+    #[arg(long, default_value_t = 1)]
+    pub keep: usize,
+
+    /// Show what would be removed without removing anything
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+impl Cleanup {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let options = CleanupOptions {
+            time_filter: InstallTimeFilter {
+                newer_than: self.newer_than,
+                older_than: self.older_than,
+            },
+            keep_latest: self.keep,
+            dry_run: self.dry_run,
+        };
+        let registry = KegRegistry::new(config.clone());
+        let kegs = registry.cleanup(&options)?;
+
+        if kegs.is_empty() {
+            println!("Nothing to clean up.");
+            return Ok(());
+        }
+        let verb = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+        for keg in &kegs {
+            println!("{} {}", verb, keg.path.display());
+        }
+        println!(
+            "{}",
+            format!("{} {} keg(s)", verb, kegs.len()).green().bold()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! Contains the logic for the `config` command.
+use std::sync::Arc;
+
+use clap::{Args, Subcommand};
+use colored::Colorize;
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::{unknown_install_option, Config, InstallOptions};
+use sapphire_core::utils::error::Result;
+
+#[derive(Args, Debug)]
+pub struct ConfigCommand {
+    #[command(subcommand)]
+    action: Option<ConfigAction>,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Show all default install options (the default action)
+    List,
+    /// Show the value of one install option
+    Get { key: String },
+    /// Persist a default install option
+    Set { key: String, value: String },
+    /// Reset an install option to its built-in default
+    Unset { key: String },
+}
+
+impl ConfigCommand {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        match self.action.as_ref().unwrap_or(&ConfigAction::List) {
+            ConfigAction::List => {
+                println!("{} {}", "Config file:".bold(), config.config_file.display());
+                for key in InstallOptions::KEYS {
+                    print_option(config, key);
+                }
+                Ok(())
+            }
+            ConfigAction::Get { key } => {
+                if config.install_options.get(key).is_none() {
+                    return Err(unknown_install_option(key));
+                }
+                print_option(config, key);
+                Ok(())
+            }
+            ConfigAction::Set { key, value } => {
+                let mut updated = config.clone();
+                updated.persisted_install_options.set(key, value)?;
+                updated.save()?;
+                println!(
+                    "Set {} = {}",
+                    key,
+                    updated
+                        .persisted_install_options
+                        .get(key)
+                        .unwrap_or_default()
+                );
+                Ok(())
+            }
+            ConfigAction::Unset { key } => {
+                let mut updated = config.clone();
+                updated.persisted_install_options.unset(key)?;
+                updated.save()?;
+                println!(
+                    "Reset {} to default ({})",
+                    key,
+                    updated
+                        .persisted_install_options
+                        .get(key)
+                        .unwrap_or_default()
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Prints `key = value`, noting when an environment variable overrides the saved value.
+fn print_option(config: &Config, key: &str) {
+    let saved = config
+        .persisted_install_options
+        .get(key)
+        .unwrap_or_default();
+    let effective = config.install_options.get(key).unwrap_or_default();
+    if saved == effective {
+        println!("{} = {}", key, saved);
+    } else {
+        println!(
+            "{} = {} {}",
+            key,
+            effective,
+            format!("(overridden by environment; saved: {})", saved).dimmed()
+        );
+    }
+}
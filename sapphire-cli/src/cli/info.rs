@@ -4,6 +4,7 @@ use std::sync::Arc;
 
 use clap::Args;
 use colored::Colorize;
+use sapphire_core::build::formula::{bottle_availability, Platform};
 use sapphire_core::fetch::api;
 use sapphire_core::model::formula::Formula;
 use sapphire_core::utils::cache::Cache;
@@ -262,6 +263,34 @@ fn print_formula_info(_name: &str, formula: &Value) {
         dep_table.printstd();
     }
 
+    // Bottles, if the formula parses into the model
+    if let Ok(parsed) = serde_json::from_value::<Formula>(formula.clone()) {
+        let availability = bottle_availability(&parsed);
+        if availability.iter().any(|(_, available)| *available) {
+            let current = Platform::current();
+            let mut bottle_table = prettytable::Table::new();
+            bottle_table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+            for (platform, available) in availability {
+                let mark = if available {
+                    "✓".green()
+                } else {
+                    "✗".red()
+                };
+                let note = if current == Some(platform) {
+                    "(this machine)"
+                } else {
+                    ""
+                };
+                bottle_table.add_row(prettytable::row![platform, mark, note]);
+            }
+            println!("\n{}", "Bottles".blue().bold());
+            bottle_table.printstd();
+        } else {
+            println!("\n{}", "Bottles".blue().bold());
+            println!("  None; installs build from source");
+        }
+    }
+
     // Installation hint
     println!("\n{}", "Installation".blue().bold());
     println!(
@@ -7,22 +7,33 @@ use colored::Colorize;
 use futures::future::{BoxFuture, FutureExt};
 use reqwest::Client;
 use sapphire_core::build;
-use sapphire_core::build::formula::has_bottle_for_current_platform;
+use sapphire_core::build::devtools::TargetArch;
+use sapphire_core::build::env::OptimizationLevel;
+use sapphire_core::build::events::{self, BuildEvent, InstallStage, ReportFormat, Reporter};
+use sapphire_core::build::formula::{
+    bottle_availability, has_bottle_for_current_platform, reinstall, BinLinkFilter, LinkOptions,
+    ReinstallSource,
+};
 use sapphire_core::build::get_formula_opt_path;
+use sapphire_core::build::process::{self, OutputFilter, OutputVerbosity};
+use sapphire_core::build::warnings::{group_by_code, read_receipt_warnings, BuildPhase};
 use sapphire_core::dependency::{
-    DependencyResolver, DependencyTag, ResolutionContext, ResolutionStatus,
+    DependencyResolver, DependencyTag, ResolutionContext, ResolutionStatus, ResolvedGraph,
 };
+use sapphire_core::fetch::prefetch::Prefetcher;
 use sapphire_core::formulary::Formulary;
 use sapphire_core::keg::KegRegistry;
 use sapphire_core::model::cask::Cask;
 use sapphire_core::model::formula::Formula;
 use sapphire_core::utils::cache::Cache;
-use sapphire_core::utils::config::Config;
+use sapphire_core::utils::config::{Config, InstallOptions};
 use sapphire_core::utils::error::{Result, SapphireError};
 use tokio::sync::Semaphore;
 use tokio::task::{JoinError, JoinSet};
 use tracing::{error, info, warn};
 
+use crate::ui::DownloadBars;
+
 #[derive(Debug, Args)]
 pub struct Install {
     #[arg(required = true)]
@@ -42,8 +53,284 @@ pub struct Install {
         help = "Force building the formula from source, even if a bottle is available"
     )]
     build_from_source: bool,
+    #[arg(
+        long,
+        conflicts_with = "build_from_source",
+        help = "Install bottles even if building from source is the configured default; fail if a named formula has none for this platform"
+    )]
+    force_bottle: bool,
+    #[arg(short, long, help = "Number of parallel jobs for source builds")]
+    jobs: Option<usize>,
+    #[arg(
+        long,
+        overrides_with = "no_sanitizers",
+        help = "Build with sanitizers enabled"
+    )]
+    sanitizers: bool,
+    #[arg(long, overrides_with = "sanitizers", help = "Build without sanitizers")]
+    no_sanitizers: bool,
+    #[arg(
+        long,
+        overrides_with = "no_sandbox",
+        help = "Run source builds in the sandbox"
+    )]
+    sandbox: bool,
+    #[arg(
+        long,
+        overrides_with = "sandbox",
+        help = "Run source builds outside the sandbox"
+    )]
+    no_sandbox: bool,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Only put these executables of the named formulae on PATH"
+    )]
+    link_only: Vec<String>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Keep these executables of the named formulae off PATH"
+    )]
+    link_except: Vec<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Build from source with the compilers and flags in this toolchain file"
+    )]
+    toolchain: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Don't download later formulae's bottles and sources while earlier ones build"
+    )]
+    no_prefetch: bool,
+    #[arg(
+        long,
+        help = "Also build and install documentation when building from source"
+    )]
+    build_docs: bool,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = ["quiet", "normal", "full"],
+        help = "How much build output to show: warnings/errors, also milestones, or everything"
+    )]
+    build_output: Option<String>,
+    #[arg(
+        long,
+        value_name = "PHASE",
+        value_parser = ["environment", "configure", "build", "install", "relocation"],
+        help = "Build the named formulae from source, stop once this phase is done and keep the build directory"
+    )]
+    stop_after: Option<String>,
+    #[arg(
+        long,
+        help = "Print the commands building the named formulae from source would run, without running them"
+    )]
+    dry_run: bool,
+    #[arg(
+        long = "HEAD",
+        help = "Build the named formulae from the latest commit of their git repository"
+    )]
+    head: bool,
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_parser = ["text", "json"],
+        help = "Also report install progress events on stderr, as text or as JSON lines"
+    )]
+    progress: Option<String>,
+    #[arg(
+        long,
+        help = "Compile C and C++ through ccache when building from source"
+    )]
+    ccache: bool,
+    #[arg(
+        long,
+        help = "Build universal (x86_64 and arm64) binaries from source; macOS only"
+    )]
+    universal: bool,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = ["O2", "O3", "Os"],
+        help = "Build from source with this compiler optimization level"
+    )]
+    optimize: Option<String>,
+    #[arg(
+        long,
+        help = "Build from source with link-time optimization, where the compiler supports it"
+    )]
+    lto: bool,
+    #[arg(
+        long,
+        help = "Build from source with -Werror, failing on any compiler warning (for auditing formulae)"
+    )]
+    werror: bool,
+    #[arg(
+        long,
+        value_name = "ARCH",
+        value_parser = ["x86_64", "arm64"],
+        help = "Build from source for this CPU architecture instead of the native one"
+    )]
+    arch: Option<String>,
+    #[arg(
+        long,
+        help = "Make source builds reproducible: fixed timestamps and no build paths in the output"
+    )]
+    reproducible: bool,
+    #[arg(
+        long,
+        alias = "keep-tmp",
+        help = "Keep the build directory of a failed source build for debugging"
+    )]
+    keep_build_dir: bool,
+    #[arg(
+        long,
+        help = "Download sources again and extract and patch them afresh, ignoring the caches"
+    )]
+    force_fetch: bool,
+    #[arg(
+        long,
+        value_name = "LOAD",
+        help = "Have make start no new jobs while the load average is above LOAD"
+    )]
+    max_load: Option<u32>,
+    #[arg(
+        long,
+        help = "Install the named formulae again even if they are installed, keeping the old keg if that fails"
+    )]
+    reinstall: bool,
 }
 impl Install {
+    /// Applies this invocation's flags on top of the configured defaults.
+    /// See [`InstallOptions`] for the full precedence order.
+    fn resolve_options(&self, cfg: &Config) -> InstallOptions {
+        let mut opts = cfg.install_options.clone();
+        if let Some(jobs) = self.jobs.filter(|j| *j > 0) {
+            opts.jobs = Some(jobs);
+        }
+        if self.build_from_source {
+            opts.build_from_source = true;
+        } else if self.force_bottle {
+            opts.build_from_source = false;
+        }
+        if self.sanitizers {
+            opts.sanitizers = true;
+        } else if self.no_sanitizers {
+            opts.sanitizers = false;
+        }
+        if self.sandbox {
+            opts.sandbox = true;
+        } else if self.no_sandbox {
+            opts.sandbox = false;
+        }
+        if let Some(toolchain) = &self.toolchain {
+            // A toolchain only affects source builds, so asking for one implies building
+            opts.toolchain = Some(toolchain.clone());
+            opts.build_from_source = true;
+        }
+        if self.build_docs {
+            opts.build_docs = true;
+        }
+        if self.ccache {
+            opts.ccache = true;
+        }
+        if self.universal {
+            // Bottles are single-arch, so a universal keg has to be built
+            opts.universal = true;
+            opts.build_from_source = true;
+        }
+        // Both change the built code, which a bottle can't provide
+        if let Some(level) = self.optimize.as_deref().and_then(OptimizationLevel::parse) {
+            opts.optimization = level;
+            opts.build_from_source = true;
+        }
+        if self.lto {
+            opts.lto = true;
+            opts.build_from_source = true;
+        }
+        if self.werror {
+            opts.werror = true;
+            opts.build_from_source = true;
+        }
+        if let Some(arch) = self.arch.as_deref().and_then(TargetArch::parse) {
+            opts.target_arch = Some(arch);
+            opts.build_from_source = true;
+        }
+        if let Some(phase) = self.stop_after.as_deref().and_then(BuildPhase::parse) {
+            opts.stop_after = Some(phase);
+        }
+        if self.dry_run {
+            opts.dry_run = true;
+        }
+        if self.head {
+            opts.head = true;
+        }
+        if self.reproducible {
+            opts.reproducible = true;
+        }
+        if self.keep_build_dir {
+            opts.keep_build_dir_on_failure = true;
+        }
+        if self.force_fetch {
+            opts.force_fetch = true;
+        }
+        if let Some(load) = self.max_load {
+            opts.max_load = (load > 0).then_some(load);
+        }
+        if opts.stop_after.is_some() || opts.dry_run || opts.head {
+            // Only a source build has phases to stop after, commands to print or a repository
+            opts.build_from_source = true;
+        }
+        if self.skip_deps {
+            // Dependencies weren't installed on purpose, so don't insist on them
+            opts.verify_runtime_deps = false;
+        }
+        opts
+    }
+
+    /// Link options for `name`. The bin filter only applies to formulae named on the command
+    /// line, not to dependencies pulled in for them.
+    fn link_options_for(&self, name: &str) -> LinkOptions {
+        let bin_filter = self.names.iter().any(|n| n == name).then(|| BinLinkFilter {
+            link_only: self.link_only.clone(),
+            link_except: self.link_except.clone(),
+        });
+        LinkOptions {
+            overwrite_conflicts: true,
+            bin_filter,
+        }
+    }
+
+    /// With `--force-bottle`, fails up front if a named formula has no bottle for this
+    /// platform, listing the platforms that do have one.
+    fn check_bottles_available(&self, graph: &ResolvedGraph) -> Result<()> {
+        for dep in &graph.install_plan {
+            let formula = &dep.formula;
+            if !self.names.iter().any(|n| n == formula.name())
+                || has_bottle_for_current_platform(formula)
+            {
+                continue;
+            }
+            let available: Vec<String> = bottle_availability(formula)
+                .into_iter()
+                .filter(|(_, available)| *available)
+                .map(|(platform, _)| platform.tag())
+                .collect();
+            return Err(SapphireError::Generic(format!(
+                "--force-bottle: {} has no bottle for this platform ({})",
+                formula.name(),
+                if available.is_empty() {
+                    "it has no bottles at all".to_string()
+                } else {
+                    format!("bottles exist for {}", available.join(", "))
+                }
+            )));
+        }
+        Ok(())
+    }
+
     pub async fn run(&self, cfg: &Config, cache: Arc<Cache>) -> Result<()> {
         if self.cask {
             return install_casks(
@@ -54,12 +341,29 @@ impl Install {
             )
             .await;
         }
+        if let Some(verbosity) = self
+            .build_output
+            .as_deref()
+            .and_then(OutputVerbosity::parse)
+        {
+            process::set_output_filter(OutputFilter::from_env().with_verbosity(verbosity));
+        }
         if self.skip_deps {
             warn!("--skip-deps not fully supported; dependencies will still be processed.");
         }
 
+        let reporter = self
+            .progress
+            .as_deref()
+            .and_then(ReportFormat::parse)
+            .map(Reporter::spawn);
+        let result = self.install_formulae(cfg, Arc::clone(&cache)).await;
+        if let Some(reporter) = reporter {
+            reporter.finish().await;
+        }
+
         // Try installing as formulae first…
-        match self.install_formulae(cfg, Arc::clone(&cache)).await {
+        match result {
             Ok(()) => {
                 // success as formula
                 Ok(())
@@ -96,6 +400,11 @@ impl Install {
     async fn install_formulae(&self, cfg: &Config, cache: Arc<Cache>) -> Result<()> {
         info!("{}", "Beginning bottle installation…".blue().bold());
 
+        let mut cfg = cfg.clone();
+        cfg.install_options = self.resolve_options(&cfg);
+        tracing::debug!("Resolved install options: {:?}", cfg.install_options);
+        let cfg = &cfg;
+
         // Phase 1: Dependency Resolution
         let formulary = Formulary::new(cfg.clone());
         let keg_registry = KegRegistry::new(cfg.clone());
@@ -107,6 +416,7 @@ impl Install {
             include_test: false,
             skip_recommended: self.skip_recommended,
             force_build: false,
+            reinstall: self.reinstall,
         };
         let mut resolver = DependencyResolver::new(ctx);
         let graph = resolver.resolve_targets(&self.names)?;
@@ -114,6 +424,9 @@ impl Install {
             info!("Everything already installed – nothing to do.");
             return Ok(());
         }
+        if self.force_bottle {
+            self.check_bottles_available(&graph)?;
+        }
 
         // Phase 2: Build Node Map
         let mut nodes: HashMap<String, Node> = HashMap::new();
@@ -174,11 +487,28 @@ impl Install {
         let sem = Arc::new(Semaphore::new(self.max_concurrent_installs));
         let mut js: JoinSet<(String, Result<PathBuf>)> = JoinSet::new();
         let client = Arc::new(Client::new());
+        let download_bars = DownloadBars::default();
+        let bars = download_bars.clone();
+        let prefetcher = Prefetcher::new(cfg.clone(), client, self.max_concurrent_installs)
+            .with_progress(Arc::new(move |name, progress| bars.update(name, progress)));
+        if !self.no_prefetch {
+            // Plan order is dependency order, so the bounded pool fetches what's needed soonest
+            for dep in &graph.install_plan {
+                if let Some(node) = nodes.get(dep.formula.name()) {
+                    if cfg.install_options.head
+                        && node.formula.head().is_some()
+                        && self.names.iter().any(|n| n == node.formula.name())
+                    {
+                        // Checked out from git when its turn comes; the release isn't needed
+                        continue;
+                    }
+                    prefetcher
+                        .prefetch(node.formula.clone(), cfg.install_options.build_from_source);
+                }
+            }
+        }
 
-        while !nodes
-            .values()
-            .all(|n| matches!(n.state, InstallState::Ok(_) | InstallState::Failed(_)))
-        {
+        while !nodes.values().all(|n| n.state.is_finished()) {
             while let Some(name) = queue.pop_front() {
                 if let Some(node) = nodes.get(&name) {
                     if !matches!(node.state, InstallState::Ready) {
@@ -199,11 +529,18 @@ impl Install {
                         let node = nodes.get_mut(&name).unwrap();
                         node.state = InstallState::Running;
                         let formula = node.formula.clone();
-                        let task_cfg = cfg.clone();
-                        let cli = client.clone();
+                        let mut task_cfg = cfg.clone();
+                        if !self.names.contains(&name) {
+                            // Dependencies are built in full so the requested formula can be.
+                            // A dry run stays one all the way down, installing nothing
+                            task_cfg.install_options.stop_after = None;
+                            task_cfg.install_options.head = false;
+                        }
+                        let prefetcher = prefetcher.clone();
                         let _cache_clone = Arc::clone(&cache);
                         let name_clone = name.clone();
-                        let force_source_build = self.build_from_source;
+                        let force_source_build = task_cfg.install_options.build_from_source;
+                        let link_options = self.link_options_for(&name);
                         let all_paths_for_build = graph
                             .install_plan
                             .iter()
@@ -215,9 +552,10 @@ impl Install {
                                 &name_clone,
                                 formula,
                                 task_cfg,
-                                cli,
+                                prefetcher,
                                 all_paths_for_build,
                                 force_source_build,
+                                link_options,
                             )
                             .await;
                             drop(permit);
@@ -238,10 +576,7 @@ impl Install {
             }
 
             if js.is_empty() && queue.is_empty() {
-                if nodes
-                    .values()
-                    .all(|n| matches!(n.state, InstallState::Ok(_) | InstallState::Failed(_)))
-                {
+                if nodes.values().all(|n| n.state.is_finished()) {
                     break;
                 } else {
                     error!("Install loop stalled: No running tasks or queued items, but not all nodes are finished.");
@@ -263,6 +598,9 @@ impl Install {
                 tokio::task::yield_now().await;
             }
         }
+        download_bars.clear();
+
+        report_build_warnings(&nodes);
 
         // Final Check
         let failures: Vec<_> = nodes
@@ -274,7 +612,14 @@ impl Install {
             .collect();
 
         if failures.is_empty() {
-            info!("{}", "All bottles installed".green().bold());
+            if cfg.install_options.dry_run {
+                info!(
+                    "{}",
+                    "Dry run finished; nothing was installed".green().bold()
+                );
+            } else {
+                info!("{}", "All bottles installed".green().bold());
+            }
             Ok(())
         } else {
             error!("Installation failed for:");
@@ -288,6 +633,36 @@ impl Install {
         }
     }
 }
+/// Prints the warnings recorded in each newly installed keg's receipt, grouped by code.
+fn report_build_warnings(nodes: &HashMap<String, Node>) {
+    let mut names: Vec<_> = nodes.keys().collect();
+    names.sort();
+    for name in names {
+        let InstallState::Ok(opt_path) = &nodes[name].state else {
+            continue;
+        };
+        let warnings = read_receipt_warnings(opt_path);
+        if warnings.is_empty() {
+            continue;
+        }
+        warn!(
+            "{}",
+            format!("{} installed with {} warning(s):", name, warnings.len()).yellow()
+        );
+        for (code, group) in group_by_code(warnings) {
+            warn!("  {} ({})", code, group.len());
+            for warning in group {
+                match &warning.location {
+                    Some(location) => {
+                        warn!("    {} ({})", warning.message, location.display())
+                    }
+                    None => warn!("    {}", warning.message),
+                }
+            }
+        }
+    }
+}
+
 fn join_to_err(e: JoinError) -> SapphireError {
     SapphireError::Generic(format!("Task join error: {}", e))
 }
@@ -298,9 +673,17 @@ enum InstallState {
     Ready,
     Running,
     Ok(PathBuf),
+    /// The build's commands were printed; nothing was installed.
+    DryRun,
     Failed(String),
 }
 
+impl InstallState {
+    fn is_finished(&self) -> bool {
+        matches!(self, Self::Ok(_) | Self::DryRun | Self::Failed(_))
+    }
+}
+
 #[derive(Debug)]
 struct Node {
     formula: Arc<Formula>,
@@ -327,16 +710,28 @@ fn process_task_outcome(
     };
     match outcome {
         Ok(opt_path) => {
+            events::publish(BuildEvent::InstallFinished {
+                formula: name.clone(),
+                keg: opt_path.clone(),
+            });
             node.state = InstallState::Ok(opt_path);
             tracing::debug!("{} installed successfully", name);
         }
+        Err(SapphireError::DryRun(_)) => {
+            // Dependents only print their commands too, so they don't need it installed
+            node.state = InstallState::DryRun;
+        }
         Err(e) => {
             let msg = format!("{}", e);
+            events::publish(BuildEvent::InstallFailed {
+                formula: name.clone(),
+                error: msg.clone(),
+            });
             node.state = InstallState::Failed(msg.clone());
             error!("install of {} failed: {}", name, msg);
         }
     }
-    let succeeded = matches!(node.state, InstallState::Ok(_));
+    let succeeded = matches!(node.state, InstallState::Ok(_) | InstallState::DryRun);
     let failure_msg = if !succeeded {
         if let InstallState::Failed(m) = &node.state {
             m.clone()
@@ -346,90 +741,178 @@ fn process_task_outcome(
     } else {
         String::new()
     };
+    if !succeeded {
+        cancel_dependents(nodes, queue, &name, &failure_msg);
+        return;
+    }
     for dependent in node.dependents.clone() {
         if let Some(dep_node) = nodes.get_mut(&dependent) {
-            if succeeded {
-                if matches!(dep_node.state, InstallState::Pending | InstallState::Ready) {
-                    dep_node.deps_remaining = dep_node.deps_remaining.saturating_sub(1);
-                    if dep_node.deps_remaining == 0 {
-                        dep_node.state = InstallState::Ready;
-                        if !queue.contains(&dependent) {
-                            queue.push_back(dependent.clone());
-                        }
+            if matches!(dep_node.state, InstallState::Pending | InstallState::Ready) {
+                dep_node.deps_remaining = dep_node.deps_remaining.saturating_sub(1);
+                if dep_node.deps_remaining == 0 {
+                    dep_node.state = InstallState::Ready;
+                    if !queue.contains(&dependent) {
+                        queue.push_back(dependent.clone());
                     }
                 }
-            } else if matches!(
-                dep_node.state,
-                InstallState::Pending | InstallState::Ready | InstallState::Running
-            ) {
-                dep_node.state =
-                    InstallState::Failed(format!("dependency '{}' failed: {}", name, failure_msg));
-                tracing::debug!(
-                    "Marking dependent '{}' as failed due to upstream failure of '{}'",
-                    dependent,
-                    name
-                );
-                queue.retain(|i| i != &dependent);
             }
         }
     }
 }
 
+/// Marks everything depending on `failed`, directly or through other dependencies, as failed
+/// without running it. Nodes that don't depend on it are left to run.
+fn cancel_dependents(
+    nodes: &mut HashMap<String, Node>,
+    queue: &mut VecDeque<String>,
+    failed: &str,
+    failure_msg: &str,
+) {
+    let mut pending: VecDeque<(String, String)> = nodes
+        .get(failed)
+        .map(|n| {
+            n.dependents
+                .iter()
+                .map(|d| (d.clone(), failed.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    while let Some((dependent, cause)) = pending.pop_front() {
+        let Some(dep_node) = nodes.get_mut(&dependent) else {
+            continue;
+        };
+        // A node can't start before all its dependencies succeeded, so none of these is running
+        if !matches!(dep_node.state, InstallState::Pending | InstallState::Ready) {
+            continue;
+        }
+        let msg = if cause == failed {
+            format!("dependency '{}' failed: {}", failed, failure_msg)
+        } else {
+            format!("dependency '{}' was not installed", cause)
+        };
+        events::publish(BuildEvent::InstallFailed {
+            formula: dependent.clone(),
+            error: msg.clone(),
+        });
+        dep_node.state = InstallState::Failed(msg);
+        tracing::debug!(
+            "Marking dependent '{}' as failed due to upstream failure of '{}'",
+            dependent,
+            failed
+        );
+        queue.retain(|i| i != &dependent);
+        pending.extend(
+            dep_node
+                .dependents
+                .iter()
+                .map(|d| (d.clone(), dependent.clone())),
+        );
+    }
+}
+
 // Complete, corrected install_formula_task function
 async fn install_formula_task(
     name: &str,
     formula: Arc<Formula>,
     cfg: Config,
-    client: Arc<Client>,
+    prefetcher: Prefetcher,
     all_installed_paths: Vec<PathBuf>,
     force_source_build: bool,
+    link_options: LinkOptions,
 ) -> Result<PathBuf> {
     let should_build_source = force_source_build || !has_bottle_for_current_platform(&formula);
     let final_opt_path = get_formula_opt_path(&formula, &cfg);
+    // Reinstalled with the old keg set aside, so a failure puts the installed one back
+    let keg_exists = cfg
+        .formula_keg_path(name, &formula.version_str_full())
+        .exists();
+    let stage = |stage| {
+        events::publish(BuildEvent::StageStarted {
+            formula: name.to_string(),
+            stage,
+        })
+    };
+    events::publish(BuildEvent::InstallStarted {
+        formula: name.to_string(),
+        version: formula.version_str_full(),
+        from_source: should_build_source,
+    });
 
     if should_build_source {
         info!("Building {} from source...", name);
         info!("Downloading source for {}...", name);
+        stage(InstallStage::Download);
 
-        let source_path =
-            sapphire_core::build::formula::source::download_source(&formula, &cfg).await?;
+        let mut formula = formula;
+        let source_path = if cfg.install_options.head && formula.head().is_some() {
+            sapphire_core::build::formula::source::checkout_head_source(
+                Arc::make_mut(&mut formula),
+                &cfg,
+            )
+            .await?
+        } else {
+            if cfg.install_options.head {
+                warn!("{} has no HEAD version; building its stable release", name);
+            }
+            prefetcher.fetch(&formula, true).await?
+        };
 
         info!("Compiling {}...", name);
-        let install_dir: PathBuf = sapphire_core::build::formula::source::build_from_source(
-            &source_path,
-            &formula,
-            &cfg,
-            &all_installed_paths,
-        )
-        .await?;
+        stage(InstallStage::Build);
+        let install_dir: PathBuf = if keg_exists {
+            reinstall(
+                &formula,
+                &cfg,
+                ReinstallSource::Source {
+                    source_path: &source_path,
+                    all_installed_paths: &all_installed_paths,
+                },
+            )
+            .await?
+        } else {
+            sapphire_core::build::formula::source::build_from_source(
+                &source_path,
+                &formula,
+                &cfg,
+                &all_installed_paths,
+            )
+            .await?
+        };
 
         info!("Linking {}...", name);
-        sapphire_core::build::formula::link::link_formula_artifacts(&formula, &install_dir, &cfg)?;
+        stage(InstallStage::Link);
+        sapphire_core::build::formula::link_keg(name, &install_dir, &cfg, &link_options)?;
 
         info!("Built and linked {}", name);
     } else {
         info!("Downloading bottle for {}...", name);
-        let bottle_path =
-            sapphire_core::build::formula::bottle::download_bottle(&formula, &cfg, &client).await?;
+        stage(InstallStage::Download);
+        let bottle_path = prefetcher.fetch(&formula, false).await?;
 
         info!("Pouring bottle for {}...", name);
-        let install_dir: PathBuf = tokio::task::spawn_blocking({
-            let formula = formula.clone();
-            let cfg_clone = cfg.clone();
-            let bottle_clone = bottle_path.clone();
-            move || -> Result<PathBuf> {
-                sapphire_core::build::formula::bottle::install_bottle(
-                    &bottle_clone,
-                    &formula,
-                    &cfg_clone,
-                )
-            }
-        })
-        .await
-        .map_err(join_to_err)??;
+        stage(InstallStage::Pour);
+        let install_dir: PathBuf = if keg_exists {
+            reinstall(&formula, &cfg, ReinstallSource::Bottle(&bottle_path)).await?
+        } else {
+            tokio::task::spawn_blocking({
+                let formula = formula.clone();
+                let cfg_clone = cfg.clone();
+                let bottle_clone = bottle_path.clone();
+                move || -> Result<PathBuf> {
+                    sapphire_core::build::formula::bottle::install_bottle(
+                        &bottle_clone,
+                        &formula,
+                        &cfg_clone,
+                    )
+                }
+            })
+            .await
+            .map_err(join_to_err)??
+        };
 
         info!("Linking {}...", name);
-        sapphire_core::build::formula::link::link_formula_artifacts(&formula, &install_dir, &cfg)?;
+        stage(InstallStage::Link);
+        sapphire_core::build::formula::link_keg(name, &install_dir, &cfg, &link_options)?;
 
         info!("Poured and linked {}", name);
     }
@@ -515,6 +998,33 @@ async fn install_cask_task(token: &str, cache: Arc<Cache>, cfg: &Config) -> Resu
                 skip_recommended: false,
                 max_concurrent_installs: 4,
                 build_from_source: false,
+                force_bottle: false,
+                jobs: None,
+                sanitizers: false,
+                no_sanitizers: false,
+                sandbox: false,
+                no_sandbox: false,
+                link_only: vec![],
+                link_except: vec![],
+                toolchain: None,
+                no_prefetch: false,
+                build_docs: false,
+                build_output: None,
+                stop_after: None,
+                dry_run: false,
+                head: false,
+                progress: None,
+                ccache: false,
+                universal: false,
+                optimize: None,
+                lto: false,
+                werror: false,
+                arch: None,
+                reproducible: false,
+                keep_build_dir: false,
+                force_fetch: false,
+                max_load: None,
+                reinstall: false,
             };
             dep_args.install_formulae(cfg, Arc::clone(&cache)).await?;
         }
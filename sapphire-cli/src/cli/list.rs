@@ -0,0 +1,49 @@
+//! Contains the logic for the `list` command.
+use std::sync::Arc;
+
+use clap::Args;
+use sapphire_core::keg::{parse_time_cutoff, InstallTimeFilter, KegRegistry};
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::Config;
+use sapphire_core::utils::error::Result;
+
+#[derive(Args, Debug)]
+pub struct List {
+    /// Only list kegs installed after this date, timestamp or age (e.g. 2024-05-01, 30d)
+    #[arg(long, value_parser = parse_time_cutoff)]
+    pub newer_than: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Only list kegs installed before this date, timestamp or age
+    #[arg(long, value_parser = parse_time_cutoff)]
+    pub older_than: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl List {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let filter = InstallTimeFilter {
+            newer_than: self.newer_than,
+            older_than: self.older_than,
+        };
+        let registry = KegRegistry::new(config.clone());
+        let mut kegs = registry.list_installed_kegs_filtered(&filter)?;
+        kegs.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then_with(|| (&a.version, a.revision).cmp(&(&b.version, b.revision)))
+        });
+
+        for keg in &kegs {
+            let installed = keg
+                .installed_at()
+                .map(|t| t.format("%Y-%m-%d").to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            let version = keg
+                .path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| keg.version.to_string());
+            println!("{} {} (installed {})", keg.name, version, installed);
+        }
+        Ok(())
+    }
+}
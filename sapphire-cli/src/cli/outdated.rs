@@ -0,0 +1,41 @@
+//! Contains the logic for the `outdated` command.
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sapphire_core::outdated::outdated;
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::Config;
+use sapphire_core::utils::error::Result;
+
+#[derive(Args, Debug)]
+pub struct Outdated {
+    /// Hide pinned formulae
+    #[arg(long)]
+    pub exclude_pinned: bool,
+}
+
+impl Outdated {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let formulae = outdated(config)?;
+        for formula in &formulae {
+            if self.exclude_pinned && formula.pinned {
+                continue;
+            }
+            let mut line = format!(
+                "{} ({}) < {}",
+                formula.name.bold(),
+                formula.installed_version,
+                formula.available_version.green()
+            );
+            if !formula.bottle_available {
+                line.push_str(" [source]");
+            }
+            if !formula.is_upgradeable() {
+                line.push_str(&format!(" {}", "[pinned]".yellow()));
+            }
+            println!("{}", line);
+        }
+        Ok(())
+    }
+}
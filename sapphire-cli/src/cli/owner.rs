@@ -0,0 +1,40 @@
+//! Contains the logic for the `owner` command.
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use sapphire_core::keg::KegRegistry;
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::Config;
+use sapphire_core::utils::error::{Result, SapphireError};
+
+#[derive(Args, Debug)]
+pub struct Owner {
+    /// Files in the prefix or Cellar, absolute or relative to the prefix
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+}
+
+impl Owner {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let registry = KegRegistry::new(config.clone());
+        let mut unowned = 0;
+        for path in &self.paths {
+            match registry.owner_of(path)? {
+                Some(owner) => println!("{}: {}", path.display(), owner),
+                None => {
+                    println!("{}: not owned by any installed formula", path.display());
+                    unowned += 1;
+                }
+            }
+        }
+        if unowned == 0 {
+            Ok(())
+        } else {
+            Err(SapphireError::NotFound(format!(
+                "{} path(s) not owned by any installed formula",
+                unowned
+            )))
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Contains the logic for the `pin` and `unpin` commands.
+use std::sync::Arc;
+
+use clap::Args;
+use sapphire_core::keg::KegRegistry;
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::Config;
+use sapphire_core::utils::error::Result;
+
+#[derive(Args, Debug)]
+pub struct Pin {
+    /// The formulae to pin. Without names, lists pinned formulae
+    pub names: Vec<String>,
+}
+
+impl Pin {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let registry = KegRegistry::new(config.clone());
+        if self.names.is_empty() {
+            for name in registry.pinned_formulae()? {
+                println!("{}", name);
+            }
+            return Ok(());
+        }
+        for name in &self.names {
+            registry.pin(name)?;
+            println!("Pinned {}", name);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct Unpin {
+    /// The formulae to unpin
+    #[arg(required = true)]
+    pub names: Vec<String>,
+}
+
+impl Unpin {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let registry = KegRegistry::new(config.clone());
+        for name in &self.names {
+            registry.unpin(name)?;
+            println!("Unpinned {}", name);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+//! Contains the logic for the `relink` command.
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sapphire_core::build::formula::relink_all;
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::Config;
+use sapphire_core::utils::error::{Result, SapphireError};
+
+#[derive(Args, Debug)]
+pub struct Relink;
+
+impl Relink {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let summary = relink_all(config)?;
+
+        for conflict in &summary.conflicts {
+            println!(
+                "{} {} (owned by {})",
+                "Conflict:".yellow(),
+                conflict.path.display(),
+                conflict.owner.as_deref().unwrap_or("an unmanaged file")
+            );
+        }
+        for (name, reason) in &summary.failures {
+            println!("{} {}: {}", "Failed:".red(), name, reason);
+        }
+        println!(
+            "{}",
+            format!(
+                "Relinked {} formula(e) ({} keg-only), repaired {} link(s)",
+                summary.relinked.len() + summary.keg_only.len(),
+                summary.keg_only.len(),
+                summary.links_repaired
+            )
+            .green()
+            .bold()
+        );
+
+        if summary.failures.is_empty() {
+            Ok(())
+        } else {
+            Err(SapphireError::Generic(format!(
+                "{} formula(e) could not be relinked",
+                summary.failures.len()
+            )))
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Contains the logic for the `test` command.
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sapphire_core::build::formula::run_formula_test;
+use sapphire_core::keg::KegRegistry;
+use sapphire_core::utils::cache::Cache;
+use sapphire_core::utils::config::Config;
+use sapphire_core::utils::error::{Result, SapphireError};
+
+use crate::cli::info;
+
+#[derive(Args, Debug)]
+pub struct Test {
+    /// The installed formulae to test
+    #[arg(required = true)]
+    pub names: Vec<String>,
+}
+
+impl Test {
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let registry = KegRegistry::new(config.clone());
+        let mut failed = 0;
+        for name in &self.names {
+            let Some(keg) = registry.get_installed_keg(name)? else {
+                println!("{} {} is not installed", "Failed:".red(), name);
+                failed += 1;
+                continue;
+            };
+            let formula = info::get_formula_info(name, config, Arc::clone(&cache)).await?;
+            let Some(test) = formula.test.as_ref().filter(|t| !t.commands.is_empty()) else {
+                println!("{} defines no test", name);
+                continue;
+            };
+            let dep_opt_paths: Vec<PathBuf> = formula
+                .dependencies()?
+                .iter()
+                .map(|dep| config.formula_opt_link_path(&dep.name))
+                .collect();
+            match run_formula_test(&keg.path, test, &dep_opt_paths, config.prefix()) {
+                Ok(()) => println!("{} {}", "Passed:".green(), name),
+                Err(e) => {
+                    println!("{} {}: {}", "Failed:".red(), name, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed == 0 {
+            Ok(())
+        } else {
+            Err(SapphireError::Generic(format!(
+                "{} formula(e) failed their test",
+                failed
+            )))
+        }
+    }
+}
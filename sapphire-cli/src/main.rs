@@ -44,6 +44,14 @@ async fn main() -> Result<()> {
         process::exit(1);
     });
 
+    // Build commands run in their own process groups, out of reach of the terminal's Ctrl-C
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            sapphire_core::build::process::kill_running_commands();
+            process::exit(130);
+        }
+    });
+
     // Create Cache once and wrap in Arc
     let cache = Arc::new(
         Cache::new(&config.cache_dir)
@@ -1,8 +1,11 @@
 //! UI utility functions for creating common elements like spinners.
 
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use sapphire_core::fetch::http::DownloadProgress;
 
 /// Creates and configures a default spinner ProgressBar.
 ///
@@ -20,3 +23,71 @@ pub fn create_spinner(message: &str) -> ProgressBar {
     pb.enable_steady_tick(Duration::from_millis(100)); // Standard tick rate
     pb
 }
+
+/// Progress bars for downloads running side by side, one per formula, drawn together.
+///
+/// A bar appears with the first progress report for a formula and is cleared once its download
+/// has everything. Downloads of unknown size show a spinner instead, left to [`Self::clear`].
+#[derive(Clone, Default)]
+pub struct DownloadBars {
+    multi: MultiProgress,
+    bars: Arc<Mutex<HashMap<String, ProgressBar>>>,
+}
+
+impl DownloadBars {
+    /// Updates the bar of `name`'s download with `progress`.
+    pub fn update(&self, name: &str, progress: DownloadProgress) {
+        let mut bars = self.bars.lock().unwrap_or_else(|e| e.into_inner());
+        let bar = bars
+            .entry(name.to_string())
+            .or_insert_with(|| self.multi.add(new_download_bar(name, progress.total)));
+        if let Some(total) = progress.total {
+            bar.set_length(total);
+        }
+        bar.set_position(progress.downloaded);
+        if progress
+            .total
+            .is_some_and(|total| progress.downloaded >= total)
+        {
+            bar.finish_and_clear();
+            bars.remove(name);
+        }
+    }
+
+    /// Removes every bar still drawn.
+    pub fn clear(&self) {
+        let mut bars = self.bars.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, bar) in bars.drain() {
+            bar.finish_and_clear();
+        }
+        let _ = self.multi.clear();
+    }
+}
+
+fn new_download_bar(name: &str, total: Option<u64>) -> ProgressBar {
+    let bar = match total {
+        Some(total) => {
+            let bar = ProgressBar::new(total);
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{msg} [{bar:30.blue/white}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+                )
+                .unwrap()
+                .progress_chars("=> "),
+            );
+            bar
+        }
+        None => {
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{spinner:.blue.bold} {msg} {bytes} ({bytes_per_sec})",
+                )
+                .unwrap(),
+            );
+            bar
+        }
+    };
+    bar.set_message(format!("Downloading {}", name));
+    bar
+}
@@ -0,0 +1,84 @@
+// sapphire-core/src/audit.rs
+// Maintainer-facing checks for formula definitions. Currently this verifies that carried
+// patches still apply to the formula's current source, which is the first thing to break when
+// a version is bumped and the patch is not.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info};
+
+use crate::build::formula::source;
+use crate::model::formula::Formula;
+use crate::utils::config::Config;
+use crate::utils::error::{Result, SapphireError};
+
+/// A problem found while auditing a formula.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditFinding {
+    /// The patch no longer applies cleanly to the formula's source.
+    StalePatch { patch: PathBuf },
+}
+
+impl fmt::Display for AuditFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StalePatch { patch } => {
+                write!(f, "Patch {} no longer applies cleanly", patch.display())
+            }
+        }
+    }
+}
+
+/// Fetches `formula`'s declared patches, downloads and stages a fresh copy of its source, and
+/// checks that each patch still applies to it. See [`check_patches`].
+pub async fn audit_patches(formula: &Formula, config: &Config) -> Result<Vec<AuditFinding>> {
+    if formula.patches().is_empty() {
+        return Ok(Vec::new());
+    }
+    let patches = source::fetch_patches(formula, config).await?;
+    let source_path = source::download_source(formula, config, &|_| {}).await?;
+
+    let temp_dir_base = config.build_root();
+    std::fs::create_dir_all(&temp_dir_base)?;
+    let stage_dir = tempfile::Builder::new()
+        .prefix(&format!("{}-audit-", formula.name()))
+        .tempdir_in(&temp_dir_base)
+        .map_err(|e| SapphireError::IoError(format!("Failed to create audit stage dir: {}", e)))?;
+    source::stage_source(&source_path, stage_dir.path())?;
+
+    check_patches(stage_dir.path(), &patches)
+}
+
+/// Checks `patches`, in order, against the source tree in `source_dir` with a dry run of
+/// `patch -p1`. Patches that apply are then applied for real, since later patches commonly build
+/// on earlier ones, so `source_dir` should be a throwaway copy.
+pub fn check_patches(source_dir: &Path, patches: &[PathBuf]) -> Result<Vec<AuditFinding>> {
+    let patch_exe = source::find_patch_exe(None)?;
+    let mut findings = Vec::new();
+    for patch in patches {
+        let patch = source::canonical_patch(patch)?;
+        let dry_run = source::patch_command(&patch_exe, source_dir, &patch, true).output()?;
+        if dry_run.status.success() {
+            debug!("Patch {} applies cleanly", patch.display());
+            let applied = source::patch_command(&patch_exe, source_dir, &patch, false).output()?;
+            if !applied.status.success() {
+                return Err(SapphireError::CommandExecError(format!(
+                    "Applying {} failed after a clean dry run: {}",
+                    patch.display(),
+                    String::from_utf8_lossy(&applied.stdout).trim()
+                )));
+            }
+        } else {
+            debug!(
+                "Dry run of {} failed:\n{}{}",
+                patch.display(),
+                String::from_utf8_lossy(&dry_run.stdout),
+                String::from_utf8_lossy(&dry_run.stderr)
+            );
+            info!("Patch {} no longer applies", patch.display());
+            findings.push(AuditFinding::StalePatch { patch });
+        }
+    }
+    Ok(findings)
+}
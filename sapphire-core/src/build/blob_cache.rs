@@ -0,0 +1,229 @@
+// sapphire-core/src/build/blob_cache.rs
+// Local cache of kegs built from source. After a successful build the keg is archived under a
+// key made of the formula, its version and source, the exact dependency kegs it was built
+// against, a hash of the formula's build recipe and a hash of the build environment.
+// Rebuilding with the same key restores the archive instead, which makes it a personal bottle
+// cache for repeated installs of one spec.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use crate::build::env::BuildEnvironment;
+use crate::build::extract;
+use crate::model::formula::Formula;
+use crate::utils::config::Config;
+use crate::utils::error::{Result, SapphireError};
+
+/// Directory under the cache dir holding the archives.
+const BLOB_DIR: &str = "blobs";
+
+/// Everything that decides whether a cached keg can stand in for a fresh build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobKey {
+    pub formula: String,
+    pub version: String,
+    pub source_sha256: String,
+    /// Resolved keg paths of the dependencies, sorted. These include the dependency versions.
+    pub dependencies: Vec<PathBuf>,
+    /// SHA-256 of everything else in the formula and config that shapes the keg: patches,
+    /// resources, install and post-install steps, and the post-install passes skipped.
+    pub recipe_hash: String,
+    /// [`BuildEnvSnapshot::hash`](crate::build::env::BuildEnvSnapshot::hash) of the build env.
+    pub env_hash: String,
+}
+
+impl BlobKey {
+    pub fn new(
+        formula: &Formula,
+        config: &Config,
+        build_env: &BuildEnvironment,
+        all_installed_paths: &[PathBuf],
+    ) -> Self {
+        // Opt paths are symlinks; their targets name the exact dependency versions
+        let mut dependencies: Vec<PathBuf> = all_installed_paths
+            .iter()
+            .map(|p| fs::canonicalize(p).unwrap_or_else(|_| p.clone()))
+            .collect();
+        dependencies.sort();
+        dependencies.dedup();
+        Self {
+            formula: formula.name().to_string(),
+            version: formula.version_str_full(),
+            source_sha256: formula.source_sha256().to_string(),
+            dependencies,
+            recipe_hash: recipe_hash(formula, config),
+            env_hash: build_env.snapshot().hash(),
+        }
+    }
+
+    /// SHA-256 of the whole key, used as the archive's file name.
+    pub fn digest(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        hex::encode(Sha256::digest(&json))
+    }
+}
+
+/// SHA-256 of the parts of `formula`, and of the post-install passes `config` skips for it,
+/// that change what its build installs beyond the source and the environment.
+fn recipe_hash(formula: &Formula, config: &Config) -> String {
+    let recipe = serde_json::json!({
+        "resources": formula.resources,
+        "patches": formula.patches,
+        "configure_args": formula.configure_args,
+        "dependency_tracking": formula.dependency_tracking,
+        "deparallelize": formula.deparallelize,
+        "no_destdir": formula.no_destdir,
+        "make_check": formula.make_check,
+        "env": formula.env,
+        "compiler": formula.compiler,
+        "skip_clean": formula.skip_clean,
+        "rewrite_shebangs": formula.rewrite_shebangs,
+        "keep_la_files": formula.keep_la_files,
+        "post_install": formula.post_install,
+        "post_install_skips": config.post_install_skips_for(formula.name()),
+    });
+    hex::encode(Sha256::digest(recipe.to_string().as_bytes()))
+}
+
+/// The blob cache in `<cache_dir>/blobs`, limited to `max_bytes` in total. The archive
+/// handling is shared with the cache of prepared source trees (see `build::source_cache`).
+pub struct BlobCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl BlobCache {
+    /// The cache configured by `blob_cache_size`, or `None` if it is disabled.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let size_mb = config.install_options.blob_cache_size;
+        (size_mb > 0).then(|| Self::at(config.cache_dir.join(BLOB_DIR), size_mb))
+    }
+
+    /// A cache of directory archives in `dir`, limited to `max_mb` MiB in total.
+    pub fn at(dir: PathBuf, max_mb: u64) -> Self {
+        Self {
+            dir,
+            max_bytes: max_mb * 1024 * 1024,
+        }
+    }
+
+    fn archive_path(&self, digest: &str) -> PathBuf {
+        self.dir.join(format!("{}.tar.gz", digest))
+    }
+
+    /// Whether an archive is cached under `digest`.
+    pub fn contains(&self, digest: &str) -> bool {
+        self.archive_path(digest).is_file()
+    }
+
+    /// Restores the keg cached under `key` into `install_dir`. Returns `false` if nothing is
+    /// cached for the key. A failed restore removes the partial keg and the broken entry.
+    pub fn restore(&self, key: &BlobKey, install_dir: &Path) -> Result<bool> {
+        if !self.contains(&key.digest()) {
+            debug!(
+                "No cached build of {} for key {}",
+                key.formula,
+                key.digest()
+            );
+            return Ok(false);
+        }
+        info!(
+            "==> Restoring {} {} from the local build cache",
+            key.formula, key.version
+        );
+        self.restore_dir(&key.digest(), install_dir)
+    }
+
+    /// Replaces `dest` with the directory archived under `digest`. Returns `false` if nothing
+    /// is cached under it. A failed restore removes the partial directory and the broken entry.
+    pub fn restore_dir(&self, digest: &str, dest: &Path) -> Result<bool> {
+        let archive = self.archive_path(digest);
+        if !archive.is_file() {
+            return Ok(false);
+        }
+        if dest.exists() {
+            fs::remove_dir_all(dest)?;
+        }
+        if let Err(e) = extract::extract_archive(&archive, dest, 0, "gz") {
+            warn!(
+                "Cached archive {} is unusable ({}); ignoring it",
+                archive.display(),
+                e
+            );
+            let _ = fs::remove_dir_all(dest);
+            let _ = fs::remove_file(&archive);
+            return Ok(false);
+        }
+        // Restores count as use for eviction
+        if let Ok(file) = File::options().append(true).open(&archive) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Ok(true)
+    }
+
+    /// Archives the finished keg in `install_dir` under `key`, then evicts the least recently
+    /// used entries until the cache fits its size limit.
+    pub fn store(&self, key: &BlobKey, install_dir: &Path) -> Result<()> {
+        self.store_dir(&key.digest(), install_dir)?;
+        debug!("Cached build of {} {}", key.formula, key.version);
+        Ok(())
+    }
+
+    /// Archives the directory `src` under `digest`, then evicts the least recently used entries
+    /// until the cache fits its size limit.
+    pub fn store_dir(&self, digest: &str, src: &Path) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let archive = self.archive_path(digest);
+        let partial = archive.with_extension("partial");
+        let write = || -> Result<()> {
+            let encoder = GzEncoder::new(File::create(&partial)?, Compression::fast());
+            let mut builder = tar::Builder::new(encoder);
+            builder.follow_symlinks(false);
+            builder.append_dir_all(".", src)?;
+            builder.into_inner()?.finish()?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            let _ = fs::remove_file(&partial);
+            return Err(SapphireError::Cache(format!(
+                "Failed to archive {} into {}: {}",
+                src.display(),
+                self.dir.display(),
+                e
+            )));
+        }
+        fs::rename(&partial, &archive)?;
+        debug!("Archived {} as {}", src.display(), archive.display());
+        self.evict()
+    }
+
+    /// Deletes the least recently used archives until the total size is within the limit.
+    pub fn evict(&self) -> Result<()> {
+        let mut entries: Vec<(SystemTime, u64, PathBuf)> = fs::read_dir(&self.dir)?
+            .flatten()
+            .filter(|e| e.path().to_string_lossy().ends_with(".tar.gz"))
+            .filter_map(|e| {
+                let meta = e.metadata().ok()?;
+                Some((meta.modified().ok()?, meta.len(), e.path()))
+            })
+            .collect();
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        entries.sort();
+        for (_, size, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            debug!("Evicting {} from the build cache", path.display());
+            fs::remove_file(&path)?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+// sapphire-core/src/build/build_log.rs
+// Persistent log of a source build. Every line the build's commands print is appended to
+// `<cache>/logs/<formula>-<timestamp>.log` whatever the terminal verbosity, each command under
+// a header, so a failed build leaves a full record behind to attach to a bug report.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tracing::debug;
+
+use crate::build::process;
+use crate::utils::error::Result;
+
+/// Directory under the cache dir holding build logs.
+const LOG_DIR: &str = "logs";
+
+/// The `-W` option GCC and Clang name at the end of a warning, e.g. `[-Wunused-variable]`.
+static WARNING_OPTION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\[(-W[\w=+-]+)\]").expect("valid warning option pattern"));
+
+tokio::task_local! {
+    /// The log commands run by [`crate::build::process`] write to. Per task, as builds of
+    /// different formulae run concurrently, each in its own task.
+    static ACTIVE: Option<Arc<BuildLog>>;
+}
+
+/// A build log file. Failing to write to it never fails the build.
+pub struct BuildLog {
+    path: PathBuf,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl BuildLog {
+    /// Creates `<cache_dir>/logs/<formula>-<timestamp>.log`.
+    pub fn create(cache_dir: &Path, formula: &str) -> Result<Self> {
+        let dir = cache_dir.join(LOG_DIR);
+        fs::create_dir_all(&dir)?;
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let path = dir.join(format!("{}-{}.log", formula, timestamp));
+        let file = File::create(&path)?;
+        debug!("Logging the build of {} to {}", formula, path.display());
+        let log = Self {
+            path,
+            file: Mutex::new(BufWriter::new(file)),
+        };
+        log.header(&format!("Building {}", formula));
+        Ok(log)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes a `==> text` header line with the current time.
+    pub fn header(&self, text: &str) {
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+        self.write(format!("\n==> [{}] {}\n", now, text).as_bytes());
+    }
+
+    /// Records the environment build commands run with, one `KEY=value` per line. Pass
+    /// [`BuildEnvironment::displayed_env`](crate::build::env::BuildEnvironment::displayed_env)
+    /// so that credentials stay out of the log.
+    pub fn environment(&self, env: &[(String, String)]) {
+        self.header("Build environment");
+        for (key, value) in env {
+            self.write(format!("{}={}\n", key, value).as_bytes());
+        }
+    }
+
+    /// Starts the section for one command, named by `context` (`configure`, `make install`...).
+    /// The command is written as [`process::shell_command_line`] shows it, with only the
+    /// variables that differ from sapphire's own environment, so the user's credentials that a
+    /// `std` build passes through aren't copied into the log.
+    pub fn command_started(&self, context: &str, cmd: &Command) {
+        self.header(&format!(
+            "{}: {}",
+            context,
+            process::shell_command_line(cmd)
+        ));
+    }
+
+    /// Closes the section for one command with how it exited.
+    pub fn command_finished(&self, context: &str, status: &ExitStatus) {
+        self.write(format!("--> {} exited with {}\n", context, status).as_bytes());
+    }
+
+    /// Appends one line of command output, as read.
+    pub fn line(&self, line: &[u8]) {
+        self.write(line);
+        if !line.ends_with(b"\n") {
+            self.write(b"\n");
+        }
+    }
+
+    /// Counts the `warning:` lines logged so far.
+    pub fn scan_warnings(&self) -> Result<LogWarnings> {
+        let content = fs::read(&self.path)?;
+        let mut warnings = LogWarnings::default();
+        for line in String::from_utf8_lossy(&content).lines() {
+            if !line.contains("warning:") {
+                continue;
+            }
+            warnings.total += 1;
+            if let Some(option) = WARNING_OPTION.captures(line) {
+                *warnings.by_option.entry(option[1].to_string()).or_default() += 1;
+            }
+        }
+        Ok(warnings)
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        // Flushed as it goes so the log is complete even if sapphire itself dies
+        if let Err(e) = file.write_all(bytes).and_then(|_| file.flush()) {
+            debug!(
+                "Could not write to build log {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// The warnings found in a build log by [`BuildLog::scan_warnings`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogWarnings {
+    /// Lines containing `warning:`, from the compiler or any other tool.
+    pub total: usize,
+    /// Counts of the compiler warnings that name their `-W` option, by option.
+    pub by_option: BTreeMap<String, usize>,
+}
+
+impl fmt::Display for LogWarnings {
+    /// `12 warning(s): 5 -Wunused-variable, 3 -Wsign-compare, 4 other`, with the five most
+    /// frequent options.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} warning(s)", self.total)?;
+        let mut options: Vec<_> = self.by_option.iter().collect();
+        options.sort_by(|a, b| b.1.cmp(a.1));
+        let mut parts: Vec<String> = options
+            .iter()
+            .take(5)
+            .map(|(option, count)| format!("{} {}", count, option))
+            .collect();
+        let listed: usize = options.iter().take(5).map(|(_, count)| **count).sum();
+        if !parts.is_empty() && listed < self.total {
+            parts.push(format!("{} other", self.total - listed));
+        }
+        if !parts.is_empty() {
+            write!(f, ": {}", parts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `future` with `log` as the log build commands write to. Commands run outside the
+/// task, e.g. on a `spawn_blocking` thread, aren't logged.
+pub async fn scope<F: Future>(log: Option<Arc<BuildLog>>, future: F) -> F::Output {
+    ACTIVE.scope(log, future).await
+}
+
+/// The active build log, if this task is running a source build.
+pub fn active() -> Option<Arc<BuildLog>> {
+    ACTIVE.try_with(Option::clone).ok().flatten()
+}
@@ -1,34 +1,102 @@
 // **File:** sapphire-core/src/build/devtools.rs (New file)
-use std::env;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::{env, fmt};
 
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
 use which;
 
+use crate::build::warnings::{BuildPhase, WarningCode, WarningCollector};
+use crate::dependency::{CompilerRequirement, CompilerVendor};
 use crate::utils::error::{Result, SapphireError};
 /// Finds the path to the specified compiler executable (e.g., "cc", "c++").
 ///
 /// Tries environment variables (e.g., `CC`, `CXX`) first, then `xcrun` on macOS,
-/// then falls back to searching the system `PATH`.
+/// then falls back to searching the system `PATH`. ccache wrappers (such as the
+/// `/usr/lib/ccache/cc` masquerade links) are skipped so the result is always the real compiler
+/// and the build environment can't wrap it twice.
+///
+/// For "gcc", "g++" and "gfortran" (also asked for as "fortran", and named by `FC`), versioned
+/// drivers on `PATH` (`gcc-14`, `gcc-13`, ...) are tried before `xcrun`, newest first, as the
+/// unversioned names are often clang in disguise or missing. They are also the last resort for
+/// "cc" and "c++" on systems with only a versioned GCC.
+///
+/// The result is remembered for the rest of the process, per compiler name and value of the
+/// environment variable; failures are not, so a later call tries again.
 pub fn find_compiler(name: &str) -> Result<PathBuf> {
+    static FOUND: Lazy<Mutex<HashMap<ToolKey, PathBuf>>> = Lazy::new(Default::default);
+    find_memoized(&FOUND, name, detect_compiler)
+}
+
+/// Finds a binutil such as "ld", "ar", "ranlib", "strip" or "nm" the same way as
+/// [`find_compiler`]: its environment variable (`LD`, `AR`, ...) first, then `xcrun`, so it
+/// belongs to the same toolchain as the compiler on macOS, then `PATH`. Remembered likewise.
+pub fn find_tool(name: &str) -> Result<PathBuf> {
+    static FOUND: Lazy<Mutex<HashMap<ToolKey, PathBuf>>> = Lazy::new(Default::default);
+    find_memoized(&FOUND, name, detect_tool)
+}
+
+/// A tool name and the value of its environment variable at lookup time.
+type ToolKey = (String, Option<String>);
+
+fn find_memoized(
+    found: &Mutex<HashMap<ToolKey, PathBuf>>,
+    name: &str,
+    detect: fn(&str) -> Result<PathBuf>,
+) -> Result<PathBuf> {
+    let env_value = tool_env_var(name).and_then(|var| env::var(var).ok());
+    let key = (name.to_string(), env_value);
+    // A panic elsewhere can't leave the map half-updated, so a poisoned lock is still usable
+    if let Some(path) = found.lock().unwrap_or_else(|e| e.into_inner()).get(&key) {
+        return Ok(path.clone());
+    }
+    let path = detect(name)?;
+    found
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key, path.clone());
+    Ok(path)
+}
+
+/// The variable that names the tool `name`, if there is one.
+fn tool_env_var(name: &str) -> Option<&'static str> {
+    match name {
+        "cc" | "gcc" => Some("CC"),
+        "c++" | "cxx" | "g++" => Some("CXX"),
+        "fortran" | "gfortran" => Some("FC"),
+        "ld" => Some("LD"),
+        "ar" => Some("AR"),
+        "ranlib" => Some("RANLIB"),
+        "strip" => Some("STRIP"),
+        "nm" => Some("NM"),
+        _ => None,
+    }
+}
+
+fn detect_compiler(name: &str) -> Result<PathBuf> {
     // 1. Check environment variables (CC for "cc", CXX for "c++")
-    let env_var_name = match name {
-        "cc" => "CC",
-        "c++" | "cxx" => "CXX",
-        _ => "", // Only handle common cases for now
-    };
-    if !env_var_name.is_empty() {
+    if let Some(env_var_name) = tool_env_var(name) {
         if let Ok(compiler_path) = env::var(env_var_name) {
             let path = PathBuf::from(compiler_path);
-            if path.is_file() {
-                println!(
+            if is_ccache(&path) {
+                debug!(
+                    "Env var {} points to ccache, ignoring it: {}",
+                    env_var_name,
+                    path.display()
+                );
+            } else if path.is_file() {
+                debug!(
                     "Using compiler from env var {}: {}",
                     env_var_name,
                     path.display()
                 );
                 return Ok(path);
             } else {
-                println!(
+                warn!(
                     "Env var {} points to non-existent file: {}",
                     env_var_name,
                     path.display()
@@ -37,62 +105,563 @@ pub fn find_compiler(name: &str) -> Result<PathBuf> {
         }
     }
 
-    // 2. Use xcrun on macOS (if available)
-    if cfg!(target_os = "macos") {
-        println!("Attempting to find '{}' using xcrun", name);
-        let output = Command::new("xcrun")
-            .arg("--find")
-            .arg(name)
-            .stderr(Stdio::piped()) // Capture stderr for better error messages
-            .output();
+    // 2. Versioned GCC drivers, for GCC specifically
+    let driver = gcc_driver(name);
+    if let Some(driver) = driver {
+        if let Some((version, path)) = versioned_gcc_drivers(path_dirs(), driver)
+            .into_iter()
+            .next()
+        {
+            debug!("Using GCC {} for '{}': {}", version, name, path.display());
+            return Ok(path);
+        }
+    }
+    let name = if driver == Some("gfortran") {
+        "gfortran"
+    } else {
+        name
+    };
 
-        match output {
-            Ok(out) if out.status.success() => {
-                let path_str = String::from_utf8_lossy(&out.stdout).trim().to_string();
-                if !path_str.is_empty() {
-                    let path = PathBuf::from(path_str);
-                    if path.is_file() {
-                        println!("Found compiler via xcrun: {}", path.display());
-                        return Ok(path);
-                    } else {
-                        println!(
-                            "xcrun found '{}' but path doesn't exist or isn't a file: {}",
-                            name,
-                            path.display()
-                        );
-                    }
+    // 3. Use xcrun on macOS (if available); Xcode has no Fortran compiler
+    if name != "gfortran" {
+        if let Some(path) = xcrun_find(name) {
+            return Ok(path);
+        }
+    }
+
+    // 4. Fallback to searching PATH
+    debug!("Falling back to searching PATH for '{}'", name);
+    let mut found = which::which_all(name).into_iter().flatten();
+    if let Some(path) = found.find(|p| !is_ccache(p)) {
+        return Ok(path);
+    }
+
+    // 5. A versioned GCC standing in for the generic driver
+    let driver = match name {
+        "cc" => Some("gcc"),
+        "c++" | "cxx" => Some("g++"),
+        _ => None,
+    };
+    if let Some((version, path)) = driver.and_then(|driver| {
+        versioned_gcc_drivers(path_dirs(), driver)
+            .into_iter()
+            .next()
+    }) {
+        debug!(
+            "No '{}' on PATH; using GCC {}: {}",
+            name,
+            version,
+            path.display()
+        );
+        return Ok(path);
+    }
+    Err(SapphireError::BuildEnvError(format!(
+        "Failed to find compiler '{}' on PATH",
+        name
+    )))
+}
+
+/// Asks `xcrun` for `name` in the active developer toolchain. Always `None` outside macOS.
+fn xcrun_find(name: &str) -> Option<PathBuf> {
+    if !cfg!(target_os = "macos") {
+        return None;
+    }
+    debug!("Attempting to find '{}' using xcrun", name);
+    let output = Command::new("xcrun")
+        .arg("--find")
+        .arg(name)
+        .stderr(Stdio::piped()) // Capture stderr for better error messages
+        .output();
+
+    match output {
+        Ok(out) if out.status.success() => {
+            let path_str = String::from_utf8_lossy(&out.stdout).trim().to_string();
+            if !path_str.is_empty() {
+                let path = PathBuf::from(path_str);
+                if path.is_file() {
+                    debug!("Found '{}' via xcrun: {}", name, path.display());
+                    return Some(path);
                 } else {
-                    println!("xcrun found '{}' but returned empty path.", name);
+                    warn!(
+                        "xcrun found '{}' but path doesn't exist or isn't a file: {}",
+                        name,
+                        path.display()
+                    );
                 }
+            } else {
+                warn!("xcrun found '{}' but returned empty path.", name);
             }
-            Ok(out) => {
-                // xcrun ran but failed
-                let stderr = String::from_utf8_lossy(&out.stderr);
-                // Don't treat xcrun failure as fatal, just means it couldn't find it this way
-                println!("xcrun failed to find '{}': {}", name, stderr.trim());
+        }
+        Ok(out) => {
+            // xcrun ran but failed
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            // Don't treat xcrun failure as fatal, just means it couldn't find it this way
+            warn!("xcrun failed to find '{}': {}", name, stderr.trim());
+        }
+        Err(e) => {
+            // xcrun command itself failed to execute (likely not installed or not in PATH)
+            warn!(
+                "Failed to execute xcrun: {}. Falling back to PATH search.",
+                e
+            );
+        }
+    }
+    None
+}
+
+fn detect_tool(name: &str) -> Result<PathBuf> {
+    if let Some(env_var_name) = tool_env_var(name) {
+        if let Ok(tool_path) = env::var(env_var_name) {
+            let path = PathBuf::from(tool_path);
+            if path.is_file() {
+                debug!("Using {} from env var {}", path.display(), env_var_name);
+                return Ok(path);
+            }
+            warn!(
+                "Env var {} points to non-existent file: {}",
+                env_var_name,
+                path.display()
+            );
+        }
+    }
+    if let Some(path) = xcrun_find(name) {
+        return Ok(path);
+    }
+    which::which(name).map_err(|e| {
+        SapphireError::BuildEnvError(format!("Failed to find tool '{}' on PATH: {}", name, e))
+    })
+}
+
+/// The binutil `tool` (`ar`, `ranlib`, `nm` or `strip`) of the toolchain the C compiler at `cc`
+/// belongs to, if its name tells. The name is the compiler's own, through symlinks, with its
+/// target triple and version carried over: `aarch64-linux-gnu-gcc-13` gets
+/// `aarch64-linux-gnu-ar` and `clang-17` gets `llvm-ar-17`. With `lto`, GCC gets its
+/// `gcc-ar`-style wrappers, which load the LTO plugin plain `ar` needs to index its objects;
+/// LLVM's tools read bitcode anyway.
+///
+/// Tools are looked for beside the compiler, and on `PATH` only when the name pins a triple or
+/// version, so a compiler doesn't get another toolchain's tools. `None` leaves the choice to
+/// [`find_tool`].
+pub fn compiler_binutil(cc: &Path, tool: &str, lto: bool) -> Option<PathBuf> {
+    let resolved = std::fs::canonicalize(cc).unwrap_or_else(|_| cc.to_path_buf());
+    let name = resolved.file_name()?.to_str()?;
+    let (prefix, version, candidates) = if let Some(at) = name.find("clang") {
+        let (prefix, version) = (
+            &name[..at],
+            name[at + "clang".len()..].trim_start_matches("++"),
+        );
+        let mut candidates = vec![format!("{}llvm-{}{}", prefix, tool, version)];
+        if !prefix.is_empty() {
+            candidates.push(format!("llvm-{}{}", tool, version));
+        }
+        (prefix, version, candidates)
+    } else if let Some(at) = name.rfind("gcc") {
+        let (prefix, version) = (&name[..at], &name[at + "gcc".len()..]);
+        let mut candidates = Vec::new();
+        if lto && tool != "strip" {
+            candidates.push(format!("{}gcc-{}{}", prefix, tool, version));
+        }
+        if !prefix.is_empty() {
+            candidates.push(format!("{}{}", prefix, tool));
+        }
+        (prefix, version, candidates)
+    } else {
+        return None;
+    };
+
+    let mut dirs: Vec<PathBuf> = [cc.parent(), resolved.parent()]
+        .into_iter()
+        .flatten()
+        .map(Path::to_path_buf)
+        .collect();
+    if !prefix.is_empty() || !version.is_empty() {
+        dirs.extend(path_dirs());
+    }
+    let found = candidates.iter().find_map(|candidate| {
+        dirs.iter()
+            .map(|dir| dir.join(candidate))
+            .find(|p| p.is_file())
+    });
+    if let Some(path) = &found {
+        debug!(
+            "Using {} for {} with {}",
+            path.display(),
+            tool,
+            cc.display()
+        );
+    }
+    found
+}
+
+/// Finds GCC's C driver, or its C++ driver if `cxx`. `CC`/`CXX` still take precedence, then
+/// GCC `preferred` (a major version) if it is installed, then the newest versioned driver on
+/// `PATH` and finally plain `gcc`/`g++`.
+pub fn find_gcc(cxx: bool, preferred: Option<u32>) -> Result<PathBuf> {
+    let name = if cxx { "g++" } else { "gcc" };
+    let overridden = tool_env_var(name).is_some_and(|var| env::var_os(var).is_some());
+    if let (Some(preferred), false) = (preferred, overridden) {
+        let drivers = versioned_gcc_drivers(path_dirs(), name);
+        match drivers
+            .into_iter()
+            .find(|(version, _)| *version == preferred)
+        {
+            Some((_, path)) => return Ok(path),
+            None => debug!("GCC {} is not installed; using the newest", preferred),
+        }
+    }
+    find_compiler(name)
+}
+
+/// The GCC driver (`gcc`, `g++` or `gfortran`) `name` asks for, or `None` if it isn't
+/// GCC-specific.
+fn gcc_driver(name: &str) -> Option<&'static str> {
+    match name {
+        "gcc" => Some("gcc"),
+        "g++" => Some("g++"),
+        "fortran" | "gfortran" => Some("gfortran"),
+        _ => None,
+    }
+}
+
+fn path_dirs() -> Vec<PathBuf> {
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_default()
+}
+
+/// The GCC `driver`s (`gcc`, `g++`, `gfortran`) named with their major version (`gcc-13`) in
+/// `dirs`, newest first. Of equal versions, the one in the earlier directory comes first.
+fn versioned_gcc_drivers(
+    dirs: impl IntoIterator<Item = PathBuf>,
+    driver: &str,
+) -> Vec<(u32, PathBuf)> {
+    let prefix = format!("{}-", driver);
+    let mut found: Vec<(u32, PathBuf)> = dirs
+        .into_iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let version = file_name.to_str()?.strip_prefix(&prefix)?.parse().ok()?;
+            let path = entry.path();
+            (path.is_file() && !is_ccache(&path)).then_some((version, path))
+        })
+        .collect();
+    // Stable, so PATH order decides between equal versions
+    found.sort_by_key(|(version, _)| std::cmp::Reverse(*version));
+    found
+}
+
+/// Whether `path` is ccache itself or a link to it.
+pub fn is_ccache(path: &Path) -> bool {
+    let named_ccache = |p: &Path| p.file_name().is_some_and(|n| n == "ccache");
+    named_ccache(path) || std::fs::canonicalize(path).is_ok_and(|p| named_ccache(&p))
+}
+
+/// Finds a C and a C++ compiler satisfying `requirement`, returned as `(cc, cxx)`.
+///
+/// C compilers in the LLVM and GCC kegs among `kegs` (dependency opt paths) are tried first,
+/// then the system compiler [`find_compiler`] would pick, then `clang` and GCC on `PATH`, the
+/// latter in the major version a GCC requirement names if it is installed (see [`find_gcc`]).
+/// Each candidate's version is checked by running it with `--version`; the first that satisfies
+/// the requirement and has a C++ counterpart beside it (see [`cxx_counterpart`]) of the same
+/// vendor and major version wins, so the two never come from different compilers.
+pub fn find_compiler_for(
+    requirement: &CompilerRequirement,
+    kegs: &[PathBuf],
+) -> Result<(PathBuf, PathBuf)> {
+    let mut candidates = keg_compilers(kegs);
+    let default_vendor = match find_compiler("cc") {
+        Ok(path) => {
+            let vendor = compiler_info(&path).ok().map(|info| info.vendor);
+            candidates.push(path);
+            vendor
+        }
+        Err(e) => {
+            debug!("No system C compiler: {}", e);
+            None
+        }
+    };
+    let wants_gcc = requirement.vendor == Some(CompilerVendor::Gcc);
+    if !wants_gcc {
+        candidates.extend(find_compiler("clang").ok());
+    }
+    let preferred_gcc = requirement.min_version.filter(|_| wants_gcc);
+    candidates.extend(find_gcc(false, preferred_gcc).ok());
+    candidates.dedup();
+
+    let mut rejected = Vec::new();
+    for cc in candidates {
+        let info = match compiler_info(&cc) {
+            Ok(info) if requirement.accepts(info.vendor, info.major(), default_vendor) => info,
+            Ok(info) => {
+                rejected.push(format!("{} ({})", cc.display(), info));
+                continue;
             }
             Err(e) => {
-                // xcrun command itself failed to execute (likely not installed or not in PATH)
-                println!(
-                    "Failed to execute xcrun: {}. Falling back to PATH search.",
-                    e
+                debug!("Skipping {}: {}", cc.display(), e);
+                rejected.push(format!("{} (unknown version)", cc.display()));
+                continue;
+            }
+        };
+        let cxx = cxx_counterpart(&cc).filter(|cxx| cxx.is_file());
+        let cxx_info = cxx.as_deref().map(compiler_info);
+        match (cxx, cxx_info) {
+            (Some(cxx), Some(Ok(cxx_info)))
+                if cxx_info.vendor == info.vendor && cxx_info.major() == info.major() =>
+            {
+                debug!(
+                    "Using {} and {} ({}) for {}",
+                    cc.display(),
+                    cxx.display(),
+                    info,
+                    requirement
                 );
+                return Ok((cc, cxx));
             }
+            _ => rejected.push(format!(
+                "{} ({}, no matching C++ compiler)",
+                cc.display(),
+                info
+            )),
         }
     }
+    Err(SapphireError::BuildEnvError(format!(
+        "No compiler satisfying {} found{}",
+        requirement,
+        if rejected.is_empty() {
+            String::new()
+        } else {
+            format!("; rejected {}", rejected.join(", "))
+        }
+    )))
+}
 
-    // 3. Fallback to searching PATH
-    println!("Falling back to searching PATH for '{}'", name);
-    which::which(name).map_err(|e| {
-        SapphireError::BuildEnvError(format!("Failed to find compiler '{}' on PATH: {}", name, e))
+/// The C++ driver installed beside the C compiler `cc`: `clang-17` pairs with `clang++-17`,
+/// `x86_64-linux-gnu-gcc-13` with `x86_64-linux-gnu-g++-13` and `cc` with `c++`.
+fn cxx_counterpart(cc: &Path) -> Option<PathBuf> {
+    let name = cc.file_name()?.to_str()?;
+    let cxx_name = if let Some(i) = name.rfind("clang") {
+        format!("{}clang++{}", &name[..i], &name[i + "clang".len()..])
+    } else if let Some(i) = name.rfind("gcc") {
+        format!("{}g++{}", &name[..i], &name[i + "gcc".len()..])
+    } else if name == "cc" || name.ends_with("-cc") {
+        format!("{}c++", &name[..name.len() - "cc".len()])
+    } else {
+        return None;
+    };
+    Some(cc.with_file_name(cxx_name))
+}
+
+/// Finds a Fortran compiler for a build whose dependencies are at `kegs`: `FC` if set, else
+/// the newest `gfortran` of a GCC keg among them, else [`find_compiler`]`("gfortran")`.
+pub fn find_fortran_compiler(kegs: &[PathBuf]) -> Result<PathBuf> {
+    if env::var_os("FC").is_none() {
+        let bins = kegs
+            .iter()
+            .filter(|keg| {
+                keg.file_name()
+                    .is_some_and(|name| is_gcc_keg(&name.to_string_lossy()))
+            })
+            .map(|keg| keg.join("bin"));
+        if let Some((version, path)) = versioned_gcc_drivers(bins, "gfortran").into_iter().next() {
+            debug!(
+                "Using gfortran {} from a dependency: {}",
+                version,
+                path.display()
+            );
+            return Ok(path);
+        }
+    }
+    find_compiler("gfortran")
+}
+
+fn is_gcc_keg(keg_name: &str) -> bool {
+    keg_name == "gcc" || keg_name.starts_with("gcc@")
+}
+
+/// C compilers in the LLVM and GCC kegs among `kegs`. GCC kegs only ship versioned drivers
+/// (`gcc-13`), so the newest of those is used.
+fn keg_compilers(kegs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    for keg in kegs {
+        let Some(keg_name) = keg.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        let bin = keg.join("bin");
+        if keg_name == "llvm" || keg_name.starts_with("llvm@") {
+            found.push(bin.join("clang"));
+        } else if is_gcc_keg(&keg_name) {
+            let newest = versioned_gcc_drivers([bin], "gcc").into_iter().next();
+            found.extend(newest.map(|(_, path)| path));
+        }
+    }
+    found.retain(|p| p.is_file());
+    found
+}
+
+/// Whether `compiler` can compile and link a trivial C program with `-flto`. Linking is part of
+/// the check as LTO also needs a linker that understands the compiler's bitcode.
+pub fn supports_lto(compiler: &Path) -> bool {
+    let Ok(dir) = tempfile::tempdir() else {
+        return false;
+    };
+    let source = dir.path().join("lto.c");
+    if std::fs::write(&source, "int main(void) { return 0; }\n").is_err() {
+        return false;
+    }
+    let status = Command::new(compiler)
+        .arg("-flto")
+        .arg(&source)
+        .arg("-o")
+        .arg(dir.path().join("lto"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    debug!("LTO check with {}: {:?}", compiler.display(), status);
+    status.is_ok_and(|s| s.success())
+}
+
+/// Whether `compiler` accepts `flag` when compiling a trivial C file.
+pub fn supports_flag(compiler: &Path, flag: &str) -> bool {
+    let Ok(dir) = tempfile::tempdir() else {
+        return false;
+    };
+    let source = dir.path().join("flag.c");
+    if std::fs::write(&source, "int main(void) { return 0; }\n").is_err() {
+        return false;
+    }
+    let status = Command::new(compiler)
+        .arg(flag)
+        .arg("-c")
+        .arg(&source)
+        .arg("-o")
+        .arg(dir.path().join("flag.o"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    debug!(
+        "Check of {} with {}: {:?}",
+        flag,
+        compiler.display(),
+        status
+    );
+    status.is_ok_and(|s| s.success())
+}
+
+/// Whether `make` is GNU make, which (unlike BSD make) supports `-l` to limit the load
+/// average. Remembered per executable for the rest of the process.
+pub fn is_gnu_make(make: &Path) -> bool {
+    static CHECKED: Lazy<Mutex<HashMap<PathBuf, bool>>> = Lazy::new(Default::default);
+    let mut checked = CHECKED.lock().unwrap_or_else(|e| e.into_inner());
+    *checked.entry(make.to_path_buf()).or_insert_with(|| {
+        let gnu = Command::new(make)
+            .arg("--version")
+            .stderr(Stdio::null())
+            .output()
+            .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).starts_with("GNU Make"));
+        debug!("{} is GNU make: {}", make.display(), gnu);
+        gnu
+    })
+}
+
+/// A compiler's vendor and version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompilerInfo {
+    pub vendor: CompilerVendor,
+    /// `(major, minor, patch)`, in the vendor's own numbering; missing parts are 0.
+    pub version: (u32, u32, u32),
+}
+
+impl CompilerInfo {
+    pub fn major(&self) -> u32 {
+        self.version.0
+    }
+}
+
+impl fmt::Display for CompilerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (major, minor, patch) = self.version;
+        write!(f, "{} {}.{}.{}", self.vendor, major, minor, patch)
+    }
+}
+
+/// Runs `compiler --version` and parses its vendor and version. Fails if the compiler doesn't
+/// run or its output isn't recognised as clang or GCC.
+pub fn compiler_info(compiler: &Path) -> Result<CompilerInfo> {
+    let output = Command::new(compiler)
+        .arg("--version")
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| {
+            SapphireError::CommandExecError(format!(
+                "Failed to run {} --version: {}",
+                compiler.display(),
+                e
+            ))
+        })?;
+    if !output.status.success() {
+        return Err(SapphireError::CommandExecError(format!(
+            "{} --version failed with {}",
+            compiler.display(),
+            output.status
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse_compiler_version(&stdout).ok_or_else(|| {
+        SapphireError::BuildEnvError(format!(
+            "Unrecognised version output from {}: {}",
+            compiler.display(),
+            stdout.lines().next().unwrap_or_default()
+        ))
+    })
+}
+
+/// Parses the first line of `--version` output, such as `Apple clang version 15.0.0 (...)`,
+/// `Homebrew clang version 17.0.6` or `gcc-13 (Homebrew GCC 13.2.0) 13.2.0`. Xcode before 10.2
+/// called its clang `Apple LLVM`.
+fn parse_compiler_version(output: &str) -> Option<CompilerInfo> {
+    let first_line = output.lines().next()?;
+    let (vendor, version) = if first_line.starts_with("Apple clang version")
+        || first_line.starts_with("Apple LLVM version")
+    {
+        (
+            CompilerVendor::AppleClang,
+            first_line.split("version ").nth(1)?,
+        )
+    } else if first_line.contains("clang version") {
+        (CompilerVendor::Clang, first_line.split("version ").nth(1)?)
+    } else if first_line.contains("GCC") || output.contains("Free Software Foundation") {
+        // GCC puts its version last, after the packager's description
+        (CompilerVendor::Gcc, first_line.split_whitespace().last()?)
+    } else {
+        return None;
+    };
+    // Distributions append their own suffixes, as in `14.0.0-1ubuntu1.1`
+    let version = version
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()?;
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    let patch = parts.next().flatten().unwrap_or(0);
+    Some(CompilerInfo {
+        vendor,
+        version: (major, minor, patch),
     })
 }
 
 /// Finds the path to the active macOS SDK.
 /// Returns "/" on non-macOS platforms or if detection fails.
+/// Detected once per process; a failure is retried on the next call.
 pub fn find_sdk_path() -> Result<PathBuf> {
+    static SDK_PATH: OnceCell<PathBuf> = OnceCell::new();
+    SDK_PATH.get_or_try_init(detect_sdk_path).cloned()
+}
+
+fn detect_sdk_path() -> Result<PathBuf> {
     if cfg!(target_os = "macos") {
-        println!("Attempting to find macOS SDK path using xcrun");
+        debug!("Attempting to find macOS SDK path using xcrun");
         let output = Command::new("xcrun")
             .arg("--show-sdk-path")
             .stderr(Stdio::piped())
@@ -102,7 +671,7 @@ pub fn find_sdk_path() -> Result<PathBuf> {
             Ok(out) if out.status.success() => {
                 let path_str = String::from_utf8_lossy(&out.stdout).trim().to_string();
                 if path_str.is_empty() || path_str == "/" {
-                    println!("xcrun returned empty or invalid SDK path ('{}'). Check Xcode/CLT installation.", path_str);
+                    warn!("xcrun returned empty or invalid SDK path ('{}'). Check Xcode/CLT installation.", path_str);
                     // Fallback or error? Homebrew errors here. Let's error.
                     return Err(SapphireError::BuildEnvError(
                         "xcrun returned empty or invalid SDK path. Is Xcode or Command Line Tools installed correctly?".to_string()
@@ -115,7 +684,7 @@ pub fn find_sdk_path() -> Result<PathBuf> {
                         sdk_path.display()
                     )));
                 }
-                println!("Found SDK path: {}", sdk_path.display());
+                debug!("Found SDK path: {}", sdk_path.display());
                 Ok(sdk_path)
             }
             Ok(out) => {
@@ -135,16 +704,22 @@ pub fn find_sdk_path() -> Result<PathBuf> {
         }
     } else {
         // No SDK concept in this way on Linux/other platforms usually
-        println!("Not on macOS, returning '/' as SDK path placeholder");
+        debug!("Not on macOS, returning '/' as SDK path placeholder");
         Ok(PathBuf::from("/"))
     }
 }
 
 /// Gets the macOS product version string (e.g., "14.4").
 /// Returns "0.0" on non-macOS platforms.
+/// Detected once per process; a failure is retried on the next call.
 pub fn get_macos_version() -> Result<String> {
+    static MACOS_VERSION: OnceCell<String> = OnceCell::new();
+    MACOS_VERSION.get_or_try_init(detect_macos_version).cloned()
+}
+
+fn detect_macos_version() -> Result<String> {
     if cfg!(target_os = "macos") {
-        println!("Attempting to get macOS version using sw_vers");
+        debug!("Attempting to get macOS version using sw_vers");
         let output = Command::new("sw_vers")
             .arg("-productVersion")
             .stderr(Stdio::piped())
@@ -160,7 +735,7 @@ pub fn get_macos_version() -> Result<String> {
                 } else {
                     version_full.clone() // Fallback if format is unexpected
                 };
-                println!(
+                debug!(
                     "Found macOS version: {} (short: {})",
                     version_full, version_short
                 );
@@ -183,33 +758,351 @@ pub fn get_macos_version() -> Result<String> {
             }
         }
     } else {
-        println!("Not on macOS, returning '0.0' as version placeholder");
+        debug!("Not on macOS, returning '0.0' as version placeholder");
         Ok(String::from("0.0")) // Not applicable
     }
 }
 
+/// A CPU architecture source builds can target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetArch {
+    X86_64,
+    Arm64,
+}
+
+impl TargetArch {
+    /// Parses Apple and GNU spellings: `x86_64`/`amd64`/`intel`, `arm64`/`aarch64`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "x86_64" | "amd64" | "intel" => Some(Self::X86_64),
+            "arm64" | "aarch64" => Some(Self::Arm64),
+            _ => None,
+        }
+    }
+
+    /// The architecture builds and bottles target by default, if it is one of these. See
+    /// [`effective_host_arch`].
+    pub fn native() -> Option<Self> {
+        Self::parse(effective_host_arch())
+    }
+
+    /// Apple's name, as used by `-arch`.
+    pub fn apple_name(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "x86_64",
+            Self::Arm64 => "arm64",
+        }
+    }
+
+    /// The GNU target triple for this architecture on the running OS, as used by
+    /// `./configure --host`.
+    pub fn triple(&self) -> String {
+        let cpu = match self {
+            Self::X86_64 => "x86_64",
+            Self::Arm64 => "aarch64",
+        };
+        if cfg!(target_os = "macos") {
+            format!("{}-apple-darwin", cpu)
+        } else if cfg!(target_env = "musl") {
+            format!("{}-linux-musl", cpu)
+        } else {
+            format!("{}-linux-gnu", cpu)
+        }
+    }
+}
+
+impl TargetArch {
+    /// The Rust target triple for this architecture on the running OS, as used by
+    /// `cargo --target`.
+    pub fn rust_target(&self) -> String {
+        let cpu = match self {
+            Self::X86_64 => "x86_64",
+            Self::Arm64 => "aarch64",
+        };
+        if cfg!(target_os = "macos") {
+            format!("{}-apple-darwin", cpu)
+        } else if cfg!(target_env = "musl") {
+            format!("{}-unknown-linux-musl", cpu)
+        } else {
+            format!("{}-unknown-linux-gnu", cpu)
+        }
+    }
+
+    /// Go's name, as used by `GOARCH`.
+    pub fn go_arch(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "amd64",
+            Self::Arm64 => "arm64",
+        }
+    }
+}
+
+impl fmt::Display for TargetArch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.apple_name())
+    }
+}
+
 /// Gets the appropriate architecture flag (e.g., "-arch arm64") for the current build target.
 pub fn get_arch_flag() -> String {
-    if cfg!(target_os = "macos") {
-        // On macOS, we explicitly use -arch flags
-        if cfg!(target_arch = "x86_64") {
-            println!("Detected target arch: x86_64");
-            "-arch x86_64".to_string()
-        } else if cfg!(target_arch = "aarch64") {
-            println!("Detected target arch: aarch64 (arm64)");
-            "-arch arm64".to_string()
-        } else {
-            let arch = env::consts::ARCH;
-            println!("Unknown target architecture on macOS: {}, cannot determine -arch flag. Build might fail.", arch);
-            // Provide no flag in this unknown case? Or default to native?
-            // Homebrew might error or try native. Let's return empty for safety.
+    match TargetArch::native() {
+        Some(native) => get_arch_flag_for(native).unwrap_or_default(),
+        None => {
+            // Compilers target the machine by default, which is the best guess left
+            warn!(
+                "Unknown target architecture {}, cannot determine -arch flag. Build might fail.",
+                env::consts::ARCH
+            );
             String::new()
         }
+    }
+}
+
+/// The compiler flag building for `target`. On macOS that is `-arch`, for either
+/// architecture. Elsewhere the native architecture needs no flag, and other targets need a
+/// cross compiler from a toolchain file, so they are an error.
+pub fn get_arch_flag_for(target: TargetArch) -> Result<String> {
+    if cfg!(target_os = "macos") {
+        return Ok(format!("-arch {}", target.apple_name()));
+    }
+    if TargetArch::native() == Some(target) {
+        return Ok(String::new());
+    }
+    Err(SapphireError::BuildEnvError(format!(
+        "Building for {} on {} needs a cross compiler; set one with a toolchain file",
+        target,
+        compiled_arch()
+    )))
+}
+
+/// The flags for a universal (fat) binary holding both Intel and Apple Silicon code,
+/// `-arch x86_64 -arch arm64`. Empty outside macOS, where only Apple's toolchain understands
+/// multiple `-arch` flags.
+pub fn get_universal_arch_flags() -> String {
+    if cfg!(target_os = "macos") {
+        "-arch x86_64 -arch arm64".to_string()
     } else {
-        // On Linux/other, -march=native is common but less portable for distribution.
-        // Compilers usually target the host architecture by default without specific flags.
-        // Let's return an empty string for non-macOS for now. Flags can be added later if needed.
-        println!("Not on macOS, returning empty arch flag.");
         String::new()
     }
 }
+
+/// The architecture this sapphire binary was compiled for, in Apple's naming (`arm64`,
+/// `x86_64`). Builds and bottle selection follow [`effective_host_arch`] instead.
+pub fn compiled_arch() -> &'static str {
+    if cfg!(target_arch = "aarch64") {
+        "arm64"
+    } else if cfg!(target_arch = "x86_64") {
+        "x86_64"
+    } else {
+        env::consts::ARCH
+    }
+}
+
+/// The physical machine's architecture, probed at runtime and cached.
+///
+/// On macOS `hw.optional.arm64` reports Apple Silicon even to a process translated by Rosetta,
+/// where `uname -m` would say `x86_64`. Elsewhere this is `uname -m`, with Linux's `aarch64`
+/// named `arm64` like [`compiled_arch`] names it. Falls back to [`compiled_arch`] if probing
+/// fails.
+pub fn host_arch() -> &'static str {
+    static HOST_ARCH: Lazy<String> = Lazy::new(|| {
+        let probed = if cfg!(target_os = "macos") && sysctl_flag("hw.optional.arm64") {
+            Some("arm64".to_string())
+        } else {
+            command_output("uname", &["-m"]).map(|arch| match arch.as_str() {
+                "aarch64" => "arm64".to_string(),
+                _ => arch,
+            })
+        };
+        let arch = probed.unwrap_or_else(|| compiled_arch().to_string());
+        debug!("Host architecture: {}", arch);
+        arch
+    });
+    &HOST_ARCH
+}
+
+/// Whether this process is an x86_64 binary being translated by Rosetta 2.
+pub fn running_under_rosetta() -> bool {
+    static TRANSLATED: Lazy<bool> =
+        Lazy::new(|| cfg!(target_os = "macos") && sysctl_flag("sysctl.proc_translated"));
+    *TRANSLATED
+}
+
+/// The architecture installs target unless told otherwise, in Apple's naming.
+///
+/// That is [`compiled_arch`], except under Rosetta: an x86_64 sapphire on an Apple Silicon Mac
+/// still targets `arm64`, as its compilers can build for it and the machine runs the result
+/// natively. `--arch x86_64` (or `SAPPHIRE_TARGET_ARCH`) still builds for Intel.
+pub fn effective_host_arch() -> &'static str {
+    if running_under_rosetta() {
+        host_arch()
+    } else {
+        compiled_arch()
+    }
+}
+
+/// Describes the mismatch if installs target a different architecture than the machine they
+/// run on, e.g. an x86_64 sapphire under emulation on an arm64 Linux machine.
+pub fn arch_mismatch() -> Option<String> {
+    let (target, host) = (effective_host_arch(), host_arch());
+    if target == host {
+        return None;
+    }
+    Some(format!(
+        "sapphire is a {target} binary on a {host} machine, so installs target {target}. Use a \
+         native {host} build of sapphire to target {host}."
+    ))
+}
+
+/// Whether this machine refuses to run unsigned code, as Apple Silicon Macs do.
+pub fn requires_codesign() -> bool {
+    cfg!(target_os = "macos") && host_arch() == "arm64"
+}
+
+/// Records an [`arch_mismatch`] warning, if there is one.
+pub fn record_arch_mismatch(warnings: &WarningCollector) {
+    if let Some(message) = arch_mismatch() {
+        warnings.warn(
+            WarningCode::ArchitectureMismatch,
+            BuildPhase::Environment,
+            message,
+            None,
+        );
+    }
+}
+
+fn sysctl_flag(name: &str) -> bool {
+    command_output("sysctl", &["-n", name]).is_some_and(|v| v == "1")
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cxx_counterparts_keep_prefix_and_version() {
+        let counterpart = |cc: &str| cxx_counterpart(Path::new(cc)).unwrap();
+        assert_eq!(
+            counterpart("/opt/llvm/bin/clang"),
+            Path::new("/opt/llvm/bin/clang++")
+        );
+        assert_eq!(
+            counterpart("/usr/bin/clang-17"),
+            Path::new("/usr/bin/clang++-17")
+        );
+        assert_eq!(
+            counterpart("/usr/bin/x86_64-linux-gnu-gcc-13"),
+            Path::new("/usr/bin/x86_64-linux-gnu-g++-13")
+        );
+        assert_eq!(counterpart("/usr/bin/cc"), Path::new("/usr/bin/c++"));
+        assert_eq!(cxx_counterpart(Path::new("/usr/bin/icx")), None);
+    }
+
+    #[test]
+    fn minimum_versions_compare_within_a_vendor() {
+        let min_14 = CompilerRequirement {
+            vendor: None,
+            min_version: Some(14),
+        };
+        let apple = Some(CompilerVendor::AppleClang);
+        assert!(min_14.accepts(CompilerVendor::AppleClang, 15, apple));
+        assert!(!min_14.accepts(CompilerVendor::AppleClang, 13, apple));
+        // GCC 14 and clang 17 aren't newer than Apple clang 14 in any meaningful sense
+        assert!(!min_14.accepts(CompilerVendor::Gcc, 14, apple));
+        assert!(!min_14.accepts(CompilerVendor::Clang, 17, apple));
+        // With no system compiler to compare against, any vendor will do
+        assert!(min_14.accepts(CompilerVendor::Gcc, 14, None));
+
+        let clang_16 = CompilerRequirement {
+            vendor: Some(CompilerVendor::Clang),
+            min_version: Some(16),
+        };
+        assert!(clang_16.accepts(CompilerVendor::Clang, 17, apple));
+        assert!(!clang_16.accepts(CompilerVendor::AppleClang, 17, apple));
+        assert!(CompilerRequirement::default().accepts(CompilerVendor::Gcc, 4, apple));
+    }
+
+    #[test]
+    fn compiler_versions_are_parsed_per_vendor() {
+        let parsed = |output: &str| parse_compiler_version(output).map(|i| (i.vendor, i.version));
+        assert_eq!(
+            parsed("Apple clang version 15.0.0 (clang-1500.1.0.2.5)\nTarget: arm64-apple-darwin23.2.0\n"),
+            Some((CompilerVendor::AppleClang, (15, 0, 0)))
+        );
+        assert_eq!(
+            parsed("Apple LLVM version 10.0.0 (clang-1000.11.45.5)\n"),
+            Some((CompilerVendor::AppleClang, (10, 0, 0)))
+        );
+        assert_eq!(
+            parsed("Homebrew clang version 17.0.6\nTarget: x86_64-apple-darwin\n"),
+            Some((CompilerVendor::Clang, (17, 0, 6)))
+        );
+        assert_eq!(
+            parsed("Ubuntu clang version 14.0.0-1ubuntu1.1\n"),
+            Some((CompilerVendor::Clang, (14, 0, 0)))
+        );
+        assert_eq!(
+            parsed("gcc-13 (Homebrew GCC 13.2.0) 13.2.0\nCopyright (C) 2023 Free Software Foundation, Inc.\n"),
+            Some((CompilerVendor::Gcc, (13, 2, 0)))
+        );
+        assert_eq!(
+            parsed(
+                "cc (Debian 12.2.0-14) 12.2.0\nCopyright (C) 2022 Free Software Foundation, Inc.\n"
+            ),
+            Some((CompilerVendor::Gcc, (12, 2, 0)))
+        );
+        assert_eq!(
+            parsed("gcc (GCC) 9\n"),
+            Some((CompilerVendor::Gcc, (9, 0, 0)))
+        );
+        assert_eq!(
+            parsed("Intel(R) oneAPI DPC++/C++ Compiler 2024.0.0\n"),
+            None
+        );
+        assert_eq!(parsed(""), None);
+    }
+
+    #[test]
+    fn binutils_follow_the_compiler_name() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bin = tmp.path();
+        for tool in [
+            "x86_64-linux-gnu-gcc-13",
+            "x86_64-linux-gnu-gcc-ar-13",
+            "x86_64-linux-gnu-ar",
+            "clang-17",
+            "llvm-ar-17",
+        ] {
+            std::fs::write(bin.join(tool), "").unwrap();
+        }
+        std::os::unix::fs::symlink("x86_64-linux-gnu-gcc-13", bin.join("cc")).unwrap();
+
+        let gcc = bin.join("cc");
+        assert_eq!(
+            compiler_binutil(&gcc, "ar", false),
+            Some(bin.join("x86_64-linux-gnu-ar"))
+        );
+        assert_eq!(
+            compiler_binutil(&gcc, "ar", true),
+            Some(bin.join("x86_64-linux-gnu-gcc-ar-13"))
+        );
+        assert_eq!(
+            compiler_binutil(&bin.join("clang-17"), "ar", true),
+            Some(bin.join("llvm-ar-17"))
+        );
+    }
+}
@@ -2,15 +2,36 @@
 // *** No major changes needed here for this specific fix, but ensure PERL5LIB/PYTHONPATH handling
 // is correct ***
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::debug;
 
-use crate::build::devtools;
+use crate::build::events::{self, BuildEvent};
+use crate::build::toolchain::Toolchain;
+use crate::build::warnings::{BuildPhase, WarningCode, WarningCollector};
+use crate::build::{build_log, devtools, process};
+use crate::dependency::CompilerRequirement;
 use crate::model::formula::FormulaDependencies;
+use crate::receipt::read_receipt_field;
 use crate::utils::error::{Result, SapphireError};
 
+/// Binutils the build environment names explicitly, from the same toolchain as the compiler
+/// (see [`binutil_for`]). `LD` is left out: build systems link through the compiler driver,
+/// and the few that honour `LD` would run the bare linker without the driver's flags.
+const BINUTIL_VARS: [(&str, &str); 4] = [
+    ("AR", "ar"),
+    ("RANLIB", "ranlib"),
+    ("STRIP", "strip"),
+    ("NM", "nm"),
+];
+
 // Constants remain the same...
 const ENV_VARS_TO_REMOVE: &[&str] = &[
     "RUBYLIB",
@@ -60,8 +81,12 @@ const ENV_VARS_TO_REMOVE: &[&str] = &[
     "CPPFLAGS",
     "OBJCFLAGS",
     "OBJCXXFLAGS",
+    "FFLAGS",
+    "FCFLAGS",
     "CC",
     "CXX",
+    "FC",
+    "F77",
     "CPP",
     "LD",
     "DEBUG",
@@ -136,29 +161,155 @@ const ENV_VARS_TO_KEEP: &[&str] = &[
     "TZ",
 ];
 
+/// Dynamic loader variables. A build run with them set can load the wrong libraries into its
+/// own tools or bake wrong paths into its output, so no mode passes them through; any variable
+/// starting with `DYLD_` counts too.
+const LOADER_VARS: &[&str] = &["LD_LIBRARY_PATH", "LD_PRELOAD", "LD_RUN_PATH"];
+
+/// Of the variables inherited through `ENV_VARS_TO_KEEP`, the ones a sandboxed build still
+/// sees. Everything else it gets was set by sapphire. The locale stays, since tools that read
+/// or write non-ASCII text misbehave when forced into the C locale.
+const SANDBOX_INHERITED_VARS: &[&str] = &["HOME", "TMPDIR", "LANG", "LC_ALL", "LC_CTYPE"];
+
+/// Compiler optimization level of source builds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OptimizationLevel {
+    #[default]
+    O2,
+    O3,
+    /// Optimize for size.
+    Os,
+}
+
+impl OptimizationLevel {
+    /// Parses `O2`, `-O3`, `s` and the like (case-insensitive).
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().trim_start_matches('-');
+        let s = s.strip_prefix(['O', 'o']).unwrap_or(s);
+        match s.to_ascii_lowercase().as_str() {
+            "2" => Some(Self::O2),
+            "3" => Some(Self::O3),
+            "s" => Some(Self::Os),
+            _ => None,
+        }
+    }
+
+    /// The compiler flag, e.g. `-O3`.
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Self::O2 => "-O2",
+            Self::O3 => "-O3",
+            Self::Os => "-Os",
+        }
+    }
+}
+
+impl std::fmt::Display for OptimizationLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.flag()[1..])
+    }
+}
+
+/// How much of the user's environment build commands see, as with Homebrew's `env :std`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvMode {
+    /// Commands start from an empty environment and get only what sapphire sets: compilers,
+    /// flags, a PATH of its own and, outside the sandbox, a few harmless variables such as
+    /// `LANG`.
+    #[default]
+    Super,
+    /// Commands inherit the user's environment, less the variables known to break builds
+    /// (`ENV_VARS_TO_REMOVE` and the loader variables), with sapphire's variables on top and
+    /// the user's PATH after sapphire's. For formulae whose builds need something from the
+    /// user's shell.
+    Std,
+}
+
 /// Represents the sanitized build environment, mimicking Homebrew's "superenv".
 #[derive(Debug, Clone)]
 pub struct BuildEnvironment {
     /// The final map of environment variables to be used for build commands.
     vars: HashMap<String, String>,
     /// The ordered list of directories constituting the final PATH.
-    #[allow(dead_code)]
     path_dirs: Vec<PathBuf>,
     /// The root installation directory for Sapphire (e.g., /opt/homebrew or /usr/local).
     #[allow(dead_code)]
     sapphire_prefix: PathBuf,
+    /// Name of the formula being built.
+    formula_name: String,
     /// The specific installation prefix for the formula being built.
-    #[allow(dead_code)]
     formula_install_prefix: PathBuf,
     /// Resolved path to the C compiler.
-    #[allow(dead_code)]
     cc: PathBuf,
     /// Resolved path to the C++ compiler.
-    #[allow(dead_code)]
     cxx: PathBuf,
     /// Resolved path to the macOS SDK (or "/" if not applicable).
-    #[allow(dead_code)]
     sdk_path: PathBuf,
+    /// Warnings raised while setting up and running the build.
+    warnings: WarningCollector,
+    /// Toolchain definition applied on top of the detected compilers, if any.
+    toolchain: Option<Toolchain>,
+    /// Whether backends should also build and install documentation targets.
+    build_docs: bool,
+    /// Phase after which the build should stop, if any.
+    stop_after: Option<BuildPhase>,
+    /// Phases already passed through [`Self::checkpoint`], so each is only reported once.
+    finished_phases: Arc<Mutex<BTreeSet<BuildPhase>>>,
+    /// Whether build steps are printed as shell commands instead of being run.
+    dry_run: bool,
+    /// Whether `make install` goes through a `DESTDIR` staging directory.
+    staged_install: bool,
+    /// Whether make-based backends run the project's test suite before installing.
+    run_checks: bool,
+    /// Commands the formula declares its build runs, checked for before the build starts.
+    required_tools: Vec<String>,
+    /// Formula-specific arguments appended to an Autotools `configure`.
+    extra_configure_args: Vec<String>,
+    /// Whether Autotools `configure` gets `--disable-dependency-tracking`.
+    disable_dependency_tracking: bool,
+    /// Longest a single configure, build or install step may run, if limited.
+    phase_timeout: Option<Duration>,
+    /// Parallel jobs passed to make as `-j<N>`, if set explicitly.
+    make_jobs: Option<usize>,
+    /// Load average passed to make as `-l<N>`, if limited.
+    max_load: Option<u32>,
+    /// `MAKEFLAGS` the user set, kept instead of sapphire's own outside the sandbox.
+    user_makeflags: Option<String>,
+    /// Whether CC and CXX point at ccache shims.
+    use_ccache: bool,
+    /// Cache directory handed to ccache as `CCACHE_DIR`.
+    ccache_dir: Option<PathBuf>,
+    /// The `-arch` flag for the host, as put into the compiler and linker flags.
+    arch_flag: String,
+    /// Whether the flags build universal binaries instead of host-only ones.
+    universal: bool,
+    /// Version of the running macOS, `major.minor`.
+    macos_version: String,
+    /// Oldest macOS the build targets, exported as `MACOSX_DEPLOYMENT_TARGET`. `None` outside
+    /// macOS.
+    deployment_target: Option<String>,
+    /// Opt paths of dependencies that aren't linked into the prefix.
+    keg_only_deps: Vec<PathBuf>,
+    /// Whether commands only get sapphire-controlled variables plus `SANDBOX_INHERITED_VARS`.
+    sandbox: bool,
+    /// Whether commands start from an empty environment or the user's.
+    env_mode: EnvMode,
+    /// Optimization level in `CFLAGS` and `CXXFLAGS`.
+    optimization: OptimizationLevel,
+    /// Whether compiling and linking use `-flto`.
+    lto: bool,
+    /// Whether compiler warnings are errors (`-Werror`).
+    werror: bool,
+    /// Whether code is built with AddressSanitizer and UndefinedBehaviorSanitizer.
+    sanitizers: bool,
+    /// Timestamp exported as `SOURCE_DATE_EPOCH` in reproducible mode.
+    source_date_epoch: Option<u64>,
+    /// Architecture being built for, when it isn't the native one.
+    target_arch: Option<devtools::TargetArch>,
+    /// Source tree the build runs in. Commands run there unless they set their own directory,
+    /// which is then taken relative to it.
+    build_dir: Option<PathBuf>,
 }
 
 impl BuildEnvironment {
@@ -166,7 +317,7 @@ impl BuildEnvironment {
     pub fn new<F: FormulaDependencies>(
         formula: &F,
         sapphire_prefix: &Path,
-        cellar_path: &Path,
+        install_prefix: &Path,
         all_installed_opt_paths: &[PathBuf],
     ) -> Result<Self> {
         debug!(
@@ -181,6 +332,7 @@ impl BuildEnvironment {
 
         let mut vars = HashMap::new();
         let mut path_dirs = Vec::new();
+        let warnings = WarningCollector::for_formula(formula.name());
 
         filter_initial_environment(&mut vars);
         debug!("Initial environment filtering complete.");
@@ -190,7 +342,8 @@ impl BuildEnvironment {
         let sdk_path = devtools::find_sdk_path()?;
         let macos_version = devtools::get_macos_version()?;
         let arch_flag = devtools::get_arch_flag();
-        let formula_install_prefix = formula.install_prefix(cellar_path)?;
+        devtools::record_arch_mismatch(&warnings);
+        let formula_install_prefix = install_prefix.to_path_buf();
 
         debug!(
             "Resolved tools: CC={}, CXX={}, SDK={}, macOS={}, ArchFlag='{}', InstallPrefix={}",
@@ -204,21 +357,21 @@ impl BuildEnvironment {
 
         let mut include_paths = Vec::new();
         let mut lib_paths = Vec::new();
-        let mut pkgconfig_paths = Vec::new();
+        let pkgconfig_paths = pkg_config_dirs(all_installed_opt_paths);
         let mut aclocal_paths = Vec::new();
         let mut cmake_prefix_paths = Vec::new();
         let mut cmake_framework_paths = Vec::new();
+        // Keg-only deps get include and lib flags like any other, but their tools stay off
+        // PATH until marked otherwise (see `mark_keg_only`)
+        let mut keg_only_deps = Vec::new();
 
         debug!("Processing provided dependency paths for environment...");
         for dep_opt_path in all_installed_opt_paths {
             debug!("Adding paths for dependency: {}", dep_opt_path.display());
-            let bin_path = dep_opt_path.join("bin");
-            if bin_path.is_dir() {
-                path_dirs.push(bin_path);
-            }
-            let sbin_path = dep_opt_path.join("sbin");
-            if sbin_path.is_dir() {
-                path_dirs.push(sbin_path);
+            if is_keg_only_install(dep_opt_path) {
+                keg_only_deps.push(dep_opt_path.clone());
+            } else {
+                path_dirs.extend(tool_dirs(dep_opt_path));
             }
             let include_path = dep_opt_path.join("include");
             if include_path.is_dir() {
@@ -226,18 +379,10 @@ impl BuildEnvironment {
             }
             let lib_path = dep_opt_path.join("lib");
             if lib_path.is_dir() {
-                lib_paths.push(lib_path.clone());
-                let pkgconfig_lib_path = lib_path.join("pkgconfig");
-                if pkgconfig_lib_path.is_dir() {
-                    pkgconfig_paths.push(pkgconfig_lib_path);
-                }
+                lib_paths.push(lib_path);
             }
             let share_path = dep_opt_path.join("share");
             if share_path.is_dir() {
-                let pkgconfig_share_path = share_path.join("pkgconfig");
-                if pkgconfig_share_path.is_dir() {
-                    pkgconfig_paths.push(pkgconfig_share_path);
-                }
                 let aclocal_share_path = share_path.join("aclocal");
                 if aclocal_share_path.is_dir() {
                     aclocal_paths.push(aclocal_share_path);
@@ -327,6 +472,25 @@ impl BuildEnvironment {
             ""
         };
         debug!("Set CC={} CXX={}", cc.display(), cxx.display());
+        // Few builds compile Fortran, and those fail clearly without FC
+        match devtools::find_fortran_compiler(all_installed_opt_paths) {
+            Ok(fc) => {
+                debug!("Set FC and F77 to {}", fc.display());
+                vars.insert("FC".to_string(), fc.to_string_lossy().to_string());
+                vars.insert("F77".to_string(), fc.to_string_lossy().to_string());
+            }
+            Err(e) => debug!("Leaving FC and F77 unset: {}", e),
+        }
+        for (var, tool) in BINUTIL_VARS {
+            match binutil_for(&cc, tool, false) {
+                Ok(path) => {
+                    debug!("Set {}={}", var, path.display());
+                    vars.insert(var.to_string(), path.to_string_lossy().to_string());
+                }
+                // Not every build links or archives; one that does fails with a clear error
+                Err(e) => debug!("Leaving {} unset: {}", var, e),
+            }
+        }
         if !stdlib_flag.is_empty() {
             debug!("Adding default C++ stdlib flag: {}", stdlib_flag);
         }
@@ -344,9 +508,20 @@ impl BuildEnvironment {
         } else {
             String::new()
         };
-        let cflags = format!("{} -O2 {}", arch_flag, sysroot_flag)
-            .trim()
-            .to_string();
+        let deployment_target = cfg!(target_os = "macos").then(|| macos_version.clone());
+        let version_min_flag = deployment_target
+            .as_deref()
+            .map(version_min_flag)
+            .unwrap_or_default();
+        let cflags = format!(
+            "{} {} {} {}",
+            arch_flag,
+            OptimizationLevel::default().flag(),
+            sysroot_flag,
+            version_min_flag
+        )
+        .trim()
+        .to_string();
         vars.insert("CFLAGS".to_string(), cflags.clone());
         debug!("Set CFLAGS={}", cflags);
 
@@ -354,20 +529,30 @@ impl BuildEnvironment {
         vars.insert("CXXFLAGS".to_string(), cxxflags.clone());
         debug!("Set CXXFLAGS={}", cxxflags);
 
+        // gfortran doesn't take `-arch` or Apple's deployment flags
+        let fflags = OptimizationLevel::default().flag().to_string();
+        vars.insert("FFLAGS".to_string(), fflags.clone());
+        vars.insert("FCFLAGS".to_string(), fflags.clone());
+        debug!("Set FFLAGS and FCFLAGS to {}", fflags);
+
         let ldflags_lib_part = lib_paths
             .iter()
             .map(|p| format!("-L{}", p.display()))
             .collect::<Vec<_>>()
             .join(" ");
-        let ldflags = format!("{} {} {}", ldflags_lib_part, arch_flag, sysroot_flag)
-            .trim()
-            .to_string();
+        let ldflags = format!(
+            "{} {} {} {}",
+            ldflags_lib_part, arch_flag, sysroot_flag, version_min_flag
+        )
+        .trim()
+        .to_string();
         vars.insert("LDFLAGS".to_string(), ldflags.clone());
         debug!("Set LDFLAGS={}", ldflags);
 
-        let jobs = num_cpus::get().to_string();
-        vars.insert("MAKEFLAGS".to_string(), format!("-j{}", jobs));
-        debug!("Set MAKEFLAGS=-j{}", jobs);
+        // Under a parent make, MAKEFLAGS is that make's rather than something the user chose
+        let user_makeflags = std::env::var("MAKEFLAGS")
+            .ok()
+            .filter(|flags| !flags.trim().is_empty() && std::env::var_os("MAKELEVEL").is_none());
 
         Self::set_path_list_var(&mut vars, "PKG_CONFIG_PATH", &pkgconfig_paths)?;
         Self::set_path_list_var(&mut vars, "PKG_CONFIG_LIBDIR", &pkgconfig_paths)?;
@@ -380,28 +565,862 @@ impl BuildEnvironment {
         // *** Ensure PERL5LIB and PYTHONPATH are NOT set globally here ***
         // They should be handled specifically during resource installation or via wrapper scripts.
         // The initial filtering should remove them, but double-check they aren't added back.
-        if vars.contains_key("PERL5LIB") {
-            tracing::warn!("PERL5LIB unexpectedly present in global build env, removing.");
-            vars.remove("PERL5LIB");
-        }
-        if vars.contains_key("PYTHONPATH") {
-            tracing::warn!("PYTHONPATH unexpectedly present in global build env, removing.");
-            vars.remove("PYTHONPATH");
+        for leaked in ["PERL5LIB", "PYTHONPATH"] {
+            if vars.remove(leaked).is_some() {
+                warnings.warn(
+                    WarningCode::ImpureEnvironment,
+                    BuildPhase::Environment,
+                    format!(
+                        "{} unexpectedly present in global build env, removed",
+                        leaked
+                    ),
+                    None,
+                );
+            }
         }
 
         debug!("BuildEnvironment created successfully.");
 
-        Ok(Self {
+        let mut env = Self {
             vars,
             path_dirs, // Keep for reference
             sapphire_prefix: sapphire_prefix.to_path_buf(),
+            formula_name: formula.name().to_string(),
             formula_install_prefix,
             cc,
             cxx,
             sdk_path,
+            warnings,
+            toolchain: None,
+            build_docs: false,
+            stop_after: None,
+            finished_phases: Arc::default(),
+            dry_run: false,
+            staged_install: false,
+            run_checks: false,
+            required_tools: Vec::new(),
+            extra_configure_args: Vec::new(),
+            disable_dependency_tracking: true,
+            phase_timeout: None,
+            make_jobs: None,
+            max_load: None,
+            user_makeflags,
+            use_ccache: false,
+            ccache_dir: None,
+            arch_flag,
+            universal: false,
+            macos_version,
+            deployment_target,
+            keg_only_deps,
+            sandbox: false,
+            env_mode: EnvMode::Super,
+            optimization: OptimizationLevel::default(),
+            lto: false,
+            werror: false,
+            sanitizers: false,
+            source_date_epoch: None,
+            target_arch: None,
+            build_dir: None,
+        };
+        env.update_makeflags();
+        Ok(env)
+    }
+
+    /// Marks the dependency at `dep_opt_path` keg-only: it isn't linked into the prefix, so
+    /// its `include` and `lib` directories go first in `CPPFLAGS` and `LDFLAGS`, and its
+    /// pkg-config files are added. Its `bin` and `sbin` go first on `PATH` only if `on_path`,
+    /// as the compiler's directory (e.g. /usr/bin or the Xcode toolchain) often ships older
+    /// copies of the same tools (bison, flex, m4...). Dependencies whose install receipt says
+    /// they are keg-only are marked, without `on_path`, when the environment is created.
+    pub fn mark_keg_only(&mut self, dep_opt_path: &Path, on_path: bool) -> Result<()> {
+        let include_dir = dep_opt_path.join("include");
+        if include_dir.is_dir() {
+            self.prepend_flag("CPPFLAGS", format!("-I{}", include_dir.display()));
+        }
+        let lib_dir = dep_opt_path.join("lib");
+        if lib_dir.is_dir() {
+            self.prepend_flag("LDFLAGS", format!("-L{}", lib_dir.display()));
+        }
+        self.add_pkg_config_paths(&[dep_opt_path.to_path_buf()])?;
+        if on_path {
+            for dir in tool_dirs(dep_opt_path).iter().rev() {
+                self.prepend_path(dir)?;
+            }
+        }
+        if !self.keg_only_deps.iter().any(|p| p == dep_opt_path) {
+            self.keg_only_deps.push(dep_opt_path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// Opt paths of the dependencies marked keg-only.
+    pub fn keg_only_deps(&self) -> &[PathBuf] {
+        &self.keg_only_deps
+    }
+
+    /// Appends `flag` to `CFLAGS`. See [`add_flag`](Self::add_flag).
+    pub fn add_cflag(&mut self, flag: &str) {
+        self.add_flag("CFLAGS", flag);
+    }
+
+    /// Appends `flag` to `CXXFLAGS`. See [`add_flag`](Self::add_flag).
+    pub fn add_cxxflag(&mut self, flag: &str) {
+        self.add_flag("CXXFLAGS", flag);
+    }
+
+    /// Appends `flag` to `CPPFLAGS`. See [`add_flag`](Self::add_flag).
+    pub fn add_cppflag(&mut self, flag: &str) {
+        self.add_flag("CPPFLAGS", flag);
+    }
+
+    /// Appends `flag` to `LDFLAGS`. See [`add_flag`](Self::add_flag).
+    pub fn add_ldflag(&mut self, flag: &str) {
+        self.add_flag("LDFLAGS", flag);
+    }
+
+    /// Appends `flag` to the flags variable `var`, unless it is already there, so flags keep
+    /// the order they were first added in. A flag with an argument (`-isysroot <sdk>`) counts
+    /// as present only if both words appear together.
+    pub fn add_flag(&mut self, var: &str, flag: &str) {
+        let flag_words: Vec<&str> = flag.split_whitespace().collect();
+        if flag_words.is_empty() {
+            return;
+        }
+        let current = self.get_var(var).unwrap_or_default();
+        let words: Vec<&str> = current.split_whitespace().collect();
+        if words
+            .windows(flag_words.len())
+            .any(|w| w == flag_words.as_slice())
+        {
+            return;
+        }
+        let flags = words
+            .into_iter()
+            .chain(flag_words)
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.set_var(var, flags);
+    }
+
+    /// Puts `flag` first in the flags variable `var`, moving it there if already present.
+    fn prepend_flag(&mut self, var: &str, flag: String) {
+        let rest: Vec<&str> = self
+            .get_var(var)
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter(|f| *f != flag)
+            .collect();
+        let flags = std::iter::once(flag.as_str())
+            .chain(rest)
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.set_var(var, flags);
+    }
+
+    /// Switches `CC` and `CXX` to compilers satisfying `requirement`, preferring LLVM or GCC
+    /// kegs among `kegs` (the dependency opt paths) to the system compiler. A keg compiler's
+    /// `bin` also goes first on `PATH`, so tools it calls by name come from the same keg. Call
+    /// before applying a toolchain or ccache, which build on `CC`/`CXX`.
+    pub fn require_compiler(
+        &mut self,
+        requirement: &CompilerRequirement,
+        kegs: &[PathBuf],
+    ) -> Result<()> {
+        let (cc, cxx) = devtools::find_compiler_for(requirement, kegs)?;
+        for compiler in [&cc, &cxx] {
+            if let Some(bin) = compiler.parent() {
+                if kegs.iter().any(|keg| compiler.starts_with(keg)) {
+                    self.prepend_path(bin)?;
+                }
+            }
+        }
+        self.set_var("CC", cc.to_string_lossy().to_string());
+        self.set_var("CXX", cxx.to_string_lossy().to_string());
+        self.cc = cc;
+        self.cxx = cxx;
+        self.update_binutils();
+        Ok(())
+    }
+
+    /// Points `AR`, `RANLIB`, `STRIP` and `NM` at the binutils of the C compiler in use (see
+    /// [`binutil_for`]), after it or LTO changed. Tools a toolchain names are kept.
+    fn update_binutils(&mut self) {
+        let toolchain = self.toolchain.as_ref();
+        let cc = toolchain
+            .and_then(|t| t.cc.clone())
+            .unwrap_or_else(|| self.cc.clone());
+        let named_by_toolchain = |var: &str| {
+            toolchain.is_some_and(|t| match var {
+                "AR" => t.ar.is_some(),
+                "RANLIB" => t.ranlib.is_some(),
+                "STRIP" => t.strip.is_some(),
+                _ => false,
+            })
+        };
+        let tools: Vec<(&str, Result<PathBuf>)> = BINUTIL_VARS
+            .into_iter()
+            .filter(|(var, _)| !named_by_toolchain(var))
+            .map(|(var, tool)| (var, binutil_for(&cc, tool, self.lto)))
+            .collect();
+        for (var, tool) in tools {
+            match tool {
+                Ok(path) => {
+                    debug!("Set {}={}", var, path.display());
+                    self.set_var(var, path.to_string_lossy().to_string());
+                }
+                Err(e) => debug!("Leaving {} as it is: {}", var, e),
+            }
+        }
+    }
+
+    /// Vendor and version of the C compiler `CC` names, for backends and formulae that depend
+    /// on compiler features. Runs the compiler, so callers should keep the result.
+    pub fn compiler_info(&self) -> Result<devtools::CompilerInfo> {
+        let cc = self
+            .get_var("CC")
+            .map_or_else(|| self.cc.clone(), PathBuf::from);
+        devtools::compiler_info(&cc)
+    }
+
+    /// Applies `toolchain` to the environment and keeps it so backends can generate their own
+    /// toolchain/cross files from it.
+    pub fn set_toolchain(&mut self, toolchain: Toolchain) {
+        toolchain.apply_to_env(self);
+        self.toolchain = Some(toolchain);
+        self.update_binutils();
+    }
+
+    pub fn toolchain(&self) -> Option<&Toolchain> {
+        self.toolchain.as_ref()
+    }
+
+    /// Sets the number of parallel make jobs, both for backends that pass `-j` themselves and
+    /// through `MAKEFLAGS` for make invocations they don't control.
+    pub fn set_make_jobs(&mut self, jobs: usize) {
+        self.make_jobs = Some(jobs.max(1));
+        self.update_makeflags();
+    }
+
+    /// Parallel jobs for make. Defaults to the `-j` of a `MAKEFLAGS` the user set, where that
+    /// applies (see [`BuildEnvironment::user_makeflags`]), else the number of logical CPUs.
+    pub fn make_jobs(&self) -> usize {
+        self.make_jobs
+            .or_else(|| self.user_makeflags().and_then(makeflags_jobs))
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// The `MAKEFLAGS` the user set, unless the sandbox applies: a sandboxed build ignores them
+    /// entirely, for the job count as much as for what make sees.
+    fn user_makeflags(&self) -> Option<&str> {
+        if self.sandbox && self.env_mode == EnvMode::Super {
+            None
+        } else {
+            self.user_makeflags.as_deref()
+        }
+    }
+
+    /// Sets `MAKEFLAGS` to the job count, after the other flags of a `MAKEFLAGS` the user set
+    /// where that applies; the user's own `-j` is replaced.
+    fn update_makeflags(&mut self) {
+        let jobs_flag = format!("-j{}", self.make_jobs());
+        let makeflags = match self.user_makeflags() {
+            Some(flags) => without_jobs_flags(flags)
+                .into_iter()
+                .chain(std::iter::once(jobs_flag))
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => jobs_flag,
+        };
+        self.set_var("MAKEFLAGS", makeflags);
+    }
+
+    /// Has make start no new jobs while the load average is above `load`. Only passed to makes
+    /// that understand `-l` (see [`BuildEnvironment::make_parallel_args`]).
+    pub fn set_max_load(&mut self, load: Option<u32>) {
+        self.max_load = load.filter(|&l| l > 0);
+    }
+
+    pub fn max_load(&self) -> Option<u32> {
+        self.max_load
+    }
+
+    /// The `-j<N>` and, when a load limit is set, `-l<load>` arguments for ninja.
+    pub fn ninja_parallel_args(&self) -> Vec<String> {
+        let mut args = vec![format!("-j{}", self.make_jobs())];
+        args.extend(self.max_load.map(|load| format!("-l{}", load)));
+        args
+    }
+
+    /// The `-j<N>` argument for `make_exe`, followed by `-l<load>` when a load limit is set and
+    /// `make_exe` is GNU make. BSD make has no load limit, so it is left out there.
+    pub fn make_parallel_args(&self, make_exe: &Path) -> Vec<String> {
+        let mut args = vec![format!("-j{}", self.make_jobs())];
+        if let Some(load) = self.max_load {
+            if devtools::is_gnu_make(make_exe) {
+                args.push(format!("-l{}", load));
+            } else {
+                debug!(
+                    "{} does not support -l; not limiting the load",
+                    make_exe.display()
+                );
+            }
+        }
+        args
+    }
+
+    pub fn set_build_docs(&mut self, build_docs: bool) {
+        self.build_docs = build_docs;
+    }
+
+    /// Whether backends should run the project's documentation targets after installing.
+    pub fn build_docs(&self) -> bool {
+        self.build_docs
+    }
+
+    pub fn set_stop_after(&mut self, phase: Option<BuildPhase>) {
+        self.stop_after = phase;
+    }
+
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Whether [`run_or_print`](Self::run_or_print) prints build steps instead of running them.
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Runs the build step `cmd` with `run`. In a dry run, prints it instead as a command line
+    /// that can be pasted into a shell, and returns a successful, empty `Output`. `cmd` should
+    /// already have the build environment applied, so the printed line includes its changes
+    /// to the environment.
+    pub fn run_or_print(
+        &self,
+        cmd: &mut Command,
+        context: &str,
+        run: impl FnOnce(&mut Command) -> Result<Output>,
+    ) -> Result<Output> {
+        if !self.dry_run {
+            return run(cmd);
+        }
+        println!("# {}\n{}", context, process::shell_command_line(cmd));
+        Ok(Output {
+            status: ExitStatus::from_raw(0),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        })
+    }
+
+    pub fn set_run_checks(&mut self, run_checks: bool) {
+        self.run_checks = run_checks;
+    }
+
+    /// Whether make-based backends should run `make check` between building and installing.
+    pub fn run_checks(&self) -> bool {
+        self.run_checks
+    }
+
+    pub fn set_required_tools(&mut self, tools: Vec<String>) {
+        self.required_tools = tools;
+    }
+
+    /// Commands the formula's build runs beyond those of its build system's backend.
+    pub fn required_tools(&self) -> &[String] {
+        &self.required_tools
+    }
+
+    pub fn set_extra_configure_args(&mut self, args: Vec<String>) {
+        self.extra_configure_args = args;
+    }
+
+    /// Arguments the formula adds to an Autotools `configure`, after the standard ones.
+    pub fn extra_configure_args(&self) -> &[String] {
+        &self.extra_configure_args
+    }
+
+    pub fn set_disable_dependency_tracking(&mut self, disable: bool) {
+        self.disable_dependency_tracking = disable;
+    }
+
+    /// Whether Autotools `configure` gets `--disable-dependency-tracking`, which speeds up
+    /// one-off builds. On unless the formula needs the tracking.
+    pub fn disable_dependency_tracking(&self) -> bool {
+        self.disable_dependency_tracking
+    }
+
+    pub fn set_phase_timeout(&mut self, timeout: Option<Duration>) {
+        self.phase_timeout = timeout;
+    }
+
+    /// How long each configure, build and install step may take before it is killed with
+    /// [`SapphireError::BuildTimeout`]. `None` leaves only the output-silence watchdog.
+    pub fn phase_timeout(&self) -> Option<Duration> {
+        self.phase_timeout
+    }
+
+    pub fn set_staged_install(&mut self, staged: bool) {
+        self.staged_install = staged;
+    }
+
+    /// Whether make-based backends install into a `DESTDIR` staging directory and move the
+    /// result into the keg, rather than installing into the keg directly.
+    pub fn staged_install(&self) -> bool {
+        self.staged_install
+    }
+
+    /// Called once `phase` has finished, which is published as [`BuildEvent::PhaseFinished`].
+    /// Fails with [`SapphireError::BuildStopped`] if the build was asked to stop after it, which
+    /// `build_from_source` turns into keeping the build directory. Backends without a separate
+    /// step for a phase check it along with the step that covers it; a phase checked again is
+    /// ignored.
+    pub fn checkpoint(&self, phase: BuildPhase) -> Result<()> {
+        let first = self
+            .finished_phases
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(phase);
+        if !first {
+            return Ok(());
+        }
+        if let Some(log) = build_log::active() {
+            log.header(&format!("{} phase finished", phase));
+        }
+        events::publish(BuildEvent::PhaseFinished {
+            formula: self.formula_name.clone(),
+            phase,
+        });
+        if self.stop_after != Some(phase) {
+            return Ok(());
+        }
+        Err(SapphireError::BuildStopped {
+            phase: phase.to_string(),
+            build_dir: self.build_dir().to_path_buf(),
         })
     }
 
+    /// Routes the C and C++ compilers through ccache, keeping its cache in `cache_dir`.
+    ///
+    /// Writes `cc` and `c++` shims under `cache_dir/shims/<formula>` that exec ccache with the
+    /// current `CC`/`CXX`, so a toolchain applied earlier is what gets cached, and points
+    /// `CC`/`CXX` at them. Build systems that only accept a single executable for the compiler
+    /// handle the shims like any other compiler. Returns `false`, leaving the environment
+    /// untouched, if ccache can't be found.
+    pub fn enable_ccache(&mut self, cache_dir: &Path) -> Result<bool> {
+        if self.use_ccache {
+            return Ok(true);
+        }
+        let Ok(ccache) = which::which_in("ccache", self.get_path_string(), Path::new("."))
+            .or_else(|_| which::which("ccache"))
+        else {
+            debug!("ccache not found; compiling without it");
+            return Ok(false);
+        };
+
+        let shim_dir = cache_dir.join("shims").join(&self.formula_name);
+        std::fs::create_dir_all(&shim_dir)?;
+        for (var, shim_name) in [("CC", "cc"), ("CXX", "c++")] {
+            let compiler = match self.get_var(var) {
+                Some(c) if !devtools::is_ccache(Path::new(c)) => PathBuf::from(c),
+                _ if var == "CC" => self.cc.clone(),
+                _ => self.cxx.clone(),
+            };
+            let shim = shim_dir.join(shim_name);
+            write_ccache_shim(&shim, &ccache, &compiler)?;
+            self.set_var(var, shim.to_string_lossy().to_string());
+        }
+        self.set_var("CCACHE_DIR", cache_dir.to_string_lossy().to_string());
+        debug!(
+            "Compiling through {} with cache {}",
+            ccache.display(),
+            cache_dir.display()
+        );
+        self.use_ccache = true;
+        self.ccache_dir = Some(cache_dir.to_path_buf());
+        Ok(true)
+    }
+
+    /// Switches `CFLAGS`, `CXXFLAGS` and `LDFLAGS` from the host's `-arch` flag to
+    /// [`devtools::get_universal_arch_flags`], so everything is built for both Intel and Apple
+    /// Silicon. Only possible on macOS; elsewhere a warning is recorded and the build stays
+    /// host-only.
+    ///
+    /// Configure scripts often choke on multiple `-arch` flags: their preprocessor probes
+    /// (`cc -E`) refuse them outright. Configure steps should therefore go through
+    /// [`apply_host_arch`](Self::apply_host_arch) and the compile and install steps pass
+    /// [`universal_make_args`](Self::universal_make_args) to restore both arches.
+    pub fn set_universal(&mut self, universal: bool) {
+        if !universal || self.universal {
+            return;
+        }
+        let universal_flags = devtools::get_universal_arch_flags();
+        if universal_flags.is_empty() {
+            self.warnings.warn(
+                WarningCode::ArchitectureMismatch,
+                BuildPhase::Environment,
+                "Universal binaries can only be built on macOS; building for the host only"
+                    .to_string(),
+                None,
+            );
+            return;
+        }
+        for var in UNIVERSAL_FLAG_VARS {
+            let flags = self.get_var(var).unwrap_or_default();
+            let flags = replace_arch_flags(flags, &self.arch_flag, &universal_flags);
+            self.set_var(var, flags);
+        }
+        self.universal = true;
+    }
+
+    /// Targets macOS `version` (`major.minor`, e.g. `11.0`) instead of the running one, for
+    /// binaries that must run on older systems. Updates `MACOSX_DEPLOYMENT_TARGET` and the
+    /// `-mmacosx-version-min` compiler and linker flags. A target newer than the running macOS
+    /// is applied but warned about, as the build's own test runs may then fail. Ignored
+    /// outside macOS.
+    pub fn set_deployment_target(&mut self, version: &str) -> Result<()> {
+        let Some(current) = self.deployment_target.clone() else {
+            debug!("Not on macOS, ignoring deployment target {}", version);
+            return Ok(());
+        };
+        let requested = parse_macos_version(version).ok_or_else(|| {
+            SapphireError::BuildEnvError(format!(
+                "Invalid macOS deployment target '{}' (expected e.g. 11.0)",
+                version
+            ))
+        })?;
+        if parse_macos_version(&self.macos_version).is_some_and(|running| requested > running) {
+            self.warnings.warn(
+                WarningCode::DeploymentTargetMismatch,
+                BuildPhase::Environment,
+                format!(
+                    "Deployment target {} is newer than the running macOS {}",
+                    version, self.macos_version
+                ),
+                None,
+            );
+        }
+        for var in ["CFLAGS", "CXXFLAGS", "LDFLAGS"] {
+            let flags = self
+                .get_var(var)
+                .unwrap_or_default()
+                .replace(&version_min_flag(&current), &version_min_flag(version));
+            self.set_var(var, flags);
+        }
+        self.set_var("MACOSX_DEPLOYMENT_TARGET", version);
+        self.deployment_target = Some(version.to_string());
+        Ok(())
+    }
+
+    /// Builds against the macOS SDK at `sdk` instead of the one `xcrun` reports, for pinning an
+    /// SDK version. Updates `SDKROOT` and the `-isysroot` compiler and linker flags. Fails if
+    /// `sdk` isn't an SDK directory. Ignored outside macOS.
+    pub fn set_sdk_path(&mut self, sdk: &Path) -> Result<()> {
+        if !cfg!(target_os = "macos") {
+            debug!("Not on macOS, ignoring SDK {}", sdk.display());
+            return Ok(());
+        }
+        if !sdk.join("usr/include").is_dir() {
+            return Err(SapphireError::BuildEnvError(format!(
+                "SDK {} does not exist or has no usr/include",
+                sdk.display()
+            )));
+        }
+        let new_flag = format!("-isysroot {}", sdk.display());
+        if self.sdk_path == Path::new("/") {
+            self.add_cflag(&new_flag);
+            self.add_cxxflag(&new_flag);
+            self.add_ldflag(&new_flag);
+        } else {
+            let old_flag = format!("-isysroot {}", self.sdk_path.display());
+            for var in ["CFLAGS", "CXXFLAGS", "LDFLAGS"] {
+                let flags = self
+                    .get_var(var)
+                    .unwrap_or_default()
+                    .replace(&old_flag, &new_flag);
+                self.set_var(var, flags);
+            }
+        }
+        self.set_var("SDKROOT", sdk.to_string_lossy());
+        self.sdk_path = sdk.to_path_buf();
+        Ok(())
+    }
+
+    /// The macOS SDK builds compile against, or `/` outside macOS.
+    pub fn sdk_path(&self) -> &Path {
+        &self.sdk_path
+    }
+
+    /// Replaces the optimization flag in `CFLAGS`, `CXXFLAGS`, `FFLAGS` and `FCFLAGS` with
+    /// `level`'s.
+    pub fn set_optimization(&mut self, level: OptimizationLevel) {
+        if level == self.optimization {
+            return;
+        }
+        let current = self.optimization.flag();
+        for var in ["CFLAGS", "CXXFLAGS", "FFLAGS", "FCFLAGS"] {
+            let flags = self
+                .get_var(var)
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(|f| if f == current { level.flag() } else { f })
+                .collect::<Vec<_>>()
+                .join(" ");
+            self.set_var(var, flags);
+        }
+        self.optimization = level;
+    }
+
+    pub fn optimization(&self) -> OptimizationLevel {
+        self.optimization
+    }
+
+    /// Adds `-flto` to the compiler and linker flags, if the compiler `CC` names can compile
+    /// and link a program with it. Otherwise records a warning and builds without LTO. Returns
+    /// whether LTO is on.
+    pub fn enable_lto(&mut self) -> bool {
+        if self.lto {
+            return true;
+        }
+        let cc = self
+            .get_var("CC")
+            .map_or_else(|| self.cc.clone(), PathBuf::from);
+        if !devtools::supports_lto(&cc) {
+            self.warnings.warn(
+                WarningCode::UnsupportedFeature,
+                BuildPhase::Environment,
+                format!(
+                    "{} can't build with -flto; building without LTO",
+                    cc.display()
+                ),
+                None,
+            );
+            return false;
+        }
+        self.add_cflag("-flto");
+        self.add_cxxflag("-flto");
+        self.add_ldflag("-flto");
+        self.lto = true;
+        // Archiving LTO objects takes tools that can read them
+        self.update_binutils();
+        true
+    }
+
+    /// Whether compiling and linking use link-time optimization.
+    pub fn lto(&self) -> bool {
+        self.lto
+    }
+
+    /// Adds `-Werror` to `CFLAGS` and `CXXFLAGS`, so any compiler warning fails the build.
+    /// Meant for auditing a formula: configure scripts also compile with these flags, and a
+    /// probe that only warns then reports the feature missing, so fragile builds may fail or
+    /// come out different.
+    pub fn enable_werror(&mut self) {
+        if self.werror {
+            return;
+        }
+        self.add_cflag("-Werror");
+        self.add_cxxflag("-Werror");
+        self.werror = true;
+    }
+
+    /// Whether compiler warnings are errors.
+    pub fn werror(&self) -> bool {
+        self.werror
+    }
+
+    /// Compiles and links with AddressSanitizer and UndefinedBehaviorSanitizer
+    /// (`-fsanitize=address,undefined`), keeping frame pointers for readable reports. For
+    /// debugging a formula; the keg's binaries then need the sanitizer runtimes and run slower.
+    pub fn enable_sanitizers(&mut self) {
+        if self.sanitizers {
+            return;
+        }
+        for flag in ["-fsanitize=address,undefined", "-fno-omit-frame-pointer"] {
+            self.add_cflag(flag);
+            self.add_cxxflag(flag);
+        }
+        self.add_ldflag("-fsanitize=address,undefined");
+        self.sanitizers = true;
+    }
+
+    /// Asks the toolchain to produce the same bytes from the same source. All of this is best
+    /// effort; tools that ignore it still build, just not reproducibly:
+    /// - `SOURCE_DATE_EPOCH` replaces the current time in `__DATE__`/`__TIME__` (GCC 7+, Clang 16+)
+    ///   and in docs and archives from tools following reproducible-builds.org.
+    /// - `ZERO_AR_DATE=1` makes Apple's `ar`, `libtool` and `ld` write zero timestamps into static
+    ///   archives and debug maps. GNU `ar` is deterministic by default.
+    /// - `-ffile-prefix-map=<build_dir>=.` keeps the random build directory out of debug info and
+    ///   `__FILE__` (GCC 8+, Clang 10+). It is skipped with a warning for other compilers.
+    ///
+    /// Nothing like `-Wl,-no_uuid` is passed: `ld64` and `--build-id` derive the UUID from the
+    /// linked contents, and recent dyld refuses to load images without one.
+    pub fn enable_reproducible(&mut self, source_date_epoch: u64, build_dir: &Path) {
+        self.set_var("SOURCE_DATE_EPOCH", source_date_epoch.to_string());
+        self.set_var("ZERO_AR_DATE", "1");
+        self.source_date_epoch = Some(source_date_epoch);
+
+        let prefix_map = format!("-ffile-prefix-map={}=.", build_dir.display());
+        let cc = self
+            .get_var("CC")
+            .map_or_else(|| self.cc.clone(), PathBuf::from);
+        if devtools::supports_flag(&cc, &prefix_map) {
+            self.add_cflag(&prefix_map);
+            self.add_cxxflag(&prefix_map);
+        } else {
+            self.warnings.warn(
+                WarningCode::UnsupportedFeature,
+                BuildPhase::Environment,
+                format!(
+                    "{} doesn't support -ffile-prefix-map; the build directory may end up in \
+                     the binaries",
+                    cc.display()
+                ),
+                None,
+            );
+        }
+    }
+
+    /// The `SOURCE_DATE_EPOCH` of a reproducible build, `None` otherwise.
+    pub fn source_date_epoch(&self) -> Option<u64> {
+        self.source_date_epoch
+    }
+
+    /// Builds for `target` instead of the native architecture, replacing the `-arch` flag in
+    /// the compiler and linker flags. Fails where that needs a cross compiler (see
+    /// [`devtools::get_arch_flag_for`]) and no toolchain file has set one, which should be done
+    /// first. Autotools configure steps add
+    /// [`target_configure_args`](Self::target_configure_args) so they know they cross-compile.
+    pub fn set_target_arch(&mut self, target: devtools::TargetArch) -> Result<()> {
+        if devtools::TargetArch::native() == Some(target) {
+            return Ok(());
+        }
+        let arch_flag = match devtools::get_arch_flag_for(target) {
+            Ok(flag) => flag,
+            // The toolchain's compilers are trusted to build for the target on their own
+            Err(_) if self.toolchain.is_some() => {
+                debug!(
+                    "Building for {} with the compilers of the toolchain file",
+                    target
+                );
+                String::new()
+            }
+            Err(e) => return Err(e),
+        };
+        for var in UNIVERSAL_FLAG_VARS {
+            let flags = self.get_var(var).unwrap_or_default();
+            let flags = replace_arch_flags(flags, &self.arch_flag, &arch_flag);
+            self.set_var(var, flags);
+        }
+        self.arch_flag = arch_flag;
+        self.target_arch = Some(target);
+        Ok(())
+    }
+
+    /// The architecture being built for, if not the native one.
+    pub fn target_arch(&self) -> Option<devtools::TargetArch> {
+        self.target_arch
+    }
+
+    /// The architecture being built for when it isn't the one sapphire was compiled for. Only
+    /// the C and C++ flags carry `-arch`; toolchains such as cargo and go default to their own
+    /// architecture, which under Rosetta is x86_64 while the build targets arm64, so they must
+    /// be told this one explicitly.
+    pub fn foreign_arch(&self) -> Option<devtools::TargetArch> {
+        let arch = self.target_arch.or_else(devtools::TargetArch::native)?;
+        (arch.apple_name() != devtools::compiled_arch()).then_some(arch)
+    }
+
+    /// `--build=<triple>` and `--host=<triple>` for Autotools configure scripts, so they don't
+    /// have to guess the platform from `uname`, which under Rosetta or with an unusual
+    /// `config.guess` gets it wrong. `--host` is the target architecture's triple, or the
+    /// native one; it is left to the toolchain file when that names a cross target. Empty if
+    /// sapphire doesn't know the native architecture.
+    pub fn target_configure_args(&self) -> Vec<String> {
+        let Some(native) = devtools::TargetArch::native() else {
+            return Vec::new();
+        };
+        let mut args = vec![format!("--build={}", native.triple())];
+        if !self.toolchain.as_ref().is_some_and(|t| t.is_cross()) {
+            let host = self.target_arch.unwrap_or(native);
+            args.push(format!("--host={}", host.triple()));
+        }
+        args
+    }
+
+    /// The macOS version the build targets, if building on macOS.
+    pub fn deployment_target(&self) -> Option<&str> {
+        self.deployment_target.as_deref()
+    }
+
+    /// Whether the flags build universal binaries.
+    pub fn universal(&self) -> bool {
+        self.universal
+    }
+
+    /// In universal mode, overrides the compiler and linker flags of `command` with host-only
+    /// ones. Meant for configure steps, after the environment has been applied.
+    pub fn apply_host_arch(&self, command: &mut std::process::Command) {
+        if !self.universal {
+            return;
+        }
+        let universal_flags = devtools::get_universal_arch_flags();
+        for var in UNIVERSAL_FLAG_VARS {
+            let flags = self.get_var(var).unwrap_or_default();
+            command.env(
+                var,
+                replace_arch_flags(flags, &universal_flags, &self.arch_flag),
+            );
+        }
+    }
+
+    /// In universal mode, `VAR=value` make arguments putting the universal compiler and linker
+    /// flags back over whatever a host-only configure step wrote into the Makefiles. Command
+    /// line variables also reach recursive makes. Empty otherwise.
+    pub fn universal_make_args(&self) -> Vec<String> {
+        if !self.universal {
+            return Vec::new();
+        }
+        UNIVERSAL_FLAG_VARS
+            .iter()
+            .map(|var| format!("{}={}", var, self.get_var(var).unwrap_or_default()))
+            .collect()
+    }
+
+    /// Whether compilers run through ccache.
+    pub fn use_ccache(&self) -> bool {
+        self.use_ccache
+    }
+
+    /// The ccache cache directory, if ccache is enabled.
+    pub fn ccache_dir(&self) -> Option<&Path> {
+        self.ccache_dir.as_deref()
+    }
+
+    /// Name of the formula being built. Backends use this rather than inferring it from the
+    /// install directory, whose shape depends on the prefix layout.
+    pub fn formula_name(&self) -> &str {
+        &self.formula_name
+    }
+
+    /// The source tree commands run in, or `.` before one is set. Backends resolve the files
+    /// they look for against it, never against the process's working directory, which stays
+    /// put so that several builds can run at once.
+    pub fn build_dir(&self) -> &Path {
+        self.build_dir.as_deref().unwrap_or(Path::new("."))
+    }
+
+    /// A copy of this environment whose commands run in `dir`, e.g. a resource's stage
+    /// directory. Warnings still go to the same collector.
+    pub fn in_build_dir(&self, dir: &Path) -> Self {
+        let mut env = self.clone();
+        env.build_dir = Some(dir.to_path_buf());
+        env
+    }
+
+    /// The collector for warnings raised during this build. Build backends record into it.
+    pub fn warnings(&self) -> &WarningCollector {
+        &self.warnings
+    }
+
     // is_controlled_homebrew_var remains unchanged
     fn is_controlled_homebrew_var(key: &str) -> bool {
         matches!(
@@ -462,11 +1481,150 @@ impl BuildEnvironment {
         Ok(())
     }
 
-    /// Applies the sanitized environment to a `std::process::Command`.
+    /// Adds each dependency's `lib` directory to the runtime search path of everything linked
+    /// in this environment, so built binaries find cellar libraries without
+    /// `DYLD_LIBRARY_PATH`/`LD_LIBRARY_PATH`. On Linux the directories also go into
+    /// `-rpath-link`, which the linker needs to resolve the dependencies' own dependencies.
+    /// Prefixes without a `lib` directory are skipped.
+    pub fn with_rpaths(mut self, dep_prefixes: &[PathBuf]) -> Self {
+        for lib_dir in dep_prefixes.iter().map(|p| p.join("lib")) {
+            if !lib_dir.is_dir() {
+                debug!("No lib directory to add to rpath: {}", lib_dir.display());
+                continue;
+            }
+            self.add_ldflag(&format!("-Wl,-rpath,{}", lib_dir.display()));
+            if !cfg!(target_os = "macos") {
+                self.add_ldflag(&format!("-Wl,-rpath-link,{}", lib_dir.display()));
+            }
+        }
+        self
+    }
+
+    /// Adds the `lib/pkgconfig` and `share/pkgconfig` directories of each of `prefixes` to
+    /// `PKG_CONFIG_PATH`, after those already there, for dependencies that become available
+    /// after the environment was created. Missing directories are skipped and duplicates
+    /// dropped. `PKG_CONFIG_LIBDIR` gets the same list, so `.pc` files from outside sapphire
+    /// can't shadow a dependency's.
+    pub fn add_pkg_config_paths(&mut self, prefixes: &[PathBuf]) -> Result<()> {
+        let mut dirs: Vec<PathBuf> = self
+            .get_var("PKG_CONFIG_PATH")
+            .map(|current| std::env::split_paths(current).collect())
+            .unwrap_or_default();
+        dirs.extend(pkg_config_dirs(prefixes));
+        Self::set_path_list_var(&mut self.vars, "PKG_CONFIG_PATH", &dirs)?;
+        Self::set_path_list_var(&mut self.vars, "PKG_CONFIG_LIBDIR", &dirs)
+    }
+
+    /// Isolates commands from the user's shell: only `HOME`, `TMPDIR`, the locale and the
+    /// variables sapphire sets itself reach them, so editor, terminal and the like can't change
+    /// what gets built. Without it, the few harmless variables in `ENV_VARS_TO_KEEP` pass through
+    /// too. Compiler, make and pkg-config variables are never inherited either way.
+    pub fn set_sandbox(&mut self, sandbox: bool) {
+        self.sandbox = sandbox;
+        self.update_makeflags();
+    }
+
+    pub fn sandbox(&self) -> bool {
+        self.sandbox
+    }
+
+    /// Whether `key=value` is a variable passed through from the user's environment that a
+    /// sandboxed build shouldn't see. A kept variable sapphire has since overridden is its own,
+    /// as is `MAKEFLAGS`, which sapphire always sets (see [`BuildEnvironment::user_makeflags`]).
+    fn is_sandboxed_out(&self, key: &str, value: &str) -> bool {
+        ENV_VARS_TO_KEEP.contains(&key)
+            && !SANDBOX_INHERITED_VARS.contains(&key)
+            && key != "MAKEFLAGS"
+            && std::env::var(key).is_ok_and(|inherited| inherited == value)
+    }
+
+    /// Selects how much of the user's environment commands see. The sandbox only applies to
+    /// [`EnvMode::Super`], as [`EnvMode::Std`] opts into the user's environment.
+    pub fn set_env_mode(&mut self, mode: EnvMode) {
+        self.env_mode = mode;
+        self.update_makeflags();
+    }
+
+    pub fn env_mode(&self) -> EnvMode {
+        self.env_mode
+    }
+
+    /// Whether `key` is kept from commands even in [`EnvMode::Std`].
+    fn is_stripped_from_stdenv(key: &str) -> bool {
+        ENV_VARS_TO_REMOVE.contains(&key)
+            || LOADER_VARS.contains(&key)
+            || key.starts_with("DYLD_")
+            || (key.starts_with("HOMEBREW_") && !Self::is_controlled_homebrew_var(key))
+    }
+
+    /// The PATH of an [`EnvMode::Std`] command: sapphire's directories, then the user's.
+    fn stdenv_path(&self) -> Option<String> {
+        let mut dirs: Vec<PathBuf> = std::env::split_paths(self.get_path_string()?).collect();
+        if let Some(user_path) = std::env::var_os("PATH") {
+            for dir in std::env::split_paths(&user_path) {
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+        }
+        std::env::join_paths(dirs).ok()?.into_string().ok()
+    }
+
+    /// The complete environment commands run with, sorted by name: in [`EnvMode::Super`]
+    /// sapphire's variables less any the sandbox withholds, in [`EnvMode::Std`] the user's
+    /// environment with those applied on top.
+    pub fn applied_env(&self) -> Vec<(String, String)> {
+        let mut env: BTreeMap<String, String> = match self.env_mode {
+            EnvMode::Super => self
+                .vars
+                .iter()
+                .filter(|(key, value)| !self.sandbox || !self.is_sandboxed_out(key, value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            EnvMode::Std => std::env::vars_os()
+                .filter_map(|(key, value)| {
+                    Some((key.into_string().ok()?, value.into_string().ok()?))
+                })
+                .filter(|(key, _)| !Self::is_stripped_from_stdenv(key))
+                .chain(self.vars.clone())
+                .collect(),
+        };
+        if self.env_mode == EnvMode::Std {
+            if let Some(path) = self.stdenv_path() {
+                env.insert("PATH".to_string(), path);
+            }
+        }
+        env.into_iter().collect()
+    }
+
+    /// [`applied_env`](Self::applied_env) as shown to the user or written to the build log. In
+    /// [`EnvMode::Std`], which passes the user's environment through, the values of variables
+    /// whose names suggest credentials (a `TOKEN`, `SECRET`, `KEY`, `AUTH`, password or
+    /// credential word, as in `GITHUB_TOKEN` or `NPM_CONFIG__AUTH`) are replaced with
+    /// `<redacted>`.
+    pub fn displayed_env(&self) -> Vec<(String, String)> {
+        let mut env = self.applied_env();
+        if self.env_mode == EnvMode::Std {
+            for (key, value) in &mut env {
+                if is_secret_name(key) {
+                    *value = "<redacted>".to_string();
+                }
+            }
+        }
+        env
+    }
+
+    /// Replaces the environment of `command` with [`applied_env`](Self::applied_env) and runs
+    /// it in the [`build_dir`](Self::build_dir), if one is set.
     pub fn apply_to_command(&self, command: &mut std::process::Command) {
-        // Unchanged
         command.env_clear();
-        command.envs(&self.vars);
+        command.envs(self.applied_env());
+        if let Some(build_dir) = &self.build_dir {
+            let dir = command
+                .get_current_dir()
+                .map_or_else(|| build_dir.clone(), |dir| build_dir.join(dir));
+            command.current_dir(dir);
+        }
         debug!(
             "Applying sanitized environment to command: {:?}",
             command.get_program()
@@ -475,6 +1633,16 @@ impl BuildEnvironment {
         // debug!("  Arguments: {:?}", command.get_args().collect::<Vec<_>>());
     }
 
+    /// Like [`apply_to_command`](Self::apply_to_command), for configure steps. Their compiler
+    /// probes are many tiny one-off compiles, so ccache is disabled for them: caching those
+    /// costs more than it saves and a cached probe result could outlive a changed system.
+    pub fn apply_to_configure_command(&self, command: &mut std::process::Command) {
+        self.apply_to_command(command);
+        if self.use_ccache {
+            command.env("CCACHE_DISABLE", "1");
+        }
+    }
+
     /// Gets the configured PATH string.
     pub fn get_path_string(&self) -> Option<&str> {
         // Unchanged
@@ -492,6 +1660,167 @@ impl BuildEnvironment {
         // Unchanged
         self.vars.get(key).map(|s| s.as_str())
     }
+
+    /// Moves `dir` to the front of PATH, ahead of the compiler and system directories.
+    pub fn prepend_path(&mut self, dir: &Path) -> Result<()> {
+        self.path_dirs.retain(|p| p != dir);
+        self.path_dirs.insert(0, dir.to_path_buf());
+        let path = std::env::join_paths(self.path_dirs.iter())
+            .map_err(|e| SapphireError::BuildEnvError(format!("Failed to join PATH: {}", e)))?
+            .into_string()
+            .map_err(|os_str| {
+                SapphireError::BuildEnvError(format!(
+                    "Final PATH contains non-UTF8 characters: {:?}",
+                    os_str
+                ))
+            })?;
+        debug!("Prepended {} to PATH", dir.display());
+        self.vars.insert("PATH".to_string(), path);
+        Ok(())
+    }
+
+    /// Sets (or replaces) a variable in the sanitized environment.
+    pub fn set_var(&mut self, key: &str, value: impl Into<String>) {
+        let value = value.into();
+        debug!("Overriding build env var {}={}", key, value);
+        self.vars.insert(key.to_string(), value);
+    }
+
+    /// The parts of this environment that can change what a build produces. Variables that
+    /// only describe the user's session (`HOME`, `TERM`, ...) are left out, and the build
+    /// directory is replaced with a placeholder wherever a variable names it.
+    pub fn snapshot(&self) -> BuildEnvSnapshot {
+        // Each build gets a fresh temporary directory, which reproducible mode names in flags
+        let build_dir = self
+            .build_dir
+            .as_ref()
+            .map(|dir| dir.to_string_lossy().into_owned());
+        BuildEnvSnapshot {
+            vars: self
+                .vars
+                .iter()
+                .filter(|(k, _)| !SESSION_ONLY_VARS.contains(&k.as_str()))
+                .map(|(k, v)| {
+                    let v = match &build_dir {
+                        Some(dir) => v.replace(dir.as_str(), BUILD_DIR_PLACEHOLDER),
+                        None => v.clone(),
+                    };
+                    (k.clone(), v)
+                })
+                .collect(),
+            install_prefix: self.formula_install_prefix.clone(),
+            cc: self.cc.clone(),
+            cxx: self.cxx.clone(),
+            sdk_path: self.sdk_path.clone(),
+            arch: devtools::effective_host_arch().to_string(),
+            build_docs: self.build_docs,
+            configure_args: self.extra_configure_args.clone(),
+            disable_dependency_tracking: self.disable_dependency_tracking,
+        }
+    }
+}
+
+/// The binutil `tool` for the C compiler at `cc`: its own toolchain's (see
+/// [`devtools::compiler_binutil`]), or else whichever [`devtools::find_tool`] finds.
+fn binutil_for(cc: &Path, tool: &str, lto: bool) -> Result<PathBuf> {
+    match devtools::compiler_binutil(cc, tool, lto) {
+        Some(path) => Ok(path),
+        None => devtools::find_tool(tool),
+    }
+}
+
+fn version_min_flag(version: &str) -> String {
+    format!("-mmacosx-version-min={}", version)
+}
+
+/// Parses `major` or `major.minor` into a comparable pair.
+fn parse_macos_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map_or(Some(0), |m| m.parse().ok())?;
+    Some((major, minor))
+}
+
+/// Flag variables whose `-arch` flags change in universal mode.
+const UNIVERSAL_FLAG_VARS: [&str; 3] = ["CFLAGS", "CXXFLAGS", "LDFLAGS"];
+
+/// Replaces the `from` flags in `flags` with `to`, adding `to` in front if `from` isn't there.
+fn replace_arch_flags(flags: &str, from: &str, to: &str) -> String {
+    let rest = if from.is_empty() {
+        flags.to_string()
+    } else {
+        flags.replace(from, "")
+    };
+    format!(
+        "{} {}",
+        to,
+        rest.split_whitespace().collect::<Vec<_>>().join(" ")
+    )
+    .trim()
+    .to_string()
+}
+
+/// Stands in for the build directory in snapshot variables.
+const BUILD_DIR_PLACEHOLDER: &str = "$BUILD_DIR";
+
+/// Kept variables that say nothing about the build itself.
+const SESSION_ONLY_VARS: &[&str] = &[
+    "USER",
+    "LOGNAME",
+    "HOME",
+    "TMPDIR",
+    "TERM",
+    "SHELL",
+    "EDITOR",
+    "DISPLAY",
+    "XAUTHORITY",
+];
+
+/// A stable, comparable description of a [`BuildEnvironment`]. Two builds of the same source
+/// with equal snapshots are expected to produce the same keg.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BuildEnvSnapshot {
+    pub vars: BTreeMap<String, String>,
+    pub install_prefix: PathBuf,
+    pub cc: PathBuf,
+    pub cxx: PathBuf,
+    pub sdk_path: PathBuf,
+    pub arch: String,
+    pub build_docs: bool,
+    /// Formula-specific `configure` arguments, and whether dependency tracking is disabled.
+    pub configure_args: Vec<String>,
+    pub disable_dependency_tracking: bool,
+}
+
+impl BuildEnvSnapshot {
+    /// SHA-256 of the snapshot, hex encoded.
+    pub fn hash(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        hex::encode(Sha256::digest(&json))
+    }
+}
+
+/// The `bin` and `sbin` directories of `prefix` that exist.
+fn tool_dirs(prefix: &Path) -> Vec<PathBuf> {
+    [prefix.join("bin"), prefix.join("sbin")]
+        .into_iter()
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+/// Checks the install receipt behind a dependency's opt path for the `keg_only` flag.
+/// Kegs without a receipt (or installed before the flag was recorded) count as linked.
+fn is_keg_only_install(dep_opt_path: &Path) -> bool {
+    read_receipt_field(dep_opt_path, "keg_only").unwrap_or(false)
+}
+
+/// The pkg-config directories of `prefixes` that exist, in order.
+fn pkg_config_dirs(prefixes: &[PathBuf]) -> Vec<PathBuf> {
+    prefixes
+        .iter()
+        .flat_map(|prefix| [prefix.join("lib/pkgconfig"), prefix.join("share/pkgconfig")])
+        .filter(|dir| dir.is_dir())
+        .collect()
 }
 
 /// Filters the initial environment, keeping only specified safe variables.
@@ -518,3 +1847,203 @@ fn filter_initial_environment(vars: &mut HashMap<String, String>) {
         }
     }
 }
+
+/// Writes an executable shell script at `shim` that runs `compiler` through `ccache`. The script
+/// is written next to its final path and renamed into place, so a concurrent build never sees a
+/// half-written shim.
+fn write_ccache_shim(shim: &Path, ccache: &Path, compiler: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let script = format!(
+        "#!/bin/sh\nexec \"{}\" \"{}\" \"$@\"\n",
+        ccache.display(),
+        compiler.display()
+    );
+    let partial = shim.with_extension("partial");
+    std::fs::write(&partial, script)?;
+    std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))?;
+    std::fs::rename(&partial, shim)?;
+    Ok(())
+}
+
+/// Whether the variable `key` likely holds a credential: one of its `_`-separated words names
+/// one. Whole words keep `KEYCHAIN_PATH` or `PASSTHROUGH` from matching.
+fn is_secret_name(key: &str) -> bool {
+    const SECRET_WORDS: [&str; 9] = [
+        "TOKEN",
+        "SECRET",
+        "PASSWORD",
+        "PASSWD",
+        "PASS",
+        "KEY",
+        "AUTH",
+        "CREDENTIAL",
+        "CREDENTIALS",
+    ];
+    key.split('_').any(|word| {
+        SECRET_WORDS
+            .iter()
+            .any(|secret| word.eq_ignore_ascii_case(secret))
+    })
+}
+
+/// The job count a `MAKEFLAGS` value asks for with `-jN`, `-j N` or `--jobs=N`.
+fn makeflags_jobs(flags: &str) -> Option<usize> {
+    let mut words = flags.split_whitespace();
+    while let Some(word) = words.next() {
+        let count = match word {
+            "-j" | "--jobs" => words.next(),
+            _ => word
+                .strip_prefix("--jobs=")
+                .or_else(|| word.strip_prefix("-j")),
+        };
+        if let Some(jobs) = count.and_then(|c| c.parse().ok()) {
+            return Some(jobs);
+        }
+    }
+    None
+}
+
+/// The words of a `MAKEFLAGS` value without its job count.
+fn without_jobs_flags(flags: &str) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut words = flags.split_whitespace().peekable();
+    while let Some(word) = words.next() {
+        if word == "-j" || word == "--jobs" {
+            if words.peek().is_some_and(|w| w.parse::<usize>().is_ok()) {
+                words.next();
+            }
+        } else if !(word.starts_with("-j") || word.starts_with("--jobs=")) {
+            kept.push(word.to_string());
+        }
+    }
+    kept
+}
+
+/// Build environments for tests, for a formula `foo` without dependencies.
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+
+    pub(crate) struct TestFormula;
+
+    impl FormulaDependencies for TestFormula {
+        fn name(&self) -> &str {
+            "foo"
+        }
+        fn install_prefix(&self, cellar_path: &Path) -> Result<PathBuf> {
+            Ok(cellar_path.join("foo/1.0"))
+        }
+        fn resolved_runtime_dependency_paths(&self) -> Result<Vec<PathBuf>> {
+            Ok(Vec::new())
+        }
+        fn resolved_build_dependency_paths(&self) -> Result<Vec<PathBuf>> {
+            Ok(Vec::new())
+        }
+        fn all_resolved_dependency_paths(&self) -> Result<Vec<PathBuf>> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// The environment for building `foo` into `root/Cellar/foo/1.0`, with the prefix at
+    /// `root/prefix`.
+    pub(crate) fn test_env(root: &Path) -> BuildEnvironment {
+        BuildEnvironment::new(
+            &TestFormula,
+            &root.join("prefix"),
+            &root.join("Cellar/foo/1.0"),
+            &[],
+        )
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::{test_env, TestFormula};
+    use super::*;
+
+    fn path_dirs(env: &BuildEnvironment) -> Vec<PathBuf> {
+        std::env::split_paths(env.get_path_string().unwrap()).collect()
+    }
+
+    #[test]
+    fn keg_only_tool_dirs_go_first_on_path() {
+        let root = tempfile::tempdir().unwrap();
+        let linked = root.path().join("opt/m4");
+        let keg_only = root.path().join("opt/bison");
+        std::fs::create_dir_all(linked.join("bin")).unwrap();
+        std::fs::create_dir_all(keg_only.join("bin")).unwrap();
+        std::fs::write(
+            crate::receipt::receipt_path(&keg_only),
+            r#"{"keg_only": true}"#,
+        )
+        .unwrap();
+
+        let mut env = BuildEnvironment::new(
+            &TestFormula,
+            &root.path().join("prefix"),
+            &root.path().join("Cellar/foo/1.0"),
+            &[linked.clone(), keg_only.clone()],
+        )
+        .unwrap();
+        assert_eq!(env.keg_only_deps(), std::slice::from_ref(&keg_only));
+        let path = path_dirs(&env);
+        assert!(path.contains(&linked.join("bin")));
+        assert!(!path.contains(&keg_only.join("bin")));
+
+        env.mark_keg_only(&keg_only, true).unwrap();
+        let path = path_dirs(&env);
+        assert_eq!(path[0], keg_only.join("bin"));
+        let compiler_dir = devtools::find_compiler("cc").unwrap();
+        let compiler_dir = compiler_dir.parent().unwrap();
+        for later in [compiler_dir, &linked.join("bin"), Path::new("/usr/bin")] {
+            let index = path.iter().position(|p| p == later).unwrap();
+            assert!(index > 0, "{} precedes the keg-only bin", later.display());
+        }
+    }
+
+    #[test]
+    fn secrets_are_recognized_by_whole_words() {
+        for secret in [
+            "GITHUB_TOKEN",
+            "AWS_SECRET_ACCESS_KEY",
+            "NPM_CONFIG__AUTH",
+            "DOCKER_PASSWD",
+            "FTP_PASS",
+            "GOOGLE_APPLICATION_CREDENTIALS",
+            "api_key",
+        ] {
+            assert!(is_secret_name(secret), "{secret} should be redacted");
+        }
+        for plain in [
+            "KEYCHAIN_PATH",
+            "PASSTHROUGH",
+            "MONKEY",
+            "AUTHOR_NAME",
+            "PATH",
+        ] {
+            assert!(!is_secret_name(plain), "{plain} should be shown");
+        }
+    }
+
+    #[test]
+    fn each_phase_is_reported_once() {
+        let root = tempfile::tempdir().unwrap();
+        let env = test_env(root.path());
+        let mut events = events::subscribe();
+        env.checkpoint(BuildPhase::Build).unwrap();
+        env.checkpoint(BuildPhase::Build).unwrap();
+        env.checkpoint(BuildPhase::Install).unwrap();
+
+        let mut phases = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let BuildEvent::PhaseFinished { formula, phase } = event {
+                if formula == "foo" {
+                    phases.push(phase);
+                }
+            }
+        }
+        assert_eq!(phases, [BuildPhase::Build, BuildPhase::Install]);
+    }
+}
@@ -0,0 +1,196 @@
+// sapphire-core/src/build/events.rs
+// Process-wide bus of install progress events. The install lifecycle publishes to it and any
+// number of subscribers receive every event, so a GUI or TUI embedding sapphire can render its
+// own progress without scraping log output. The text and JSON reporters are subscribers too.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::build::warnings::{BuildPhase, BuildWarning};
+
+/// Events a subscriber may fall behind by before it starts missing them.
+const BUS_CAPACITY: usize = 1024;
+
+static BUS: Lazy<Sender<BuildEvent>> = Lazy::new(|| broadcast::channel(BUS_CAPACITY).0);
+
+/// A coarse step of installing one formula.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InstallStage {
+    Download,
+    Build,
+    Pour,
+    Link,
+}
+
+impl std::fmt::Display for InstallStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Download => "download",
+            Self::Build => "build",
+            Self::Pour => "pour",
+            Self::Link => "link",
+        })
+    }
+}
+
+/// Something that happened while installing a formula.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum BuildEvent {
+    InstallStarted {
+        formula: String,
+        version: String,
+        from_source: bool,
+    },
+    StageStarted {
+        formula: String,
+        stage: InstallStage,
+    },
+    /// A phase of a source build completed.
+    PhaseFinished {
+        formula: String,
+        phase: BuildPhase,
+    },
+    Warning {
+        formula: String,
+        #[serde(flatten)]
+        warning: BuildWarning,
+    },
+    InstallFinished {
+        formula: String,
+        keg: PathBuf,
+    },
+    InstallFailed {
+        formula: String,
+        error: String,
+    },
+}
+
+impl std::fmt::Display for BuildEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InstallStarted {
+                formula,
+                version,
+                from_source,
+            } => write!(
+                f,
+                "{} {}: installing {}",
+                formula,
+                version,
+                if *from_source {
+                    "from source"
+                } else {
+                    "bottle"
+                }
+            ),
+            Self::StageStarted { formula, stage } => write!(f, "{}: {}", formula, stage),
+            Self::PhaseFinished { formula, phase } => {
+                write!(f, "{}: {} phase done", formula, phase)
+            }
+            Self::Warning { formula, warning } => write!(f, "{}: warning {}", formula, warning),
+            Self::InstallFinished { formula, keg } => {
+                write!(f, "{}: installed to {}", formula, keg.display())
+            }
+            Self::InstallFailed { formula, error } => write!(f, "{}: failed: {}", formula, error),
+        }
+    }
+}
+
+/// Sends `event` to every current subscriber. Events published while nobody is subscribed are
+/// dropped.
+pub fn publish(event: BuildEvent) {
+    // An error only means there are no subscribers
+    let _ = BUS.send(event);
+}
+
+/// Subscribes to every event published from now on. A subscriber that falls more than
+/// `BUS_CAPACITY` events behind gets [`RecvError::Lagged`] and skips ahead.
+pub fn subscribe() -> Receiver<BuildEvent> {
+    BUS.subscribe()
+}
+
+/// How a built-in reporter prints events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One human-readable line per event.
+    Text,
+    /// One JSON object per line, for tools.
+    Json,
+}
+
+impl ReportFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A built-in subscriber writing events to stderr. Call [`Reporter::finish`] once the install
+/// is done so events still queued are written before the process exits.
+pub struct Reporter {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Reporter {
+    /// Subscribes and starts writing events in `format`. Must be called within a Tokio runtime.
+    pub fn spawn(format: ReportFormat) -> Self {
+        let mut events = subscribe();
+        let (stop, mut stopped) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(event) => report(format, &event),
+                        Err(RecvError::Lagged(missed)) => {
+                            debug!("Event reporter fell behind and missed {} events", missed)
+                        }
+                        Err(RecvError::Closed) => return,
+                    },
+                    _ = &mut stopped => break,
+                }
+            }
+            loop {
+                match events.try_recv() {
+                    Ok(event) => report(format, &event),
+                    Err(TryRecvError::Lagged(_)) => continue,
+                    Err(_) => return,
+                }
+            }
+        });
+        Self { stop, task }
+    }
+
+    /// Writes out the remaining events and stops the reporter.
+    pub async fn finish(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+    }
+}
+
+fn report(format: ReportFormat, event: &BuildEvent) {
+    let line = match format {
+        ReportFormat::Text => format!("==> {}", event),
+        ReportFormat::Json => match serde_json::to_string(event) {
+            Ok(json) => json,
+            Err(e) => {
+                debug!("Could not serialize {:?}: {}", event, e);
+                return;
+            }
+        },
+    };
+    let _ = writeln!(std::io::stderr().lock(), "{}", line);
+}
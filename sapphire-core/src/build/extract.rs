@@ -7,6 +7,7 @@ use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io::{self, Read, Seek};
 use std::path::{Component, Path, PathBuf};
+use std::process::Command;
 
 use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
@@ -14,6 +15,7 @@ use tar::Archive;
 use tracing::{debug, error, warn};
 use xz2::read::XzDecoder;
 use zip::read::ZipArchive;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 use crate::utils::error::{Result, SapphireError};
 
@@ -50,6 +52,10 @@ pub(crate) fn infer_archive_root_dir(
             let decompressed = XzDecoder::new(file);
             infer_tar_root(decompressed, archive_path)
         }
+        "zst" | "tzst" => {
+            let decompressed = ZstdDecoder::new(file)?;
+            infer_tar_root(decompressed, archive_path)
+        }
         "tar" => infer_tar_root(file, archive_path),
         _ => Err(SapphireError::Generic(format!(
             "Cannot infer root dir for unsupported archive type '{}' in {}",
@@ -243,15 +249,217 @@ fn infer_zip_root<R: Read + Seek>(
     }
 }
 
+/// Splits `foo.tar.gz.part3` into (`foo.tar.gz`, 3).
+/// Returns `None` if the file name doesn't look like a piece of a split archive.
+pub fn split_archive_part_index(path: &Path) -> Option<(String, u32)> {
+    let file_name = path.file_name()?.to_str()?;
+    let (base, suffix) = file_name.rsplit_once(".part")?;
+    if base.is_empty() || suffix.is_empty() || !suffix.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((base.to_string(), suffix.parse().ok()?))
+}
+
+/// Finds every part of the split archive that `any_part` belongs to, in order.
+///
+/// Parts must be numbered consecutively starting at 0 or 1 and live in the same directory.
+/// A gap in the numbering is an error naming the missing part. (A missing *last* part can't be
+/// detected from file names alone; the combined checksum catches that.)
+pub fn collect_split_archive_parts(any_part: &Path) -> Result<Vec<PathBuf>> {
+    let (base, _) = split_archive_part_index(any_part).ok_or_else(|| {
+        SapphireError::Generic(format!(
+            "{} is not part of a split archive",
+            any_part.display()
+        ))
+    })?;
+    let dir = any_part.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut parts: Vec<(u32, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some((entry_base, index)) = split_archive_part_index(&path) {
+            if entry_base == base && path.is_file() {
+                parts.push((index, path));
+            }
+        }
+    }
+    parts.sort_by_key(|(index, _)| *index);
+
+    let first_index = parts.first().map(|(i, _)| *i).unwrap_or(0);
+    if first_index > 1 {
+        return Err(SapphireError::NotFound(format!(
+            "Split archive {} is missing part {} in {}",
+            base,
+            first_index - 1,
+            dir.display()
+        )));
+    }
+    for (expected, (index, _)) in (first_index..).zip(&parts) {
+        if *index != expected {
+            return Err(SapphireError::NotFound(format!(
+                "Split archive {} is missing part {} in {}",
+                base,
+                expected,
+                dir.display()
+            )));
+        }
+    }
+    debug!("Found {} parts for split archive {}", parts.len(), base);
+    Ok(parts.into_iter().map(|(_, p)| p).collect())
+}
+
+/// Concatenates `parts` in order into `output`.
+/// If `expected_sha256` is given, the combined file must match it or it is removed and a
+/// checksum error returned.
+pub fn reassemble_split_archive(
+    parts: &[PathBuf],
+    output: &Path,
+    expected_sha256: Option<&str>,
+) -> Result<()> {
+    if parts.is_empty() {
+        return Err(SapphireError::Generic(format!(
+            "No parts given to reassemble {}",
+            output.display()
+        )));
+    }
+    debug!(
+        "Reassembling {} parts into {}",
+        parts.len(),
+        output.display()
+    );
+    let mut out = File::create(output).map_err(|e| {
+        SapphireError::Io(std::io::Error::new(
+            e.kind(),
+            format!("Failed to create {}: {}", output.display(), e),
+        ))
+    })?;
+    for part in parts {
+        let mut input = File::open(part).map_err(|e| {
+            SapphireError::Io(std::io::Error::new(
+                e.kind(),
+                format!("Failed to open archive part {}: {}", part.display(), e),
+            ))
+        })?;
+        io::copy(&mut input, &mut out)?;
+    }
+    drop(out);
+
+    if let Some(expected) = expected_sha256.filter(|s| !s.is_empty()) {
+        if let Err(e) = crate::fetch::http::verify_checksum(output, expected) {
+            let _ = fs::remove_file(output);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Reassembles a split archive into a temporary file and extracts that into `target_dir`.
+/// See [`reassemble_split_archive`] and [`extract_archive`].
+pub fn extract_split_archive(
+    parts: &[PathBuf],
+    target_dir: &Path,
+    strip_components: usize,
+    archive_type: &str,
+    expected_sha256: Option<&str>,
+) -> Result<()> {
+    let combined = tempfile::NamedTempFile::new()?;
+    reassemble_split_archive(parts, combined.path(), expected_sha256)?;
+    extract_archive(combined.path(), target_dir, strip_components, archive_type)
+}
+
+/// Identifies the archive format of `archive_path` from its first bytes, whatever its name.
+/// Returns the type [`extract_archive`] takes (`"gz"`, `"bz2"`, `"xz"`, `"zst"`, `"zip"` or
+/// `"tar"`), or `None` if the format isn't one of those.
+pub fn detect_archive_type(archive_path: &Path) -> Result<Option<&'static str>> {
+    let mut head = Vec::with_capacity(512);
+    File::open(archive_path)?.take(512).read_to_end(&mut head)?;
+    let archive_type = if head.starts_with(&[0x1f, 0x8b]) {
+        "gz"
+    } else if head.starts_with(b"BZh") {
+        "bz2"
+    } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        "xz"
+    } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        "zst"
+    } else if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+        "zip"
+    } else if head.get(257..262) == Some(b"ustar") {
+        "tar"
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(archive_type))
+}
+
+/// [`detect_archive_type`], failing on formats it doesn't recognize.
+fn known_archive_type(archive_path: &Path) -> Result<&'static str> {
+    detect_archive_type(archive_path)?.ok_or_else(|| {
+        SapphireError::Generic(format!(
+            "Unrecognized archive format for {} (expected a tar.gz, tar.xz, tar.bz2, tar.zst \
+             or zip archive)",
+            archive_path.display()
+        ))
+    })
+}
+
+/// Extracts the source archive `archive_path` into `dest` and returns the archive's single
+/// top-level directory there, which is where the build runs. The format is detected from the
+/// file's contents. Fails on formats other than those of [`detect_archive_type`] and on
+/// archives without exactly one top-level directory.
+pub fn extract_source_archive(archive_path: &Path, dest: &Path) -> Result<PathBuf> {
+    let archive_type = known_archive_type(archive_path)?;
+    let root = infer_archive_root_dir(archive_path, archive_type)?.ok_or_else(|| {
+        SapphireError::Generic(format!(
+            "{} does not contain a single top-level directory",
+            archive_path.display()
+        ))
+    })?;
+    extract_archive(archive_path, dest, 0, archive_type)?;
+    Ok(dest.join(root))
+}
+
+/// Extracts the resource archive `archive_path` into `dest` and returns where its contents
+/// are: the archive's single top-level directory if it has one, or else `dest` itself, for flat
+/// archives such as wheels and most zips.
+pub fn extract_resource_archive(archive_path: &Path, dest: &Path) -> Result<PathBuf> {
+    let archive_type = known_archive_type(archive_path)?;
+    let root = infer_archive_root_dir(archive_path, archive_type)?;
+    extract_archive(archive_path, dest, 0, archive_type)?;
+    Ok(match root {
+        Some(root) => dest.join(root),
+        None => dest.to_path_buf(),
+    })
+}
+
 /// Extracts an archive to the target directory using native Rust crates.
-/// Supports `.tar`, `.tar.gz`, `.tar.bz2`, `.tar.xz`, and `.zip`.
+/// Supports `.tar`, `.tar.gz`, `.tar.bz2`, `.tar.xz`, `.tar.zst` and `.zip`.
 /// `strip_components` behaves like the GNU tar `--strip-components` flag.
-/// `archive_type` should be the determined extension (e.g., "zip", "gz", "bz2", "xz", "tar").
+/// `archive_type` should be the determined extension (e.g., "zip", "gz", "bz2", "xz", "zst",
+/// "tar"). Relative symlinks in the archive must resolve inside `target_dir`.
 pub fn extract_archive(
     archive_path: &Path,
     target_dir: &Path,
     strip_components: usize,
     archive_type: &str, // <-- Parameter added
+) -> Result<()> {
+    extract_archive_linking_within(
+        archive_path,
+        target_dir,
+        strip_components,
+        archive_type,
+        target_dir,
+    )
+}
+
+/// Like [`extract_archive`], but relative symlinks may point anywhere under `link_root`, which
+/// contains `target_dir`. Bottles are extracted straight into the keg and link to other
+/// formulae through `../../../opt/...`, so they are checked against the prefix.
+pub fn extract_archive_linking_within(
+    archive_path: &Path,
+    target_dir: &Path,
+    strip_components: usize,
+    archive_type: &str,
+    link_root: &Path,
 ) -> Result<()> {
     debug!(
         "Extracting archive '{}' (type: {}) to '{}' (strip_components={}) using native Rust crates.",
@@ -282,23 +490,27 @@ pub fn extract_archive(
     // --- Determine archive type and extract ---
     // Use the provided archive_type instead of inspecting filename/extension here
     match archive_type {
-        "zip" => extract_zip_archive(file, target_dir, strip_components, archive_path),
+        "zip" => extract_zip_archive(file, target_dir, strip_components, archive_path, link_root),
         "gz" | "tgz" => {
             // infer often returns "gz" for .tar.gz
             let tar = GzDecoder::new(file);
-            extract_tar_archive(tar, target_dir, strip_components, archive_path)
+            extract_tar_archive(tar, target_dir, strip_components, archive_path, link_root)
         }
         "bz2" | "tbz" | "tbz2" => {
             let tar = BzDecoder::new(file);
-            extract_tar_archive(tar, target_dir, strip_components, archive_path)
+            extract_tar_archive(tar, target_dir, strip_components, archive_path, link_root)
         }
         "xz" | "txz" => {
             let tar = XzDecoder::new(file);
-            extract_tar_archive(tar, target_dir, strip_components, archive_path)
+            extract_tar_archive(tar, target_dir, strip_components, archive_path, link_root)
+        }
+        "zst" | "tzst" => {
+            let tar = ZstdDecoder::new(file)?;
+            extract_tar_archive(tar, target_dir, strip_components, archive_path, link_root)
         }
         "tar" => {
             // No decompression needed
-            extract_tar_archive(file, target_dir, strip_components, archive_path)
+            extract_tar_archive(file, target_dir, strip_components, archive_path, link_root)
         }
         // Add other types like "7z" here if you add support
         _ => Err(SapphireError::Generic(format!(
@@ -315,6 +527,7 @@ fn extract_tar_archive<R: Read>(
     target_dir: &Path,
     strip_components: usize,
     archive_path_for_log: &Path, // For logging only
+    link_root: &Path,
 ) -> Result<()> {
     let mut archive = tar::Archive::new(reader);
     archive.set_preserve_permissions(true); // Preserve permissions
@@ -326,6 +539,7 @@ fn extract_tar_archive<R: Read>(
         archive_path_for_log.display()
     );
 
+    let mut symlinks = Vec::new();
     for entry_result in archive.entries()? {
         let mut entry = entry_result.map_err(|e| {
             SapphireError::Generic(format!(
@@ -411,6 +625,21 @@ fn extract_tar_archive<R: Read>(
             }
         }
 
+        if entry.header().entry_type().is_symlink() {
+            let link_target = entry.link_name()?.ok_or_else(|| {
+                SapphireError::Generic(format!(
+                    "Symlink {} in {} has no target",
+                    original_path.display(),
+                    archive_path_for_log.display()
+                ))
+            })?;
+            symlinks.push(DeferredSymlink {
+                path: target_path,
+                target: link_target.into_owned(),
+            });
+            continue;
+        }
+
         // Unpack entry
         match entry.unpack(&target_path) {
             Ok(_) => debug!("Unpacked TAR entry to: {}", target_path.display()),
@@ -435,6 +664,7 @@ fn extract_tar_archive<R: Read>(
             }
         }
     }
+    create_deferred_symlinks(&symlinks, link_root, archive_path_for_log)?;
     debug!(
         "Finished TAR extraction for {}",
         archive_path_for_log.display()
@@ -448,6 +678,7 @@ fn extract_zip_archive<R: Read + Seek>(
     target_dir: &Path,
     strip_components: usize,
     archive_path_for_log: &Path,
+    link_root: &Path,
 ) -> Result<()> {
     let mut archive = ZipArchive::new(reader).map_err(|e| {
         SapphireError::Generic(format!(
@@ -461,6 +692,7 @@ fn extract_zip_archive<R: Read + Seek>(
         archive_path_for_log.display()
     );
 
+    let mut symlinks = Vec::new();
     for i in 0..archive.len() {
         let mut file = archive.by_index(i).map_err(|e| {
             SapphireError::Generic(format!(
@@ -540,35 +772,14 @@ fn extract_zip_archive<R: Read + Seek>(
                 })?;
             }
         } else if file.is_symlink() {
-            // Symlink entry in zip
+            // Symlink entry in zip; its content is the link target
             let mut buf = Vec::new();
             file.read_to_end(&mut buf)?;
-            let link_target = PathBuf::from(String::from_utf8_lossy(&buf).to_string());
-            #[cfg(unix)]
-            {
-                // Remove existing file/link at target first
-                if target_path.exists() || target_path.symlink_metadata().is_ok() {
-                    let _ = fs::remove_file(&target_path); // Ignore error if it doesn't exist
-                }
-                std::os::unix::fs::symlink(&link_target, &target_path).map_err(|e| {
-                    warn!(
-                        "Failed to create symlink {} -> {}: {}",
-                        target_path.display(),
-                        link_target.display(),
-                        e
-                    );
-                    SapphireError::Io(e) // Propagate error
-                })?;
-            }
-            #[cfg(not(unix))]
-            {
-                warn!(
-                    "Cannot create symlink on non-unix system: {} -> {}",
-                    target_path.display(),
-                    link_target.display()
-                );
-                // Potentially write a file with the link target path?
-            }
+            symlinks.push(DeferredSymlink {
+                path: target_path,
+                target: PathBuf::from(String::from_utf8_lossy(&buf).to_string()),
+            });
+            continue;
         } else {
             // Regular file entry in zip
             // Remove existing file at target first to avoid errors
@@ -589,16 +800,252 @@ fn extract_zip_archive<R: Read + Seek>(
         {
             use std::os::unix::fs::PermissionsExt;
             if let Some(mode) = file.unix_mode() {
-                // Check if it's a symlink; don't chmod symlinks directly
-                if !file.is_symlink() {
-                    fs::set_permissions(&target_path, fs::Permissions::from_mode(mode))?;
-                }
+                fs::set_permissions(&target_path, fs::Permissions::from_mode(mode))?;
             }
         }
     }
+    create_deferred_symlinks(&symlinks, link_root, archive_path_for_log)?;
     debug!(
         "Finished ZIP extraction for {}",
         archive_path_for_log.display()
     );
     Ok(())
 }
+
+/// A symlink entry, created only once every regular file and directory has been extracted.
+struct DeferredSymlink {
+    /// Where the link goes, inside the target directory.
+    path: PathBuf,
+    /// What the link points at, as stored in the archive.
+    target: PathBuf,
+}
+
+/// Creates the symlinks collected during extraction. Deferring them means relative links to
+/// other entries are never dangling when created, and no later entry can be written through a
+/// symlinked directory.
+///
+/// Relative links must stay inside `link_root`; one that escapes it is rejected like any other
+/// path traversal. They are checked once every link exists, against the filesystem, so a link
+/// can't escape through another one (`b -> a/..` with `a -> .`) whatever their order. Absolute
+/// links are created but warned about, since they point outside the keg and won't survive
+/// relocation.
+fn create_deferred_symlinks(
+    symlinks: &[DeferredSymlink],
+    link_root: &Path,
+    archive_path_for_log: &Path,
+) -> Result<()> {
+    let mut created = Vec::new();
+    for link in symlinks {
+        if link.target.is_absolute() {
+            warn!(
+                "Absolute symlink {} -> {} in {} is a relocation hazard",
+                link.path.display(),
+                link.target.display(),
+                archive_path_for_log.display()
+            );
+        }
+
+        if link.path.symlink_metadata().is_ok() {
+            warn!("Entry exists, skipping symlink {}", link.path.display());
+            continue;
+        }
+        std::os::unix::fs::symlink(&link.target, &link.path).map_err(|e| {
+            SapphireError::Io(io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to create symlink {} -> {}: {}",
+                    link.path.display(),
+                    link.target.display(),
+                    e
+                ),
+            ))
+        })?;
+        debug!(
+            "Created symlink {} -> {}",
+            link.path.display(),
+            link.target.display()
+        );
+        created.push(link);
+    }
+
+    for link in created.into_iter().filter(|l| l.target.is_relative()) {
+        let parent = link.path.parent().unwrap_or(link_root);
+        match resolve_within(link_root, parent, &link.target) {
+            Some(resolved) if !resolved.exists() => {
+                warn!(
+                    "Symlink {} -> {} in {} is dangling",
+                    link.path.display(),
+                    link.target.display(),
+                    archive_path_for_log.display()
+                );
+            }
+            Some(_) => {}
+            None => {
+                error!(
+                    "Symlink {} -> {} escapes {}",
+                    link.path.display(),
+                    link.target.display(),
+                    link_root.display()
+                );
+                let _ = fs::remove_file(&link.path);
+                return Err(SapphireError::Generic(format!(
+                    "Symlink traversal detected in {}",
+                    archive_path_for_log.display()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes the `com.apple.quarantine` attribute from `path` and everything under it, so that
+/// Gatekeeper doesn't block or prompt for the scripts and binaries of a downloaded source while
+/// it builds. Symlinks are not followed out of the tree. Does nothing on other platforms, or
+/// where the attribute isn't set. A failure is only warned about: most builds never notice the
+/// attribute.
+pub fn strip_quarantine(path: &Path) {
+    if !cfg!(target_os = "macos") {
+        return;
+    }
+    debug!("Removing the quarantine attribute from {}", path.display());
+    let output = match Command::new("/usr/bin/xattr")
+        .args(["-d", "-r", "-s", "com.apple.quarantine"])
+        .arg(path)
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            warn!(
+                "Could not run xattr to remove the quarantine attribute: {}",
+                e
+            );
+            return;
+        }
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = stderr.trim();
+    // A missing attribute is reported (and fails the run) per file without it
+    if output.status.success()
+        || (!stderr.is_empty() && stderr.lines().all(|l| l.contains("No such xattr")))
+    {
+        return;
+    }
+    warn!(
+        "Failed to remove the quarantine attribute from {} ({}): {}",
+        path.display(),
+        output.status,
+        stderr
+    );
+}
+
+/// Resolves the relative `target` of a link in the directory `dir`, following the symlinks
+/// already on disk, and returns `None` if it leaves `root`. Components past what exists are
+/// resolved lexically, as nothing there can be a link.
+fn resolve_within(root: &Path, dir: &Path, target: &Path) -> Option<PathBuf> {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let mut resolved = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    for comp in target.components() {
+        match comp {
+            // Popping past `/` leaves `/`, which no root contains
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                let next = resolved.join(name);
+                resolved = next.canonicalize().unwrap_or(next);
+            }
+            _ => {}
+        }
+    }
+    resolved.starts_with(&root).then_some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a `.tar` at `path` with `pkg/bin/tool` and a `pkg/bin/link` symlink to `target`,
+    /// the link listed first so it has to be deferred until its target exists.
+    fn write_fixture(path: &Path, target: &str) {
+        write_links_fixture(path, &[("pkg/bin/link", target)]);
+    }
+
+    /// Writes a `.tar` at `path` with the symlinks in `links` (path, target), in that order,
+    /// followed by `pkg/bin/tool`.
+    fn write_links_fixture(path: &Path, links: &[(&str, &str)]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+
+        for (link_path, target) in links {
+            let mut link = tar::Header::new_gnu();
+            link.set_entry_type(tar::EntryType::Symlink);
+            link.set_size(0);
+            link.set_mode(0o777);
+            builder.append_link(&mut link, link_path, target).unwrap();
+        }
+
+        let content = b"#!/bin/sh\n";
+        let mut file = tar::Header::new_gnu();
+        file.set_size(content.len() as u64);
+        file.set_mode(0o755);
+        builder
+            .append_data(&mut file, "pkg/bin/tool", &content[..])
+            .unwrap();
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn symlink_listed_before_its_target_resolves() {
+        let tmp = tempfile::tempdir().unwrap();
+        let archive = tmp.path().join("fixture.tar");
+        write_fixture(&archive, "tool");
+        let out = tmp.path().join("out");
+
+        extract_archive(&archive, &out, 1, "tar").unwrap();
+
+        let link = out.join("bin/link");
+        assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(&link).unwrap(), b"#!/bin/sh\n");
+    }
+
+    #[test]
+    fn symlink_escaping_the_target_dir_is_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let archive = tmp.path().join("fixture.tar");
+        write_fixture(&archive, "../../../opt/dep/bin/tool");
+        let out = tmp.path().join("Cellar/pkg/1.0");
+
+        assert!(extract_archive(&archive, &out, 1, "tar").is_err());
+    }
+
+    #[test]
+    fn symlink_escaping_through_another_symlink_is_rejected() {
+        // `b` looks like it stays put, but `a` is the directory itself, so `a/..` is its parent
+        for links in [
+            [("pkg/a", "."), ("pkg/b", "a/..")],
+            [("pkg/b", "a/.."), ("pkg/a", ".")],
+        ] {
+            let tmp = tempfile::tempdir().unwrap();
+            let archive = tmp.path().join("fixture.tar");
+            write_links_fixture(&archive, &links);
+            let out = tmp.path().join("Cellar/pkg/1.0");
+
+            assert!(extract_archive(&archive, &out, 1, "tar").is_err());
+            assert!(out.join("b").symlink_metadata().is_err());
+        }
+    }
+
+    #[test]
+    fn symlink_escaping_the_target_dir_is_allowed_inside_the_link_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let archive = tmp.path().join("fixture.tar");
+        write_fixture(&archive, "../../../opt/dep/bin/tool");
+        let out = tmp.path().join("Cellar/pkg/1.0");
+
+        extract_archive_linking_within(&archive, &out, 1, "tar", tmp.path()).unwrap();
+
+        assert_eq!(
+            fs::read_link(out.join("bin/link")).unwrap(),
+            Path::new("../../../opt/dep/bin/tool")
+        );
+    }
+}
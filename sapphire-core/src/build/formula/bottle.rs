@@ -1,31 +1,149 @@
 // ===== sapphire-core/src/build/formula/bottle.rs =====
 // Corrected E0061 and Compiler Warnings
 
-use std::collections::{HashMap, HashSet}; // Added HashSet
 use std::fs::{self, File};
-use std::io::{Read, Write}; // Added Write for atomic write
+use std::io::Write;
 use std::os::unix::fs::{symlink, PermissionsExt}; // Combined unix imports
 use std::path::{Path, PathBuf};
 
 use reqwest::Client;
 use semver; // For find_brewed_perl
-use tempfile::NamedTempFile;
-use tracing::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
 use walkdir::WalkDir;
 
-use super::macho; // Assuming macho module exists within super (build::formula)
-use crate::build::formula::get_current_platform;
+use super::relocate::{relocate_keg, Relocation, Replacements};
+use super::share;
+use super::source::{run_post_install, PostInstallEnv};
+use crate::build::formula::{get_current_platform, runtime_path};
+use crate::build::warnings::WarningCollector;
+use crate::build::{devtools, linkage};
 use crate::fetch::{http, oci};
-use crate::model::formula::{BottleFileSpec, Formula, FormulaDependencies};
-use crate::utils::config::Config;
+use crate::keg::KegRegistry;
+use crate::model::formula::{BottleFileSpec, Formula};
+use crate::receipt::{read_receipt, read_receipt_field};
+use crate::utils::config::{Config, PostInstallPass};
 use crate::utils::error::{Result, SapphireError}; // For atomic write
 
+/// The manifest [`create_bottle`] puts at the top of the keg in a bottle.
+pub const BOTTLE_MANIFEST: &str = "BOTTLE_MANIFEST.json";
+
+/// macOS releases with Apple silicon bottles, newest first.
+const ARM_MACOS_VERSIONS: &[&str] = &["sequoia", "sonoma", "ventura", "monterey", "big_sur"];
+/// macOS releases with Intel bottles, newest first.
+const INTEL_MACOS_VERSIONS: &[&str] = &[
+    "sequoia", "sonoma", "ventura", "monterey", "big_sur", "catalina", "mojave",
+];
+
+/// A platform bottles are built for, identified by its bottle tag (`arm64_sonoma`, `ventura`,
+/// `x86_64_linux`, ...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// A macOS release by its bottle codename. `arm64` is Apple silicon, otherwise Intel.
+    MacOs {
+        release: &'static str,
+        arm64: bool,
+    },
+    Linux {
+        arm64: bool,
+    },
+}
+
+impl Platform {
+    /// Every platform sapphire can pour bottles on.
+    pub fn supported() -> Vec<Platform> {
+        let arm = ARM_MACOS_VERSIONS.iter().map(|release| Self::MacOs {
+            release,
+            arm64: true,
+        });
+        let intel = INTEL_MACOS_VERSIONS.iter().map(|release| Self::MacOs {
+            release,
+            arm64: false,
+        });
+        arm.chain(intel)
+            .chain([Self::Linux { arm64: false }, Self::Linux { arm64: true }])
+            .collect()
+    }
+
+    /// The platform sapphire is running on, if it is a supported one.
+    pub fn current() -> Option<Platform> {
+        Self::from_tag(&get_current_platform())
+    }
+
+    /// Parses a bottle tag. `all` and unknown tags are not platforms.
+    pub fn from_tag(tag: &str) -> Option<Platform> {
+        Self::supported().into_iter().find(|p| p.tag() == tag)
+    }
+
+    pub fn tag(&self) -> String {
+        match self {
+            Self::MacOs {
+                release,
+                arm64: true,
+            } => format!("arm64_{}", release),
+            Self::MacOs {
+                release,
+                arm64: false,
+            } => release.to_string(),
+            Self::Linux { arm64: true } => "arm64_linux".to_string(),
+            Self::Linux { arm64: false } => "x86_64_linux".to_string(),
+        }
+    }
+
+    /// Bottle tags that can be poured on this platform, most specific first: its own tag, bottles
+    /// for older macOS releases on the same architecture, then the platform-independent `all`.
+    pub fn compatible_tags(&self) -> Vec<String> {
+        let mut tags = vec![self.tag()];
+        if let Self::MacOs { release, arm64 } = *self {
+            let releases = if arm64 {
+                ARM_MACOS_VERSIONS
+            } else {
+                INTEL_MACOS_VERSIONS
+            };
+            if let Some(index) = releases.iter().position(|r| *r == release) {
+                tags.extend(
+                    releases[index + 1..]
+                        .iter()
+                        .map(|release| Self::MacOs { release, arm64 }.tag()),
+                );
+            }
+        }
+        tags.push("all".to_string());
+        tags
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.tag())
+    }
+}
+
+/// Whether `formula` has a bottle that can be poured on each supported platform, in the order
+/// of [`Platform::supported`]. Bottles for older macOS releases and `all` bottles count.
+pub fn bottle_availability(formula: &Formula) -> Vec<(Platform, bool)> {
+    let files = formula.bottle.stable.as_ref().map(|spec| &spec.files);
+    Platform::supported()
+        .into_iter()
+        .map(|platform| {
+            let available = files.is_some_and(|files| {
+                platform
+                    .compatible_tags()
+                    .iter()
+                    .any(|tag| files.contains_key(tag))
+            });
+            (platform, available)
+        })
+        .collect()
+}
+
 // --- Bottle Functions ---
 
 pub async fn download_bottle(
     formula: &Formula,
     config: &Config,
     client: &Client,
+    on_progress: &http::ProgressCallback,
 ) -> Result<PathBuf> {
     debug!("Attempting to download bottle for {}", formula.name);
 
@@ -52,38 +170,9 @@ pub async fn download_bottle(
     fs::create_dir_all(&cache_dir).map_err(SapphireError::Io)?;
     let bottle_cache_path = cache_dir.join(&filename);
 
-    if bottle_cache_path.is_file() {
-        debug!("Bottle found in cache: {}", bottle_cache_path.display());
-        if !bottle_file_spec.sha256.is_empty() {
-            match http::verify_checksum(&bottle_cache_path, &bottle_file_spec.sha256) {
-                Ok(_) => {
-                    debug!("Using valid cached bottle: {}", bottle_cache_path.display());
-                    return Ok(bottle_cache_path);
-                }
-                Err(e) => {
-                    warn!(
-                        "Cached bottle checksum mismatch ({}): {}. Redownloading.",
-                        bottle_cache_path.display(),
-                        e
-                    );
-                    if let Err(remove_err) = fs::remove_file(&bottle_cache_path) {
-                        warn!(
-                            "Failed to remove corrupted cached bottle {}: {}",
-                            bottle_cache_path.display(),
-                            remove_err
-                        );
-                    }
-                }
-            }
-        } else {
-            debug!(
-                "Using cached bottle (checksum not specified): {}",
-                bottle_cache_path.display()
-            );
-            return Ok(bottle_cache_path);
-        }
-    } else {
-        debug!("Bottle not found in cache.");
+    let cached = http::check_cached_entry(&bottle_cache_path, &bottle_file_spec.sha256);
+    if cached == http::CachedEntry::Valid {
+        return Ok(bottle_cache_path);
     }
 
     let bottle_url_str = &bottle_file_spec.url;
@@ -104,12 +193,28 @@ pub async fn download_bottle(
             "Detected OCI blob URL, initiating direct blob download: {}",
             bottle_url_str
         );
-        match oci::download_oci_blob(bottle_url_str, &bottle_cache_path, config, client).await {
+        match oci::download_oci_blob(
+            bottle_url_str,
+            &bottle_cache_path,
+            config,
+            client,
+            on_progress,
+        )
+        .await
+        {
             Ok(_) => {
                 debug!(
                     "Successfully downloaded OCI blob to {}",
                     bottle_cache_path.display()
                 );
+                if !bottle_file_spec.sha256.is_empty() {
+                    if let Err(e) =
+                        http::verify_checksum(&bottle_cache_path, &bottle_file_spec.sha256)
+                    {
+                        let _ = fs::remove_file(&bottle_cache_path);
+                        return Err(http::checksum_failure(e, bottle_url_str, cached));
+                    }
+                }
             }
             Err(e) => {
                 error!("Failed to download OCI blob from {}: {}", bottle_url_str, e);
@@ -133,6 +238,7 @@ pub async fn download_bottle(
             &bottle_file_spec.sha256, // Pass expected checksum
             &[],                      // No mirrors specified here, could add if needed
             config,
+            on_progress,
         )
         .await
         {
@@ -204,11 +310,6 @@ pub(crate) fn get_bottle_for_platform(formula: &Formula) -> Result<(String, &Bot
     debug!("No exact match found for {}", current_platform);
 
     // 2. OS Version Fallback (macOS specific logic)
-    const ARM_MACOS_VERSIONS: &[&str] = &["sequoia", "sonoma", "ventura", "monterey", "big_sur"]; // Newest first
-    const INTEL_MACOS_VERSIONS: &[&str] = &[
-        "sequoia", "sonoma", "ventura", "monterey", "big_sur", "catalina", "mojave",
-    ]; // Newest first
-
     if cfg!(target_os = "macos") {
         if let Some(current_os_name) = current_platform
             .strip_prefix("arm64_")
@@ -301,7 +402,13 @@ pub(crate) fn get_bottle_for_platform(formula: &Formula) -> Result<(String, &Bot
 }
 
 pub fn install_bottle(bottle_path: &Path, formula: &Formula, config: &Config) -> Result<PathBuf> {
-    let install_dir = formula.install_prefix(&config.cellar)?; // Use ? to propagate error
+    let install_dir = config.formula_keg_path(formula.name(), &formula.version_str_full());
+
+    // The download was checked, but the cached copy may have been damaged or swapped since
+    let (bottle_tag, bottle_spec) = get_bottle_for_platform(formula)?;
+    if !bottle_spec.sha256.is_empty() {
+        http::verify_checksum(bottle_path, &bottle_spec.sha256)?;
+    }
 
     // --- Cleanup existing directory ---
     if install_dir.exists() {
@@ -351,8 +458,29 @@ pub fn install_bottle(bottle_path: &Path, formula: &Formula, config: &Config) ->
         install_dir.display(),
         strip_components
     );
-    // Provide the correct archive type argument ("gz")
-    crate::build::extract::extract_archive(bottle_path, &install_dir, strip_components, "gz")?; // <-- Fixed call
+    // Bottles link to other kegs through `../../../opt/...`, so their relative symlinks only
+    // have to stay inside the prefix rather than the keg.
+    crate::build::extract::extract_archive_linking_within(
+        bottle_path,
+        &install_dir,
+        strip_components,
+        "gz",
+        config.prefix(),
+    )?;
+
+    // Platform-independent bottles are poured anywhere, whatever they were built on
+    if bottle_tag != "all" {
+        if let Err(e) = validate_bottle_target(&install_dir) {
+            if let Err(rm) = fs::remove_dir_all(&install_dir) {
+                warn!(
+                    "Failed to remove keg {} after its bottle was rejected: {}",
+                    install_dir.display(),
+                    rm
+                );
+            }
+            return Err(e);
+        }
+    }
 
     // --- Post-Extraction Steps ---
     // Run ensure_write_permissions *before* relocation, as relocation might need to write
@@ -362,15 +490,94 @@ pub fn install_bottle(bottle_path: &Path, formula: &Formula, config: &Config) ->
     );
     ensure_write_permissions(&install_dir)?;
 
+    let warnings = WarningCollector::for_formula(formula.name());
+    // Bottles are chosen for the architecture sapphire was built for, not the machine
+    devtools::record_arch_mismatch(&warnings);
+
     // Run relocation *after* permissions are set
-    debug!("Performing bottle relocation in {}", install_dir.display());
-    perform_bottle_relocation(formula, &install_dir, config)?;
+    if !config.skips_post_install_pass(formula.name(), PostInstallPass::Relocation) {
+        debug!("Performing bottle relocation in {}", install_dir.display());
+        perform_bottle_relocation(formula, &install_dir, config, &warnings)?;
+        // Bottles sapphire made hold their build prefix instead of Homebrew's placeholders
+        if let Some(manifest) = read_bottle_manifest(&install_dir) {
+            let relocation = Relocation {
+                replacements: manifest.replacements(config.prefix(), config.cellar_path()),
+                binaries: true,
+                resign: !config.skips_post_install_pass(formula.name(), PostInstallPass::Codesign),
+                skip_clean: formula.skip_clean(),
+            };
+            relocate_keg(&install_dir, &relocation, &warnings)?;
+        }
+        share::relocate_share_paths(
+            formula.name(),
+            formula.skip_clean(),
+            &install_dir,
+            &config.formula_opt_link_path(formula.name()),
+            None,
+            !config.skips_post_install_pass(formula.name(), PostInstallPass::Codesign),
+            &warnings,
+        )?;
+    }
+
+    // Only the bottle needs it; the receipt describes the installed keg
+    let _ = fs::remove_file(install_dir.join(BOTTLE_MANIFEST));
 
     // Run LLVM symlink creation *after* relocation (though order might not matter much here)
     ensure_llvm_symlinks(&install_dir, formula, config)?;
 
+    // The bottle's own receipt is about to be replaced by ours, so check it first
+    if config.install_options.verify_runtime_deps {
+        if let Err(e) = verify_bottle_runtime_dependencies(formula, &install_dir, config) {
+            // Don't leave a keg behind that can't run
+            if let Err(rm) = fs::remove_dir_all(&install_dir) {
+                warn!(
+                    "Failed to remove keg {} after dependency check failed: {}",
+                    install_dir.display(),
+                    rm
+                );
+            }
+            return Err(e);
+        }
+    }
+
+    if !formula.post_install().is_empty() {
+        let opt_paths: Vec<PathBuf> = formula
+            .dependencies()?
+            .iter()
+            .map(|dep| config.formula_opt_link_path(&dep.name))
+            .collect();
+        let env = PostInstallEnv::Pour {
+            path: runtime_path(&install_dir, &opt_paths, config.prefix()),
+            warnings: &warnings,
+        };
+        if let Err(e) = run_post_install(&install_dir, &env, formula.post_install()) {
+            if let Err(rm) = fs::remove_dir_all(&install_dir) {
+                warn!(
+                    "Failed to remove keg {} after a post-install step failed: {}",
+                    install_dir.display(),
+                    rm
+                );
+            }
+            return Err(e);
+        }
+    }
+
+    // Catches load commands relocation missed or left pointing at kegs that aren't installed
+    if !config.skips_post_install_pass(formula.name(), PostInstallPass::LinkageCheck) {
+        if let Err(e) = linkage::verify_library_references(&install_dir, config) {
+            if let Err(rm) = fs::remove_dir_all(&install_dir) {
+                warn!(
+                    "Failed to remove keg {} after linkage check failed: {}",
+                    install_dir.display(),
+                    rm
+                );
+            }
+            return Err(e);
+        }
+    }
+
     // Write receipt *last* after all installation steps are complete
-    crate::build::write_receipt(formula, &install_dir)?;
+    crate::build::write_receipt(formula, &install_dir, &warnings)?;
 
     debug!(
         "Bottle installation complete for {} at {}",
@@ -380,6 +587,294 @@ pub fn install_bottle(bottle_path: &Path, formula: &Formula, config: &Config) ->
     Ok(install_dir)
 }
 
+/// The platform fields of the INSTALL_RECEIPT.json shipped inside a bottle. Older bottles may
+/// lack either.
+#[derive(Debug, Default, Deserialize)]
+struct BottleTarget {
+    #[serde(default)]
+    arch: Option<String>,
+    #[serde(default)]
+    built_on: Option<BottleBuiltOn>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct BottleBuiltOn {
+    /// `Macintosh` or `Linux` from Homebrew, `macos` or `linux` from sapphire.
+    #[serde(default)]
+    os: Option<String>,
+}
+
+/// Checks that the bottle poured into `keg_path` was built for this OS and for the CPU
+/// architecture sapphire pours bottles for. That is read from the [`BottleManifest`] of a
+/// bottle sapphire created, or else the `built_on.os` and `arch` fields of its receipt. A
+/// bottle recording neither is accepted.
+pub fn validate_bottle_target(keg_path: &Path) -> Result<()> {
+    let (os, arch) = match read_bottle_manifest(keg_path) {
+        Some(manifest) => (Some(manifest.os), Some(manifest.arch)),
+        None => match read_receipt(keg_path)
+            .and_then(|v| serde_json::from_value::<BottleTarget>(v).ok())
+        {
+            Some(target) => (target.built_on.and_then(|b| b.os), target.arch),
+            None => return Ok(()),
+        },
+    };
+    let host_os_names: [&str; 2] = if cfg!(target_os = "macos") {
+        ["Macintosh", "macos"]
+    } else {
+        ["Linux", "linux"]
+    };
+    if let Some(os) = os {
+        if !host_os_names.iter().any(|n| os.eq_ignore_ascii_case(n)) {
+            return Err(SapphireError::InstallError(format!(
+                "Bottle at {} was built for {}, not {}",
+                keg_path.display(),
+                os,
+                std::env::consts::OS
+            )));
+        }
+    }
+    if let Some(arch) = arch.as_deref() {
+        let native = devtools::TargetArch::native();
+        if devtools::TargetArch::parse(arch) != native {
+            return Err(SapphireError::InstallError(format!(
+                "Bottle at {} was built for {}, not {}",
+                keg_path.display(),
+                arch,
+                native.map_or("this architecture".to_string(), |n| n.to_string())
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// What a bottle made by [`create_bottle`] holds and was built for, stored at the top of the
+/// keg in it as [`BOTTLE_MANIFEST`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BottleManifest {
+    pub name: String,
+    pub version: String,
+    /// `std::env::consts::OS` of the machine that built it.
+    pub os: String,
+    /// Architecture the keg's binaries were built for.
+    pub arch: String,
+    /// Prefix and cellar the keg was installed into, which pouring relocates to the local ones.
+    pub prefix: PathBuf,
+    pub cellar: PathBuf,
+}
+
+impl BottleManifest {
+    /// Replacements relocating the keg from the prefix and cellar it was built in to `prefix`
+    /// and `cellar`. Both are made in the same pass, so neither is rewritten twice where the
+    /// paths overlap; sapphire builds compile them into binaries too.
+    pub fn replacements(&self, prefix: &Path, cellar: &Path) -> Replacements {
+        let mut replacements = Replacements::new();
+        replacements.insert(self.cellar.to_string_lossy(), cellar.to_string_lossy());
+        replacements.insert(self.prefix.to_string_lossy(), prefix.to_string_lossy());
+        replacements
+    }
+}
+
+/// A bottle written by [`create_bottle`].
+#[derive(Debug, Clone)]
+pub struct Bottle {
+    pub path: PathBuf,
+    pub sha256: String,
+    pub manifest: BottleManifest,
+}
+
+/// Packages the installed keg at `install_dir`, which must be `<prefix>/Cellar/<name>/<version>`,
+/// as a bottle at `output_path`. Like Homebrew's, the bottle is a gzipped tarball of
+/// `<name>/<version>/...`; a [`BottleManifest`] is added recording the prefix so that pouring it
+/// elsewhere can relocate it. Entries are sorted and their timestamps and ownership zeroed, so
+/// the same keg always packs to the same bytes.
+pub fn create_bottle(install_dir: &Path, output_path: &Path) -> Result<Bottle> {
+    let version_dir = install_dir.file_name();
+    let name_dir = install_dir.parent().and_then(Path::file_name);
+    let cellar = install_dir.parent().and_then(Path::parent);
+    let (Some(version), Some(name), Some(cellar)) = (version_dir, name_dir, cellar) else {
+        return Err(SapphireError::InstallError(format!(
+            "{} is not a keg in a cellar",
+            install_dir.display()
+        )));
+    };
+    let manifest = BottleManifest {
+        name: name.to_string_lossy().into_owned(),
+        version: version.to_string_lossy().into_owned(),
+        os: std::env::consts::OS.to_string(),
+        arch: devtools::effective_host_arch().to_string(),
+        prefix: cellar.parent().unwrap_or(cellar).to_path_buf(),
+        cellar: cellar.to_path_buf(),
+    };
+    let root = Path::new(&manifest.name).join(&manifest.version);
+
+    let partial = output_path.with_extension("partial");
+    let write = || -> Result<()> {
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let encoder = flate2::GzBuilder::new()
+            .mtime(0)
+            .write(File::create(&partial)?, flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for entry in WalkDir::new(install_dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let Ok(rel) = entry.path().strip_prefix(install_dir) else {
+                continue;
+            };
+            if rel.as_os_str() == BOTTLE_MANIFEST {
+                continue;
+            }
+            append_normalized(&mut builder, entry.path(), &root.join(rel))?;
+        }
+        let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = normalized_header(tar::EntryType::Regular, 0o644);
+        header.set_size(manifest_json.len() as u64);
+        builder.append_data(&mut header, root.join(BOTTLE_MANIFEST), &manifest_json[..])?;
+        builder.into_inner()?.finish()?;
+        Ok(())
+    };
+    if let Err(e) = write() {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, output_path)?;
+
+    let sha256 = http::sha256_file(output_path)?;
+    info!(
+        "==> Created bottle {} (sha256 {})",
+        output_path.display(),
+        sha256
+    );
+    Ok(Bottle {
+        path: output_path.to_path_buf(),
+        sha256,
+        manifest,
+    })
+}
+
+/// A tar header with everything that varies between machines and builds zeroed.
+fn normalized_header(entry_type: tar::EntryType, mode: u32) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_size(0);
+    header
+}
+
+/// Appends the file, directory or symlink at `path` to `builder` as `name`.
+fn append_normalized<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    name: &Path,
+) -> Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    let mode = meta.permissions().mode() & 0o7777;
+    if meta.file_type().is_symlink() {
+        let mut header = normalized_header(tar::EntryType::Symlink, 0o755);
+        builder.append_link(&mut header, name, fs::read_link(path)?)?;
+    } else if meta.is_dir() {
+        let mut header = normalized_header(tar::EntryType::Directory, mode);
+        builder.append_data(&mut header, name, std::io::empty())?;
+    } else {
+        let mut header = normalized_header(tar::EntryType::Regular, mode);
+        header.set_size(meta.len());
+        builder.append_data(&mut header, name, File::open(path)?)?;
+    }
+    Ok(())
+}
+
+/// The [`BottleManifest`] of a keg poured from a bottle sapphire created.
+fn read_bottle_manifest(keg_path: &Path) -> Option<BottleManifest> {
+    let contents = fs::read_to_string(keg_path.join(BOTTLE_MANIFEST)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// A runtime dependency recorded in the INSTALL_RECEIPT.json shipped inside a bottle.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BottleRuntimeDependency {
+    /// Possibly tap-qualified name, e.g. `openssl@3` or `homebrew/core/openssl@3`.
+    pub full_name: String,
+    /// Version the bottle was built against.
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+impl BottleRuntimeDependency {
+    /// The formula name without any tap prefix.
+    pub fn name(&self) -> &str {
+        self.full_name.rsplit('/').next().unwrap_or(&self.full_name)
+    }
+}
+
+/// Reads the runtime dependencies recorded in a freshly poured bottle's receipt. Bottles
+/// without a receipt (or with one that doesn't list dependencies) yield an empty list.
+pub fn bottle_runtime_dependencies(keg_path: &Path) -> Vec<BottleRuntimeDependency> {
+    read_receipt_field(keg_path, "runtime_dependencies").unwrap_or_default()
+}
+
+/// Checks that every runtime dependency recorded in the bottle at `keg_path` is installed at a
+/// compatible version: the same major version, and no older than the one the bottle was built
+/// against. Versions that don't parse are accepted as long as the dependency is installed.
+///
+/// Returns `SapphireError::MissingRuntimeDependency` for the first unmet dependency; all unmet
+/// dependencies are logged.
+pub fn verify_bottle_runtime_dependencies(
+    formula: &Formula,
+    keg_path: &Path,
+    config: &Config,
+) -> Result<()> {
+    let recorded = bottle_runtime_dependencies(keg_path);
+    debug!(
+        "Verifying {} recorded runtime dependencies of {}",
+        recorded.len(),
+        formula.name()
+    );
+    let registry = KegRegistry::new(config.clone());
+    let mut unmet = Vec::new();
+    for dep in &recorded {
+        let installed = registry.get_installed_keg(dep.name())?;
+        let required = dep.version.clone().unwrap_or_default();
+        let satisfied = match (&installed, parse_padded_version(&required)) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(keg), Some(required)) => {
+                keg.version.major == required.major && keg.version >= required
+            }
+        };
+        if !satisfied {
+            let err = SapphireError::MissingRuntimeDependency {
+                formula: formula.name().to_string(),
+                dependency: dep.name().to_string(),
+                required,
+                found: installed.map(|k| k.version.to_string()),
+            };
+            error!("{}", err);
+            unmet.push(err);
+        }
+    }
+    match unmet.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Parses a formula version like `3`, `3.3` or `3.3.2`, padding missing components with zero.
+fn parse_padded_version(version: &str) -> Option<semver::Version> {
+    let core = version.split('_').next().unwrap_or(version);
+    let padded = match core.split('.').count() {
+        1 => format!("{}.0.0", core),
+        2 => format!("{}.0", core),
+        _ => core.to_string(),
+    };
+    semver::Version::parse(&padded).ok()
+}
+
 fn ensure_write_permissions(path: &Path) -> Result<()> {
     if !path.exists() {
         warn!(
@@ -443,48 +938,46 @@ fn ensure_write_permissions(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn perform_bottle_relocation(formula: &Formula, install_dir: &Path, config: &Config) -> Result<()> {
-    let mut repl: HashMap<String, String> = HashMap::new();
-    // Use config methods for consistency
-    repl.insert(
-        "@@HOMEBREW_CELLAR@@".into(),
-        config.cellar_path().to_string_lossy().into(),
-    );
-    repl.insert(
-        "@@HOMEBREW_PREFIX@@".into(),
-        config.prefix().to_string_lossy().into(),
-    );
+/// Replaces Homebrew's placeholders (`@@HOMEBREW_PREFIX@@`, `@@HOMEBREW_CELLAR@@`...) in the
+/// keg poured at `install_dir` with this installation's paths, then makes sure everything in
+/// `bin`/`sbin` and everything that was executable is executable by all.
+fn perform_bottle_relocation(
+    formula: &Formula,
+    install_dir: &Path,
+    config: &Config,
+    warnings: &WarningCollector,
+) -> Result<()> {
+    let mut repl = Replacements::new();
     repl.insert(
-        "@@HOMEBREW_REPOSITORY@@".into(), // Often /opt/homebrew or /usr/local
-        config.prefix().to_string_lossy().into(),
+        "@@HOMEBREW_CELLAR@@",
+        config.cellar_path().to_string_lossy(),
     );
+    repl.insert("@@HOMEBREW_PREFIX@@", config.prefix().to_string_lossy());
+    // Often /opt/homebrew or /usr/local
+    repl.insert("@@HOMEBREW_REPOSITORY@@", config.prefix().to_string_lossy());
+    // Often <prefix>/Library
     repl.insert(
-        "@@HOMEBREW_LIBRARY@@".into(), // Often <prefix>/Library
-        config.prefix().join("Library").to_string_lossy().into(),
+        "@@HOMEBREW_LIBRARY@@",
+        config.prefix().join("Library").to_string_lossy(),
     );
 
     // Opt path placeholder for the formula itself
-    let opt_placeholder = format!(
-        "@@HOMEBREW_OPT_{}@@",
-        formula.name().to_uppercase().replace(['-', '+', '.'], "_") // Sanitize name
-    );
     repl.insert(
-        opt_placeholder,
+        format!("@@HOMEBREW_OPT_{}@@", placeholder_name(formula.name())),
         config
             .formula_opt_link_path(formula.name())
-            .to_string_lossy()
-            .into(), // Use config method
+            .to_string_lossy(),
     );
 
     // System Perl fallback
-    if let Some(p) = find_brewed_perl(config.prefix()).or_else(|| {
+    if let Some(p) = find_brewed_perl(&config.opt_dir()).or_else(|| {
         if cfg!(target_os = "macos") {
             Some(PathBuf::from("/usr/bin/perl"))
         } else {
             None
         }
     }) {
-        repl.insert("@@HOMEBREW_PERL@@".into(), p.to_string_lossy().into());
+        repl.insert("@@HOMEBREW_PERL@@", p.to_string_lossy());
     }
 
     // LLVM Path (Check dependencies for llvm formula)
@@ -495,373 +988,64 @@ fn perform_bottle_relocation(formula: &Formula, install_dir: &Path, config: &Con
         .map(|d| d.name.clone());
 
     if let Some(name) = llvm_dep_name {
-        let llvm_opt_path = config.formula_opt_link_path(&name); // Use config method
-        let llvm_lib = llvm_opt_path.join("lib");
+        let llvm_lib = config.formula_opt_link_path(&name).join("lib");
         if llvm_lib.is_dir() {
+            repl.insert("@loader_path/../lib", llvm_lib.to_string_lossy());
             repl.insert(
-                "@loader_path/../lib".into(),
-                llvm_lib.to_string_lossy().into(),
-            );
-            repl.insert(
-                format!(
-                    "@@HOMEBREW_OPT_{}@@/lib",
-                    name.to_uppercase().replace(['-', '+', '.'], "_")
-                ),
-                llvm_lib.to_string_lossy().into(),
+                format!("@@HOMEBREW_OPT_{}@@/lib", placeholder_name(&name)),
+                llvm_lib.to_string_lossy(),
             );
         }
     }
 
     tracing::debug!("Relocation table:");
-    for (k, v) in &repl {
+    for (k, v) in repl.iter() {
         tracing::debug!("  {}  →  {}", k, v);
     }
 
-    // Call the actual patching function
-    original_relocation_scan_and_patch(formula, install_dir, config, repl)
+    let relocation = Relocation {
+        replacements: repl,
+        binaries: false,
+        resign: !config.skips_post_install_pass(formula.name(), PostInstallPass::Codesign),
+        skip_clean: formula.skip_clean(),
+    };
+    relocate_keg(install_dir, &relocation, warnings)?;
+    ensure_executable(install_dir)
 }
 
-fn original_relocation_scan_and_patch(
-    _formula: &Formula,
-    install_dir: &Path,
-    _config: &Config,
-    replacements: HashMap<String, String>,
-) -> Result<()> {
-    let mut text_replaced_count = 0;
-    let mut macho_patched_count = 0;
-    let mut permission_errors = 0;
-    let mut macho_errors = 0;
-    let mut io_errors = 0;
-    let mut files_to_chmod: Vec<PathBuf> = Vec::new();
-
-    for entry in WalkDir::new(install_dir).into_iter().filter_map(|e| e.ok())
-    // Process directories too, in case symlinks point to them and need +x
-    // But only process content of files
-    // .filter(|e| e.file_type().is_file() || e.path_is_symlink())
-    {
-        let path = entry.path();
-        let file_type = entry.file_type();
-
-        // Skip relocation inside .app bundles
-        if path
-            .components()
-            .any(|c| c.as_os_str().to_string_lossy().ends_with(".app"))
-        {
-            if file_type.is_file() {
-                // Only log for files inside .app
-                debug!("Skipping relocation inside .app bundle: {}", path.display());
-            }
-            continue;
-        }
-
-        // Handle symlinks separately: just check if they need chmodding later
-        if file_type.is_symlink() {
-            debug!("Checking symlink for potential chmod: {}", path.display());
-            // If symlink is in bin/sbin, it might need execute permissions (less common)
-            // Or if it points to something that needs it (harder to check reliably here)
-            // Let's add to chmod check if it's directly in bin/sbin for now.
-            if path
-                .parent()
-                .is_some_and(|p| p.ends_with("bin") || p.ends_with("sbin"))
-            {
-                files_to_chmod.push(path.to_path_buf());
-            }
-            continue; // Don't process content of symlinks
-        }
+/// A formula name as Homebrew spells it in `@@HOMEBREW_OPT_<NAME>@@` placeholders.
+fn placeholder_name(name: &str) -> String {
+    name.to_uppercase().replace(['-', '+', '.'], "_")
+}
 
-        // Only process content of actual files
-        if !file_type.is_file() {
+/// Gives every file in `bin` or `sbin` of the keg, and every file its owner can execute,
+/// execute permission for everyone.
+fn ensure_executable(install_dir: &Path) -> Result<()> {
+    for entry in WalkDir::new(install_dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
             continue;
         }
-
-        debug!("Scanning file for relocation: {}", path.display());
-
-        // Fix unused assignment: Declare initially_executable inside the Ok arm
-        let (meta, initially_executable) = match fs::metadata(path) {
-            Ok(m) => {
-                #[cfg(unix)]
-                let ie = m.permissions().mode() & 0o111 != 0;
-                #[cfg(not(unix))]
-                let ie = true; // Assume executable potential on non-unix
-                (m, ie)
-            }
-            Err(e) => {
-                warn!("Could not stat {}: {}. Skipping file.", path.display(), e);
-                io_errors += 1;
-                continue;
-            }
-        };
-
-        let is_in_exec_dir = path
+        let path = entry.path();
+        let in_exec_dir = path
             .parent()
             .is_some_and(|p| p.ends_with("bin") || p.ends_with("sbin"));
-
-        if meta.permissions().readonly() {
-            warn!(
-                "Skipping readonly file during relocation: {}",
-                path.display()
-            );
+        let Ok(meta) = entry.metadata() else {
             continue;
-        }
-
-        let mut was_modified = false;
-
-        // --- Mach-O Patching (macOS only) ---
-        if cfg!(target_os = "macos") {
-            if initially_executable
-                || is_in_exec_dir
-                || path
-                    .extension()
-                    .is_some_and(|e| e == "dylib" || e == "so" || e == "bundle")
-            {
-                match macho::patch_macho_file(path, &replacements) {
-                    Ok(patched) if patched => {
-                        debug!("Successfully patched Mach-O file: {}", path.display());
-                        macho_patched_count += 1;
-                        was_modified = true; // Set modification flag
-                    }
-                    Ok(_) => {} // Not Mach-O or no patch needed
-                    // Handle specific errors if needed, otherwise fall through
-                    Err(SapphireError::PathTooLongError(e)) => {
-                        error!(
-                            "Mach-O patch failed (path too long) for {}: {}",
-                            path.display(),
-                            e
-                        );
-                        macho_errors += 1;
-                        continue;
-                    }
-                    Err(SapphireError::CodesignError(e)) => {
-                        error!(
-                            "Mach-O patch failed (codesign error) for {}: {}",
-                            path.display(),
-                            e
-                        );
-                        macho_errors += 1;
-                        continue;
-                    }
-                    Err(e @ SapphireError::MachOError(_)) | Err(e @ SapphireError::Object(_)) => {
-                        warn!(
-                            "Mach-O processing failed for {}: {}. Skipping Mach-O patch.",
-                            path.display(),
-                            e
-                        );
-                        macho_errors += 1;
-                    }
-                    Err(e) => {
-                        warn!(
-                            "Mach-O check/patch failed for {}: {}. Falling back to text replacer.",
-                            path.display(),
-                            e
-                        );
-                    }
-                }
-            } else {
-                debug!(
-                    "Skipping Mach-O check for non-executable/non-library file: {}",
-                    path.display()
-                );
-            }
-        }
-
-        // --- Text Replacement ---
-        if !was_modified {
-            let mut is_likely_text = true;
-            if let Ok(mut f) = File::open(path) {
-                let mut buf = [0; 1024];
-                if let Ok(n) = f.read(&mut buf) {
-                    if n > 0 && buf[..n].contains(&0) {
-                        is_likely_text = false;
-                    }
-                } else {
-                    warn!("Could not read from {} to check for text.", path.display());
-                    is_likely_text = false;
-                }
-            } else {
-                warn!("Could not open file {} for text check.", path.display());
-                is_likely_text = false;
-            }
-
-            if is_likely_text {
-                debug!(
-                    "Attempting text replacement for likely text file: {}",
-                    path.display()
-                );
-                match fs::read_to_string(path) {
-                    Ok(content) => {
-                        let mut new_content = content.clone();
-                        let mut replacements_made = false;
-                        for (placeholder, replacement) in &replacements {
-                            if new_content.contains(placeholder) {
-                                new_content = new_content.replace(placeholder, replacement);
-                                replacements_made = true;
-                                debug!("  Text Replaced '{}' in {}", placeholder, path.display());
-                            }
-                        }
-                        if replacements_made {
-                            match write_text_file_atomic(path, &new_content) {
-                                Ok(_) => {
-                                    text_replaced_count += 1;
-                                    was_modified = true; // Set modification flag here too
-                                    debug!(
-                                        "Successfully wrote replaced text to {}",
-                                        path.display()
-                                    );
-                                }
-                                Err(e) => {
-                                    error!(
-                                        "Failed to write replaced text to {}: {}",
-                                        path.display(),
-                                        e
-                                    );
-                                    io_errors += 1;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        debug!(
-                            "File {} is not valid UTF-8, skipping text replacement. Error: {}",
-                            path.display(),
-                            e
-                        );
-                    }
-                }
+        };
+        let mode = meta.permissions().mode();
+        if (in_exec_dir || mode & 0o100 != 0) && mode & 0o111 != 0o111 {
+            if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode | 0o111)) {
+                warn!("Failed to set +x on {}: {}", path.display(), e);
             } else {
-                debug!(
-                    "Skipping text replacement for likely binary file: {}",
-                    path.display()
-                );
+                debug!("Set +x on {}", path.display());
             }
         }
-
-        // --- Collect for chmod ---
-        // Add if modified OR if initially executable OR in bin/sbin dir
-        if was_modified || initially_executable || is_in_exec_dir {
-            files_to_chmod.push(path.to_path_buf());
-        }
-    } // End loop over files
-
-    // --- Final chmod Pass ---
-    #[cfg(unix)]
-    {
-        debug!(
-            "Ensuring execute permissions for {} potentially executable files/links...",
-            files_to_chmod.len()
-        );
-        let unique_files_to_chmod: HashSet<_> = files_to_chmod.into_iter().collect(); // Use HashSet for uniqueness
-
-        for p in &unique_files_to_chmod {
-            if !p.exists() && p.symlink_metadata().is_err() {
-                debug!("Skipping chmod for non-existent path: {}", p.display());
-                continue;
-            }
-            match fs::symlink_metadata(p) {
-                // Use symlink_metadata
-                Ok(m) => {
-                    if m.is_file() {
-                        // Only chmod actual files
-                        let mut perms = m.permissions();
-                        let current_mode = perms.mode();
-                        // Ensure execute bits are set if they should be (user, group, other)
-                        let new_mode = current_mode | 0o111;
-                        if new_mode != current_mode {
-                            perms.set_mode(new_mode);
-                            if let Err(e) = fs::set_permissions(p, perms) {
-                                warn!("Failed to set +x on {}: {}", p.display(), e);
-                                permission_errors += 1;
-                            } else {
-                                debug!("Set +x on {}", p.display());
-                            }
-                        }
-                    }
-                    // Don't chmod symlinks themselves
-                }
-                Err(e) => {
-                    warn!(
-                        "Could not stat {} during final chmod pass: {}",
-                        p.display(),
-                        e
-                    );
-                    permission_errors += 1;
-                }
-            }
-        }
-    }
-
-    // --- Report Results ---
-    debug!(
-        "Relocation scan complete. Text files replaced: {}, Mach-O files patched: {}",
-        text_replaced_count, macho_patched_count
-    );
-    if permission_errors > 0 || macho_errors > 0 || io_errors > 0 {
-        error!(
-            "Bottle relocation finished with issues: {} chmod errors, {} Mach-O errors, {} IO errors in {}.",
-            permission_errors, macho_errors, io_errors, install_dir.display()
-        );
-        if macho_errors > 0 {
-            return Err(SapphireError::InstallError(format!(
-                "Bottle relocation failed due to {} Mach-O errors in {}",
-                macho_errors,
-                install_dir.display()
-            )));
-        }
     }
     Ok(())
 }
 
-fn write_text_file_atomic(original_path: &Path, content: &str) -> Result<()> {
-    let dir = original_path.parent().ok_or_else(|| {
-        SapphireError::Generic(format!(
-            "Cannot get parent directory for {}",
-            original_path.display()
-        ))
-    })?;
-    // Note: create_dir_all called earlier in install_bottle usually
-
-    let mut temp_file = NamedTempFile::new_in(dir)?;
-    let temp_path = temp_file.path().to_path_buf();
-
-    debug!(
-        "    Writing relocated text to temporary file: {}",
-        temp_path.display()
-    );
-    temp_file.write_all(content.as_bytes())?;
-    temp_file.flush()?;
-    temp_file.as_file().sync_all()?;
-
-    let original_perms = fs::metadata(original_path).map(|m| m.permissions()).ok();
-
-    temp_file.persist(original_path).map_err(|e| {
-        error!(
-            "    Failed to persist/rename temporary text file {} over {}: {}",
-            temp_path.display(),
-            original_path.display(),
-            e.error
-        );
-        SapphireError::Io(e.error)
-    })?;
-
-    if let Some(perms) = original_perms {
-        if let Err(e) = fs::set_permissions(original_path, perms) {
-            warn!(
-                "Failed to restore permissions on {}: {}",
-                original_path.display(),
-                e
-            );
-        }
-    }
-
-    debug!(
-        "    Atomically replaced {} with relocated text version",
-        original_path.display()
-    );
-    Ok(())
-}
-
 #[cfg(unix)]
-fn find_brewed_perl(prefix: &Path) -> Option<PathBuf> {
-    let opt_dir = prefix.join("opt");
+fn find_brewed_perl(opt_dir: &Path) -> Option<PathBuf> {
     if !opt_dir.is_dir() {
         return None;
     }
@@ -906,11 +1090,7 @@ fn find_brewed_perl(prefix: &Path) -> Option<PathBuf> {
             }
         }
         Err(e) => {
-            warn!(
-                "Could not read opt directory {}: {}",
-                prefix.join("opt").display(),
-                e
-            );
+            warn!("Could not read opt directory {}: {}", opt_dir.display(), e);
         }
     }
 
@@ -1003,3 +1183,77 @@ fn ensure_llvm_symlinks(install_dir: &Path, formula: &Formula, config: &Config)
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::extract::extract_archive_linking_within;
+
+    #[test]
+    fn bottle_pours_under_a_prefix_extending_its_own() {
+        let tmp = tempfile::tempdir().unwrap();
+        let built = tmp.path().join("s");
+        let keg = built.join("Cellar/foo/1.0");
+        fs::create_dir_all(keg.join("bin")).unwrap();
+        fs::create_dir_all(keg.join("lib/pkgconfig")).unwrap();
+        let script = keg.join("bin/foo");
+        fs::write(
+            &script,
+            format!(
+                "#!{}/bin/sh\nexec {}/libexec/foo\n",
+                built.display(),
+                keg.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(
+            keg.join("lib/pkgconfig/foo.pc"),
+            format!("prefix={}\n", keg.display()),
+        )
+        .unwrap();
+        symlink("foo", keg.join("bin/foo-1")).unwrap();
+
+        let bottle = create_bottle(&keg, &tmp.path().join("foo--1.0.bottle.tar.gz")).unwrap();
+        assert_eq!(bottle.manifest.prefix, built);
+        assert_eq!(bottle.manifest.cellar, built.join("Cellar"));
+
+        // Rewriting the cellar and then the prefix would turn `s/Cellar` into `s22/Cellar`
+        let prefix = tmp.path().join("s2");
+        let cellar = prefix.join("Cellar");
+        let poured = cellar.join("foo/1.0");
+        extract_archive_linking_within(&bottle.path, &poured, 2, "gz", &prefix).unwrap();
+        let manifest = read_bottle_manifest(&poured).unwrap();
+        assert_eq!(manifest, bottle.manifest);
+        let relocation = Relocation {
+            binaries: true,
+            ..Relocation::new(manifest.replacements(&prefix, &cellar))
+        };
+        relocate_keg(&poured, &relocation, &WarningCollector::new()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(poured.join("bin/foo")).unwrap(),
+            format!(
+                "#!{}/bin/sh\nexec {}/libexec/foo\n",
+                prefix.display(),
+                poured.display()
+            )
+        );
+        assert_eq!(
+            fs::metadata(poured.join("bin/foo"))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777,
+            0o755
+        );
+        assert_eq!(
+            fs::read_to_string(poured.join("lib/pkgconfig/foo.pc")).unwrap(),
+            format!("prefix={}\n", poured.display())
+        );
+        assert_eq!(
+            fs::read_link(poured.join("bin/foo-1")).unwrap(),
+            Path::new("foo")
+        );
+    }
+}
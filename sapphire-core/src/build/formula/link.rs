@@ -1,4 +1,5 @@
 // ===== sapphire-core/src/build/formula/link.rs =====
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::Write;
 use std::os::unix::fs as unix_fs;
@@ -6,15 +7,70 @@ use std::os::unix::fs as unix_fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 
+use serde::{Deserialize, Serialize};
 use serde_json;
 use tracing::{debug, error, warn};
 
+use crate::keg::{InstalledKeg, KegRegistry};
 use crate::model::formula::Formula;
+use crate::receipt::{read_receipt_field, set_receipt_field, RECEIPT_FILE_NAME};
 use crate::utils::config::Config; // Import Config
 use crate::utils::error::{Result, SapphireError};
 
 const STANDARD_KEG_DIRS: [&str; 6] = ["bin", "lib", "share", "include", "etc", "Frameworks"];
 
+/// Summary of a single keg's linking pass.
+#[derive(Debug, Clone, Default)]
+pub struct LinkReport {
+    /// Links or wrappers that were missing or pointed elsewhere and have been (re)created.
+    pub links_repaired: usize,
+    /// Links that already pointed at this keg and were left alone.
+    pub links_unchanged: usize,
+    /// Prefix paths that could not be linked because something else owns them.
+    pub conflicts: Vec<LinkConflict>,
+}
+
+/// A prefix path that is occupied by something other than this keg.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkConflict {
+    pub path: PathBuf,
+    /// The formula owning the path, or `None` if it isn't managed by Sapphire.
+    pub owner: Option<String>,
+}
+
+/// Options for [`link_keg`].
+#[derive(Debug, Clone, Default)]
+pub struct LinkOptions {
+    /// Replace links owned by other formulae instead of reporting them as conflicts.
+    pub overwrite_conflicts: bool,
+    /// Which executables to put on PATH. `None` reuses the filter recorded in the keg's
+    /// receipt by the last link that set one; `Some` replaces (and records) it.
+    pub bin_filter: Option<BinLinkFilter>,
+}
+
+/// Selects which of a keg's executables get wrappers in the prefix `bin/`. The rest stay in
+/// the keg and remain reachable through `opt/<name>/bin`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BinLinkFilter {
+    /// If non-empty, only these executables are linked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub link_only: Vec<String>,
+    /// Executables that are never linked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub link_except: Vec<String>,
+}
+
+impl BinLinkFilter {
+    pub fn is_empty(&self) -> bool {
+        self.link_only.is_empty() && self.link_except.is_empty()
+    }
+
+    pub fn allows(&self, executable: &str) -> bool {
+        (self.link_only.is_empty() || self.link_only.iter().any(|n| n == executable))
+            && !self.link_except.iter().any(|n| n == executable)
+    }
+}
+
 /// Link all artifacts from a formula's installation directory.
 // Added Config parameter
 pub fn link_formula_artifacts(
@@ -22,26 +78,43 @@ pub fn link_formula_artifacts(
     installed_keg_path: &Path,
     config: &Config, // Added config
 ) -> Result<()> {
+    // A fresh install takes over whatever currently occupies its link targets
+    let options = LinkOptions {
+        overwrite_conflicts: true,
+        bin_filter: None,
+    };
+    link_keg(formula.name(), installed_keg_path, config, &options).map(|_| ())
+}
+
+/// Links a keg into the prefix by formula name.
+///
+/// With `overwrite_conflicts` set, existing files and links owned by other formulae are
+/// replaced (the install behaviour). Without it, they are left alone and reported in
+/// [`LinkReport::conflicts`]. Links that already point at this keg are not touched, so running
+/// this repeatedly is a no-op. Executables excluded by the bin filter are not linked, and
+/// wrappers this keg previously put in the prefix for them are removed.
+pub fn link_keg(
+    formula_name: &str,
+    installed_keg_path: &Path,
+    config: &Config,
+    options: &LinkOptions,
+) -> Result<LinkReport> {
+    let overwrite_conflicts = options.overwrite_conflicts;
     debug!(
         "Linking artifacts for {} from {}",
-        formula.name(),
+        formula_name,
         installed_keg_path.display()
     );
+    let mut report = LinkReport::default();
 
     let formula_content_root = determine_content_root(installed_keg_path)?;
     let mut symlinks_created = Vec::<String>::new();
 
     // Use config methods for paths
-    let opt_link_path = config.formula_opt_link_path(formula.name());
+    let opt_link_path = config.formula_opt_link_path(formula_name);
     let target_keg_dir = &formula_content_root;
 
-    remove_existing_link_target(&opt_link_path)?;
-    unix_fs::symlink(target_keg_dir, &opt_link_path).map_err(|e| {
-        SapphireError::Io(std::io::Error::new(
-            e.kind(),
-            format!("Failed to create opt symlink for {}: {}", formula.name(), e),
-        ))
-    })?;
+    ensure_opt_link(formula_name, target_keg_dir, &opt_link_path, &mut report)?;
     symlinks_created.push(opt_link_path.to_string_lossy().to_string());
     debug!(
         "  Linked opt path: {} -> {}",
@@ -49,7 +122,7 @@ pub fn link_formula_artifacts(
         target_keg_dir.display()
     );
 
-    if let Some((base, _version)) = formula.name().split_once('@') {
+    if let Some((base, _version)) = formula_name.split_once('@') {
         let alias_path = config.opt_dir().join(base); // Use config.opt_dir()
         if !alias_path.exists() {
             match unix_fs::symlink(target_keg_dir, &alias_path) {
@@ -89,8 +162,28 @@ pub fn link_formula_artifacts(
                 }
 
                 let target_link = target_prefix_subdir.join(&file_name);
+                if is_symlink_to(&target_link, &source_item_path).unwrap_or(false) {
+                    report.links_unchanged += 1;
+                    symlinks_created.push(target_link.to_string_lossy().to_string());
+                    continue;
+                }
+                if !overwrite_conflicts {
+                    if let Some(conflict) = link_conflict(&target_link, config, formula_name) {
+                        warn!(
+                            "  Not linking {}: already owned by {}",
+                            target_link.display(),
+                            conflict
+                                .owner
+                                .as_deref()
+                                .unwrap_or("something outside Sapphire")
+                        );
+                        report.conflicts.push(conflict);
+                        continue;
+                    }
+                }
                 remove_existing_link_target(&target_link)?;
                 unix_fs::symlink(&source_item_path, &target_link).ok(); // ignore errors for individual links?
+                report.links_repaired += 1;
                 symlinks_created.push(target_link.to_string_lossy().to_string());
                 debug!(
                     "  Linked {} -> {}",
@@ -105,13 +198,32 @@ pub fn link_formula_artifacts(
     let target_bin_dir = config.bin_dir();
     fs::create_dir_all(&target_bin_dir).ok();
 
+    let runtime_env = read_runtime_env(installed_keg_path);
+    if !runtime_env.is_empty() {
+        install_runtime_env_wrappers(&formula_content_root, &runtime_env)?;
+    }
+    let bin_filter = match &options.bin_filter {
+        Some(filter) => {
+            record_bin_link_filter(installed_keg_path, filter)?;
+            filter.clone()
+        }
+        None => read_bin_link_filter(installed_keg_path),
+    };
     let source_bin_dir = formula_content_root.join("bin");
+    let mut wrapper_ctx = WrapperContext {
+        config,
+        formula_name,
+        overwrite_conflicts,
+        bin_filter: &bin_filter,
+        report: &mut report,
+    };
     if source_bin_dir.is_dir() {
         create_wrappers_in_dir(
             &source_bin_dir,
             &target_bin_dir,
             &formula_content_root,
             &mut symlinks_created,
+            &mut wrapper_ctx,
         )?;
     }
     let source_libexec_dir = formula_content_root.join("libexec");
@@ -121,24 +233,234 @@ pub fn link_formula_artifacts(
             &target_bin_dir,
             &formula_content_root,
             &mut symlinks_created,
+            &mut wrapper_ctx,
         )?;
     }
 
+    for wanted in &bin_filter.link_only {
+        let wrapper = target_bin_dir.join(wanted).to_string_lossy().to_string();
+        if !symlinks_created.contains(&wrapper) {
+            warn!(
+                "{} has no executable named '{}' to link; ignoring it",
+                formula_name, wanted
+            );
+        }
+    }
+
     write_install_manifest(installed_keg_path, &symlinks_created)?;
 
     debug!(
-        "Successfully completed linking artifacts for {}",
-        formula.name()
+        "Successfully completed linking artifacts for {} ({} repaired, {} unchanged, {} conflicts)",
+        formula_name,
+        report.links_repaired,
+        report.links_unchanged,
+        report.conflicts.len()
     );
+    Ok(report)
+}
+
+/// Points `opt/<name>` at `target_keg_dir` unless it already does. The opt link always belongs
+/// to the formula itself, so it is never treated as a conflict.
+fn ensure_opt_link(
+    formula_name: &str,
+    target_keg_dir: &Path,
+    opt_link_path: &Path,
+    report: &mut LinkReport,
+) -> Result<()> {
+    if is_symlink_to(opt_link_path, target_keg_dir).unwrap_or(false) {
+        report.links_unchanged += 1;
+        return Ok(());
+    }
+    remove_existing_link_target(opt_link_path)?;
+    unix_fs::symlink(target_keg_dir, opt_link_path).map_err(|e| {
+        SapphireError::Io(std::io::Error::new(
+            e.kind(),
+            format!("Failed to create opt symlink for {}: {}", formula_name, e),
+        ))
+    })?;
+    report.links_repaired += 1;
     Ok(())
 }
 
+/// Outcome of [`relink_all`].
+#[derive(Debug, Clone, Default)]
+pub struct RelinkSummary {
+    /// Formulae whose keg was relinked into the prefix.
+    pub relinked: Vec<String>,
+    /// Keg-only formulae, for which only the opt link was refreshed.
+    pub keg_only: Vec<String>,
+    /// Total number of links and wrappers that had to be recreated.
+    pub links_repaired: usize,
+    /// Prefix paths left alone because another formula (or nothing Sapphire manages) owns them.
+    pub conflicts: Vec<LinkConflict>,
+    /// Formulae that could not be relinked, with the reason.
+    pub failures: Vec<(String, String)>,
+}
+
+/// Re-runs [`link_keg`] for every installed formula to repair broken or missing links.
+///
+/// The keg `opt/<name>` currently points at is relinked, falling back to the newest installed
+/// version. Keg-only formulae only get their opt link refreshed. Paths owned by other formulae
+/// are reported as conflicts rather than overwritten, so the result is the same no matter what
+/// order formulae are visited in, and a second run repairs nothing.
+pub fn relink_all(config: &Config) -> Result<RelinkSummary> {
+    let registry = KegRegistry::new(config.clone());
+    let mut by_formula: BTreeMap<String, Vec<InstalledKeg>> = BTreeMap::new();
+    for keg in registry.list_installed_kegs()? {
+        by_formula.entry(keg.name.clone()).or_default().push(keg);
+    }
+
+    let mut summary = RelinkSummary::default();
+    for (name, kegs) in by_formula {
+        let linked = kegs.iter().position(|k| k.is_linked(config));
+        let keg = match linked {
+            Some(i) => &kegs[i],
+            None => match kegs
+                .iter()
+                .max_by(|a, b| (&a.version, a.revision).cmp(&(&b.version, b.revision)))
+            {
+                Some(keg) => keg,
+                None => continue,
+            },
+        };
+
+        let result = if is_keg_only_keg(&keg.path) {
+            debug!("{} is keg-only; refreshing its opt link only", name);
+            let mut report = LinkReport::default();
+            determine_content_root(&keg.path)
+                .and_then(|root| {
+                    let runtime_env = read_runtime_env(&keg.path);
+                    if !runtime_env.is_empty() {
+                        install_runtime_env_wrappers(&root, &runtime_env)?;
+                    }
+                    ensure_opt_link(
+                        &name,
+                        &root,
+                        &config.formula_opt_link_path(&name),
+                        &mut report,
+                    )
+                })
+                .map(|_| (report, true))
+        } else {
+            link_keg(&name, &keg.path, config, &LinkOptions::default())
+                .map(|report| (report, false))
+        };
+        match result {
+            Ok((report, keg_only)) => {
+                summary.links_repaired += report.links_repaired;
+                summary.conflicts.extend(report.conflicts);
+                if keg_only {
+                    summary.keg_only.push(name);
+                } else {
+                    summary.relinked.push(name);
+                }
+            }
+            Err(e) => {
+                error!("Failed to relink {}: {}", name, e);
+                summary.failures.push((name, e.to_string()));
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Reads the `keg_only` flag recorded in a keg's install receipt.
+fn is_keg_only_keg(keg_path: &Path) -> bool {
+    read_receipt_field(keg_path, "keg_only").unwrap_or(false)
+}
+
+/// Conflict-handling state threaded through the recursive wrapper creation.
+struct WrapperContext<'a> {
+    config: &'a Config,
+    formula_name: &'a str,
+    overwrite_conflicts: bool,
+    bin_filter: &'a BinLinkFilter,
+    report: &'a mut LinkReport,
+}
+
+/// Reads the bin filter recorded in a keg's install receipt; no record means link everything.
+fn read_bin_link_filter(keg_path: &Path) -> BinLinkFilter {
+    read_receipt_field(keg_path, "link_filter").unwrap_or_default()
+}
+
+/// Stores `filter` in the keg's install receipt so later relinks apply the same selection.
+/// An empty filter removes the record.
+fn record_bin_link_filter(keg_path: &Path, filter: &BinLinkFilter) -> Result<()> {
+    let value = if filter.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_value(filter)?)
+    };
+    set_receipt_field(keg_path, "link_filter", value)
+}
+
+/// Returns a conflict if `target` exists and belongs to something other than `formula_name`.
+///
+/// Symlinks and Sapphire wrapper scripts pointing into another formula's Cellar directory are
+/// owned by that formula; any other existing file is treated as unmanaged. Dangling links are
+/// stale rather than conflicting and may be replaced.
+fn link_conflict(target: &Path, config: &Config, formula_name: &str) -> Option<LinkConflict> {
+    let meta = target.symlink_metadata().ok()?;
+    let pointed_to = if meta.file_type().is_symlink() {
+        let dest = fs::read_link(target).ok()?;
+        if dest.is_absolute() {
+            dest
+        } else {
+            target.parent()?.join(dest)
+        }
+    } else if meta.is_file() {
+        read_wrapper_target(target)
+    } else {
+        PathBuf::new()
+    };
+    if pointed_to.as_os_str().is_empty() {
+        return Some(LinkConflict {
+            path: target.to_path_buf(),
+            owner: None,
+        });
+    }
+    if !pointed_to.exists() {
+        return None;
+    }
+    let owner = pointed_to
+        .strip_prefix(config.cellar_path())
+        .ok()
+        .and_then(|rel| rel.components().next())
+        .map(|c| c.as_os_str().to_string_lossy().into_owned());
+    match owner {
+        Some(owner) if owner == formula_name => None,
+        owner => Some(LinkConflict {
+            path: target.to_path_buf(),
+            owner,
+        }),
+    }
+}
+
+/// Returns the executable a Sapphire wrapper script `exec`s, or an empty path for any other
+/// file, including binaries and files that can't be read, so those count as unmanaged.
+fn read_wrapper_target(path: &Path) -> PathBuf {
+    let Ok(content) = fs::read(path) else {
+        return PathBuf::new();
+    };
+    let content = String::from_utf8_lossy(&content);
+    if !content.contains(WRAPPER_MARKER) {
+        return PathBuf::new();
+    }
+    content
+        .lines()
+        .find_map(|l| l.strip_prefix("exec \""))
+        .and_then(|rest| rest.split('"').next())
+        .map(PathBuf::from)
+        .unwrap_or_default()
+}
+
 // remove_existing_link_target, write_install_manifest remain mostly unchanged internally) ...
 fn create_wrappers_in_dir(
     source_dir: &Path,
     target_bin_dir: &Path,
     formula_content_root: &Path,
     wrappers_created: &mut Vec<String>,
+    ctx: &mut WrapperContext<'_>,
 ) -> Result<()> {
     debug!(
         "Scanning for executables in {} to create wrappers in {}",
@@ -163,24 +485,76 @@ fn create_wrappers_in_dir(
                                 target_bin_dir,
                                 formula_content_root,
                                 wrappers_created,
+                                ctx,
                             )?;
                         } else if source_item_path.is_file() {
                             match is_executable(&source_item_path) {
                                 Ok(true) => {
                                     let wrapper_path = target_bin_dir.join(&file_name);
                                     debug!("  Found executable: {}", source_item_path.display());
+                                    if !ctx.bin_filter.allows(&file_name.to_string_lossy()) {
+                                        debug!(
+                                            "  Not linking {}: excluded by bin filter",
+                                            source_item_path.display()
+                                        );
+                                        // Drop a wrapper left over from before the filter was set
+                                        if read_wrapper_target(&wrapper_path)
+                                            .starts_with(formula_content_root)
+                                        {
+                                            fs::remove_file(&wrapper_path)?;
+                                        }
+                                        continue;
+                                    }
+                                    let wrapper_key = wrapper_path.to_string_lossy().to_string();
+                                    if wrappers_created.contains(&wrapper_key) {
+                                        // bin/ takes precedence over a same-named libexec entry,
+                                        // e.g. the real binary behind a runtime env wrapper
+                                        debug!(
+                                            "  Skipping {}: {} already created",
+                                            source_item_path.display(),
+                                            wrapper_path.display()
+                                        );
+                                        continue;
+                                    }
+                                    let script = wrapper_script_content(
+                                        &source_item_path,
+                                        formula_content_root,
+                                    );
+                                    if fs::read_to_string(&wrapper_path)
+                                        .is_ok_and(|existing| existing == script)
+                                    {
+                                        ctx.report.links_unchanged += 1;
+                                        wrappers_created
+                                            .push(wrapper_path.to_string_lossy().to_string());
+                                        continue;
+                                    }
+                                    if !ctx.overwrite_conflicts {
+                                        if let Some(conflict) = link_conflict(
+                                            &wrapper_path,
+                                            ctx.config,
+                                            ctx.formula_name,
+                                        ) {
+                                            warn!(
+                                                "  Not creating wrapper {}: already owned by {}",
+                                                wrapper_path.display(),
+                                                conflict
+                                                    .owner
+                                                    .as_deref()
+                                                    .unwrap_or("something outside Sapphire")
+                                            );
+                                            ctx.report.conflicts.push(conflict);
+                                            continue;
+                                        }
+                                    }
                                     if remove_existing_link_target(&wrapper_path).is_ok() {
                                         debug!(
                                             "    Creating wrapper script: {} -> {}",
                                             wrapper_path.display(),
                                             source_item_path.display()
                                         );
-                                        match create_wrapper_script(
-                                            &source_item_path,
-                                            &wrapper_path,
-                                            formula_content_root,
-                                        ) {
+                                        match write_wrapper_script(&wrapper_path, &script) {
                                             Ok(_) => {
+                                                ctx.report.links_repaired += 1;
                                                 debug!(
                                                     "  Created wrapper {} -> {}",
                                                     wrapper_path.display(),
@@ -227,18 +601,107 @@ fn create_wrappers_in_dir(
     }
     Ok(())
 }
-fn create_wrapper_script(
-    target_executable: &Path,
-    wrapper_path: &Path,
+/// Reads the `runtime_env` map recorded in a keg's install receipt.
+fn read_runtime_env(keg_path: &Path) -> BTreeMap<String, String> {
+    read_receipt_field(keg_path, "runtime_env").unwrap_or_default()
+}
+
+/// Moves each executable in the keg's `bin/` to `libexec/bin/` and puts an env wrapper in its
+/// place, so the formula's runtime environment is set however the tool is reached (prefix
+/// `bin/`, `opt/<name>/bin`, or the Cellar path). Already-wrapped entries are refreshed.
+fn install_runtime_env_wrappers(
     formula_content_root: &Path,
+    env: &BTreeMap<String, String>,
 ) -> Result<()> {
+    let bin_dir = formula_content_root.join("bin");
+    if !bin_dir.is_dir() {
+        return Ok(());
+    }
+    let real_bin_dir = formula_content_root.join("libexec").join("bin");
+    let prefix = formula_content_root.to_string_lossy();
+    let env: BTreeMap<String, String> = env
+        .iter()
+        .map(|(k, v)| (k.clone(), v.replace("{prefix}", &prefix)))
+        .collect();
+
+    for entry in fs::read_dir(&bin_dir)? {
+        let wrapper_path = entry?.path();
+        let Some(file_name) = wrapper_path.file_name() else {
+            continue;
+        };
+        let real_bin = real_bin_dir.join(file_name);
+        let already_wrapped = fs::read_to_string(&wrapper_path)
+            .is_ok_and(|c| c.contains(WRAPPER_MARKER))
+            && real_bin.is_file();
+        if !already_wrapped {
+            if wrapper_path.is_symlink() || !is_executable(&wrapper_path)? {
+                continue;
+            }
+            fs::create_dir_all(&real_bin_dir)?;
+            fs::rename(&wrapper_path, &real_bin)?;
+        }
+        install_wrapper(&real_bin, &env, &wrapper_path)?;
+    }
+    Ok(())
+}
+
+/// Writes an executable script at `wrapper_path` that exports `env` and then `exec`s
+/// `real_bin` with the original arguments.
+///
+/// Values are placed inside double quotes, so they may reference other variables (e.g.
+/// `"{prefix}/plugins:$PLUGIN_PATH"`); literal `"`, `\` and `` ` `` are escaped.
+pub fn install_wrapper(
+    real_bin: &Path,
+    env: &BTreeMap<String, String>,
+    wrapper_path: &Path,
+) -> Result<()> {
+    let mut script_content = String::new();
+    script_content.push_str("#!/bin/bash\n");
+    script_content.push_str(WRAPPER_MARKER);
+    script_content.push('\n');
+    for (key, value) in env {
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(SapphireError::Generic(format!(
+                "Invalid runtime environment variable name '{}' for wrapper {}",
+                key,
+                wrapper_path.display()
+            )));
+        }
+        let escaped: String = value
+            .chars()
+            .flat_map(|c| match c {
+                '"' | '\\' | '`' => vec!['\\', c],
+                _ => vec![c],
+            })
+            .collect();
+        script_content.push_str(&format!("export {}=\"{}\"\n", key, escaped));
+    }
+    script_content.push_str(&format!("exec \"{}\" \"$@\"\n", real_bin.display()));
+
+    if fs::read_to_string(wrapper_path).is_ok_and(|existing| existing == script_content) {
+        return Ok(());
+    }
+    remove_existing_link_target(wrapper_path)?;
+    debug!(
+        "  Writing runtime env wrapper {} -> {}",
+        wrapper_path.display(),
+        real_bin.display()
+    );
+    write_wrapper_script(wrapper_path, &script_content)
+}
+
+/// Comment line identifying wrapper scripts written by [`write_wrapper_script`].
+const WRAPPER_MARKER: &str = "# Wrapper script generated by Sapphire";
+
+fn wrapper_script_content(target_executable: &Path, formula_content_root: &Path) -> String {
     let libexec_path = formula_content_root.join("libexec");
     let perl_lib_path = libexec_path.join("lib").join("perl5");
     let python_lib_path = libexec_path.join("vendor"); // Assuming simple vendor dir
 
     let mut script_content = String::new();
     script_content.push_str("#!/bin/bash\n");
-    script_content.push_str("# Wrapper script generated by Sapphire\n");
+    script_content.push_str(WRAPPER_MARKER);
+    script_content.push('\n');
     script_content.push_str("set -e\n\n");
 
     if perl_lib_path.exists() && perl_lib_path.is_dir() {
@@ -266,7 +729,10 @@ fn create_wrapper_script(
         "\nexec \"{}\" \"$@\"\n",
         target_executable.display()
     ));
+    script_content
+}
 
+fn write_wrapper_script(wrapper_path: &Path, script_content: &str) -> Result<()> {
     let mut file = fs::File::create(wrapper_path).map_err(|e| {
         SapphireError::Io(std::io::Error::new(
             e.kind(),
@@ -323,7 +789,7 @@ fn determine_content_root(installed_keg_path: &Path) -> Result<PathBuf> {
                     let file_name_osstr = file_name.as_os_str();
                     if file_name_osstr.to_string_lossy().starts_with('.')
                         || file_name_osstr == "INSTALL_MANIFEST.json"
-                        || file_name_osstr == "INSTALL_RECEIPT.json"
+                        || file_name_osstr == RECEIPT_FILE_NAME
                     {
                         continue;
                     }
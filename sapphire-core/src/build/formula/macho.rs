@@ -3,7 +3,6 @@
 // Updated to use MachOFatFile32 and MachOFatFile64 for FAT binary parsing.
 // Refactored to separate immutable analysis from mutable patching to fix borrow checker errors.
 
-use std::collections::HashMap;
 use std::fs;
 use std::io::Write; // Keep for write_patched_buffer
 use std::path::Path;
@@ -29,9 +28,9 @@ use object::{
 use tempfile::NamedTempFile; // Keep for write_patched_buffer
 use tracing::{debug, error, warn};
 
-use crate::utils::error::Result; // Keep top-level Result
-#[cfg(target_os = "macos")]
-use crate::utils::error::SapphireError;
+use crate::build::devtools;
+use crate::build::formula::relocate::Replacements;
+use crate::utils::error::{Result, SapphireError};
 
 // Constants for Mach-O header sizes
 #[cfg(target_os = "macos")]
@@ -53,23 +52,26 @@ struct PatchInfo {
     new_path: String,
 }
 
-/// Main entry point for patching Mach-O files.
-pub fn patch_macho_file(path: &Path, replacements: &HashMap<String, String>) -> Result<bool> {
+/// Main entry point for patching Mach-O files: makes `replacements` in the paths of its load
+/// commands, in place. Fails with `PathTooLongError`, changing nothing, if a new path doesn't
+/// fit where the old one was. `resign` controls whether a patched binary is re-signed.
+pub fn patch_macho_file(path: &Path, replacements: &Replacements, resign: bool) -> Result<bool> {
     #[cfg(target_os = "macos")]
     {
-        patch_macho_file_macos(path, replacements)
+        patch_macho_file_macos(path, replacements, resign)
     }
     #[cfg(not(target_os = "macos"))]
     {
         // No-op on non-macOS platforms
         let _ = path;
         let _ = replacements;
+        let _ = resign;
         Ok(false)
     }
 }
 
 #[cfg(target_os = "macos")]
-fn patch_macho_file_macos(path: &Path, replacements: &HashMap<String, String>) -> Result<bool> {
+fn patch_macho_file_macos(path: &Path, replacements: &Replacements, resign: bool) -> Result<bool> {
     debug!("Processing potential Mach-O file: {}", path.display());
 
     // 1) Read the entire file into memory
@@ -142,11 +144,11 @@ fn patch_macho_file_macos(path: &Path, replacements: &HashMap<String, String>) -
     write_patched_buffer(path, &buffer)?;
     debug!("  Wrote patched Mach-O: {}", path.display());
 
-    // 8) Re‑sign on Apple Silicon
-    #[cfg(target_arch = "aarch64")]
-    {
+    // 8) Re‑sign where unsigned code won't run
+    if resign {
         resign_binary(path)?;
-        debug!("  Re‑signed patched binary: {}", path.display());
+    } else {
+        debug!("  Not re-signing patched binary: {}", path.display());
     }
 
     Ok(true)
@@ -161,7 +163,7 @@ const AR_MAGIC: &[u8; 8] = b"!<arch>\n";
 fn collect_macho_patches(
     buffer: &[u8],
     kind: FileKind,
-    replacements: &HashMap<String, String>,
+    replacements: &Replacements,
     path_for_log: &Path,
 ) -> Result<Vec<PatchInfo>> {
     let mut patches = Vec::<PatchInfo>::new();
@@ -275,13 +277,13 @@ fn collect_macho_patches(
 }
 
 /// Iterates through load commands of a parsed MachOFile (slice) and returns
-/// patch details.  (SKIPS paths that are too long instead of erroring.)
+/// patch details. A path too long for its load command is an error.
 #[cfg(target_os = "macos")]
 fn find_patches_in_commands<'data, Mach, R>(
     macho_file: &MachOFile<'data, Mach, R>,
     slice_base_offset: usize,
     header_size: usize,
-    replacements: &HashMap<String, String>,
+    replacements: &Replacements,
     file_path_for_log: &Path,
 ) -> Result<Vec<PatchInfo>>
 where
@@ -325,19 +327,17 @@ where
 
         if let Some((offset_in_cmd, bytes)) = path_info {
             if let Ok(old_path) = std::str::from_utf8(bytes) {
-                if let Some(new_path) = find_and_replace_placeholders(old_path, replacements) {
+                if let Some(new_path) = replacements.apply_str(old_path) {
                     let allocated = cmd_size.saturating_sub(offset_in_cmd as usize);
 
                     if new_path.len() + 1 > allocated {
-                        // would overflow – log & **skip** instead of throwing
-                        tracing::debug!(
-                            "Skip patch (too long): '{}' → '{}' (alloc {} B) in {}",
+                        return Err(SapphireError::PathTooLongError(format!(
+                            "'{}' → '{}' doesn't fit the {} bytes of its load command in {}",
                             old_path,
                             new_path,
                             allocated,
                             file_path_for_log.display()
-                        );
-                        continue;
+                        )));
                     }
 
                     patches.push(PatchInfo {
@@ -352,35 +352,6 @@ where
     Ok(patches)
 }
 
-/// Helper to replace placeholders in a string based on the replacements map.
-/// Returns `Some(String)` with replacements if any were made, `None` otherwise.
-fn find_and_replace_placeholders(
-    current_path: &str,
-    replacements: &HashMap<String, String>,
-) -> Option<String> {
-    let mut new_path = current_path.to_string();
-    let mut path_modified = false;
-    // Iterate through all placeholder/replacement pairs
-    for (placeholder, replacement) in replacements {
-        // Check if the current path string contains the placeholder
-        if new_path.contains(placeholder) {
-            // Replace all occurrences of the placeholder
-            new_path = new_path.replace(placeholder, replacement);
-            path_modified = true; // Mark that a change was made
-            debug!(
-                "   Replaced '{}' with '{}' -> '{}'",
-                placeholder, replacement, new_path
-            );
-        }
-    }
-    // Return the modified string only if changes occurred
-    if path_modified {
-        Some(new_path)
-    } else {
-        None
-    }
-}
-
 /// Write a new (null‑padded) path into the mutable buffer.  
 /// Assumes the caller already verified the length.
 #[cfg(target_os = "macos")]
@@ -455,12 +426,15 @@ fn write_patched_buffer(original_path: &Path, buffer: &[u8]) -> Result<()> {
     Ok(())
 }
 
-/// Re-signs the binary using the `codesign` command-line tool.
-/// This is typically necessary on Apple Silicon (aarch64) after modifying executables.
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-fn resign_binary(path: &Path) -> Result<()> {
-    // Suppressed: debug!("    Re-signing patched binary: {}", path.display());
-    let status = StdCommand::new("codesign")
+/// Re-signs the Mach-O file at `path` ad hoc with `codesign`, keeping its identifier and
+/// entitlements. Patching a binary invalidates its signature, and Apple Silicon refuses to run
+/// code whose signature is broken or missing. Does nothing unless
+/// [`devtools::requires_codesign`].
+pub(crate) fn resign_binary(path: &Path) -> Result<()> {
+    if !devtools::requires_codesign() {
+        return Ok(());
+    }
+    let output = StdCommand::new("codesign")
         .args([
             "-s",
             "-",
@@ -468,9 +442,8 @@ fn resign_binary(path: &Path) -> Result<()> {
             "--preserve-metadata=identifier,entitlements",
         ])
         .arg(path)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status() // Execute the command and get its exit status
+        .stdin(Stdio::null())
+        .output()
         .map_err(|e| {
             error!(
                 "    Failed to execute codesign command for {}: {}",
@@ -479,33 +452,20 @@ fn resign_binary(path: &Path) -> Result<()> {
             );
             SapphireError::Io(e)
         })?;
-    if status.success() {
-        // Suppressed: debug!("    Successfully re-signed {}", path.display());
+    if output.status.success() {
+        debug!("    Re-signed {}", path.display());
         Ok(())
     } else {
         error!(
             "    codesign command failed for {} with status: {}",
             path.display(),
-            status
+            output.status
         );
         Err(SapphireError::CodesignError(format!(
-            "Failed to re-sign patched binary {}, it may not be executable. Exit status: {}",
+            "Failed to re-sign patched binary {}, it may not be executable ({}): {}",
             path.display(),
-            status
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
         )))
     }
 }
-
-// No-op stub for resigning on non-Apple Silicon macOS (e.g., x86_64)
-#[cfg(all(target_os = "macos", not(target_arch = "aarch64")))]
-fn resign_binary(_path: &Path) -> Result<()> {
-    // No re-signing typically needed on Intel Macs after ad-hoc patching
-    Ok(())
-}
-
-// No-op stub for resigning Innovations on non-macOS platforms
-#[cfg(not(target_os = "macos"))]
-fn resign_binary(_path: &Path) -> Result<()> {
-    // Resigning is a macOS concept
-    Ok(())
-}
@@ -1,13 +1,16 @@
 // ===== sapphire-core/src/build/formula/mod.rs =====
+use std::ffi::OsString;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
-use crate::model::formula::Formula;
+use crate::build::devtools;
+use crate::build::warnings::WarningCollector;
+use crate::model::formula::{Formula, PathPattern};
 use crate::utils::config::Config;
 use crate::utils::error::{Result, SapphireError};
 
@@ -15,16 +18,21 @@ use crate::utils::error::{Result, SapphireError};
 pub mod bottle;
 pub mod link;
 pub mod macho;
+pub mod reinstall;
+pub mod relocate;
+pub mod share;
 pub mod source;
+pub mod test;
 
 /// Download formula resources from the internet asynchronously.
 pub async fn download_formula(
     formula: &Formula,
     config: &Config,
     client: &reqwest::Client,
+    on_progress: &crate::fetch::http::ProgressCallback,
 ) -> Result<PathBuf> {
     if has_bottle_for_current_platform(formula) {
-        bottle::download_bottle(formula, config, client).await
+        bottle::download_bottle(formula, config, client, on_progress).await
     } else {
         Err(SapphireError::Generic(format!(
             "No bottle available for {} on this platform",
@@ -50,13 +58,8 @@ pub fn has_bottle_for_current_platform(formula: &Formula) -> bool {
 // *** Updated get_current_platform function ***
 fn get_current_platform() -> String {
     if cfg!(target_os = "macos") {
-        let arch = if std::env::consts::ARCH == "aarch64" {
-            "arm64"
-        } else if std::env::consts::ARCH == "x86_64" {
-            "x86_64"
-        } else {
-            std::env::consts::ARCH
-        };
+        // The machine's, so a sapphire running under Rosetta still pours arm64 bottles
+        let arch = devtools::effective_host_arch();
 
         debug!("Attempting to determine macOS version using /usr/bin/sw_vers -productVersion...");
         match Command::new("/usr/bin/sw_vers")
@@ -260,9 +263,51 @@ pub fn get_formula_cellar_path(formula: &Formula, config: &Config) -> PathBuf {
     config.formula_cellar_dir(formula.name())
 }
 
-// --- write_receipt (unchanged) ---
-pub fn write_receipt(formula: &Formula, install_dir: &Path) -> Result<()> {
-    let receipt_path = install_dir.join("INSTALL_RECEIPT.json");
+/// Whether `path` inside `keg_path` is excluded from the clean-up passes by the formula's
+/// `skip_clean` patterns. `pass` names the pass for the log line when it is.
+pub fn skips_clean(skip_clean: &[PathPattern], keg_path: &Path, path: &Path, pass: &str) -> bool {
+    let Ok(rel) = path.strip_prefix(keg_path) else {
+        return false;
+    };
+    match skip_clean.iter().find(|p| p.matches(rel)) {
+        Some(pattern) => {
+            info!(
+                "Skipping {} of {} (skip_clean: {})",
+                pass,
+                rel.display(),
+                pattern.as_str()
+            );
+            true
+        }
+        None => false,
+    }
+}
+
+/// PATH for running what the keg at `install_dir` installed outside a build, as a poured
+/// bottle's post-install steps and smoke tests do: the `bin` and `sbin` of the keg, then of each
+/// of `opt_paths` (normally its dependencies'), then of `prefix`, then the user's PATH.
+pub fn runtime_path(install_dir: &Path, opt_paths: &[PathBuf], prefix: &Path) -> OsString {
+    let mut dirs: Vec<PathBuf> = std::iter::once(install_dir)
+        .chain(opt_paths.iter().map(PathBuf::as_path))
+        .chain(std::iter::once(prefix))
+        .flat_map(|root| [root.join("bin"), root.join("sbin")])
+        .filter(|dir| dir.is_dir())
+        .collect();
+    if let Some(path) = std::env::var_os("PATH") {
+        dirs.extend(std::env::split_paths(&path));
+    }
+    // Only fails for a directory with a `:` in it, which PATH can't hold anyway
+    std::env::join_paths(dirs).unwrap_or_default()
+}
+
+// --- write_receipt ---
+/// Writes INSTALL_RECEIPT.json, including any warnings collected while installing.
+pub fn write_receipt(
+    formula: &Formula,
+    install_dir: &Path,
+    warnings: &WarningCollector,
+) -> Result<()> {
+    let receipt_path = crate::receipt::receipt_path(install_dir);
     let receipt_file = File::create(&receipt_path);
     let mut receipt_file = match receipt_file {
         Ok(file) => file,
@@ -296,8 +341,13 @@ pub fn write_receipt(formula: &Formula, install_dir: &Path) -> Result<()> {
         "built_on": {
             "os": std::env::consts::OS, "arch": std::env::consts::ARCH,
             "platform_tag": get_current_platform(),
+            "effective_arch": devtools::effective_host_arch(), "host_arch": devtools::host_arch(),
+            "rosetta": devtools::running_under_rosetta(),
          },
         "resources_installed": resources_installed,
+        "keg_only": formula.keg_only,
+        "runtime_env": formula.runtime_env,
+        "warnings": warnings.warnings(),
     });
 
     let receipt_json = match serde_json::to_string_pretty(&receipt) {
@@ -320,5 +370,7 @@ pub fn write_receipt(formula: &Formula, install_dir: &Path) -> Result<()> {
 }
 
 // --- Re-exports (unchanged) ---
-pub use bottle::install_bottle;
-pub use link::link_formula_artifacts;
+pub use bottle::{bottle_availability, create_bottle, install_bottle, Platform};
+pub use link::{link_formula_artifacts, link_keg, relink_all, BinLinkFilter, LinkOptions};
+pub use reinstall::{reinstall, ReinstallSource};
+pub use test::run_formula_test;
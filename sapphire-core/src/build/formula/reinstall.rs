@@ -0,0 +1,129 @@
+// sapphire-core/src/build/formula/reinstall.rs
+// Reinstalls a formula over its existing keg without ever leaving it broken. The old keg is
+// moved aside and the new one poured or built at the keg path itself, so everything the build
+// bakes in (RPATHs, compiled-in data and config dirs) names the final location. A failed
+// download, build or relocation puts the old keg back exactly as it was.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, error, info, warn};
+
+use super::bottle::install_bottle;
+use super::source::build_from_source;
+use crate::model::formula::Formula;
+use crate::utils::config::Config;
+use crate::utils::error::{Result, SapphireError};
+
+/// What a reinstall installs from.
+#[derive(Debug, Clone, Copy)]
+pub enum ReinstallSource<'a> {
+    /// A downloaded bottle.
+    Bottle(&'a Path),
+    /// A downloaded source archive or git checkout, built against `all_installed_paths`.
+    Source {
+        source_path: &'a Path,
+        all_installed_paths: &'a [PathBuf],
+    },
+}
+
+/// Installs `formula` from `from` in place of its existing keg and returns the keg path.
+///
+/// The old keg is renamed to a hidden `.<version>.previous` directory beside it and the new
+/// one installed at the keg path. If that fails, whatever it left is removed and the old keg
+/// renamed back. Once it succeeds the old keg is deleted. Files of the old keg that are still
+/// in use (a running binary, an open library) stay valid after the rename; if the old keg
+/// can't be deleted it is left for `cleanup` with a warning. Without an existing keg this is a
+/// plain install.
+pub async fn reinstall(
+    formula: &Formula,
+    config: &Config,
+    from: ReinstallSource<'_>,
+) -> Result<PathBuf> {
+    let keg = config.formula_keg_path(formula.name(), &formula.version_str_full());
+    if !keg.exists() {
+        debug!(
+            "{} is not installed; installing it normally",
+            formula.name()
+        );
+        return install(formula, config, from).await;
+    }
+
+    let backup = sibling_path(&keg, "previous")?;
+    remove_if_exists(&backup)?;
+    fs::rename(&keg, &backup).map_err(|e| {
+        SapphireError::InstallError(format!(
+            "Could not move the old keg {} aside: {}",
+            keg.display(),
+            e
+        ))
+    })?;
+    info!(
+        "==> Reinstalling {}; the old keg is kept at {} until it succeeds",
+        formula.name(),
+        backup.display()
+    );
+
+    match install(formula, config, from).await {
+        Ok(installed) => {
+            if let Err(e) = fs::remove_dir_all(&backup) {
+                warn!(
+                    "Could not delete the old keg at {}: {}; remove it once nothing uses it",
+                    backup.display(),
+                    e
+                );
+            }
+            info!("==> Replaced {}", keg.display());
+            Ok(installed)
+        }
+        Err(e) => {
+            match restore(&backup, &keg) {
+                Ok(()) => info!("==> Restored the previous keg at {}", keg.display()),
+                Err(restore_err) => error!(
+                    "Could not restore the previous keg from {}: {}",
+                    backup.display(),
+                    restore_err
+                ),
+            }
+            Err(e)
+        }
+    }
+}
+
+async fn install(formula: &Formula, config: &Config, from: ReinstallSource<'_>) -> Result<PathBuf> {
+    match from {
+        ReinstallSource::Bottle(bottle_path) => {
+            let (bottle_path, formula, config) =
+                (bottle_path.to_path_buf(), formula.clone(), config.clone());
+            tokio::task::spawn_blocking(move || install_bottle(&bottle_path, &formula, &config))
+                .await
+                .map_err(|e| SapphireError::Generic(format!("Bottle install task failed: {}", e)))?
+        }
+        ReinstallSource::Source {
+            source_path,
+            all_installed_paths,
+        } => build_from_source(source_path, formula, config, all_installed_paths).await,
+    }
+}
+
+/// Puts the old keg at `backup` back at `keg`, removing what a failed install left there.
+fn restore(backup: &Path, keg: &Path) -> Result<()> {
+    remove_if_exists(keg)?;
+    fs::rename(backup, keg)?;
+    Ok(())
+}
+
+/// `.<name>.<suffix>` next to `keg`, hidden from keg listings.
+fn sibling_path(keg: &Path, suffix: &str) -> Result<PathBuf> {
+    let name = keg.file_name().ok_or_else(|| {
+        SapphireError::InstallError(format!("Invalid keg path {}", keg.display()))
+    })?;
+    Ok(keg.with_file_name(format!(".{}.{}", name.to_string_lossy(), suffix)))
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    if path.symlink_metadata().is_ok() {
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
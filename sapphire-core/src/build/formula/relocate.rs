@@ -0,0 +1,816 @@
+// sapphire-core/src/build/formula/relocate.rs
+// Rewrites paths baked into an installed keg: Homebrew's placeholders in a poured bottle, the
+// prefix a sapphire bottle was built in, a staging directory a build system leaked into what it
+// installed, or a versioned data directory that should be reached through the opt path. Text
+// files are patched freely; Mach-O load commands in place, or with `install_name_tool` where a
+// new path outgrows its slot; ELF interpreters and rpaths likewise, with `patchelf`; other
+// binaries only inside their C strings.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::ops::Range;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+use object::elf::{FileHeader32, FileHeader64, DT_RPATH, DT_RUNPATH, PT_INTERP};
+use object::read::elf::{Dyn, FileHeader, ProgramHeader, SectionHeader};
+use object::Endianness;
+use tracing::{debug, info};
+use walkdir::WalkDir;
+
+use crate::build::devtools;
+use crate::build::formula::{macho, skips_clean};
+use crate::build::warnings::{BuildPhase, WarningCode, WarningCollector};
+use crate::model::formula::PathPattern;
+use crate::utils::error::{Result, SapphireError};
+
+/// Magic numbers of thin Mach-O files, 32 and 64-bit in either byte order.
+const MACHO_MAGICS: [[u8; 4]; 4] = [
+    [0xfe, 0xed, 0xfa, 0xce],
+    [0xfe, 0xed, 0xfa, 0xcf],
+    [0xce, 0xfa, 0xed, 0xfe],
+    [0xcf, 0xfa, 0xed, 0xfe],
+];
+
+/// Magic number of fat (universal) Mach-O files, shared with Java class files.
+const FAT_MAGIC: [u8; 4] = [0xca, 0xfe, 0xba, 0xbe];
+
+/// Strings to replace throughout a keg, and what each becomes.
+///
+/// They are all replaced in a single pass: at each position the longest matching string wins,
+/// and text that was put in is never matched again. So relocating both `<prefix>/Cellar` and
+/// `<prefix>` is right however the new paths overlap the old ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Replacements {
+    /// Longest first, so a string is tried before any shorter one it starts with.
+    pairs: Vec<(String, String)>,
+}
+
+impl Replacements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `from` → `to`, replacing an earlier entry for `from`. Empty `from`s and strings
+    /// replaced by themselves are ignored.
+    pub fn insert(&mut self, from: impl Into<String>, to: impl Into<String>) {
+        let (from, to) = (from.into(), to.into());
+        if from.is_empty() || from == to {
+            return;
+        }
+        self.pairs.retain(|(f, _)| *f != from);
+        let at = self.pairs.partition_point(|(f, _)| f.len() >= from.len());
+        self.pairs.insert(at, (from, to));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(f, t)| (f.as_str(), t.as_str()))
+    }
+
+    /// `data` with every replacement made, or `None` if nothing matched.
+    pub fn apply(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut starts = [false; 256];
+        for (from, _) in &self.pairs {
+            starts[from.as_bytes()[0] as usize] = true;
+        }
+        let mut out = Vec::new();
+        let (mut pos, mut copied) = (0, 0);
+        while pos < data.len() {
+            let matched = starts[data[pos] as usize]
+                .then(|| {
+                    self.pairs
+                        .iter()
+                        .find(|(from, _)| data[pos..].starts_with(from.as_bytes()))
+                })
+                .flatten();
+            match matched {
+                Some((from, to)) => {
+                    out.extend_from_slice(&data[copied..pos]);
+                    out.extend_from_slice(to.as_bytes());
+                    pos += from.len();
+                    copied = pos;
+                }
+                None => pos += 1,
+            }
+        }
+        if copied == 0 {
+            return None;
+        }
+        out.extend_from_slice(&data[copied..]);
+        Some(out)
+    }
+
+    /// [`Self::apply`] for a string.
+    pub fn apply_str(&self, s: &str) -> Option<String> {
+        self.apply(s.as_bytes())
+            .map(|b| String::from_utf8_lossy(&b).into_owned())
+    }
+
+    /// Makes the replacements inside the NUL-terminated strings of `data` whose new value fits
+    /// in place of the old one, padding with NULs so every offset in the file stays valid.
+    /// Strings starting inside one of the `skip` ranges are left alone. Returns how many strings
+    /// changed, and the replacements that were too long to make.
+    fn apply_in_c_strings(
+        &self,
+        data: &mut [u8],
+        skip: &[Range<usize>],
+    ) -> (usize, Vec<(&str, &str)>) {
+        let mut changed = 0;
+        let mut too_long = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            let end = data[start..]
+                .iter()
+                .position(|&b| b == 0)
+                .map_or(data.len(), |p| start + p);
+            if skip.iter().any(|r| r.contains(&start)) {
+                start = end + 1;
+                continue;
+            }
+            if let Some(new) = self.apply(&data[start..end]) {
+                if new.len() <= end - start {
+                    data[start..start + new.len()].copy_from_slice(&new);
+                    data[start + new.len()..end].fill(0);
+                    changed += 1;
+                } else {
+                    let string = &data[start..end];
+                    too_long.extend(self.iter().filter(|(from, to)| {
+                        to.len() > from.len()
+                            && string.windows(from.len()).any(|w| w == from.as_bytes())
+                    }));
+                }
+            }
+            start = end + 1;
+        }
+        too_long.sort_unstable();
+        too_long.dedup();
+        (changed, too_long)
+    }
+}
+
+/// What [`relocate_keg`] rewrites, and how.
+#[derive(Debug, Clone)]
+pub struct Relocation<'a> {
+    pub replacements: Replacements,
+    /// Whether binaries also have the strings replaced in their data, not just Mach-O load
+    /// commands and ELF interpreters and rpaths, which only works where the new string is no
+    /// longer than the old one.
+    pub binaries: bool,
+    /// Whether changed Mach-O files are re-signed (see [`macho::resign_binary`]).
+    pub resign: bool,
+    /// Files left as they are.
+    pub skip_clean: &'a [PathPattern],
+}
+
+impl<'a> Relocation<'a> {
+    /// Text files and the paths binaries load from get `replacements`; nothing is skipped or
+    /// re-signed.
+    pub fn new(replacements: Replacements) -> Self {
+        Self {
+            replacements,
+            binaries: false,
+            resign: false,
+            skip_clean: &[],
+        }
+    }
+}
+
+/// How a file in the keg is relocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileKind {
+    Text,
+    MachO,
+    Elf,
+    /// Any other binary.
+    Binary,
+}
+
+/// Applies `relocation` to every file in `keg`, except those inside app bundles, whose
+/// signatures any change would break. Returns the number of files changed.
+///
+/// A file that can't be read, or a replacement that doesn't fit in a binary, is recorded as a
+/// warning rather than failing the install; the linkage check catches what that breaks.
+pub fn relocate_keg(
+    keg: &Path,
+    relocation: &Relocation,
+    warnings: &WarningCollector,
+) -> Result<usize> {
+    if relocation.replacements.is_empty() {
+        return Ok(0);
+    }
+    let (mut text, mut machos, mut elfs, mut binaries) = (0, 0, 0, 0);
+    for entry in WalkDir::new(keg).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let in_app_bundle = path.strip_prefix(keg).is_ok_and(|rel| {
+            rel.components()
+                .any(|c| c.as_os_str().to_string_lossy().ends_with(".app"))
+        });
+        if in_app_bundle || skips_clean(relocation.skip_clean, keg, path, "relocation") {
+            continue;
+        }
+        let kind = match file_kind(path) {
+            Ok(kind) => kind,
+            Err(e) => {
+                warnings.warn(
+                    WarningCode::RelocationHazard,
+                    BuildPhase::Relocation,
+                    format!("Could not read the file to relocate it: {}", e),
+                    Some(path),
+                );
+                continue;
+            }
+        };
+        match kind {
+            FileKind::Text => text += usize::from(relocate_text(path, &relocation.replacements)?),
+            FileKind::MachO => machos += usize::from(relocate_macho(path, relocation, warnings)?),
+            FileKind::Elf => elfs += usize::from(relocate_elf(path, relocation, warnings)?),
+            FileKind::Binary if relocation.binaries => {
+                binaries += usize::from(relocate_binary(path, &relocation.replacements, warnings)?)
+            }
+            FileKind::Binary => debug!("Not relocating binary file {}", path.display()),
+        }
+    }
+
+    let total = text + machos + elfs + binaries;
+    if total > 0 {
+        info!(
+            "==> Relocated {} text file(s), {} Mach-O file(s), {} ELF file(s) and {} other binaries in {}",
+            text,
+            machos,
+            elfs,
+            binaries,
+            keg.display()
+        );
+    }
+    Ok(total)
+}
+
+/// Tells text from Mach-O, ELF and other binaries by the first KiB of `path`.
+fn file_kind(path: &Path) -> std::io::Result<FileKind> {
+    let mut head = [0u8; 1024];
+    let n = fs::File::open(path)?.read(&mut head)?;
+    let head = &head[..n];
+    if is_macho(head) {
+        return Ok(FileKind::MachO);
+    }
+    if head.starts_with(b"\x7fELF") {
+        return Ok(FileKind::Elf);
+    }
+    Ok(if head.contains(&0) {
+        FileKind::Binary
+    } else {
+        FileKind::Text
+    })
+}
+
+/// Whether `data` starts like a Mach-O file, thin or fat.
+pub(crate) fn is_macho(data: &[u8]) -> bool {
+    if data.len() >= 4 && MACHO_MAGICS.iter().any(|m| data[..4] == m[..]) {
+        return true;
+    }
+    // A fat header counts its architectures next, where a class file has its version (45+)
+    data.len() >= 8
+        && data[..4] == FAT_MAGIC
+        && u32::from_be_bytes([data[4], data[5], data[6], data[7]]) < 45
+}
+
+/// Makes `replacements` in the text file at `path`. Returns whether anything changed.
+fn relocate_text(path: &Path, replacements: &Replacements) -> Result<bool> {
+    let Some(content) = replacements.apply(&fs::read(path)?) else {
+        return Ok(false);
+    };
+    // Writing through the existing file keeps its permissions
+    with_write_permission(path, || Ok(fs::write(path, content)?))?;
+    debug!("Relocated {}", path.display());
+    Ok(true)
+}
+
+/// Makes `replacements` inside the C strings of the binary at `path`, recording a warning for
+/// each that doesn't fit. Returns whether anything changed.
+fn relocate_binary(
+    path: &Path,
+    replacements: &Replacements,
+    warnings: &WarningCollector,
+) -> Result<bool> {
+    let mut data = fs::read(path)?;
+    let (changed, too_long) = replacements.apply_in_c_strings(&mut data, &[]);
+    for (from, to) in too_long {
+        warnings.warn(
+            WarningCode::RelocationHazard,
+            BuildPhase::Relocation,
+            format!("Cannot rewrite {} to the longer {} in a binary", from, to),
+            Some(path),
+        );
+    }
+    if changed == 0 {
+        return Ok(false);
+    }
+    with_write_permission(path, || Ok(fs::write(path, &data)?))?;
+    debug!("Relocated {} string(s) in {}", changed, path.display());
+    Ok(true)
+}
+
+/// Relocates the load commands of the Mach-O file at `path`, and its data too if
+/// `relocation.binaries`, then re-signs it if anything changed and `relocation.resign`.
+///
+/// Load commands are patched in place where the new path fits; if one doesn't, they are
+/// rewritten with `install_name_tool`, which can grow them into the header padding.
+fn relocate_macho(
+    path: &Path,
+    relocation: &Relocation,
+    warnings: &WarningCollector,
+) -> Result<bool> {
+    let replacements = &relocation.replacements;
+    let mut changed = match macho::patch_macho_file(path, replacements, false) {
+        Ok(changed) => changed,
+        Err(SapphireError::PathTooLongError(e)) => {
+            debug!("{}; using install_name_tool", e);
+            relocate_load_commands(path, replacements)?
+        }
+        Err(e @ (SapphireError::MachOError(_) | SapphireError::Object(_))) => {
+            warnings.warn(
+                WarningCode::RelocationHazard,
+                BuildPhase::Relocation,
+                format!("Could not read the Mach-O load commands: {}", e),
+                Some(path),
+            );
+            false
+        }
+        Err(e) => return Err(e),
+    };
+    if relocation.binaries {
+        changed |= relocate_binary(path, replacements, warnings)?;
+    }
+    if changed && relocation.resign {
+        with_write_permission(path, || macho::resign_binary(path))?;
+    }
+    Ok(changed)
+}
+
+/// A path the dynamic loader reads from an ELF file: its interpreter or an rpath.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ElfPath {
+    /// Where the string is in the file, without its NUL.
+    range: Range<usize>,
+    /// The `patchelf` options that set it.
+    options: &'static [&'static str],
+}
+
+/// The interpreter and rpaths of the ELF file `data`. Nothing if it can't be parsed.
+fn elf_paths(data: &[u8]) -> Vec<ElfPath> {
+    let paths = match object::FileKind::parse(data) {
+        Ok(object::FileKind::Elf32) => elf_paths_of::<FileHeader32<Endianness>>(data),
+        Ok(object::FileKind::Elf64) => elf_paths_of::<FileHeader64<Endianness>>(data),
+        _ => None,
+    };
+    paths.unwrap_or_default()
+}
+
+fn elf_paths_of<Elf: FileHeader<Endian = Endianness>>(data: &[u8]) -> Option<Vec<ElfPath>> {
+    let header = Elf::parse(data).ok()?;
+    let endian = header.endian().ok()?;
+    let c_string = |start: u64| {
+        let start = usize::try_from(start).ok()?;
+        let len = data.get(start..)?.iter().position(|&b| b == 0)?;
+        Some(start..start + len)
+    };
+
+    let mut paths = Vec::new();
+    for segment in header.program_headers(endian, data).ok()? {
+        if segment.p_type(endian) == PT_INTERP {
+            paths.extend(
+                c_string(segment.p_offset(endian).into()).map(|range| ElfPath {
+                    range,
+                    options: &["--set-interpreter"],
+                }),
+            );
+        }
+    }
+    let sections = header.sections(endian, data).ok()?;
+    if let Some((dynamic, link)) = sections.dynamic(endian, data).ok()? {
+        let strings: u64 = sections.section(link).ok()?.sh_offset(endian).into();
+        for entry in dynamic {
+            // patchelf turns an RPATH into a RUNPATH, which is searched after LD_LIBRARY_PATH
+            // rather than before it, unless told not to
+            let options: &'static [&'static str] = match entry.tag32(endian) {
+                Some(DT_RPATH) => &["--force-rpath", "--set-rpath"],
+                Some(DT_RUNPATH) => &["--set-rpath"],
+                _ => continue,
+            };
+            let offset: u64 = entry.d_val(endian).into();
+            paths.extend(c_string(strings + offset).map(|range| ElfPath { range, options }));
+        }
+    }
+    Some(paths)
+}
+
+/// Relocates the interpreter and rpaths of the ELF file at `path`, and its data too if
+/// `relocation.binaries`. Returns whether anything changed.
+///
+/// Paths are patched in place where the new one fits; one that doesn't is set with `patchelf`,
+/// which can move the string table to make room, or recorded as a warning without it.
+fn relocate_elf(path: &Path, relocation: &Relocation, warnings: &WarningCollector) -> Result<bool> {
+    let replacements = &relocation.replacements;
+    let mut data = fs::read(path)?;
+    let paths = elf_paths(&data);
+
+    let mut changed = false;
+    let mut outgrown = Vec::new();
+    for elf_path in &paths {
+        let range = elf_path.range.clone();
+        let Some(new) = replacements.apply(&data[range.clone()]) else {
+            continue;
+        };
+        if new.len() <= range.len() {
+            data[range.start..range.start + new.len()].copy_from_slice(&new);
+            data[range.start + new.len()..range.end].fill(0);
+            changed = true;
+        } else {
+            let old = String::from_utf8_lossy(&data[range]).into_owned();
+            outgrown.push((
+                elf_path.options,
+                old,
+                String::from_utf8_lossy(&new).into_owned(),
+            ));
+        }
+    }
+    if relocation.binaries {
+        let skip: Vec<Range<usize>> = paths.into_iter().map(|p| p.range).collect();
+        let (strings, too_long) = replacements.apply_in_c_strings(&mut data, &skip);
+        for (from, to) in too_long {
+            warnings.warn(
+                WarningCode::RelocationHazard,
+                BuildPhase::Relocation,
+                format!("Cannot rewrite {} to the longer {} in a binary", from, to),
+                Some(path),
+            );
+        }
+        changed |= strings > 0;
+    }
+    if changed {
+        with_write_permission(path, || Ok(fs::write(path, &data)?))?;
+    }
+
+    for (options, old, new) in outgrown {
+        changed |= patchelf(path, options, &old, &new, warnings);
+    }
+    if changed {
+        debug!("Relocated ELF file {}", path.display());
+    }
+    Ok(changed)
+}
+
+/// Sets the ELF path `old` of the file at `path` to `new` with `patchelf <options> <new>`.
+/// Without patchelf, or if it fails, that is recorded as a warning. Returns whether it was set.
+fn patchelf(
+    path: &Path,
+    options: &[&str],
+    old: &str,
+    new: &str,
+    warnings: &WarningCollector,
+) -> bool {
+    let set = devtools::find_tool("patchelf").and_then(|patchelf| {
+        with_write_permission(path, || {
+            let output = Command::new(&patchelf)
+                .args(options)
+                .arg(new)
+                .arg(path)
+                .output()
+                .map_err(|e| {
+                    SapphireError::CommandExecError(format!("Failed to run patchelf: {}", e))
+                })?;
+            if !output.status.success() {
+                return Err(SapphireError::CommandExecError(format!(
+                    "patchelf failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+            Ok(())
+        })
+    });
+    if let Err(e) = set {
+        warnings.warn(
+            WarningCode::RelocationHazard,
+            BuildPhase::Relocation,
+            format!("Cannot rewrite {} to the longer {}: {}", old, new, e),
+            Some(path),
+        );
+        return false;
+    }
+    true
+}
+
+/// Rewrites the install name, dependent library paths and rpaths of the Mach-O file at `path`
+/// with `install_name_tool`. Returns whether anything changed. Only done on macOS, where
+/// `otool` and `install_name_tool` exist. The caller re-signs the file.
+fn relocate_load_commands(path: &Path, replacements: &Replacements) -> Result<bool> {
+    if !cfg!(target_os = "macos") {
+        debug!(
+            "Not relocating Mach-O file {} outside macOS",
+            path.display()
+        );
+        return Ok(false);
+    }
+    let id = dylib_id(path)?;
+    let mut args: Vec<String> = Vec::new();
+    if let Some(new) = id.as_deref().and_then(|id| replacements.apply_str(id)) {
+        args.extend(["-id".to_string(), new]);
+    }
+
+    for library in linked_libraries(path)? {
+        if Some(library.as_str()) == id.as_deref() {
+            continue;
+        }
+        if let Some(new) = replacements.apply_str(&library) {
+            args.extend(["-change".to_string(), library, new]);
+        }
+    }
+
+    let mut rpaths: Vec<&str> = Vec::new();
+    let load_commands = otool(path, "-l")?;
+    for line in load_commands.lines().map(str::trim) {
+        let Some(rpath) = line.strip_prefix("path ") else {
+            continue;
+        };
+        rpaths.push(rpath.split(" (offset").next().unwrap_or(rpath));
+    }
+    rpaths.sort_unstable();
+    rpaths.dedup();
+    for rpath in rpaths {
+        if let Some(new) = replacements.apply_str(rpath) {
+            args.extend(["-rpath".to_string(), rpath.to_string(), new]);
+        }
+    }
+
+    if args.is_empty() {
+        return Ok(false);
+    }
+    install_name_tool(path, &args)?;
+    debug!("Relocated Mach-O file {}", path.display());
+    Ok(true)
+}
+
+/// Points the install name of every dylib in `install_dir` at its path under `opt_path`, the
+/// keg's stable opt link, then changes binaries in the keg loading one of them by its old name to
+/// match. Libraries built from source often keep an install name from the build directory or an
+/// `@rpath` one, which breaks their dependents; naming the versioned keg instead would break
+/// them at the next upgrade. Does nothing outside macOS. The changed files are left for
+/// [`codesign_install`] to sign.
+pub fn fix_dylib_ids(install_dir: &Path, opt_path: &Path) -> Result<()> {
+    if !cfg!(target_os = "macos") {
+        return Ok(());
+    }
+    let machos = macho_files(install_dir);
+
+    let mut old_to_new: HashMap<String, String> = HashMap::new();
+    for dylib in machos
+        .iter()
+        .filter(|p| p.extension().is_some_and(|e| e == "dylib"))
+    {
+        let Ok(Some(id)) = dylib_id(dylib) else {
+            continue;
+        };
+        let Ok(rel) = dylib.strip_prefix(install_dir) else {
+            continue;
+        };
+        let new_id = opt_path.join(rel).to_string_lossy().into_owned();
+        if id != new_id {
+            fix_dylib_id(dylib, &new_id)?;
+            debug!("Changed install name of {} from {}", dylib.display(), id);
+            old_to_new.insert(id, new_id);
+        }
+    }
+    if old_to_new.is_empty() {
+        return Ok(());
+    }
+
+    for binary in &machos {
+        let stale: HashMap<String, String> = linked_libraries(binary)?
+            .into_iter()
+            .filter_map(|lib| old_to_new.get(&lib).map(|new| (lib, new.clone())))
+            .collect();
+        fix_dylib_deps(binary, &stale)?;
+    }
+    info!(
+        "==> Fixed the install names of {} dylib(s) in {}",
+        old_to_new.len(),
+        install_dir.display()
+    );
+    Ok(())
+}
+
+/// Signs every Mach-O file in `install_dir` ad hoc (see [`macho::resign_binary`]), since Apple
+/// Silicon kills binaries the linker left unsigned or whose signature relocation broke.
+/// Does nothing where signing isn't required. Returns the number of files signed.
+pub fn codesign_install(install_dir: &Path) -> Result<usize> {
+    if !devtools::requires_codesign() {
+        return Ok(0);
+    }
+    let mut signed = 0;
+    for path in macho_files(install_dir) {
+        with_write_permission(&path, || macho::resign_binary(&path))?;
+        signed += 1;
+    }
+    if signed > 0 {
+        info!("==> Signed {} Mach-O file(s) ad hoc", signed);
+    }
+    Ok(signed)
+}
+
+/// The Mach-O files in `dir`. Files that can't be read are left out.
+fn macho_files(dir: &Path) -> Vec<std::path::PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && matches!(file_kind(e.path()), Ok(FileKind::MachO)))
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Sets the install name of the dylib at `dylib_path` to `new_id` (`install_name_tool -id`).
+/// The caller re-signs it.
+pub fn fix_dylib_id(dylib_path: &Path, new_id: &str) -> Result<()> {
+    install_name_tool(dylib_path, &["-id".to_string(), new_id.to_string()])
+}
+
+/// Changes each library `binary` loads by a path in `old_to_new` to the path it maps to
+/// (`install_name_tool -change`). The caller re-signs it.
+pub fn fix_dylib_deps(binary: &Path, old_to_new: &HashMap<String, String>) -> Result<()> {
+    if old_to_new.is_empty() {
+        return Ok(());
+    }
+    let args: Vec<String> = old_to_new
+        .iter()
+        .flat_map(|(old, new)| ["-change".to_string(), old.clone(), new.clone()])
+        .collect();
+    install_name_tool(binary, &args)
+}
+
+/// Runs `install_name_tool <args> <path>`. Changing load commands invalidates the signature,
+/// so the caller re-signs `path` unless the codesign pass is skipped.
+fn install_name_tool(path: &Path, args: &[String]) -> Result<()> {
+    let install_name_tool = devtools::find_tool("install_name_tool")?;
+    with_write_permission(path, || {
+        let output = Command::new(&install_name_tool)
+            .args(args)
+            .arg(path)
+            .output()
+            .map_err(|e| {
+                SapphireError::CommandExecError(format!("Failed to run install_name_tool: {}", e))
+            })?;
+        if !output.status.success() {
+            return Err(SapphireError::MachOError(format!(
+                "install_name_tool failed on {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    })
+}
+
+/// The install name of the Mach-O file at `path`, or `None` if it isn't a library.
+fn dylib_id(path: &Path) -> Result<Option<String>> {
+    Ok(otool(path, "-D")?
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.ends_with(':'))
+        .map(str::to_string))
+}
+
+/// The libraries the Mach-O file at `path` loads, deduplicated across architectures. A dylib's
+/// own install name is listed too.
+fn linked_libraries(path: &Path) -> Result<Vec<String>> {
+    let mut libraries: Vec<String> = otool(path, "-L")?
+        .lines()
+        .filter(|line| line.starts_with(char::is_whitespace))
+        .map(|line| {
+            let line = line.trim();
+            line.split(" (").next().unwrap_or(line).to_string()
+        })
+        .collect();
+    libraries.sort_unstable();
+    libraries.dedup();
+    Ok(libraries)
+}
+
+/// Output of `otool <flag> <path>`.
+fn otool(path: &Path, flag: &str) -> Result<String> {
+    let otool = devtools::find_tool("otool")?;
+    let output = Command::new(&otool)
+        .arg(flag)
+        .arg(path)
+        .output()
+        .map_err(|e| SapphireError::CommandExecError(format!("Failed to run otool: {}", e)))?;
+    if !output.status.success() {
+        return Err(SapphireError::MachOError(format!(
+            "otool {} failed on {}: {}",
+            flag,
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Runs `patch` with `path` made writable by its owner, restoring its permissions afterwards.
+/// `make install` commonly installs libraries and headers read-only.
+pub(crate) fn with_write_permission(path: &Path, patch: impl FnOnce() -> Result<()>) -> Result<()> {
+    let permissions = fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    if mode & 0o200 == 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode | 0o200))?;
+    }
+    let result = patch();
+    if mode & 0o200 == 0 {
+        fs::set_permissions(path, permissions)?;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replacements_prefer_the_longest_match_and_never_rematch() {
+        let mut replacements = Replacements::new();
+        replacements.insert("/opt/s", "/opt/s2");
+        replacements.insert("/opt/s/Cellar", "/opt/s2/Cellar");
+
+        assert_eq!(
+            replacements
+                .apply_str("/opt/s/Cellar/foo/1.0:/opt/s/lib")
+                .as_deref(),
+            Some("/opt/s2/Cellar/foo/1.0:/opt/s2/lib")
+        );
+        assert_eq!(replacements.apply_str("/usr/lib"), None);
+    }
+
+    #[test]
+    fn c_strings_are_patched_in_place_where_they_fit() {
+        let mut replacements = Replacements::new();
+        replacements.insert("/opt/sapphire", "/opt/s");
+        replacements.insert("/usr/local", "/usr/local/sapphire");
+        let mut data = b"\x01/opt/sapphire/lib\0/usr/local/bin\0".to_vec();
+
+        let (changed, too_long) = replacements.apply_in_c_strings(&mut data, &[]);
+
+        assert_eq!(changed, 1);
+        assert_eq!(too_long, [("/usr/local", "/usr/local/sapphire")]);
+        assert_eq!(
+            data,
+            [&b"\x01/opt/s/lib"[..], &[0; 8], b"/usr/local/bin\0"].concat()
+        );
+    }
+
+    #[test]
+    fn c_strings_in_skipped_ranges_are_left_alone() {
+        let mut replacements = Replacements::new();
+        replacements.insert("/opt/sapphire", "/opt/s");
+        let original = b"/opt/sapphire/lib\0/opt/sapphire/bin\0".to_vec();
+        let mut data = original.clone();
+
+        let (changed, _) =
+            replacements.apply_in_c_strings(&mut data, &[Range { start: 0, end: 17 }]);
+
+        assert_eq!(changed, 1);
+        assert_eq!(data[..18], original[..18]);
+        assert_eq!(data[18..], [&b"/opt/s/bin"[..], &[0; 8]].concat());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn elf_interpreter_is_patched_in_place() {
+        let tmp = tempfile::tempdir().unwrap();
+        let exe = tmp.path().join("exe");
+        fs::copy(std::env::current_exe().unwrap(), &exe).unwrap();
+        let interpreter = |data: &[u8]| {
+            elf_paths(data)
+                .into_iter()
+                .find(|p| p.options == ["--set-interpreter"])
+                .expect("the test binary is dynamically linked")
+                .range
+        };
+        let data = fs::read(&exe).unwrap();
+        let old = String::from_utf8(data[interpreter(&data)].to_vec()).unwrap();
+        let mut replacements = Replacements::new();
+        replacements.insert(old, "/x/ld.so");
+
+        let relocation = Relocation::new(replacements);
+        assert!(relocate_elf(&exe, &relocation, &WarningCollector::new()).unwrap());
+
+        let data = fs::read(&exe).unwrap();
+        assert_eq!(&data[interpreter(&data)], b"/x/ld.so");
+    }
+}
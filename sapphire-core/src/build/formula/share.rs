@@ -0,0 +1,133 @@
+// sapphire-core/src/build/formula/share.rs
+// Formulae that keep runtime data under share/<name>/ usually compile the absolute path of that
+// directory into their binaries and scripts. After a source build that path points into the
+// versioned keg, which breaks as soon as the keg is upgraded and cleaned up, and a sloppy build
+// system may even bake in the temporary build directory. This pass rewrites keg references to
+// the stable `opt` path and flags build-directory references.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+use walkdir::WalkDir;
+
+use crate::build::formula::relocate::{relocate_keg, Relocation, Replacements};
+use crate::build::warnings::{BuildPhase, WarningCode, WarningCollector};
+use crate::model::formula::PathPattern;
+use crate::utils::error::Result;
+
+/// The formula's own data directories under `share/`: `share/<name>`, versioned ones such as
+/// `share/<name>-1.2`, and `share/<base>` for a versioned formula `<base>@N`.
+pub fn formula_data_dirs(formula_name: &str, keg_path: &Path) -> Vec<PathBuf> {
+    let base = formula_name.split('@').next().unwrap_or(formula_name);
+    let Ok(entries) = fs::read_dir(keg_path.join("share")) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            [formula_name, base].iter().any(|n| {
+                name == *n
+                    || name
+                        .strip_prefix(n)
+                        .and_then(|rest| rest.strip_prefix('-'))
+                        .is_some_and(|v| v.starts_with(|c: char| c.is_ascii_digit()))
+            })
+        })
+        .map(|e| e.path())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// Rewrites references to the keg's data directories (`<keg>/share/<dir>`) to
+/// `<opt_path>/share/<dir>` in every file of the keg, and records a warning for each file that
+/// still refers to a `share` path inside `build_dir`. Returns the number of files rewritten.
+///
+/// Text files are rewritten freely. In binaries a path is only rewritten inside its C string,
+/// padded with NULs, so the new path must not be longer than the old one; rewritten Mach-O files
+/// are re-signed if `resign`. Files matching `skip_clean` are left as they are.
+pub fn relocate_share_paths(
+    formula_name: &str,
+    skip_clean: &[PathPattern],
+    keg_path: &Path,
+    opt_path: &Path,
+    build_dir: Option<&Path>,
+    resign: bool,
+    warnings: &WarningCollector,
+) -> Result<usize> {
+    let mut replacements = Replacements::new();
+    for rel in formula_data_dirs(formula_name, keg_path)
+        .iter()
+        .filter_map(|dir| dir.strip_prefix(keg_path).ok())
+    {
+        replacements.insert(
+            keg_path.join(rel).to_string_lossy(),
+            opt_path.join(rel).to_string_lossy(),
+        );
+    }
+
+    if let Some(build_dir) = build_dir {
+        let build_dir = build_dir.to_string_lossy().into_owned().into_bytes();
+        for entry in WalkDir::new(keg_path).into_iter().filter_map(|e| e.ok()) {
+            let skipped = entry
+                .path()
+                .strip_prefix(keg_path)
+                .is_ok_and(|rel| skip_clean.iter().any(|p| p.matches(rel)));
+            if !entry.file_type().is_file() || skipped {
+                continue;
+            }
+            let Ok(data) = fs::read(entry.path()) else {
+                debug!(
+                    "Could not read {}, skipping its build directory check",
+                    entry.path().display()
+                );
+                continue;
+            };
+            for reference in build_dir_share_references(&data, &build_dir) {
+                warnings.warn(
+                    WarningCode::RelocationHazard,
+                    BuildPhase::Relocation,
+                    format!("Compiled-in data path points at the build directory: {reference}"),
+                    Some(entry.path()),
+                );
+            }
+        }
+    }
+
+    let relocation = Relocation {
+        replacements,
+        binaries: true,
+        resign,
+        skip_clean,
+    };
+    relocate_keg(keg_path, &relocation, warnings)
+}
+
+/// Paths under `build_dir` that go through a `share` directory, as they appear in `data`.
+fn build_dir_share_references(data: &[u8], build_dir: &[u8]) -> Vec<String> {
+    let mut references = Vec::new();
+    let mut offset = 0;
+    while let Some(pos) = find(&data[offset..], build_dir) {
+        let start = offset + pos;
+        let end = data[start..]
+            .iter()
+            .position(|b| matches!(b, 0 | b'"' | b'\'' | b'\n' | b' ' | b':'))
+            .map_or(data.len(), |p| start + p);
+        let reference = String::from_utf8_lossy(&data[start..end]);
+        if reference.contains("/share/") || reference.ends_with("/share") {
+            references.push(reference.into_owned());
+        }
+        offset = end.max(start + 1);
+    }
+    references
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
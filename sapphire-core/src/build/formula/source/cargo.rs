@@ -1,44 +1,74 @@
 use std::path::Path;
 use std::process::Command;
 
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 use crate::build::env::BuildEnvironment;
+use crate::build::process::run_streaming;
+use crate::build::warnings::BuildPhase;
 use crate::utils::error::{Result, SapphireError};
 
-/// Build with Cargo
+/// Env var naming the Rust target triple for cross builds.
+const CARGO_BUILD_TARGET: &str = "CARGO_BUILD_TARGET";
+
+/// Build with Cargo (`cargo install --path . --root <install_dir>`), so binaries land in
+/// `<install_dir>/bin`.
+///
+/// The lockfile is honoured with `--locked` when the crate ships one. `CARGO_BUILD_TARGET`, from
+/// the build environment or else the invoking shell, is passed on as `--target`; without it, a
+/// build for another architecture than sapphire's own (see
+/// [`BuildEnvironment::foreign_arch`]) gets that architecture's triple.
 pub fn cargo_build(install_dir: &Path, build_env: &BuildEnvironment) -> Result<()> {
     info!("==> Building with Cargo");
-    let cargo_exe =
-        which::which_in("cargo", build_env.get_path_string(), Path::new(".")).map_err(|_| {
+    let cargo_exe = which::which_in("cargo", build_env.get_path_string(), Path::new("."))
+        .or_else(|_| which::which("cargo"))
+        .map_err(|_| {
             SapphireError::BuildEnvError(
-                "cargo command not found in build environment PATH.".to_string(),
+                "cargo command not found in build environment PATH or system PATH; \
+                 add rust as a build dependency or install a Rust toolchain."
+                    .to_string(),
             )
         })?;
+    // The sanitized environment drops the user's CARGO_* variables, so look at both
+    let target = build_env
+        .get_var(CARGO_BUILD_TARGET)
+        .map(str::to_string)
+        .or_else(|| std::env::var(CARGO_BUILD_TARGET).ok())
+        .filter(|t| !t.trim().is_empty())
+        .or_else(|| build_env.foreign_arch().map(|arch| arch.rust_target()));
 
     info!(
         "==> Running {} install --path . --root {}",
         cargo_exe.display(),
         install_dir.display()
     );
+    // `cargo install` configures, builds and installs in one go
+    build_env.checkpoint(BuildPhase::Configure)?;
     let mut cmd = Command::new(cargo_exe);
     cmd.arg("install")
         .arg("--path")
         .arg(".")
         .arg("--root")
         .arg(install_dir);
+    if build_env.build_dir().join("Cargo.lock").is_file() {
+        cmd.arg("--locked");
+    } else {
+        debug!("No Cargo.lock; dependencies will be resolved fresh");
+    }
+    if let Some(target) = &target {
+        info!("    (Cross-compiling for {})", target);
+        cmd.arg("--target").arg(target);
+    }
     build_env.apply_to_command(&mut cmd);
-    let output = cmd.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute cargo install: {}", e))
-    })?;
+    let output = run_streaming(&mut cmd, "cargo install")?;
 
     if !output.status.success() {
-        println!("Cargo install failed with status: {}", output.status);
-        eprintln!(
+        error!("Cargo install failed with status: {}", output.status);
+        error!(
             "Cargo install stdout:\n{}",
             String::from_utf8_lossy(&output.stdout)
         );
-        eprintln!(
+        error!(
             "Cargo install stderr:\n{}",
             String::from_utf8_lossy(&output.stderr)
         );
@@ -4,80 +4,100 @@ use std::fs;
 use std::path::Path;
 use std::process::Command;
 
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
+use super::docs::{build_docs, DocTool};
+use super::ninja::ninja_compile;
 use crate::build::env::BuildEnvironment;
+use crate::build::process::run_streaming;
+use crate::build::warnings::BuildPhase;
 use crate::utils::error::{Result, SapphireError};
 
-/// Build with CMake, using Ninja as the generator
+/// Build the CMake project in `source_dir` out of source, in `sapphire-cmake-build/` there.
+///
+/// Ninja is used as the generator when it is available. Otherwise CMake's default generator is
+/// used and the build and install steps go through `cmake --build` and `cmake --install`.
 pub fn cmake_build(
-    source_dot: &Path, // Parameter represents "." now, as CWD is the source root
+    source_dir: &Path,
     install_dir: &Path,
     build_env: &BuildEnvironment,
 ) -> Result<()> {
-    // Ensure source_dot is actually "."
-    if source_dot != Path::new(".") {
-        return Err(SapphireError::BuildEnvError(format!(
-            "cmake_build expected source path '.' but received '{}'",
-            source_dot.display()
-        )));
-    }
-
     info!("==> Building with CMake");
     let build_subdir_name = "sapphire-cmake-build";
-    // Create the build directory *outside* the source, relative to CWD
-    let build_subdir = Path::new(".").join(build_subdir_name); // Relative to CWD
+    // Create the build directory inside the source root, out of the source files' way
+    let build_subdir = source_dir.join(build_subdir_name);
     fs::create_dir_all(&build_subdir).map_err(SapphireError::Io)?;
 
     let cmake_exe = which::which_in("cmake", build_env.get_path_string(), Path::new(".")) // Check CWD and PATH
+        .or_else(|_| which::which("cmake"))
         .map_err(|_| {
             SapphireError::BuildEnvError(
-                "cmake command not found in build environment PATH.".to_string(),
+                "cmake command not found in build environment PATH or system PATH.".to_string(),
             )
         })?;
+    let ninja_exe = which::which_in("ninja", build_env.get_path_string(), Path::new("."))
+        .or_else(|_| which::which("ninja"))
+        .ok();
+    if ninja_exe.is_none() {
+        info!("    (ninja not found; using CMake's default generator)");
+    }
     info!(
         "==> Running cmake configuration in {}",
         build_subdir.display()
     );
 
-    let mut cmd = Command::new(cmake_exe);
+    let mut cmd = Command::new(&cmake_exe);
     // Pass ".." as the source directory relative to the build_subdir where cmake runs
     cmd.arg("..") // <--- CORRECTED LINE
         .arg(format!("-DCMAKE_INSTALL_PREFIX={}", install_dir.display()))
         .arg("-DCMAKE_POLICY_VERSION_MINIMUM=3.5")
         .arg("-DCMAKE_BUILD_TYPE=Release")
         .args([
-            "-G",
-            "Ninja",
             "-DCMAKE_FIND_FRAMEWORK=LAST",
             "-DCMAKE_VERBOSE_MAKEFILE=ON",
             "-Wno-dev",
         ])
         .current_dir(&build_subdir); // Run from the build subdir
+    if ninja_exe.is_some() {
+        cmd.args(["-G", "Ninja"]);
+    }
+    if build_env.universal() {
+        // CMake runs its own probes per architecture, so it takes the universal flags as is
+        cmd.arg("-DCMAKE_OSX_ARCHITECTURES=x86_64;arm64");
+    } else if let Some(target) = build_env.target_arch() {
+        cmd.arg(format!("-DCMAKE_OSX_ARCHITECTURES={}", target.apple_name()));
+    }
+    if let Some(toolchain) = build_env.toolchain() {
+        let toolchain_file =
+            toolchain.write_cmake_toolchain_file(&fs::canonicalize(&build_subdir)?)?;
+        info!("    (Using toolchain file {})", toolchain_file.display());
+        cmd.arg(format!(
+            "-DCMAKE_TOOLCHAIN_FILE={}",
+            toolchain_file.display()
+        ));
+    }
 
-    build_env.apply_to_command(&mut cmd);
-    let output = cmd
-        .output()
-        .map_err(|e| SapphireError::CommandExecError(format!("Failed to execute cmake: {}", e)))?;
+    build_env.apply_to_configure_command(&mut cmd);
+    let output = run_streaming(&mut cmd, "cmake")?;
 
     if !output.status.success() {
         // (Error handling code from your original file)
-        println!("CMake configure failed with status: {}", output.status);
-        eprintln!(
+        error!("CMake configure failed with status: {}", output.status);
+        error!(
             "CMake configure stdout:\n{}",
             String::from_utf8_lossy(&output.stdout)
         );
-        eprintln!(
+        error!(
             "CMake configure stderr:\n{}",
             String::from_utf8_lossy(&output.stderr)
         );
         let error_log = build_subdir.join("CMakeFiles/CMakeError.log");
         if error_log.exists() {
-            eprintln!("--- CMakeFiles/CMakeError.log ---");
+            error!("--- CMakeFiles/CMakeError.log ---");
             if let Ok(content) = fs::read_to_string(&error_log) {
-                eprintln!("{}", content);
+                error!("{}", content);
             }
-            eprintln!("--- End CMakeFiles/CMakeError.log ---");
+            error!("--- End CMakeFiles/CMakeError.log ---");
         }
         return Err(SapphireError::Generic(format!(
             "CMake configure failed with status: {}",
@@ -94,36 +114,41 @@ pub fn cmake_build(
             String::from_utf8_lossy(&output.stderr)
         );
     }
+    build_env.checkpoint(BuildPhase::Configure)?;
+
+    let Some(ninja_exe) = ninja_exe else {
+        cmake_build_and_install(&cmake_exe, &build_subdir, build_env)?;
+        return build_docs(
+            DocTool::Cmake(&cmake_exe),
+            &build_subdir,
+            source_dir,
+            install_dir,
+            build_env,
+        );
+    };
+    ninja_compile(&ninja_exe, &build_subdir, build_env, "ninja (CMake)")?;
+    build_env.checkpoint(BuildPhase::Build)?;
 
     // --- Use ninja for install step ---
     info!("==> Running ninja install in {}", build_subdir.display());
-    let ninja_exe = which::which_in("ninja", build_env.get_path_string(), Path::new(".")) // Find ninja
-        .map_err(|_| {
-            SapphireError::BuildEnvError(
-                "ninja command not found in build environment PATH (required for CMake build)."
-                    .to_string(),
-            )
-        })?;
 
-    let mut cmd_install = Command::new(ninja_exe); // Use ninja
+    let mut cmd_install = Command::new(&ninja_exe); // Use ninja
     cmd_install.arg("install").current_dir(&build_subdir); // Run 'ninja install' from build subdir
 
     build_env.apply_to_command(&mut cmd_install);
-    let output_install = cmd_install.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute ninja install (CMake): {}", e))
-    })?;
+    let output_install = run_streaming(&mut cmd_install, "ninja install (CMake)")?;
 
     if !output_install.status.success() {
         // (Error handling code from your original file)
-        println!(
+        error!(
             "Ninja install failed with status: {}",
             output_install.status
         );
-        eprintln!(
+        error!(
             "Ninja install stdout:\n{}",
             String::from_utf8_lossy(&output_install.stdout)
         );
-        eprintln!(
+        error!(
             "Ninja install stderr:\n{}",
             String::from_utf8_lossy(&output_install.stderr)
         );
@@ -135,5 +160,65 @@ pub fn cmake_build(
         debug!("Ninja install completed successfully.");
     }
 
+    build_docs(
+        DocTool::Ninja(&ninja_exe),
+        &build_subdir,
+        source_dir,
+        install_dir,
+        build_env,
+    )
+}
+
+/// Builds and installs a project configured with CMake's default generator, through
+/// `cmake --build` and `cmake --install` in `build_subdir`.
+fn cmake_build_and_install(
+    cmake_exe: &Path,
+    build_subdir: &Path,
+    build_env: &BuildEnvironment,
+) -> Result<()> {
+    info!("==> Running cmake --build in {}", build_subdir.display());
+    let jobs = build_env.make_jobs().to_string();
+    let mut args = vec!["--build", ".", "--parallel", &jobs];
+    // Both of the generators' native tools, make and ninja, take a load limit as -l
+    let load = build_env.max_load().map(|load| format!("-l{}", load));
+    if let Some(load) = &load {
+        args.extend(["--", load]);
+    }
+    run_cmake_step(cmake_exe, build_subdir, &args, "cmake --build", build_env)?;
+    build_env.checkpoint(BuildPhase::Build)?;
+
+    info!("==> Running cmake --install in {}", build_subdir.display());
+    run_cmake_step(
+        cmake_exe,
+        build_subdir,
+        &["--install", "."],
+        "cmake --install",
+        build_env,
+    )
+}
+
+fn run_cmake_step(
+    cmake_exe: &Path,
+    build_subdir: &Path,
+    args: &[&str],
+    context: &str,
+    build_env: &BuildEnvironment,
+) -> Result<()> {
+    let mut cmd = Command::new(cmake_exe);
+    cmd.args(args).current_dir(build_subdir);
+    build_env.apply_to_command(&mut cmd);
+    let output = run_streaming(&mut cmd, context)?;
+    if !output.status.success() {
+        error!(
+            "{} stderr:\n{}",
+            context,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(SapphireError::Generic(format!(
+            "{} failed with status: {}",
+            context, output.status
+        )));
+    }
+    debug!("{} completed successfully.", context);
     Ok(())
 }
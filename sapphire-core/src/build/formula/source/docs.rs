@@ -0,0 +1,287 @@
+// sapphire-core/src/build/formula/source/docs.rs
+// Optional documentation step shared by the make, ninja and CMake backends. Runs after the main
+// install when `build_docs` is enabled: builds the project's docs target, then either runs its
+// doc install target or copies the usual output directories into the keg.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use tracing::{debug, info};
+
+use super::ninja::ninja_targets;
+use crate::build::env::BuildEnvironment;
+use crate::build::process::run_streaming;
+use crate::build::warnings::{BuildPhase, WarningCode};
+use crate::utils::error::Result;
+
+/// Targets that build documentation, in order of preference.
+const DOC_TARGETS: [&str; 4] = ["docs", "doc", "html", "man"];
+/// Targets that install already-built documentation, in order of preference.
+const DOC_INSTALL_TARGETS: [&str; 4] =
+    ["install-docs", "install-doc", "install-html", "install-man"];
+/// Where doc builds commonly leave HTML output, relative to the build or source directory.
+const HTML_OUTPUT_DIRS: [&str; 6] = [
+    "doc/html",
+    "docs/html",
+    "doc/_build/html",
+    "docs/_build/html",
+    "docs/build/html",
+    "html",
+];
+/// Directories searched for generated man pages.
+const MAN_OUTPUT_DIRS: [&str; 4] = ["man", "doc", "docs", "doc/man"];
+
+/// The tool that drives the project's build graph.
+pub(super) enum DocTool<'a> {
+    Make(&'a Path),
+    Ninja(&'a Path),
+    /// `cmake --build`, for a CMake build directory whatever its generator.
+    Cmake(&'a Path),
+}
+
+impl DocTool<'_> {
+    fn exe(&self) -> &Path {
+        match self {
+            Self::Make(exe) | Self::Ninja(exe) | Self::Cmake(exe) => exe,
+        }
+    }
+
+    /// Returns the subset of `candidates` the build graph in `dir` defines.
+    fn available(
+        &self,
+        dir: &Path,
+        candidates: &[&str],
+        build_env: &BuildEnvironment,
+    ) -> Vec<String> {
+        match self {
+            Self::Ninja(exe) => {
+                let targets = ninja_targets(exe, dir, build_env).unwrap_or_default();
+                candidates
+                    .iter()
+                    .filter(|c| targets.iter().any(|t| t == *c))
+                    .map(|c| c.to_string())
+                    .collect()
+            }
+            Self::Make(exe) => candidates
+                .iter()
+                .filter(|target| {
+                    // A dry run fails with "No rule to make target" for unknown targets
+                    let mut cmd = Command::new(exe);
+                    cmd.arg("-n").arg(target).current_dir(dir);
+                    build_env.apply_to_command(&mut cmd);
+                    cmd.stdout(Stdio::null())
+                        .stderr(Stdio::null())
+                        .status()
+                        .is_ok_and(|s| s.success())
+                })
+                .map(|c| c.to_string())
+                .collect(),
+            Self::Cmake(exe) => {
+                let targets = cmake_targets(exe, dir, build_env);
+                candidates
+                    .iter()
+                    .filter(|c| targets.iter().any(|t| t == *c))
+                    .map(|c| c.to_string())
+                    .collect()
+            }
+        }
+    }
+
+    fn run(
+        &self,
+        dir: &Path,
+        target: &str,
+        install_dir: &Path,
+        build_env: &BuildEnvironment,
+    ) -> Result<bool> {
+        let mut cmd = Command::new(self.exe());
+        cmd.current_dir(dir);
+        match self {
+            // Plain Makefiles commonly read the prefix from PREFIX; autotools ignores it
+            Self::Make(_) => cmd
+                .arg(target)
+                .arg(format!("PREFIX={}", install_dir.display())),
+            Self::Ninja(_) => cmd.arg(target),
+            Self::Cmake(_) => cmd.args(["--build", ".", "--target", target]),
+        };
+        build_env.apply_to_command(&mut cmd);
+        let output = run_streaming(&mut cmd, target)?;
+        if !output.status.success() {
+            debug!(
+                "{} stderr:\n{}",
+                target,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(output.status.success())
+    }
+}
+
+/// Builds and installs documentation if the build environment asks for it and the build graph
+/// in `dir` has a docs target. Without an install target, output is looked for in `dir` and
+/// `source_dir`. Missing targets are skipped quietly and failures only produce a warning;
+/// documentation never fails an install.
+pub(super) fn build_docs(
+    tool: DocTool<'_>,
+    dir: &Path,
+    source_dir: &Path,
+    install_dir: &Path,
+    build_env: &BuildEnvironment,
+) -> Result<()> {
+    if !build_env.build_docs() {
+        return Ok(());
+    }
+    let warn = |message: String| {
+        build_env
+            .warnings()
+            .warn(WarningCode::ToolFailure, BuildPhase::Install, message, None)
+    };
+
+    let build_target = tool
+        .available(dir, &DOC_TARGETS, build_env)
+        .into_iter()
+        .next();
+    let install_target = tool
+        .available(dir, &DOC_INSTALL_TARGETS, build_env)
+        .into_iter()
+        .next();
+    if build_target.is_none() && install_target.is_none() {
+        info!("==> No documentation target found; skipping docs");
+        return Ok(());
+    }
+
+    if let Some(target) = &build_target {
+        info!("==> Building documentation ({})", target);
+        if !tool.run(dir, target, install_dir, build_env)? {
+            warn(format!(
+                "Documentation target '{}' failed; skipping docs",
+                target
+            ));
+            return Ok(());
+        }
+    }
+
+    if let Some(target) = &install_target {
+        info!("==> Installing documentation ({})", target);
+        if !tool.run(dir, target, install_dir, build_env)? {
+            warn(format!("Documentation install target '{}' failed", target));
+        }
+        return Ok(());
+    }
+
+    let copied = copy_doc_outputs(&[dir, source_dir], install_dir, build_env.formula_name())?;
+    if copied == 0 {
+        warn(format!(
+            "Documentation was built with '{}' but no output was found to install",
+            build_target.unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+/// The targets of the CMake build directory `dir`, from its `help` target: `... docs` lines
+/// with the Makefile generators, `docs: phony` ones with Ninja. Empty if that fails.
+fn cmake_targets(cmake_exe: &Path, dir: &Path, build_env: &BuildEnvironment) -> Vec<String> {
+    let mut cmd = Command::new(cmake_exe);
+    cmd.args(["--build", ".", "--target", "help"])
+        .current_dir(dir)
+        .stderr(Stdio::null());
+    build_env.apply_to_command(&mut cmd);
+    let Ok(output) = cmd.output() else {
+        return Vec::new();
+    };
+    if !output.status.success() {
+        return Vec::new();
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let target = line.strip_prefix("... ").unwrap_or(line);
+            let target = target.split([':', ' ']).next()?;
+            (!target.is_empty()).then(|| target.to_string())
+        })
+        .collect()
+}
+
+/// Copies HTML output directories to `share/doc/<name>/html` and man pages to
+/// `share/man/man<N>`. Returns the number of items copied.
+fn copy_doc_outputs(
+    search_roots: &[&Path],
+    install_dir: &Path,
+    formula_name: &str,
+) -> Result<usize> {
+    let mut copied = 0;
+
+    let html_dir = search_roots
+        .iter()
+        .flat_map(|root| HTML_OUTPUT_DIRS.iter().map(move |d| root.join(d)))
+        .find(|d| d.join("index.html").is_file());
+    if let Some(html_dir) = html_dir {
+        let dest = install_dir
+            .join("share/doc")
+            .join(formula_name)
+            .join("html");
+        info!(
+            "==> Installing HTML documentation from {}",
+            html_dir.display()
+        );
+        copy_dir(&html_dir, &dest)?;
+        copied += 1;
+    }
+
+    for root in search_roots {
+        for dir in MAN_OUTPUT_DIRS {
+            let Ok(entries) = fs::read_dir(root.join(dir)) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Some(section) = man_section(&path) else {
+                    continue;
+                };
+                let dest_dir = install_dir
+                    .join("share/man")
+                    .join(format!("man{}", section));
+                fs::create_dir_all(&dest_dir)?;
+                let dest = dest_dir.join(entry.file_name());
+                if !dest.exists() {
+                    debug!("Installing man page {}", path.display());
+                    fs::copy(&path, &dest)?;
+                    copied += 1;
+                }
+            }
+        }
+    }
+    Ok(copied)
+}
+
+/// The section digit of a man page file name like `foo.1`, if it is one.
+fn man_section(path: &Path) -> Option<char> {
+    if !path.is_file() {
+        return None;
+    }
+    let ext = path.extension()?.to_str()?;
+    let mut chars = ext.chars();
+    let section = chars.next().filter(|c| ('1'..='9').contains(c))?;
+    chars.next().is_none().then_some(section)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    for entry in walkdir::WalkDir::new(from)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let Ok(rel) = entry.path().strip_prefix(from) else {
+            continue;
+        };
+        let dest: PathBuf = to.join(rel);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dest)?;
+        } else if entry.file_type().is_file() {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
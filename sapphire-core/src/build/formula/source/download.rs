@@ -0,0 +1,109 @@
+// sapphire-core/src/build/formula/source/download.rs
+// Downloading sources with retries.
+
+use std::path::Path;
+
+use crate::fetch::http::{self, DownloadProgress};
+use crate::utils::error::Result;
+
+/// Downloads `url` to `dest`, making up to `max_attempts` tries. Connection failures and 5xx
+/// responses are retried with exponential backoff, each retry reported as a warning and resumed
+/// from where the previous try stopped when the server supports range requests; a 404 is not
+/// retried. Single-connection source and resource downloads retry the same way.
+///
+/// `on_progress` receives the bytes downloaded, the total size when the server reports one and
+/// the throughput, for a frontend to render as it likes.
+pub async fn download_with_retry(
+    url: &str,
+    dest: &Path,
+    max_attempts: u32,
+    on_progress: impl FnMut(DownloadProgress),
+) -> Result<()> {
+    let client = http::build_http_client()?;
+    http::download_resumable(&client, url, dest, max_attempts, on_progress).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    /// Serves one connection per entry of `responses`, in order, and returns the request heads
+    /// it received.
+    fn serve(responses: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/foo-1.0.tar.gz", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                // Up to the blank line ending the head; downloads send no body
+                while reader.read_line(&mut head).unwrap() > 2 {}
+                requests.push(head.to_ascii_lowercase());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    fn response(status: &str, headers: &[&str], body: &str) -> String {
+        let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
+        }
+        response.push_str("\r\n");
+        response.push_str(body);
+        response
+    }
+
+    #[tokio::test]
+    async fn retries_and_resumes_a_broken_download() {
+        let (url, server) = serve(vec![
+            // Cut off after six of eleven bytes
+            response("200 OK", &["Content-Length: 11"], "hello "),
+            response("503 Service Unavailable", &["Content-Length: 0"], ""),
+            response(
+                "206 Partial Content",
+                &["Content-Length: 5", "Content-Range: bytes 6-10/11"],
+                "world",
+            ),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("foo-1.0.tar.gz");
+
+        download_with_retry(&url, &dest, 4, |_| {}).await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "hello world");
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(!requests[0].contains("range:"));
+        assert!(requests[1].contains("range: bytes=6-"), "{}", requests[1]);
+        assert!(requests[2].contains("range: bytes=6-"), "{}", requests[2]);
+    }
+
+    #[tokio::test]
+    async fn not_found_is_not_retried() {
+        let (url, server) = serve(vec![response("404 Not Found", &["Content-Length: 0"], "")]);
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("foo-1.0.tar.gz");
+
+        let result = download_with_retry(&url, &dest, 4, |_| {}).await;
+
+        assert!(
+            matches!(
+                result,
+                Err(crate::utils::error::SapphireError::DownloadError(..))
+            ),
+            "{result:?}"
+        );
+        // A retry would have found the listener gone and failed with a connection error
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+}
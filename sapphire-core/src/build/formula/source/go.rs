@@ -2,58 +2,29 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 use crate::build::env::BuildEnvironment;
+use crate::build::process::run_streaming;
+use crate::build::warnings::BuildPhase;
 use crate::utils::error::{Result, SapphireError};
 
 pub fn go_build(
-    build_dir_dot: &Path,
+    module_dir: &Path,
     install_dir: &Path,
     build_env: &BuildEnvironment,
     _all_installed_paths: &[PathBuf],
 ) -> Result<()> {
-    if build_dir_dot != Path::new(".") {
-        return Err(SapphireError::BuildEnvError(format!(
-            "go_build expected build path '.' but received '{}'",
-            build_dir_dot.display()
-        )));
-    }
-
     info!("==> Building Go module (go.mod detected)");
 
-    let go_exe =
-        which::which_in("go", build_env.get_path_string(), Path::new(".")).map_err(|_| {
+    let go_exe = which::which_in("go", build_env.get_path_string(), Path::new("."))
+        .or_else(|_| which::which("go"))
+        .map_err(|_| {
             SapphireError::BuildEnvError(
-                "go command not found in build environment PATH.".to_string(),
+                "go command not found in build environment PATH or system PATH.".to_string(),
             )
         })?;
-
-    let formula_name = install_dir
-        .parent()
-        .and_then(|p| p.file_name())
-        .and_then(|n| n.to_str())
-        .ok_or_else(|| {
-            SapphireError::BuildEnvError(format!(
-                "Could not infer formula name from install path: {}",
-                install_dir.display()
-            ))
-        })?;
-
-    let cmd_pkg_path = Path::new("cmd").join(formula_name);
-    let package_to_build = if cmd_pkg_path.is_dir() {
-        debug!(
-            "Found potential command package path: {}",
-            cmd_pkg_path.display()
-        );
-        format!("./{}", cmd_pkg_path.to_string_lossy())
-    } else {
-        debug!(
-            "Command package path {} not found, building '.'",
-            cmd_pkg_path.display()
-        );
-        ".".to_string()
-    };
+    let formula_name = build_env.formula_name();
 
     let target_bin_dir = install_dir.join("bin");
     fs::create_dir_all(&target_bin_dir).map_err(|e| {
@@ -68,35 +39,89 @@ pub fn go_build(
     })?;
     let output_binary_path = target_bin_dir.join(formula_name);
 
-    info!(
-        "==> Running: {} build -o {} -ldflags \"-s -w\" {}",
-        go_exe.display(),
-        output_binary_path.display(),
-        package_to_build
-    );
+    let cmd_pkg_path = Path::new("cmd").join(formula_name);
+    let package_to_build = if module_dir.join(&cmd_pkg_path).is_dir() {
+        debug!(
+            "Found potential command package path: {}",
+            cmd_pkg_path.display()
+        );
+        Some(format!("./{}", cmd_pkg_path.to_string_lossy()))
+    } else if is_main_package(module_dir) {
+        debug!("Module root is a main package, building '.'");
+        Some(".".to_string())
+    } else {
+        debug!(
+            "No {} or main package at the module root; installing every command",
+            cmd_pkg_path.display()
+        );
+        None
+    };
+
+    // go install refuses to put cross-compiled binaries in GOBIN, so those are built into the
+    // bin directory instead
+    let cross_compiling =
+        build_env.get_var("GOARCH").is_some() || build_env.foreign_arch().is_some();
 
+    // Go modules have no configure step
+    build_env.checkpoint(BuildPhase::Configure)?;
     let mut cmd = Command::new(go_exe);
-    cmd.arg("build");
-    cmd.arg("-o");
-    cmd.arg(&output_binary_path);
+    match &package_to_build {
+        Some(package) => {
+            info!(
+                "==> Running: go build -o {} -ldflags \"-s -w\" {}",
+                output_binary_path.display(),
+                package
+            );
+            cmd.arg("build").arg("-o").arg(&output_binary_path);
+        }
+        None if cross_compiling => {
+            info!(
+                "==> Running: go build -o {}/ -ldflags \"-s -w\" ./...",
+                target_bin_dir.display()
+            );
+            // The trailing slash makes -o a directory for every main package
+            let mut out_dir = target_bin_dir.clone().into_os_string();
+            out_dir.push("/");
+            cmd.arg("build").arg("-o").arg(out_dir);
+        }
+        None => {
+            info!("==> Running: go install -ldflags \"-s -w\" ./...");
+            cmd.arg("install");
+        }
+    }
     cmd.arg("-ldflags");
     #[allow(clippy::suspicious_command_arg_space)]
     cmd.arg("-s -w");
-    cmd.arg(&package_to_build);
+    cmd.arg(package_to_build.as_deref().unwrap_or("./..."));
 
     build_env.apply_to_command(&mut cmd);
+    if package_to_build.is_none() && !cross_compiling {
+        // apply_to_command clears the environment, so GOBIN goes back on afterwards
+        cmd.env("GOBIN", &target_bin_dir);
+    }
+    // cgo follows the user's choice; the sanitized environment would otherwise drop it
+    if build_env.get_var("CGO_ENABLED").is_none() {
+        if let Ok(cgo) = std::env::var("CGO_ENABLED") {
+            cmd.env("CGO_ENABLED", cgo);
+        }
+    }
+    // go builds for its own architecture, which isn't the target's under Rosetta
+    if let Some(arch) = build_env.foreign_arch() {
+        if build_env.get_var("GOARCH").is_none() {
+            debug!("Building for GOARCH={}", arch.go_arch());
+            cmd.env("GOARCH", arch.go_arch());
+        }
+    }
 
-    let output = cmd.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute go build: {}", e))
-    })?;
+    let output = run_streaming(&mut cmd, "go build")?;
 
     if !output.status.success() {
-        println!("Go build failed with status: {}", output.status);
-        eprintln!(
+        error!("Go build failed with status: {}", output.status);
+        error!(
             "Go build stdout:\n{}",
             String::from_utf8_lossy(&output.stdout)
         );
-        eprintln!(
+        error!(
             "Go build stderr:\n{}",
             String::from_utf8_lossy(&output.stderr)
         );
@@ -115,10 +140,30 @@ pub fn go_build(
             String::from_utf8_lossy(&output.stderr)
         );
         info!(
-            "Go build successful, binary placed at: {}",
-            output_binary_path.display()
+            "Go build successful, binaries placed in: {}",
+            target_bin_dir.display()
         );
     }
 
     Ok(())
 }
+
+/// Whether the Go package in `dir` is a `main` package, i.e. builds a command.
+fn is_main_package(dir: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
+    entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.extension().is_some_and(|e| e == "go") && !p.to_string_lossy().ends_with("_test.go")
+        })
+        .filter_map(|p| fs::read_to_string(p).ok())
+        .any(|src| {
+            src.lines()
+                .map(str::trim)
+                .find(|l| l.starts_with("package "))
+                .is_some_and(|l| l.split_whitespace().nth(1) == Some("main"))
+        })
+}
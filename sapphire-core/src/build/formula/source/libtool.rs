@@ -0,0 +1,171 @@
+// sapphire-core/src/build/formula/source/libtool.rs
+// Libtool `.la` archives record absolute paths (`libdir`, `dependency_libs`) from the build,
+// which go stale once the keg moves or a dependency is upgraded, and then break the builds of
+// whatever links against them. Like Homebrew, they are deleted after install by default; a
+// formula that needs them keeps them with their paths rewritten to the keg.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info};
+use walkdir::WalkDir;
+
+use crate::build::formula::skips_clean;
+use crate::model::formula::PathPattern;
+use crate::utils::error::Result;
+
+/// Deletes the libtool archives in `install_dir` and returns how many were removed. Archives
+/// that a `.la` file staying behind still names in its `dependency_libs` are kept, as libtool
+/// fails on a missing one: those linked into `prefix/lib` by other formulae, and any in this
+/// keg that `skip_clean` protects.
+pub fn prune_la_files(
+    install_dir: &Path,
+    prefix: &Path,
+    skip_clean: &[PathPattern],
+) -> Result<usize> {
+    let (archives, mut remaining): (Vec<_>, Vec<_>) = la_files(install_dir, usize::MAX)
+        .into_iter()
+        .partition(|path| !skips_clean(skip_clean, install_dir, path, "libtool archive removal"));
+    if archives.is_empty() {
+        return Ok(0);
+    }
+    remaining.extend(la_files(&prefix.join("lib"), 1));
+    let referenced = referenced_archive_names(&remaining, &archives);
+
+    let mut removed = 0;
+    for archive in &archives {
+        let name = archive.file_name().unwrap_or_default().to_string_lossy();
+        if referenced.contains(name.as_ref()) {
+            debug!(
+                "Keeping {}, which another .la file needs",
+                archive.display()
+            );
+            continue;
+        }
+        fs::remove_file(archive)?;
+        removed += 1;
+    }
+    if removed > 0 {
+        info!("==> Removed {} libtool archive(s)", removed);
+    }
+    Ok(removed)
+}
+
+/// Points the libtool archives in `install_dir` at where they are installed: `libdir` becomes
+/// the archive's own directory, and `dependency_libs` entries that no longer exist are
+/// redirected to a same-named library in the keg, or dropped. Returns how many were rewritten.
+pub fn rewrite_la_files(install_dir: &Path, skip_clean: &[PathPattern]) -> Result<usize> {
+    let archives: Vec<PathBuf> = la_files(install_dir, usize::MAX)
+        .into_iter()
+        .filter(|path| !skips_clean(skip_clean, install_dir, path, "libtool archive rewrite"))
+        .collect();
+    let mut rewritten = 0;
+    for archive in &archives {
+        let Ok(contents) = fs::read_to_string(archive) else {
+            continue;
+        };
+        let libdir = archive.parent().unwrap_or(install_dir);
+        let new_contents = contents
+            .lines()
+            .map(|line| {
+                if line.starts_with("libdir=") {
+                    format!("libdir='{}'", libdir.display())
+                } else if let Some(value) = field_value(line, "dependency_libs") {
+                    let entries: Vec<String> = value
+                        .split_whitespace()
+                        .filter_map(|entry| relocate_dependency(entry, &archives))
+                        .collect();
+                    format!("dependency_libs=' {}'", entries.join(" "))
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n";
+        if new_contents != contents {
+            debug!("Rewrote paths in {}", archive.display());
+            fs::write(archive, new_contents)?;
+            rewritten += 1;
+        }
+    }
+    if rewritten > 0 {
+        info!("==> Rewrote paths in {} libtool archive(s)", rewritten);
+    }
+    Ok(rewritten)
+}
+
+/// One `dependency_libs` entry as it should read after install, or `None` to drop it.
+fn relocate_dependency(entry: &str, archives: &[PathBuf]) -> Option<String> {
+    let path = entry.strip_prefix("-L").unwrap_or(entry);
+    if !path.starts_with('/') || Path::new(path).exists() {
+        return Some(entry.to_string());
+    }
+    if entry.starts_with("-L") {
+        return None;
+    }
+    let name = Path::new(path).file_name()?;
+    archives
+        .iter()
+        .find(|archive| archive.file_name() == Some(name))
+        .map(|archive| archive.display().to_string())
+}
+
+/// File names of the archives in `candidates` that one of `archives` names as a dependency.
+fn referenced_archive_names(archives: &[PathBuf], candidates: &[PathBuf]) -> HashSet<String> {
+    let candidate_names: HashSet<String> = candidates
+        .iter()
+        .filter_map(|c| c.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .collect();
+    let mut referenced = HashSet::new();
+    for archive in archives {
+        let Ok(contents) = fs::read_to_string(archive) else {
+            continue;
+        };
+        for value in contents
+            .lines()
+            .filter_map(|line| field_value(line, "dependency_libs"))
+        {
+            for entry in value.split_whitespace().filter(|e| e.ends_with(".la")) {
+                let name = Path::new(entry)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned());
+                if let Some(name) = name {
+                    if candidate_names.contains(&name) {
+                        referenced.insert(name);
+                    }
+                }
+            }
+        }
+    }
+    referenced
+}
+
+/// The value of `key='value'` if `line` is that assignment.
+fn field_value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let value = line.strip_prefix(key)?.strip_prefix('=')?;
+    Some(value.trim_matches('\''))
+}
+
+/// The libtool archives under `dir`, down to `max_depth`, recognised by extension and the header
+/// libtool writes.
+fn la_files(dir: &Path, max_depth: usize) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .max_depth(max_depth)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        // Linked archives in the prefix are symlinks into their kegs
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "la"))
+        .map(|e| e.into_path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            fs::read_to_string(path).is_ok_and(|c| {
+                c.lines()
+                    .take(3)
+                    .any(|l| l.contains("libtool library file"))
+            })
+        })
+        .collect()
+}
@@ -1,129 +1,257 @@
 // sapphire-core/src/build/formula/source/make.rs
 
+use std::collections::HashSet;
 use std::fs;
 use std::io::Read; // <--- Add Read trait for reading file content
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
 
 use tracing::{debug, error, info, warn};
 
+use super::docs::{build_docs, DocTool};
 use crate::build::env::BuildEnvironment;
+use crate::build::formula::relocate::{relocate_keg, Relocation, Replacements};
+use crate::build::process::run_streaming_tail_with_timeout;
+use crate::build::warnings::{BuildPhase, WarningCode};
 use crate::utils::error::{Result, SapphireError};
 
-/// Checks if a configure script appears to be generated by GNU Autotools.
-fn is_gnu_autotools_configure(script_path: &Path) -> bool {
-    const READ_BUFFER_SIZE: usize = 4096; // Read first 4KB
-    const AUTOCONF_MARKERS: &[&str] = &[
-        "Generated by GNU Autoconf", // Common marker
-        "generated by autoconf",     // Another possible marker
-        "config.status:",            // Often present in generated scripts
-    ];
-
-    let mut buffer = String::with_capacity(READ_BUFFER_SIZE);
-    match fs::File::open(script_path)
-        .and_then(|mut file| file.read_to_string(&mut buffer)) // Read directly into String buffer
+/// Staging directory for `make install DESTDIR=...`, inside the build directory.
+const DESTDIR_STAGING: &str = ".sapphire-destdir";
+
+/// Lines of make/configure output kept for the error report. Everything is logged as it arrives.
+const OUTPUT_TAIL_LINES: usize = 100;
+
+/// Runs one step of the build, keeping the tail of its output and killing it if it outlives the
+/// build environment's phase timeout. In a dry run the step is only printed.
+fn run_step(cmd: &mut Command, context: &str, build_env: &BuildEnvironment) -> Result<Output> {
+    build_env.run_or_print(cmd, context, |cmd| {
+        run_streaming_tail_with_timeout(cmd, context, OUTPUT_TAIL_LINES, build_env.phase_timeout())
+    })
+}
+
+/// What generated a `./configure` script, as far as it matters for the flags it accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigureKind {
+    /// GNU Autoconf: takes `--disable-dependency-tracking` and friends.
+    Autotools,
+    /// A known non-Autoconf generator or wrapper (CMake, autosetup, qmake, ...), which may
+    /// reject Autoconf-only flags.
+    Foreign(&'static str),
+    /// Hand-written or unrecognised.
+    Unknown,
+}
+
+/// How much of a configure script is scanned. Autoconf banners sit near the top, but some
+/// projects prepend long license headers, and `config.status` handling comes much later.
+const CONFIGURE_SCAN_BYTES: u64 = 256 * 1024;
+
+/// Markers only Autoconf output carries.
+const AUTOCONF_MARKERS: &[&str] = &[
+    "Generated by GNU Autoconf",
+    "generated by autoconf",
+    "ac_cs_version",
+    "# Guess values for system-dependent variables",
+    // The block that writes config.status
+    "CONFIG_STATUS=./config.status",
+];
+
+/// Markers of other generators, checked when no Autoconf marker matched, with a name for logs.
+const FOREIGN_CONFIGURE_MARKERS: &[(&str, &str)] = &[
+    ("autosetup", "autosetup"),
+    ("CMAKE_INSTALL_PREFIX", "CMake wrapper"),
+    ("qmake", "qmake wrapper"),
+    ("meson setup", "Meson wrapper"),
+];
+
+/// Weaker hints of Autoconf, used when nothing more specific matched.
+const WEAK_AUTOCONF_MARKERS: &[&str] = &["config.status:"];
+
+/// Classifies the configure script at `script_path` by scanning its first
+/// [`CONFIGURE_SCAN_BYTES`]. An unreadable script counts as [`ConfigureKind::Unknown`].
+fn classify_configure_script(script_path: &Path) -> ConfigureKind {
+    let mut content = Vec::new();
+    if let Err(e) = fs::File::open(script_path)
+        .and_then(|file| file.take(CONFIGURE_SCAN_BYTES).read_to_end(&mut content))
     {
-        Ok(_) => {
-            // Check if any marker is present in the read portion
-            for marker in AUTOCONF_MARKERS {
-                if buffer.contains(marker) {
-                    debug!(
-                        "Detected Autotools marker ('{}') in configure script: {}",
-                        marker,
-                        script_path.display()
-                    );
-                    return true; // Found a marker
-                }
-            }
-            debug!(
-                "No specific Autotools markers found in first {} bytes of configure script: {}",
-                READ_BUFFER_SIZE,
-                script_path.display()
-            );
-            false // No markers found
-        }
-        Err(e) => {
-            warn!(
-                "Could not read configure script {} to check for Autotools markers: {}. Assuming not Autotools.",
-                script_path.display(), e
-            );
-            false // Failed to read, assume not Autotools
-        }
+        warn!(
+            "Could not read configure script {} to check for Autotools markers: {}. Assuming not Autotools.",
+            script_path.display(), e
+        );
+        return ConfigureKind::Unknown;
+    }
+    let kind = classify_configure_content(&String::from_utf8_lossy(&content));
+    debug!(
+        "Configure script {} classified as {:?}",
+        script_path.display(),
+        kind
+    );
+    kind
+}
+
+fn classify_configure_content(content: &str) -> ConfigureKind {
+    if AUTOCONF_MARKERS.iter().any(|m| content.contains(m)) {
+        return ConfigureKind::Autotools;
     }
+    if let Some((_, name)) = FOREIGN_CONFIGURE_MARKERS
+        .iter()
+        .find(|(marker, _)| content.contains(marker))
+    {
+        return ConfigureKind::Foreign(name);
+    }
+    if WEAK_AUTOCONF_MARKERS.iter().any(|m| content.contains(m)) {
+        return ConfigureKind::Autotools;
+    }
+    ConfigureKind::Unknown
+}
+
+/// Runs the project's own `./autogen.sh` bootstrap script to generate `./configure`.
+fn run_autogen(build_env: &BuildEnvironment) -> Result<()> {
+    info!("==> Running ./autogen.sh (no configure script)");
+    // Through sh, as the script isn't always executable in release tarballs
+    let mut cmd = Command::new("sh");
+    cmd.arg("./autogen.sh");
+    build_env.apply_to_command(&mut cmd);
+    // Many autogen.sh scripts go on to run configure themselves unless told not to
+    cmd.env("NOCONFIGURE", "1");
+    let output = run_step(&mut cmd, "autogen.sh", build_env)?;
+    if !output.status.success() {
+        return Err(phase_failed(
+            BuildPhase::AutogenBootstrap,
+            "autogen.sh",
+            &output,
+            &[],
+        ));
+    }
+    Ok(())
+}
+
+/// Generates `./configure` with the project's `autogen.sh`, or else with autoreconf where the
+/// tree has `configure.ac`/`configure.in`.
+fn generate_configure(build_env: &BuildEnvironment) -> Result<()> {
+    let build_dir = build_env.build_dir();
+    if build_dir.join("autogen.sh").is_file() {
+        run_autogen(build_env)?;
+    }
+    if !build_dir.join("configure").exists()
+        && (build_dir.join("configure.ac").exists() || build_dir.join("configure.in").exists())
+    {
+        bootstrap_autotools(build_env)?;
+    }
+    Ok(())
+}
+
+/// Generates `./configure` from `configure.ac`/`configure.in` with `autoreconf -fiv`.
+fn bootstrap_autotools(build_env: &BuildEnvironment) -> Result<()> {
+    let autoreconf = which::which_in("autoreconf", build_env.get_path_string(), Path::new("."))
+        .map_err(|_| {
+            SapphireError::BuildEnvError(
+                "autoreconf not found in build environment PATH; it is needed to generate \
+                 ./configure from configure.ac (add autoconf, automake and libtool as build \
+                 dependencies)"
+                    .to_string(),
+            )
+        })?;
+    info!("==> Running autoreconf -fiv (no configure script)");
+    let mut cmd = Command::new(autoreconf);
+    cmd.arg("-fiv");
+    build_env.apply_to_command(&mut cmd);
+    let output = run_step(&mut cmd, "autoreconf", build_env)?;
+    if !output.status.success() {
+        return Err(phase_failed(
+            BuildPhase::AutogenBootstrap,
+            "autoreconf",
+            &output,
+            &[],
+        ));
+    }
+    Ok(())
 }
 
 /// Configure and build with potentially Autotools script (./configure && make && make install)
 pub fn configure_and_make(install_dir: &Path, build_env: &BuildEnvironment) -> Result<()> {
-    let configure_script_path = Path::new("./configure"); // Assuming CWD is build_dir
+    let build_dir = build_env.build_dir();
+    let configure_script_path = &build_dir.join("configure");
 
-    // Check if configure script exists before trying to detect/run
+    // Git checkouts commonly ship only configure.ac and expect to be bootstrapped
     if !configure_script_path.exists() {
-        tracing::error!("./configure script not found in current directory.");
-        // This case should ideally be caught by detect_and_build, but check defensively.
+        if let Err(e) = generate_configure(build_env) {
+            let has_makefile = ["Makefile", "makefile"]
+                .iter()
+                .any(|name| build_dir.join(name).is_file());
+            if !has_makefile {
+                return Err(e);
+            }
+            build_env.warnings().warn(
+                WarningCode::ToolFailure,
+                BuildPhase::Configure,
+                format!(
+                    "Could not generate ./configure ({}); building with the existing Makefile",
+                    e
+                ),
+                None,
+            );
+            return simple_make(install_dir, build_env);
+        }
+    }
+    // A dry run only printed the bootstrap, so there is no script yet
+    if !configure_script_path.exists() && !build_env.dry_run() {
+        tracing::error!("./configure script not found in {}.", build_dir.display());
         return Err(SapphireError::BuildEnvError(
             "configure script not found, cannot run Autotools build.".to_string(),
         ));
     }
 
-    // *** Detect if it's likely an Autotools script ***
-    let is_autotools = is_gnu_autotools_configure(configure_script_path);
+    // Only Autoconf scripts are known to accept the standard Autotools flags
+    let configure_kind = classify_configure_script(configure_script_path);
+    let is_autotools = configure_kind == ConfigureKind::Autotools;
 
-    info!("==> Running ./configure --prefix={}", install_dir.display());
-    if is_autotools {
-        info!("    (Detected Autotools, adding standard flags)");
-    } else {
-        info!("    (Did not detect standard Autotools markers, running configure without Autotools flags)");
+    match configure_kind {
+        ConfigureKind::Autotools => info!("    (Detected Autotools, adding standard flags)"),
+        ConfigureKind::Foreign(generator) => info!(
+            "    (Configure script comes from {}, running it without Autotools flags)",
+            generator
+        ),
+        ConfigureKind::Unknown => info!("    (Did not detect standard Autotools markers, running configure without Autotools flags)"),
     }
 
-    let mut cmd = Command::new(configure_script_path); // Use the PathBuf
-    cmd.arg(format!("--prefix={}", install_dir.display()));
-
-    // *** Conditionally add Autotools flags ***
+    let mut args = vec![format!("--prefix={}", install_dir.display())];
+    if is_autotools {
+        if build_env.disable_dependency_tracking() {
+            args.push("--disable-dependency-tracking".to_string());
+        }
+        args.push("--disable-silent-rules".to_string());
+    }
+    // CC/CXX and flags come from the environment; only the target needs an argument
+    if let Some(toolchain) = build_env.toolchain() {
+        args.extend(toolchain.configure_args());
+    }
     if is_autotools {
-        cmd.args(["--disable-dependency-tracking", "--disable-silent-rules"]);
+        args.extend(build_env.target_configure_args());
     }
+    // Last, so the formula can override any of the above
+    args.extend(build_env.extra_configure_args().iter().cloned());
 
-    build_env.apply_to_command(&mut cmd);
-    let output = cmd.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute configure: {}", e))
-    })?;
+    info!("==> Running ./configure {}", args.join(" "));
+    let mut cmd = Command::new(configure_script_path);
+    cmd.args(&args);
+
+    build_env.apply_to_configure_command(&mut cmd);
+    // Configure probes can't cope with a universal build's multiple -arch flags
+    build_env.apply_host_arch(&mut cmd);
+    let output = run_step(&mut cmd, "configure", build_env)?;
 
     if !output.status.success() {
-        println!("Configure failed with status: {}", output.status);
-        eprintln!(
-            "Configure stdout:\n{}",
-            String::from_utf8_lossy(&output.stdout)
-        );
-        eprintln!(
-            "Configure stderr:\n{}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        let config_log_path = std::path::PathBuf::from("config.log");
-        if config_log_path.exists() {
-            eprintln!("--- Last 50 lines of config.log ---");
-            if let Ok(content) = fs::read_to_string(&config_log_path) {
-                let lines: Vec<&str> = content.lines().rev().take(50).collect();
-                for line in lines.iter().rev() {
-                    eprintln!("{}", line);
-                }
-            }
-            eprintln!("--- End config.log ---");
-        }
-        return Err(SapphireError::Generic(format!(
-            "Configure failed with status: {}",
-            output.status
-        )));
+        return Err(phase_failed(
+            BuildPhase::Configure,
+            "Configure",
+            &output,
+            &[build_dir.join("config.log")],
+        ));
     } else {
-        debug!(
-            "Configure stdout:\n{}",
-            String::from_utf8_lossy(&output.stdout)
-        );
-        debug!(
-            "Configure stderr:\n{}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+        debug!("Configure completed successfully.");
     }
+    build_env.checkpoint(BuildPhase::Configure)?;
 
     // --- make && make install steps remain the same ---
     info!("==> Running make");
@@ -135,58 +263,325 @@ pub fn configure_and_make(install_dir: &Path, build_env: &BuildEnvironment) -> R
             )
         })?;
     let mut cmd_make = Command::new(make_exe.clone());
+    cmd_make.args(build_env.make_parallel_args(&make_exe));
+    cmd_make.args(build_env.universal_make_args());
     build_env.apply_to_command(&mut cmd_make);
-    let output_make = cmd_make
-        .output()
-        .map_err(|e| SapphireError::CommandExecError(format!("Failed to execute make: {}", e)))?;
+    let output_make = run_step(&mut cmd_make, "make", build_env)?;
 
     if !output_make.status.success() {
-        println!("Make failed with status: {}", output_make.status);
-        eprintln!(
-            "Make stdout:\n{}",
-            String::from_utf8_lossy(&output_make.stdout)
-        );
-        eprintln!(
-            "Make stderr:\n{}",
-            String::from_utf8_lossy(&output_make.stderr)
-        );
-        return Err(SapphireError::Generic(format!(
-            "Make failed with status: {}",
-            output_make.status
-        )));
+        return Err(phase_failed(BuildPhase::Build, "Make", &output_make, &[]));
     } else {
         debug!("Make completed successfully.");
     }
+    build_env.checkpoint(BuildPhase::Build)?;
+    if build_env.run_checks() {
+        run_make_check(build_env)?;
+    }
 
-    info!("==> Running make install");
-    let mut cmd_install = Command::new(make_exe);
+    // What is in the keg already (resources, for one), to tell it from what make install adds
+    let preinstalled = if build_env.staged_install() {
+        keg_files(install_dir)
+    } else {
+        HashSet::new()
+    };
+    let staging_dir = if build_env.staged_install() {
+        let staging_dir = build_dir.join(DESTDIR_STAGING);
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+        info!("==> Running make install DESTDIR={}", staging_dir.display());
+        Some(staging_dir)
+    } else {
+        info!("==> Running make install");
+        None
+    };
+    let mut cmd_install = Command::new(&make_exe);
     cmd_install.arg("install");
+    cmd_install.args(build_env.make_parallel_args(&make_exe));
+    cmd_install.args(build_env.universal_make_args());
+    if let Some(staging_dir) = &staging_dir {
+        cmd_install.arg(format!("DESTDIR={}", staging_dir.display()));
+    }
     build_env.apply_to_command(&mut cmd_install);
-    let output_install = cmd_install.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute make install: {}", e))
-    })?;
+    let output_install = run_step(&mut cmd_install, "make install", build_env)?;
 
     if !output_install.status.success() {
-        println!("Make install failed with status: {}", output_install.status);
-        eprintln!(
-            "Make install stdout:\n{}",
-            String::from_utf8_lossy(&output_install.stdout)
-        );
-        eprintln!(
-            "Make install stderr:\n{}",
-            String::from_utf8_lossy(&output_install.stderr)
-        );
-        return Err(SapphireError::Generic(format!(
-            "Make install failed with status: {}",
-            output_install.status
-        )));
+        return Err(phase_failed(
+            BuildPhase::Install,
+            "Make install",
+            &output_install,
+            &[],
+        ));
     } else {
         debug!("Make install completed successfully.");
     }
+    if build_env.dry_run() {
+        return Ok(());
+    }
+    if let Some(staging_dir) = &staging_dir {
+        install_staged(staging_dir, install_dir, &preinstalled, build_env)?;
+    }
 
+    build_docs(
+        DocTool::Make(&make_exe),
+        build_env.build_dir(),
+        build_env.build_dir(),
+        install_dir,
+        build_env,
+    )
+}
+
+/// Moves what `make install DESTDIR=<staging_dir>` put under `<staging_dir><install_dir>` into
+/// `install_dir`, merging with `preinstalled`, what was in the keg before (installed resources,
+/// for one). Files installed outside the prefix are dropped with a warning instead of ending up
+/// on the system, and references to the staging path in what was installed, symlinks included,
+/// are relocated to `install_dir`.
+fn install_staged(
+    staging_dir: &Path,
+    install_dir: &Path,
+    preinstalled: &HashSet<PathBuf>,
+    build_env: &BuildEnvironment,
+) -> Result<()> {
+    let staged_keg = staging_dir.join(install_dir.strip_prefix("/").unwrap_or(install_dir));
+    if !staged_keg.is_dir() {
+        if keg_files(install_dir)
+            .difference(preinstalled)
+            .next()
+            .is_some()
+        {
+            // The Makefile ignored DESTDIR and installed into the prefix directly
+            warn!("make install ignored DESTDIR; keeping its direct install");
+            return Ok(());
+        }
+        return Err(SapphireError::InstallError(format!(
+            "make install DESTDIR={} installed nothing under {}",
+            staging_dir.display(),
+            install_dir.display()
+        )));
+    }
+
+    let stray: Vec<PathBuf> = walkdir::WalkDir::new(staging_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir() && !e.path().starts_with(&staged_keg))
+        .map(|e| e.into_path())
+        .collect();
+    for path in &stray {
+        build_env.warnings().warn(
+            WarningCode::RelocationHazard,
+            BuildPhase::Install,
+            format!(
+                "make install wrote {} outside the prefix; dropped",
+                Path::new("/")
+                    .join(path.strip_prefix(staging_dir).unwrap_or(path))
+                    .display()
+            ),
+            None,
+        );
+    }
+
+    debug!(
+        "Moving staged install {} into {}",
+        staged_keg.display(),
+        install_dir.display()
+    );
+    merge_tree(&staged_keg, install_dir, staging_dir)?;
+    fs::remove_dir_all(staging_dir)?;
+    // Some build systems bake the staging path into what they install, e.g. libtool relinking
+    let mut replacements = Replacements::new();
+    replacements.insert(staged_keg.to_string_lossy(), install_dir.to_string_lossy());
+    relocate_keg(
+        install_dir,
+        &Relocation::new(replacements),
+        build_env.warnings(),
+    )?;
+    Ok(())
+}
+
+/// The paths of the files and symlinks under `dir`, relative to it. Empty if it doesn't exist.
+fn keg_files(dir: &Path) -> HashSet<PathBuf> {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir())
+        .filter_map(|e| e.path().strip_prefix(dir).ok().map(Path::to_path_buf))
+        .collect()
+}
+
+/// Moves the contents of `from` into `to`. Files are renamed where possible and copied when
+/// `to` is on another filesystem. What is moved wins over what is in `to`, even where one is a
+/// directory and the other not. Symlinks pointing into `staging_dir` are pointed at the same
+/// path outside it; others are kept as they are.
+fn merge_tree(from: &Path, to: &Path, staging_dir: &Path) -> Result<()> {
+    if to.symlink_metadata().is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(to)?;
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let src = entry.path();
+        let dest = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            merge_tree(&src, &dest, staging_dir)?;
+            continue;
+        }
+        match dest.symlink_metadata() {
+            Ok(meta) if meta.is_dir() => fs::remove_dir_all(&dest)?,
+            Ok(_) => fs::remove_file(&dest)?,
+            Err(_) => {}
+        }
+        if file_type.is_symlink() {
+            let target = fs::read_link(&src)?;
+            if let Ok(rest) = target.strip_prefix(staging_dir) {
+                std::os::unix::fs::symlink(Path::new("/").join(rest), &dest)?;
+                continue;
+            }
+        }
+        if fs::rename(&src, &dest).is_ok() {
+            continue;
+        }
+        if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&src)?, &dest)?;
+        } else {
+            fs::copy(&src, &dest)?;
+        }
+    }
     Ok(())
 }
 
+/// Runs the project's test suite from the build directory: `make check`, or `make test` for
+/// Makefiles without a `check` target. Makefiles with neither are skipped with a warning. On
+/// failure the output tail and the tail of any Automake `test-suite.log` are printed.
+pub fn run_make_check(build_env: &BuildEnvironment) -> Result<()> {
+    let make_exe = which::which_in("make", build_env.get_path_string(), Path::new("."))
+        .or_else(|_| which::which("make"))
+        .map_err(|_| {
+            SapphireError::BuildEnvError(
+                "make command not found in build environment PATH or system PATH.".to_string(),
+            )
+        })?;
+    let build_dir = build_env.build_dir();
+    let Some(target) = ["check", "test"]
+        .into_iter()
+        .find(|t| makefile_has_target(build_dir, t))
+    else {
+        warn!("Makefile has neither a check nor a test target; not running tests");
+        return Ok(());
+    };
+
+    info!("==> Running make {}", target);
+    let mut cmd = Command::new(&make_exe);
+    cmd.arg(target)
+        .args(build_env.make_parallel_args(&make_exe));
+    cmd.args(build_env.universal_make_args());
+    build_env.apply_to_command(&mut cmd);
+    let context = format!("make {}", target);
+    let output = run_step(&mut cmd, &context, build_env)?;
+    if output.status.success() {
+        debug!("Test suite passed.");
+        return Ok(());
+    }
+
+    // Automake's harness keeps the details of failed tests out of make's output
+    let suite_logs: Vec<PathBuf> = walkdir::WalkDir::new(build_env.build_dir())
+        .max_depth(3)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name() == "test-suite.log")
+        .map(|e| e.into_path())
+        .collect();
+    Err(phase_failed(
+        BuildPhase::Test,
+        &format!("Make {}", target),
+        &output,
+        &suite_logs,
+    ))
+}
+
+/// Whether the Makefile make reads in `dir` has a rule for `target`. Only that file is read,
+/// not what it includes. Asking make, even with `-n` or `-q`, would still run the recipe lines
+/// that call `$(MAKE)`, which recurse into the whole tree.
+fn makefile_has_target(dir: &Path, target: &str) -> bool {
+    let Some(makefile) = ["GNUmakefile", "makefile", "Makefile"]
+        .iter()
+        .find_map(|name| fs::read_to_string(dir.join(name)).ok())
+    else {
+        return false;
+    };
+    makefile.lines().any(|line| {
+        // Recipe lines are indented; `:=`, `::=` and `=` before any `:` are assignments
+        if line.starts_with(['\t', ' ', '#']) {
+            return false;
+        }
+        let Some((targets, rest)) = line.split_once(':') else {
+            return false;
+        };
+        !targets.contains('=')
+            && !rest.starts_with('=')
+            && !rest.starts_with(":=")
+            && targets.split_whitespace().any(|t| t == target)
+    })
+}
+
+/// Logs a failed step with its output and returns its [`phase_error`].
+fn phase_failed(phase: BuildPhase, step: &str, output: &Output, logs: &[PathBuf]) -> SapphireError {
+    error!("{} failed with status: {}", step, output.status);
+    let error = phase_error(phase, step, output, logs);
+    if let SapphireError::BuildPhaseFailed { log_tail, .. } = &error {
+        error!("{}", log_tail);
+    }
+    error
+}
+
+/// The [`SapphireError::BuildPhaseFailed`] for a failed step, carrying the tail of the step's
+/// output and of `logs`, the step's own log files.
+pub(super) fn phase_error(
+    phase: BuildPhase,
+    step: &str,
+    output: &Output,
+    logs: &[PathBuf],
+) -> SapphireError {
+    let mut log_tail = String::new();
+    for (stream, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        if bytes.is_empty() {
+            continue;
+        }
+        log_tail.push_str(&format!(
+            "--- Last {} lines of {} {} ---\n",
+            OUTPUT_TAIL_LINES, step, stream
+        ));
+        log_tail.push_str(&String::from_utf8_lossy(bytes));
+        log_tail.push_str(&format!("--- End {} {} ---\n", step, stream));
+    }
+    for tail in logs.iter().filter_map(|log| file_tail(log)) {
+        log_tail.push_str(&tail);
+    }
+    SapphireError::BuildPhaseFailed {
+        phase,
+        exit_code: output.status.code(),
+        log_tail,
+    }
+}
+
+/// The last lines of a build log such as `config.log`, if it exists, between markers.
+fn file_tail(path: &Path) -> Option<String> {
+    const LOG_TAIL_LINES: usize = 50;
+    let content = fs::read_to_string(path).ok()?;
+    let lines: Vec<&str> = content.lines().rev().take(LOG_TAIL_LINES).collect();
+    let mut tail = format!(
+        "--- Last {} lines of {} ---\n",
+        LOG_TAIL_LINES,
+        path.display()
+    );
+    for line in lines.iter().rev() {
+        tail.push_str(line);
+        tail.push('\n');
+    }
+    tail.push_str(&format!("--- End {} ---\n", path.display()));
+    Some(tail)
+}
+
 pub fn simple_make(
     install_dir: &Path, // e.g., /opt/homebrew/Cellar/doggo/1.0.5
     build_env: &BuildEnvironment,
@@ -199,53 +594,35 @@ pub fn simple_make(
                 "make command not found in build environment PATH or system PATH.".to_string(),
             )
         })?;
+    // A plain Makefile has no configure step
+    build_env.checkpoint(BuildPhase::Configure)?;
 
     info!("==> Running make");
     let mut cmd_make = Command::new(make_exe.clone());
+    cmd_make.args(build_env.make_parallel_args(&make_exe));
     build_env.apply_to_command(&mut cmd_make);
-    // Assuming CWD is the build directory (e.g., ./doggo-1.0.5/)
     // Let's capture the output for potential debugging if needed
-    let output_make = cmd_make.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute make (simple): {}", e))
-    })?;
+    let output_make = run_step(&mut cmd_make, "make (simple)", build_env)?;
 
     if !output_make.status.success() {
-        println!("Make failed with status: {}", output_make.status);
-        eprintln!(
-            "Make stdout:\n{}",
-            String::from_utf8_lossy(&output_make.stdout)
-        );
-        eprintln!(
-            "Make stderr:\n{}",
-            String::from_utf8_lossy(&output_make.stderr)
-        );
-        return Err(SapphireError::Generic(format!(
-            "Make failed with status: {}",
-            output_make.status
-        )));
+        return Err(phase_failed(BuildPhase::Build, "Make", &output_make, &[]));
     } else {
         info!("Make completed successfully.");
-        // Optionally log build output if verbose enough
-        debug!(
-            "Make stdout:\n{}",
-            String::from_utf8_lossy(&output_make.stdout)
-        );
-        debug!(
-            "Make stderr:\n{}",
-            String::from_utf8_lossy(&output_make.stderr)
-        );
+    }
+    build_env.checkpoint(BuildPhase::Build)?;
+    if build_env.run_checks() {
+        run_make_check(build_env)?;
     }
 
     // --- Attempt make install ---
     info!("==> Running make install PREFIX={}", install_dir.display());
-    let mut cmd_install = Command::new(make_exe);
+    let mut cmd_install = Command::new(&make_exe);
     cmd_install.arg("install");
     // Pass PREFIX, but be prepared for it to be ignored or incomplete
     cmd_install.arg(format!("PREFIX={}", install_dir.display()));
+    cmd_install.args(build_env.make_parallel_args(&make_exe));
     build_env.apply_to_command(&mut cmd_install);
-    let output_install = cmd_install.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute make install (simple): {}", e))
-    })?;
+    let output_install = run_step(&mut cmd_install, "make install (simple)", build_env)?;
 
     let make_install_succeeded = output_install.status.success();
 
@@ -255,61 +632,82 @@ pub fn simple_make(
             "'make install' failed with status {}. Will check for manually installable artifacts.",
             output_install.status
         );
-        debug!(
-            "Make install stdout:\n{}",
-            String::from_utf8_lossy(&output_install.stdout)
-        );
-        debug!(
-            "Make install stderr:\n{}",
-            String::from_utf8_lossy(&output_install.stderr)
-        );
     } else {
         info!("Make install completed successfully (exit code 0).");
-        debug!(
-            "Make install stdout:\n{}",
-            String::from_utf8_lossy(&output_install.stdout)
-        );
-        debug!(
-            "Make install stderr:\n{}",
-            String::from_utf8_lossy(&output_install.stderr)
-        );
     }
 
+    if build_env.dry_run() {
+        return Ok(());
+    }
+    let install_error = (!make_install_succeeded)
+        .then(|| phase_error(BuildPhase::Install, "make install", &output_install, &[]));
+    install_artifacts_fallback(install_dir, "make install", install_error, build_env)?;
+    build_docs(
+        DocTool::Make(&make_exe),
+        build_env.build_dir(),
+        build_env.build_dir(),
+        install_dir,
+        build_env,
+    )
+}
+
+/// Verifies that an install step populated `install_dir/bin`, and if not, looks for a binary
+/// named after the formula in the build directory and copies it there.
+///
+/// `install_error` is why the install step failed, or `None` if it succeeded. It is only
+/// returned when nothing could be installed manually either.
+pub(super) fn install_artifacts_fallback(
+    install_dir: &Path,
+    install_step: &str,
+    install_error: Option<SapphireError>,
+    build_env: &BuildEnvironment,
+) -> Result<()> {
     // --- Verification and Manual Installation Fallback ---
     let bin_dir = install_dir.join("bin");
     let bin_populated = bin_dir.is_dir() && bin_dir.read_dir()?.next().is_some();
 
     if !bin_populated {
-        warn!(
-            "Installation directory '{}' is empty or missing after 'make install'. Attempting manual artifact installation.",
-            bin_dir.display()
+        build_env.warnings().warn(
+            WarningCode::ManualInstallFallback,
+            BuildPhase::Install,
+            format!(
+                "Installation directory is empty or missing after '{}'. Attempting manual artifact installation.",
+                install_step
+            ),
+            Some(&bin_dir),
         );
 
-        // Try to find the executable in the CWD (build dir, e.g., ./doggo-1.0.5/)
+        // Try to find the executable in the build dir (e.g., ./doggo-1.0.5/)
         // Heuristic: look for a file named like the install dir's base name (e.g., "doggo")
-        let formula_name = install_dir
-            .parent() // Get .../Cellar/doggo
-            .and_then(|p| p.file_name()) // Get "doggo"
-            .and_then(|n| n.to_str())
-            .unwrap_or(""); // Fallback to empty string if path parsing fails
-
-        let potential_binary_path = Path::new(".").join(formula_name); // Assumes CWD is build root
-        let mut found_and_installed_manually = false;
+        let formula_name = build_env.formula_name();
 
-        if !formula_name.is_empty() && potential_binary_path.is_file() {
+        let potential_binary_path = build_env.build_dir().join(formula_name);
+        let candidate = if !formula_name.is_empty() && potential_binary_path.is_file() {
             info!(
                 "Found potential binary '{}' in build directory. Manually installing...",
                 potential_binary_path.display()
             );
+            Some(potential_binary_path)
+        } else {
+            info!(
+                "No executable named '{}' in build directory; looking for other executables",
+                formula_name
+            );
+            find_executable_candidate(build_env.build_dir(), formula_name)
+        };
+
+        let mut found_and_installed_manually = false;
+        if let Some(binary_path) = candidate {
             fs::create_dir_all(&bin_dir)?; // Ensure install_dir/bin exists
 
-            let target_path = bin_dir.join(formula_name);
-            fs::copy(&potential_binary_path, &target_path).map_err(|e| {
+            let binary_name = binary_path.file_name().unwrap_or_default();
+            let target_path = bin_dir.join(binary_name);
+            fs::copy(&binary_path, &target_path).map_err(|e| {
                 SapphireError::Io(std::io::Error::new(
                     e.kind(),
                     format!(
                         "Failed to copy binary {} to {}: {}",
-                        potential_binary_path.display(),
+                        binary_path.display(),
                         target_path.display(),
                         e
                     ),
@@ -327,36 +725,337 @@ pub fn simple_make(
 
             found_and_installed_manually = true;
         } else {
-            // Optional: Could add more heuristics here, like searching for any executable file
-            // in the CWD if the named one isn't found.
             warn!(
-                "Could not find executable named '{}' in build directory for manual installation.",
+                "Could not find an executable in the build directory for manual installation of '{}'.",
                 formula_name
             );
         }
 
-        // If make install failed AND we couldn't manually install anything, then it's a real error
-        if !make_install_succeeded && !found_and_installed_manually {
+        // If the install step failed AND we couldn't manually install anything, then it's a real
+        // error
+        if let Some(install_error) = install_error.filter(|_| !found_and_installed_manually) {
             error!(
-                "make install failed and could not find/install artifacts manually from build directory."
+                "{} failed and could not find/install artifacts manually from build directory.",
+                install_step
             );
-            // Return the original make install error context if available
-            return Err(SapphireError::Generic(format!(
-                "Make install failed with status: {} and no artifacts found/installed manually",
-                output_install.status
-            )));
+            if let SapphireError::BuildPhaseFailed { log_tail, .. } = &install_error {
+                error!("{}", log_tail);
+            }
+            return Err(install_error);
         } else if !found_and_installed_manually {
-            // make install succeeded but didn't populate bin, and we found nothing manually.
+            // The install step succeeded but didn't populate bin, and we found nothing manually.
             // This is suspicious, but maybe the formula only installs libraries or other things.
             // Proceed, but maybe log a higher warning?
-            warn!("make install reported success, but '{}' was not populated and no executable found manually.", bin_dir.display());
+            warn!(
+                "{} reported success, but '{}' was not populated and no executable found manually.",
+                install_step,
+                bin_dir.display()
+            );
         }
     } else {
         info!(
-            "Installation directory '{}' appears populated after 'make install'.",
-            bin_dir.display()
+            "Installation directory '{}' appears populated after '{}'.",
+            bin_dir.display(),
+            install_step
         );
     }
 
     Ok(())
 }
+
+/// Directories of the build tree where the fallback looks for built executables.
+const EXECUTABLE_SEARCH_DIRS: [&str; 4] = [".", "src", "bin", "build"];
+
+/// Executable files every Autotools tree carries that are never the build's product.
+const BUILD_HELPER_SCRIPTS: &[&str] = &[
+    "configure",
+    "config.status",
+    "config.guess",
+    "config.sub",
+    "install-sh",
+    "libtool",
+    "missing",
+    "depcomp",
+    "compile",
+    "mkinstalldirs",
+    "autogen.sh",
+    "bootstrap",
+];
+
+/// Picks the most likely built executable in the build tree at `build_dir` for a formula whose
+/// executable isn't named after it. Native binaries beat scripts, names related to `formula_name`
+/// beat unrelated ones, and the newest file wins the rest. Every candidate is logged.
+fn find_executable_candidate(build_dir: &Path, formula_name: &str) -> Option<PathBuf> {
+    let mut candidates = Vec::new();
+    for dir in EXECUTABLE_SEARCH_DIRS {
+        let Ok(entries) = fs::read_dir(build_dir.join(dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if !meta.is_file()
+                || name.starts_with('.')
+                || BUILD_HELPER_SCRIPTS.contains(&name.as_str())
+            {
+                continue;
+            }
+            let native = infer::get_from_path(&path)
+                .ok()
+                .flatten()
+                .is_some_and(|kind| {
+                    matches!(
+                        kind.mime_type(),
+                        "application/x-executable" | "application/x-mach-binary"
+                    )
+                });
+            // Shared and relocatable objects are ELF/Mach-O too, and infer calls objects
+            // executables; only files the link step marked executable are candidates
+            let library = name.contains(".so")
+                || name.ends_with(".dylib")
+                || name.ends_with(".a")
+                || name.ends_with(".o")
+                || name.ends_with(".obj");
+            if library || meta.permissions().mode() & 0o111 == 0 {
+                continue;
+            }
+            let related = !formula_name.is_empty()
+                && (name.contains(formula_name) || formula_name.contains(name.as_str()));
+            info!(
+                "Considering {} ({}{})",
+                path.display(),
+                if native {
+                    "native binary"
+                } else {
+                    "executable file"
+                },
+                if related {
+                    ", name matches formula"
+                } else {
+                    ""
+                }
+            );
+            let modified = meta.modified().ok();
+            candidates.push(((native, related, modified), path));
+        }
+    }
+    let (_, best) = candidates.into_iter().max_by(|a, b| a.0.cmp(&b.0))?;
+    info!("Installing {} as the formula's executable", best.display());
+    Some(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::build::env::test_support::test_env;
+
+    /// An environment building in `root/src` whose PATH is `root/bin`, holding only `sh` and
+    /// `tools` (name, script) pairs.
+    fn isolated_env(root: &Path, tools: &[(&str, &str)]) -> BuildEnvironment {
+        let (src, bin) = (root.join("src"), root.join("bin"));
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&bin).unwrap();
+        symlink(which::which("sh").unwrap(), bin.join("sh")).unwrap();
+        for (name, script) in tools {
+            fs::write(bin.join(name), script).unwrap();
+            fs::set_permissions(bin.join(name), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let mut env = test_env(root).in_build_dir(&src);
+        env.set_var("PATH", bin.to_string_lossy());
+        env
+    }
+
+    #[test]
+    fn missing_autoreconf_is_named() {
+        let root = tempfile::tempdir().unwrap();
+        let env = isolated_env(root.path(), &[]);
+        fs::write(
+            env.build_dir().join("configure.ac"),
+            "AC_INIT([foo], [1.0])\n",
+        )
+        .unwrap();
+
+        let err = configure_and_make(&root.path().join("keg"), &env).unwrap_err();
+        assert!(
+            matches!(&err, SapphireError::BuildEnvError(msg) if msg.contains("autoreconf")),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn autogen_runs_before_autoreconf() {
+        let root = tempfile::tempdir().unwrap();
+        let env = isolated_env(
+            root.path(),
+            &[("autoreconf", "#!/bin/sh\n: > autoreconf-ran\n")],
+        );
+        let src = env.build_dir().to_path_buf();
+        fs::write(src.join("configure.ac"), "AC_INIT([foo], [1.0])\n").unwrap();
+        fs::write(
+            src.join("autogen.sh"),
+            ": > autogen-ran\necho 'exit 1' > configure\n",
+        )
+        .unwrap();
+
+        // The generated configure fails, which is all this needs
+        assert!(configure_and_make(&root.path().join("keg"), &env).is_err());
+        assert!(src.join("autogen-ran").exists());
+        assert!(!src.join("autoreconf-ran").exists());
+    }
+
+    #[test]
+    fn bootstrap_failures_name_their_phase() {
+        let root = tempfile::tempdir().unwrap();
+        let env = isolated_env(root.path(), &[]);
+        fs::write(
+            env.build_dir().join("autogen.sh"),
+            "echo broken >&2\nexit 3\n",
+        )
+        .unwrap();
+
+        match configure_and_make(&root.path().join("keg"), &env).unwrap_err() {
+            SapphireError::BuildPhaseFailed {
+                phase,
+                exit_code,
+                log_tail,
+            } => {
+                assert_eq!(phase, BuildPhase::AutogenBootstrap);
+                assert_eq!(exit_code, Some(3));
+                assert!(log_tail.contains("broken"));
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn dry_run_leaves_the_tree_alone() {
+        let root = tempfile::tempdir().unwrap();
+        let mut env = isolated_env(
+            root.path(),
+            &[("autoreconf", "#!/bin/sh\necho 'exit 0' > configure\n")],
+        );
+        env.set_dry_run(true);
+        let src = env.build_dir().to_path_buf();
+        fs::write(src.join("configure.ac"), "AC_INIT([foo], [1.0])\n").unwrap();
+
+        configure_and_make(&root.path().join("keg"), &env).unwrap();
+        assert!(!src.join("configure").exists());
+    }
+
+    #[test]
+    fn merge_tree_replaces_collisions_and_unstages_symlinks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let keg = tmp.path().join("Cellar/foo/1.0");
+        let staging = tmp.path().join("destdir");
+        let staged = staging.join(keg.strip_prefix("/").unwrap());
+        fs::create_dir_all(staged.join("lib")).unwrap();
+        fs::write(staged.join("lib/libfoo.so.1"), "lib").unwrap();
+        symlink(staged.join("lib/libfoo.so.1"), staged.join("lib/libfoo.so")).unwrap();
+        fs::write(staged.join("share"), "file").unwrap();
+        // A resource left a file where make installs a directory, and the other way round
+        fs::create_dir_all(keg.join("share/doc")).unwrap();
+        fs::write(keg.join("lib"), "file").unwrap();
+        fs::write(keg.join("README"), "kept").unwrap();
+
+        merge_tree(&staged, &keg, &staging).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(keg.join("lib/libfoo.so.1")).unwrap(),
+            "lib"
+        );
+        assert_eq!(
+            fs::read_link(keg.join("lib/libfoo.so")).unwrap(),
+            keg.join("lib/libfoo.so.1")
+        );
+        assert_eq!(fs::read_to_string(keg.join("share")).unwrap(), "file");
+        assert_eq!(fs::read_to_string(keg.join("README")).unwrap(), "kept");
+    }
+
+    #[test]
+    fn makefile_targets_are_read_from_rules_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(
+            tmp.path().join("Makefile"),
+            "TESTS := check\nall install: foo\ncheck-am test:: all\n\t$(MAKE) check\n",
+        )
+        .unwrap();
+
+        assert!(makefile_has_target(tmp.path(), "test"));
+        assert!(makefile_has_target(tmp.path(), "install"));
+        assert!(!makefile_has_target(tmp.path(), "check"));
+    }
+
+    #[test]
+    fn configure_scripts_are_classified_by_generator() {
+        let autoconf = "#! /bin/sh\n# Guess values for system-dependent variables and create Makefiles.\n# Generated by GNU Autoconf 2.71 for zlib 1.3.\n";
+        assert_eq!(
+            classify_configure_content(autoconf),
+            ConfigureKind::Autotools
+        );
+        let no_banner = "#!/bin/sh\nac_cs_version=\"\\\nfoo config.status 1.0\"\n";
+        assert_eq!(
+            classify_configure_content(no_banner),
+            ConfigureKind::Autotools
+        );
+        let status_block =
+            "#!/bin/sh\n: \"${CONFIG_STATUS=./config.status}\"\nCONFIG_STATUS=./config.status\n";
+        assert_eq!(
+            classify_configure_content(status_block),
+            ConfigureKind::Autotools
+        );
+
+        let cmake =
+            "#!/bin/sh\n# Thin wrapper around CMake\ncmake -DCMAKE_INSTALL_PREFIX=\"$prefix\" .\n";
+        assert_eq!(
+            classify_configure_content(cmake),
+            ConfigureKind::Foreign("CMake wrapper")
+        );
+        let autosetup =
+            "#!/bin/sh\ndir=\"`dirname \"$0\"`/autosetup\"\nexec \"$dir/autosetup\" \"$@\"\n";
+        assert_eq!(
+            classify_configure_content(autosetup),
+            ConfigureKind::Foreign("autosetup")
+        );
+        // A wrapper's mention of config.status doesn't outweigh the generator it names
+        let meson = "#!/bin/sh\necho \"config.status: not used\"\nexec meson setup build\n";
+        assert_eq!(
+            classify_configure_content(meson),
+            ConfigureKind::Foreign("Meson wrapper")
+        );
+
+        assert_eq!(
+            classify_configure_content("#!/bin/sh\necho \"config.status: creating Makefile\"\n"),
+            ConfigureKind::Autotools
+        );
+        assert_eq!(
+            classify_configure_content("#!/bin/sh\nprefix=/usr/local\necho ok > config.mk\n"),
+            ConfigureKind::Unknown
+        );
+    }
+
+    #[test]
+    fn configure_banner_after_long_license_is_found() {
+        let tmp = tempfile::tempdir().unwrap();
+        let script = tmp.path().join("configure");
+        let license =
+            "# Permission is hereby granted, free of charge, to any person.\n".repeat(200);
+        fs::write(
+            &script,
+            format!("#! /bin/sh\n{}# Generated by GNU Autoconf 2.69.\n", license),
+        )
+        .unwrap();
+        assert!(license.len() > 4096);
+
+        assert_eq!(classify_configure_script(&script), ConfigureKind::Autotools);
+        assert_eq!(
+            classify_configure_script(&tmp.path().join("missing")),
+            ConfigureKind::Unknown
+        );
+    }
+}
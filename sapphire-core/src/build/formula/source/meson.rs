@@ -1,29 +1,31 @@
+use std::fs;
 use std::path::Path;
 use std::process::Command;
 
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
+use super::docs::{build_docs, DocTool};
+use super::ninja::ninja_compile;
 use crate::build::env::BuildEnvironment;
+use crate::build::process::run_streaming;
+use crate::build::warnings::BuildPhase;
 use crate::utils::error::{Result, SapphireError};
 
-/// Build with Meson
+/// Build the Meson project in `source_dir`
 pub fn meson_build(
-    source_dot: &Path, // Parameter represents "." now, as CWD is the source root
+    source_dir: &Path,
     install_dir: &Path,
     build_env: &BuildEnvironment,
 ) -> Result<()> {
-    // Ensure source_dot is actually "."
-    if source_dot != Path::new(".") {
-        return Err(SapphireError::BuildEnvError(format!(
-            "meson_build expected source path '.' but received '{}'",
-            source_dot.display()
-        )));
-    }
-
     info!("==> Building with Meson");
     let build_subdir_name = "sapphire-meson-build";
-    // Meson prefers the build directory passed as an argument, relative to CWD
-    let build_subdir = Path::new(".").join(build_subdir_name);
+    // Meson takes the build directory as an argument; it lives inside the source root
+    let build_subdir = source_dir.join(build_subdir_name);
+    // `meson setup` won't configure into a directory left over from an earlier attempt
+    if build_subdir.exists() {
+        debug!("Removing stale {}", build_subdir.display());
+        fs::remove_dir_all(&build_subdir)?;
+    }
 
     let meson_exe =
         which::which_in("meson", build_env.get_path_string(), Path::new(".")).map_err(|_| {
@@ -35,8 +37,8 @@ pub fn meson_build(
         "==> Running meson setup: {} setup --prefix={} {} {}",
         meson_exe.display(),
         install_dir.display(),
-        build_subdir.display(), // Build directory
-        "."                     // Source directory (CWD) <-- CHANGED from source_dir
+        build_subdir.display(),
+        source_dir.display()
     );
 
     let mut cmd_setup = Command::new(&meson_exe);
@@ -45,22 +47,30 @@ pub fn meson_build(
         .arg(format!("--prefix={}", install_dir.display()))
         .arg("--buildtype=release")
         .arg("--libdir=lib")
-        .arg(&build_subdir) // Specify build directory
-        .arg("."); // Specify source directory (CWD) <-- CHANGED from source_dir
-    build_env.apply_to_command(&mut cmd_setup);
-    // Meson setup runs from the CWD (which is the source root)
-    let output_setup = cmd_setup.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute meson setup: {}", e))
-    })?;
+        .arg(&build_subdir)
+        .arg(source_dir);
+    if let Some(toolchain) = build_env.toolchain() {
+        let (machine_file, is_cross) = toolchain.write_meson_machine_file(source_dir)?;
+        info!("    (Using meson machine file {})", machine_file.display());
+        cmd_setup
+            .arg(if is_cross {
+                "--cross-file"
+            } else {
+                "--native-file"
+            })
+            .arg(machine_file);
+    }
+    build_env.apply_to_configure_command(&mut cmd_setup);
+    let output_setup = run_streaming(&mut cmd_setup, "meson setup")?;
 
     if !output_setup.status.success() {
         // (Error handling code from your original file)
-        println!("Meson setup failed with status: {}", output_setup.status);
-        eprintln!(
+        error!("Meson setup failed with status: {}", output_setup.status);
+        error!(
             "Meson setup stdout:\n{}",
             String::from_utf8_lossy(&output_setup.stdout)
         );
-        eprintln!(
+        error!(
             "Meson setup stderr:\n{}",
             String::from_utf8_lossy(&output_setup.stderr)
         );
@@ -78,32 +88,32 @@ pub fn meson_build(
             String::from_utf8_lossy(&output_setup.stderr)
         );
     }
+    build_env.checkpoint(BuildPhase::Configure)?;
 
     // Check for ninja before attempting install
-    let _ninja_exe = which::which_in("ninja", build_env.get_path_string(), Path::new("."))
+    let ninja_exe = which::which_in("ninja", build_env.get_path_string(), Path::new("."))
         .map_err(|_| SapphireError::BuildEnvError("ninja command not found (needed for meson install). Ensure ninja is installed and in build dependencies.".to_string()))?;
+    ninja_compile(&ninja_exe, &build_subdir, build_env, "ninja (Meson)")?;
+    build_env.checkpoint(BuildPhase::Build)?;
 
     // Meson install uses -C to specify the build directory
     info!("==> Running meson install -C {}", build_subdir.display());
     let mut cmd_install = Command::new(&meson_exe);
     cmd_install.arg("install").arg("-C").arg(&build_subdir); // Use -C flag
     build_env.apply_to_command(&mut cmd_install);
-    // Install command also runs from the CWD (source root)
-    let output_install = cmd_install.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute meson install: {}", e))
-    })?;
+    let output_install = run_streaming(&mut cmd_install, "meson install")?;
 
     if !output_install.status.success() {
         // (Error handling code from your original file)
-        println!(
+        error!(
             "Meson install failed with status: {}",
             output_install.status
         );
-        eprintln!(
+        error!(
             "Meson install stdout:\n{}",
             String::from_utf8_lossy(&output_install.stdout)
         );
-        eprintln!(
+        error!(
             "Meson install stderr:\n{}",
             String::from_utf8_lossy(&output_install.stderr)
         );
@@ -115,5 +125,11 @@ pub fn meson_build(
         debug!("Meson install completed successfully.");
     }
 
-    Ok(())
+    build_docs(
+        DocTool::Ninja(&ninja_exe),
+        &build_subdir,
+        source_dir,
+        install_dir,
+        build_env,
+    )
 }
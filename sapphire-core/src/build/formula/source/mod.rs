@@ -3,45 +3,78 @@
 // --- Imports ---
 use std::collections::HashMap;
 use std::fs::{self};
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Arc;
 
 use futures::future::try_join_all;
-use infer;
 use tracing::{debug, error, info, warn};
 
-use crate::build::env::BuildEnvironment;
-use crate::build::extract;
-use crate::fetch::http as http_fetch;
-use crate::model::formula::{Formula, FormulaDependencies, ResourceSpec};
-use crate::utils::config::Config;
+use crate::build::blob_cache::{BlobCache, BlobKey};
+use crate::build::build_log::{self, BuildLog};
+use crate::build::env::{BuildEnvironment, EnvMode};
+use crate::build::formula::relocate::{codesign_install, fix_dylib_ids};
+use crate::build::formula::share;
+use crate::build::process::run_streaming;
+use crate::build::source_cache::{SourceTreeCache, SourceTreeKey};
+use crate::build::toolchain::Toolchain;
+use crate::build::warnings::{BuildPhase, WarningCode, WarningCollector};
+use crate::build::{extract, linkage};
+use crate::dependency::DependencyExt;
+use crate::fetch::{git, http as http_fetch};
+use crate::model::formula::{Formula, ResourceSpec};
+use crate::utils::config::{Config, PostInstallPass};
 use crate::utils::error::{Result, SapphireError};
 
 // --- Build system submodules ---
 mod cargo;
 mod cmake;
+mod docs;
+mod download;
 mod go;
+mod libtool;
 mod make;
 mod meson;
+mod ninja;
+mod patch;
 mod perl;
+mod post_install;
 mod python;
+mod shebang;
 
 // --- Re-export build functions ---
 pub use cargo::cargo_build;
 pub use cmake::cmake_build;
+pub use download::download_with_retry;
 pub use go::go_build;
+pub use libtool::{prune_la_files, rewrite_la_files};
 pub use make::{configure_and_make, simple_make};
 pub use meson::meson_build;
+pub use ninja::ninja_build;
+pub use patch::{apply_patches, fetch_patches};
+pub(crate) use patch::{canonical_patch, find_patch_exe, patch_command};
 pub use perl::perl_build;
+pub use post_install::{run_post_install, PostInstallEnv};
 pub use python::python_build;
+pub use shebang::{interpreter_map, rewrite_shebangs, InterpreterMap, ShebangTarget};
 
 // --- Constants ---
-const SUPPORTED_ARCHIVE_EXTENSIONS: [&str; 5] = ["gz", "bz2", "xz", "tar", "zip"];
-const RECOGNISED_SINGLE_FILE_EXTENSIONS: [&str; 9] =
-    ["tar", "gz", "tgz", "bz2", "tbz", "tbz2", "xz", "txz", "zip"];
+const SUPPORTED_ARCHIVE_EXTENSIONS: [&str; 6] = ["gz", "bz2", "xz", "zst", "tar", "zip"];
+const RECOGNISED_SINGLE_FILE_EXTENSIONS: [&str; 11] = [
+    "tar", "gz", "tgz", "bz2", "tbz", "tbz2", "xz", "txz", "zst", "tzst", "zip",
+];
+
+/// `SOURCE_DATE_EPOCH` used by reproducible builds when the source has no usable timestamp:
+/// 1980-01-01, the earliest time zip archives can record.
+const FALLBACK_SOURCE_DATE_EPOCH: u64 = 315_532_800;
 
 // --- download_source ---
-pub async fn download_source(formula: &Formula, config: &Config) -> Result<PathBuf> {
+pub async fn download_source(
+    formula: &Formula,
+    config: &Config,
+    on_progress: &http_fetch::ProgressCallback,
+) -> Result<PathBuf> {
     let url = if !formula.url.is_empty() {
         formula.url.clone()
     } else if let Some(homepage) = &formula.homepage {
@@ -71,34 +104,112 @@ pub async fn download_source(formula: &Formula, config: &Config) -> Result<PathB
         &formula.sha256,
         &formula.mirrors,
         config,
+        on_progress,
     )
     .await
 }
 
-/// Restores the original working directory when dropped (RAII).
-struct CurrentWorkingDirectoryGuard {
-    original_cwd: PathBuf,
-}
-impl CurrentWorkingDirectoryGuard {
-    fn new(original_cwd: PathBuf) -> Self {
-        Self { original_cwd }
+/// Puts `formula`'s source with its patches applied into `build_dir`: restored from the source
+/// tree cache if the same source and patches were prepared before, else extracted and patched,
+/// then cached. Returns the `SOURCE_DATE_EPOCH` of the tree, from before it was patched.
+async fn prepare_source(
+    formula: &Formula,
+    config: &Config,
+    source_path: &Path,
+    build_dir: &Path,
+    build_env: &BuildEnvironment,
+) -> Result<u64> {
+    let patches = fetch_patches(formula, config).await?;
+    // A dry run only prints the patch commands, so its tree must neither come from nor go to
+    // the cache
+    let cache = match SourceTreeCache::from_config(config).filter(|_| !build_env.dry_run()) {
+        Some(cache) => SourceTreeKey::new(formula, source_path, &patches)?.map(|key| (cache, key)),
+        None => None,
+    };
+    if let Some((cache, key)) = &cache {
+        if let Some(epoch) = cache.restore(key, build_dir)? {
+            return Ok(epoch);
+        }
     }
-}
-impl Drop for CurrentWorkingDirectoryGuard {
-    fn drop(&mut self) {
-        if let Err(e) = std::env::set_current_dir(&self.original_cwd) {
-            error!(
-                "Failed to restore original working directory to {}: {}",
-                self.original_cwd.display(),
+
+    stage_source(source_path, build_dir)?;
+    debug!("==> Extracted main source to {}", build_dir.display());
+    extract::strip_quarantine(build_dir);
+    let epoch = source_date_epoch(source_path, build_dir);
+    // Before anything looks at the tree, as patches may touch the build files too
+    apply_patches(build_dir, &patches, build_env)?;
+    if let Some((cache, key)) = &cache {
+        // The tree is ready either way; a cache failure only costs the next rebuild
+        if let Err(e) = cache.store(key, build_dir, epoch) {
+            warn!(
+                "Could not add the source of {} to the cache: {}",
+                formula.name(),
                 e
             );
-        } else {
-            debug!(
-                "Restored working directory to: {}",
-                self.original_cwd.display()
-            );
         }
     }
+    Ok(epoch)
+}
+
+/// The timestamp a reproducible build of the source at `source_path`, staged in `build_dir`,
+/// records: the commit time of a git checkout, or else the newest file in the extracted
+/// archive. Unlike the mtime of the downloaded archive itself, those are the same on every
+/// machine.
+fn source_date_epoch(source_path: &Path, build_dir: &Path) -> u64 {
+    if source_path.join(".git").exists() {
+        match git::head_commit_time(source_path) {
+            Ok(time) => return time,
+            Err(e) => debug!("Could not read the commit time of the checkout: {}", e),
+        }
+    }
+    walkdir::WalkDir::new(build_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        // Directories the archive doesn't list are created with the current time
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok()?.modified().ok())
+        .max()
+        .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|age| age.as_secs())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(FALLBACK_SOURCE_DATE_EPOCH)
+}
+
+/// Checks that builds can run in `root`: that it can be created and written to, and that
+/// programs written there can be executed, as configure scripts compile and run test programs
+/// in the build directory. A `/tmp` mounted `noexec` fails the last check.
+fn validate_build_root(root: &Path) -> Result<()> {
+    let not_writable = |e: std::io::Error| {
+        SapphireError::BuildEnvError(format!(
+            "Build root {} is not writable: {} (set build_root or SAPPHIRE_BUILD_ROOT to \
+             another directory)",
+            root.display(),
+            e
+        ))
+    };
+    fs::create_dir_all(root).map_err(not_writable)?;
+    let probe_dir = tempfile::Builder::new()
+        .prefix(".sapphire-exec-check-")
+        .tempdir_in(root)
+        .map_err(not_writable)?;
+    let probe = probe_dir.path().join("probe");
+    fs::write(&probe, "#!/bin/sh\nexit 0\n").map_err(not_writable)?;
+    fs::set_permissions(&probe, fs::Permissions::from_mode(0o755))?;
+    match Command::new(&probe).status() {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            Err(SapphireError::BuildEnvError(format!(
+                "Build root {} is on a filesystem mounted noexec, so configure can't run the \
+                 programs it compiles; set build_root or SAPPHIRE_BUILD_ROOT to a directory on \
+                 an executable mount",
+                root.display()
+            )))
+        }
+        Err(e) => {
+            debug!("Could not run the exec check in {}: {}", root.display(), e);
+            Ok(())
+        }
+        Ok(_) => Ok(()),
+    }
 }
 
 // --- Helper Functions (ensure these are present or imported) ---
@@ -111,223 +222,194 @@ fn create_dir_all_with_context(path: &Path, context: &str) -> Result<()> {
     })
 }
 
-/// Helper function to temporarily change CWD for executing a build function.
-fn with_cwd<F>(target_cwd: &Path, build_func: F) -> Result<()>
-where
-    F: FnOnce() -> Result<()>,
-{
-    let original_cwd = std::env::current_dir().map_err(SapphireError::Io)?;
-    let mut must_restore = false;
-
-    // Only change CWD if target_cwd is not the current CWD (".")
-    if target_cwd != Path::new(".") {
-        debug!("Temporarily changing CWD to {}", target_cwd.display());
-        if let Err(e) = std::env::set_current_dir(target_cwd) {
-            error!("Failed to change CWD to {}: {}", target_cwd.display(), e);
-            return Err(SapphireError::Io(e));
+/// The build systems sapphire has a backend for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildSystem {
+    CMake,
+    Meson,
+    /// A hand-written `build.ninja`.
+    Ninja,
+    Autotools,
+    Go,
+    Perl,
+    Cargo,
+    Python,
+    /// A `Makefile` with no configure step.
+    PlainMake,
+}
+
+impl BuildSystem {
+    /// The commands this system's backend runs to build the tree in `build_dir`. Python is
+    /// left out, as its backend takes `python3` or `python`, whichever it finds.
+    pub fn required_tools(self, build_dir: &Path) -> Vec<&'static str> {
+        let has = |marker: &str| build_dir.join(marker).exists();
+        match self {
+            Self::CMake => vec!["cmake"],
+            Self::Meson => vec!["meson", "ninja"],
+            Self::Ninja => vec!["ninja"],
+            // Without a configure script or autogen.sh, one is generated with autoreconf, unless
+            // the tree has a Makefile to fall back to
+            Self::Autotools
+                if !has("configure")
+                    && !has("autogen.sh")
+                    && !has("Makefile")
+                    && !has("makefile") =>
+            {
+                vec!["autoreconf", "make"]
+            }
+            Self::Autotools | Self::PlainMake => vec!["make"],
+            Self::Go => vec!["go"],
+            Self::Perl if has("Configure") => vec!["sh", "make"],
+            Self::Perl => vec!["perl", "make"],
+            Self::Cargo => vec!["cargo"],
+            Self::Python => Vec::new(),
         }
-        must_restore = true;
-    } else {
-        debug!("Executing build function in current CWD.");
     }
+}
 
-    // Execute the build function
-    let result = build_func();
-
-    // Restore CWD if we changed it
-    if must_restore {
-        if let Err(e) = std::env::set_current_dir(&original_cwd) {
-            error!(
-                "CRITICAL: Failed to restore CWD from {} back to {}: {}. State may be invalid.",
-                target_cwd.display(),
-                original_cwd.display(),
-                e
-            );
-            // If restoration fails, we should probably return the CWD error,
-            // potentially masking the original build result if it was Ok.
-            return Err(SapphireError::Io(e));
-        } else {
-            debug!("Restored CWD to {}", original_cwd.display());
-        }
+impl std::fmt::Display for BuildSystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::CMake => "CMake",
+            Self::Meson => "Meson",
+            Self::Ninja => "standalone build.ninja",
+            Self::Autotools => "Autotools (configure script)",
+            Self::Go => "Go module",
+            Self::Perl => "Perl (Makefile.PL or Configure)",
+            Self::Cargo => "Rust/Cargo",
+            Self::Python => "Python (setup.py/pyproject.toml)",
+            Self::PlainMake => "Simple Makefile",
+        })
     }
+}
 
-    // Return the result of the build function
-    result
+/// Identifies the build system of the source tree in `build_dir` from its marker files.
+///
+/// When several are present the more specific one wins: CMake and Meson projects often carry
+/// other markers, a `build.ninja` next to them is generated output, and a plain `Makefile` is
+/// the last resort. A tree that only has the inputs of Autotools (`configure.ac`,
+/// `autogen.sh`) and no generated `configure` ranks just above it, as bootstrapping needs tools
+/// the other systems don't.
+pub fn detect_build_system(build_dir: &Path) -> Option<BuildSystem> {
+    let has = |marker: &str| build_dir.join(marker).exists();
+    if has("CMakeLists.txt") {
+        Some(BuildSystem::CMake)
+    } else if has("meson.build") {
+        Some(BuildSystem::Meson)
+    } else if has("build.ninja") {
+        Some(BuildSystem::Ninja)
+    } else if has("configure") {
+        Some(BuildSystem::Autotools)
+    } else if has("go.mod") {
+        Some(BuildSystem::Go)
+    } else if has("Makefile.PL") || has("Configure") {
+        Some(BuildSystem::Perl)
+    } else if has("Cargo.toml") {
+        Some(BuildSystem::Cargo)
+    } else if has("setup.py") || has("pyproject.toml") {
+        Some(BuildSystem::Python)
+    } else if has("autogen.sh") || has("configure.ac") || has("configure.in") {
+        // Without a generated configure, the Autotools backend bootstraps one
+        Some(BuildSystem::Autotools)
+    } else if has("Makefile") || has("makefile") {
+        Some(BuildSystem::PlainMake)
+    } else {
+        None
+    }
 }
 
-/// Returns Ok(true) if build system found and called, Ok(false) if not found, Err on build error.
-fn check_markers_and_build(
-    dir_to_check: &Path,
+/// Detects the build system in `build_dir` and runs the matching backend there. This is the
+/// single entry point for building a staged source tree. Returns `Ok(false)` if no known build
+/// system was found.
+pub fn detect_and_build(
+    build_dir: &Path,
     install_dir: &Path,
     build_env: &BuildEnvironment,
     all_installed_paths: &[PathBuf],
 ) -> Result<bool> {
-    // --- Autoreconf Check (specific to the directory being checked) ---
-    // This should happen *before* checking for 'configure' existence
-    if (dir_to_check.join("configure.ac").exists() || dir_to_check.join("configure.in").exists())
-        && !dir_to_check.join("configure").exists()
-    {
-        // Need to run autoreconf *within* dir_to_check if it's not CWD
-        let original_cwd = std::env::current_dir().map_err(SapphireError::Io)?;
-        let mut must_restore_cwd = false;
-        if dir_to_check != Path::new(".") {
-            debug!(
-                "Temporarily changing CWD to {} for autoreconf check",
-                dir_to_check.display()
-            );
-            std::env::set_current_dir(dir_to_check).map_err(SapphireError::Io)?;
-            must_restore_cwd = true;
-        }
-
-        match which::which_in("autoreconf", build_env.get_path_string(), Path::new(".")) {
-            Ok(autoreconf_path) => {
-                info!(
-                    "==> Running autoreconf -fvi (as configure script is missing in {})",
-                    dir_to_check.display()
-                );
-                let mut cmd = Command::new(autoreconf_path);
-                cmd.args(["-fvi"]);
-                build_env.apply_to_command(&mut cmd);
-                match run_command(&mut cmd, "autoreconf") {
-                    Ok(_) => info!("Autoreconf completed successfully."),
-                    Err(e) => warn!("Autoreconf failed ({}). Continuing build detection...", e),
-                }
-            }
-            Err(_) => {
-                warn!("configure.ac/in found but configure script and autoreconf command are missing.");
-            }
-        }
-
-        // Restore CWD if we changed it
-        if must_restore_cwd {
-            if let Err(e) = std::env::set_current_dir(&original_cwd) {
-                error!(
-                    "FATAL: Failed to restore CWD after autoreconf check in {}: {}",
-                    dir_to_check.display(),
-                    e
-                );
-                // This is tricky - state is potentially bad. Maybe return a fatal error?
-                return Err(SapphireError::Io(e));
-            } else {
-                debug!("Restored CWD after autoreconf check.");
-            }
-        }
-        // After running autoreconf, the 'configure' script might now exist, so we continue
-        // detection.
-    }
-
-    // --- Marker Checks ---
-    // Note: These checks use relative paths which are interpreted based on CWD
-    // We need to ensure the build functions are called with the *correct* context/CWD.
-
-    // Check for complex build systems first (CMake, Meson often contain other languages)
-    if dir_to_check.join("CMakeLists.txt").exists() {
-        info!("Detected build system: CMake in {}", dir_to_check.display());
-        with_cwd(dir_to_check, || {
-            cmake::cmake_build(Path::new("."), install_dir, build_env)
-        })?;
-        return Ok(true);
-    }
-    if dir_to_check.join("meson.build").exists() {
-        info!("Detected build system: Meson in {}", dir_to_check.display());
-        with_cwd(dir_to_check, || {
-            meson::meson_build(Path::new("."), install_dir, build_env)
-        })?;
-        return Ok(true);
+    let system = detect_build_system(build_dir);
+    if matches!(system, None | Some(BuildSystem::PlainMake)) {
+        warn_legacy_go(build_dir, build_env);
     }
-    // Check for Autotools *after* CMake/Meson, as they might be preferred if both exist
-    if dir_to_check.join("configure").exists() {
-        info!(
-            "Detected build system: Autotools (configure script) in {}",
-            dir_to_check.display()
-        );
-        with_cwd(dir_to_check, || {
-            make::configure_and_make(install_dir, build_env)
-        })?;
-        return Ok(true);
+    let Some(system) = system else {
+        return Ok(false);
+    };
+    info!(
+        "Detected build system: {} in {}",
+        system,
+        build_dir.display()
+    );
+    // Only the make backends route their steps through `run_or_print`
+    if build_env.dry_run() && !matches!(system, BuildSystem::Autotools | BuildSystem::PlainMake) {
+        return Err(SapphireError::BuildEnvError(format!(
+            "A dry run can't show the commands of a {} build; only make-based builds support it",
+            system
+        )));
     }
 
-    // --- NEW: Go Module Check (Before generic Makefiles, Cargo, Python, Perl) ---
-    if dir_to_check.join("go.mod").exists() {
-        info!("Detected Go module (go.mod) in {}", dir_to_check.display());
-        // Use the *updated* go_build function
-        with_cwd(dir_to_check, || {
-            go::go_build(Path::new("."), install_dir, build_env, all_installed_paths)
-        })?;
-        return Ok(true);
-    }
+    let mut required = system.required_tools(build_dir);
+    required.extend(build_env.required_tools().iter().map(String::as_str));
+    check_build_deps(&required, build_env)?;
+
+    // Backends run their commands in, and resolve their files against, the env's build dir
+    let build_env = &build_env.in_build_dir(build_dir);
+    match system {
+        BuildSystem::CMake => cmake::cmake_build(build_dir, install_dir, build_env),
+        BuildSystem::Meson => meson::meson_build(build_dir, install_dir, build_env),
+        BuildSystem::Ninja => ninja::ninja_build(install_dir, build_env),
+        BuildSystem::Autotools => make::configure_and_make(install_dir, build_env),
+        BuildSystem::Go => go::go_build(build_dir, install_dir, build_env, all_installed_paths),
+        BuildSystem::Perl => perl::perl_build(build_dir, install_dir, build_env),
+        BuildSystem::Cargo => cargo::cargo_build(install_dir, build_env),
+        BuildSystem::Python => python::python_build(install_dir, build_env),
+        BuildSystem::PlainMake => make::simple_make(install_dir, build_env),
+    }?;
+    Ok(true)
+}
 
-    // Continue with other language-specific build systems
-    if dir_to_check.join("Makefile.PL").exists() || dir_to_check.join("Configure").exists() {
-        info!(
-            "Detected build system: Perl (Makefile.PL or Configure) in {}",
-            dir_to_check.display()
-        );
-        with_cwd(dir_to_check, || {
-            perl::perl_build(Path::new("."), install_dir, build_env)
-        })?;
-        return Ok(true);
-    }
-    if dir_to_check.join("Cargo.toml").exists() {
-        info!(
-            "Detected build system: Rust/Cargo in {}",
-            dir_to_check.display()
-        );
-        with_cwd(dir_to_check, || cargo::cargo_build(install_dir, build_env))?;
-        return Ok(true);
+/// Checks that every command in `required` can be found before any build phase runs, so a
+/// missing tool is reported up front rather than halfway through the build. Commands are looked
+/// up as the backends do: on the build PATH, then on sapphire's own PATH. All missing commands
+/// are listed in a single error.
+pub fn check_build_deps(required: &[&str], build_env: &BuildEnvironment) -> Result<()> {
+    let mut missing: Vec<&str> = Vec::new();
+    for &tool in required {
+        let found = which::which_in(tool, build_env.get_path_string(), Path::new("."))
+            .or_else(|_| which::which(tool))
+            .is_ok();
+        if !found && !missing.contains(&tool) {
+            missing.push(tool);
+        }
     }
-    if dir_to_check.join("setup.py").exists() {
-        info!(
-            "Detected build system: Python setup.py in {}",
-            dir_to_check.display()
-        );
-        with_cwd(dir_to_check, || {
-            python::python_build(install_dir, build_env)
-        })?;
-        return Ok(true);
+    if missing.is_empty() {
+        debug!("Build tools found: {}", required.join(", "));
+        return Ok(());
     }
-    // Deprecate or remove the old Go check based on make.bash/all.bash?
-    // For now, let's keep it *after* the go.mod check as a fallback for older Go projects
-    // that might still use this structure (like Go itself).
-    let go_src_dir = dir_to_check.join("src");
+    Err(SapphireError::BuildEnvError(format!(
+        "required build tools not found on PATH: {} (install them, or add the formulae providing \
+         them as build dependencies)",
+        missing.join(", ")
+    )))
+}
+
+/// Go itself and a few old Go projects build with `src/make.bash`, which has no backend. Such
+/// trees usually also ship a Makefile, which is used instead.
+fn warn_legacy_go(dir: &Path, build_env: &BuildEnvironment) {
+    let go_src_dir = dir.join("src");
     if go_src_dir.is_dir()
         && (go_src_dir.join("make.bash").exists() || go_src_dir.join("all.bash").exists())
     {
-        warn!(
-            "Detected legacy Go build system (make.bash/all.bash) in {}. Using older build logic.",
-            dir_to_check.display()
-        );
-        // The *original* go_build handled this. We need to decide if we keep both.
-        // For simplicity now, let's assume the new go_build is for modules, and this
-        // path might need a different function or be removed if make.bash is rare.
-        // Let's log a warning and return false for now, falling through to simple_make
-        // if a Makefile exists, or erroring out.
-        // OR, call the old go_build logic if we rename it, e.g., go_build_legacy.
-        // For now, let's assume make.bash projects likely *also* have a Makefile.
-        warn!("Legacy Go build script detected, but specific handling is pending. Falling back...");
-    }
-
-    // --- Simple Makefile Fallback (Last Resort) ---
-    if dir_to_check.join("Makefile").exists() || dir_to_check.join("makefile").exists() {
-        info!(
-            "Detected build system: Simple Makefile in {}", /* Note: Go modules might fall here
-                                                             * if go.mod check fails or legacy
-                                                             * path is taken */
-            dir_to_check.display()
+        build_env.warnings().warn(
+            WarningCode::UnsupportedBuildSystem,
+            BuildPhase::Build,
+            "Detected legacy Go build system (make.bash/all.bash), which has no dedicated backend",
+            Some(dir),
         );
-        with_cwd(dir_to_check, || make::simple_make(install_dir, build_env))?;
-        return Ok(true);
     }
-
-    // No known build system found in this directory
-    Ok(false)
 }
 
 fn run_command(cmd: &mut Command, context: &str) -> Result<std::process::Output> {
-    debug!("Running command ({}): {:?}", context, cmd);
-    let output = cmd.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute command for {}: {}", context, e))
-    })?;
+    let output = run_streaming(cmd, context)?;
 
     if !output.status.success() {
         error!("Command failed for {}. Status: {}", context, output.status);
@@ -344,26 +426,27 @@ fn run_command(cmd: &mut Command, context: &str) -> Result<std::process::Output>
     }
 }
 
-/// Detects the build system based on marker files *in the current working directory*
-/// or a single subdirectory, and dispatches to the appropriate build function.
-fn detect_and_build_in_cwd(
-    _formula: &Formula, // Prefixed as it's still not used directly here
+/// Detects the build system based on marker files in `build_dir` or a single subdirectory,
+/// and dispatches to the appropriate build function.
+fn detect_and_build_in(
+    build_dir: &Path,
     install_dir: &Path,
     build_env: &BuildEnvironment,
     all_installed_paths: &[PathBuf],
 ) -> Result<()> {
-    info!("Attempting to detect build system in current directory (CWD)");
-
-    let cwd = Path::new("."); // Represents the current working directory
+    info!(
+        "Attempting to detect build system in {}",
+        build_dir.display()
+    );
 
-    // --- Check for markers directly in CWD first ---
-    if check_markers_and_build(cwd, install_dir, build_env, all_installed_paths)? {
-        return Ok(()); // Build system found and handled in CWD
+    // --- Check for markers directly in the build dir first ---
+    if detect_and_build(build_dir, install_dir, build_env, all_installed_paths)? {
+        return Ok(()); // Build system found and handled in the build dir
     }
 
-    // --- If not found in CWD, check for a single subdirectory ---
+    // --- If not found there, check for a single subdirectory ---
     let mut subdirs = Vec::new();
-    match fs::read_dir(cwd) {
+    match fs::read_dir(build_dir) {
         Ok(entries) => {
             for entry_res in entries {
                 if let Ok(entry) = entry_res {
@@ -377,7 +460,8 @@ fn detect_and_build_in_cwd(
                     }
                 } else {
                     warn!(
-                        "Failed to read directory entry in CWD: {:?}",
+                        "Failed to read directory entry in {}: {:?}",
+                        build_dir.display(),
                         entry_res.err()
                     );
                 }
@@ -386,7 +470,11 @@ fn detect_and_build_in_cwd(
         Err(e) => {
             return Err(SapphireError::Io(std::io::Error::new(
                 e.kind(),
-                format!("Failed to read CWD to check for subdirectories: {}", e),
+                format!(
+                    "Failed to read {} to check for subdirectories: {}",
+                    build_dir.display(),
+                    e
+                ),
             )));
         }
     }
@@ -394,12 +482,12 @@ fn detect_and_build_in_cwd(
     if subdirs.len() == 1 {
         let subdir_path = &subdirs[0];
         info!(
-            "No build system found in CWD, checking single subdirectory: {}",
+            "No build system found in the build dir, checking single subdirectory: {}",
             subdir_path.display()
         );
 
         // --- Check for markers inside the subdirectory ---
-        if check_markers_and_build(subdir_path, install_dir, build_env, all_installed_paths)? {
+        if detect_and_build(subdir_path, install_dir, build_env, all_installed_paths)? {
             return Ok(()); // Build system found and handled in subdirectory
         }
     } else if subdirs.len() > 1 {
@@ -408,63 +496,86 @@ fn detect_and_build_in_cwd(
         info!("No subdirectories found to check.");
     }
 
-    // If no known build system is detected in CWD or single subdirectory
-    error!("Could not determine build system in CWD or its immediate subdirectory.");
+    // If no known build system is detected in the build dir or single subdirectory
+    error!("Could not determine build system in the build dir or its immediate subdirectory.");
     Err(SapphireError::Generic(
         "Could not determine build system in source directory.".to_string(),
     ))
 }
 
+/// Extracts the source archive `source_path` into `dest_dir`. A single top-level directory in
+/// the archive is stripped, so `dest_dir` becomes the source root.
+pub fn stage_source(source_path: &Path, dest_dir: &Path) -> Result<()> {
+    // A git checkout (`--HEAD`) is copied rather than extracted, .git included for builds
+    // that version themselves with `git describe`
+    if source_path.is_dir() {
+        info!(
+            "==> Copying source checkout {} to {}",
+            source_path.display(),
+            dest_dir.display()
+        );
+        let options = fs_extra::dir::CopyOptions::new().content_only(true);
+        fs_extra::dir::copy(source_path, dest_dir, &options).map_err(|e| {
+            SapphireError::Generic(format!(
+                "Failed to copy {} to {}: {}",
+                source_path.display(),
+                dest_dir.display(),
+                e
+            ))
+        })?;
+        return Ok(());
+    }
+
+    let source_archive_type_str = determine_archive_type(source_path, "main source archive")?;
+
+    // Infer the root directory *before* extraction
+    let inferred_root_dir = extract::infer_archive_root_dir(source_path, source_archive_type_str)?;
+
+    let strip_components = if inferred_root_dir.is_some() {
+        // If a single root dir exists, strip it during extraction
+        tracing::debug!("Detected single root dir in archive, will use strip_components=1.");
+        1
+    } else {
+        // If archive is flat or has multiple roots, don't strip
+        tracing::debug!("Archive is flat or has multiple roots, using strip_components=0.");
+        0
+    };
+
+    info!(
+        "==> Extracting main source {} to {} (strip_components={})",
+        source_path.display(),
+        dest_dir.display(),
+        strip_components
+    );
+    extract::extract_archive(
+        source_path,
+        dest_dir,
+        strip_components,
+        source_archive_type_str,
+    )
+}
+
 fn determine_archive_type(archive_path: &Path, _context: &str) -> Result<&'static str> {
     // <-- Prefixed
-    match infer::get_from_path(archive_path)? {
-        Some(kind) => {
-            let ext = kind.extension();
-            // Use the constant defined earlier
-            if SUPPORTED_ARCHIVE_EXTENSIONS.contains(&ext) {
-                SUPPORTED_ARCHIVE_EXTENSIONS
-                    .iter()
-                    .find(|&&s| s == ext)
-                    .copied()
-                    .ok_or_else(|| {
-                        SapphireError::Generic(format!(
-                            "Internal error matching inferred extension {}",
-                            ext
-                        ))
-                    })
-            } else {
-                Err(SapphireError::Generic(format!(
-                    "Unsupported inferred archive type '{}' for {}",
-                    ext,
-                    archive_path.display() // Add path for context
-                )))
-            }
-        }
-        None => {
-            let ext = archive_path
-                .extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("");
-            if SUPPORTED_ARCHIVE_EXTENSIONS.contains(&ext) {
-                SUPPORTED_ARCHIVE_EXTENSIONS
-                    .iter()
-                    .find(|&&s| s == ext)
-                    .copied()
-                    .ok_or_else(|| {
-                        SapphireError::Generic(format!(
-                            "Internal error matching file extension {}",
-                            ext
-                        ))
-                    })
-            } else {
-                Err(SapphireError::Generic(format!(
-                    "Unsupported file extension '{}' for {}",
-                    ext,
-                    archive_path.display() // Add path for context
-                )))
-            }
-        }
+    if let Some(archive_type) = extract::detect_archive_type(archive_path)? {
+        return Ok(archive_type);
     }
+    // Old tarballs lack the ustar magic, so the name has the last word
+    let ext = archive_path
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
+    SUPPORTED_ARCHIVE_EXTENSIONS
+        .iter()
+        .find(|&&s| s == ext)
+        .copied()
+        .ok_or_else(|| {
+            SapphireError::Generic(format!(
+                "Unrecognized archive format for {} (expected a tar.gz, tar.xz, tar.bz2, \
+                 tar.zst or zip archive)",
+                archive_path.display()
+            ))
+        })
 }
 
 fn install_resource(
@@ -473,15 +584,12 @@ fn install_resource(
     libexec_path: &Path, // Base libexec path (e.g., <prefix>/libexec)
     build_env: &BuildEnvironment,
 ) -> Result<()> {
-    let original_cwd = std::env::current_dir().map_err(SapphireError::Io)?;
     debug!(
-        "Changing CWD for resource '{}' install: {}",
+        "Installing resource '{}' from {}",
         resource.name,
         stage_path.display()
     );
-    std::env::set_current_dir(stage_path).map_err(SapphireError::Io)?;
-    // Use RAII guard to ensure CWD restoration even on errors
-    let _cwd_guard = CurrentWorkingDirectoryGuard::new(original_cwd);
+    let build_env = &build_env.in_build_dir(stage_path);
 
     // Check for build files within the staged resource directory
     // Prioritize Perl check if both Makefile.PL and setup.py might exist
@@ -510,18 +618,61 @@ fn install_resource(
             stage_path.display()
         );
     }
-    Ok(()) // CWD is restored when _cwd_guard goes out of scope
+    Ok(())
 }
 
 // --- build_from_source ---
+/// Checks out `formula`'s development version under `<cache>/git/<name>` and switches `formula`
+/// to it, for `--HEAD` installs. Returns the checkout, which [`build_from_source`] builds just
+/// like an extracted archive.
+pub async fn checkout_head_source(formula: &mut Formula, config: &Config) -> Result<PathBuf> {
+    let head = formula.head().cloned().ok_or_else(|| {
+        SapphireError::Generic(format!("{} has no HEAD version to build", formula.name()))
+    })?;
+    let dest = config.cache_dir.join("git").join(formula.name());
+    let (checkout, commit) = tokio::task::spawn_blocking(move || {
+        let checkout = git::fetch_git_source(&head.url, head.branch.as_deref(), &dest)?;
+        let commit = git::head_commit(&checkout)?;
+        Ok::<_, SapphireError>((checkout, commit))
+    })
+    .await
+    .map_err(|e| SapphireError::Generic(format!("git checkout task failed: {}", e)))??;
+    formula.use_head_version(&commit);
+    info!("==> Building {} from HEAD ({})", formula.name(), commit);
+    Ok(checkout)
+}
+
 pub async fn build_from_source(
-    source_path: &Path, // Path to the downloaded archive
+    source_path: &Path, // Path to the downloaded archive, or a git checkout
     formula: &Formula,
     config: &Config,
     all_installed_paths: &[PathBuf],
 ) -> Result<PathBuf> {
-    let install_dir = formula.install_prefix(&config.cellar)?;
     let formula_name = formula.name();
+    let install_dir = config.formula_keg_path(formula_name, &formula.version_str_full());
+
+    // Split archives (foo.tar.gz.part0, .part1, ...) are stitched back together next to the
+    // parts before anything inspects the source.
+    let reassembled_source;
+    let source_path = match extract::split_archive_part_index(source_path) {
+        Some((base_name, _)) => {
+            let parts = extract::collect_split_archive_parts(source_path)?;
+            reassembled_source = source_path.with_file_name(base_name);
+            info!(
+                "==> Reassembling {} split archive parts into {}",
+                parts.len(),
+                reassembled_source.display()
+            );
+            extract::reassemble_split_archive(&parts, &reassembled_source, None)?;
+            reassembled_source.as_path()
+        }
+        None => source_path,
+    };
+
+    // The download was checked, but the cached copy may have been damaged or swapped since
+    if !formula.sha256.is_empty() {
+        http_fetch::verify_checksum(source_path, &formula.sha256)?;
+    }
 
     let source_extension = source_path
         .extension()
@@ -530,36 +681,100 @@ pub async fn build_from_source(
 
     // Check if the extension indicates it's NOT a recognized archive type
     // If it's not a known archive, assume it's a single file to be installed directly.
-    if !RECOGNISED_SINGLE_FILE_EXTENSIONS.contains(&source_extension) {
+    if !source_path.is_dir() && !RECOGNISED_SINGLE_FILE_EXTENSIONS.contains(&source_extension) {
         info!("==> Installing single file formula: {}", formula_name);
         create_dir_all_with_context(&install_dir, "install directory")?;
         // Call the function that handles copying the single file
         install_single_file(source_path, formula, &install_dir)?;
-        crate::build::write_receipt(formula, &install_dir)?;
+        crate::build::write_receipt(formula, &install_dir, &WarningCollector::new())?;
         return Ok(install_dir);
     }
 
-    // --- Determine Archive Type and Infer Root ---
-    // (Assuming source_path is guaranteed to be an archive now,
-    // single file check might be moved before calling build_from_source if needed)
-    let source_archive_type_str = determine_archive_type(source_path, "main source archive")?; // Use existing helper
-
-    // Infer the root directory *before* extraction
-    let inferred_root_dir = extract::infer_archive_root_dir(source_path, source_archive_type_str)?;
-
-    let strip_components = if inferred_root_dir.is_some() {
-        // If a single root dir exists, strip it during extraction
-        tracing::debug!("Detected single root dir in archive, will use strip_components=1.");
-        1
-    } else {
-        // If archive is flat or has multiple roots, don't strip
-        tracing::debug!("Archive is flat or has multiple roots, using strip_components=0.");
-        0
-    };
+    // --- Build Environment Setup ---
+    info!("==> Setting up build environment");
+    let mut build_env =
+        BuildEnvironment::new(formula, config.prefix(), &install_dir, all_installed_paths)?
+            .with_rpaths(all_installed_paths);
+    // Keg-only build tools (bison, m4...) are what the formula asked for, so they go on PATH
+    // ahead of older system copies; keg-only libraries stay off it
+    for dep in formula.dependencies()?.build_time() {
+        let opt_path = config.formula_opt_link_path(&dep.name);
+        if build_env.keg_only_deps().contains(&opt_path) {
+            build_env.mark_keg_only(&opt_path, true)?;
+        }
+    }
+    if formula.deparallelize() {
+        // Takes precedence over a configured job count: the Makefile is known to race
+        info!(
+            "==> {} does not support parallel make; building serially",
+            formula_name
+        );
+        build_env.set_make_jobs(1);
+    } else if let Some(jobs) = config.install_options.jobs {
+        build_env.set_make_jobs(jobs);
+    }
+    build_env.set_max_load(config.install_options.max_load);
+    if let Some(requirement) = formula.compiler() {
+        build_env.require_compiler(requirement, all_installed_paths)?;
+        debug!("Compiler picked to satisfy {}", requirement);
+    }
+    if let Some(toolchain_file) = &config.install_options.toolchain {
+        info!("==> Using toolchain from {}", toolchain_file.display());
+        build_env.set_toolchain(Toolchain::load(toolchain_file)?);
+    }
+    // After the toolchain, which may name its own compiler
+    let cc = build_env.get_var("CC").unwrap_or_default().to_string();
+    match build_env.compiler_info() {
+        Ok(compiler) => info!("==> Compiling with {} ({})", compiler, cc),
+        Err(e) => warn!("Could not determine the version of {}: {}", cc, e),
+    }
+    if let Some(target) = &config.install_options.deployment_target {
+        build_env.set_deployment_target(target)?;
+    }
+    if let Some(sdk) = &config.install_options.sdk_path {
+        build_env.set_sdk_path(sdk)?;
+        info!("==> Building against the SDK at {}", sdk.display());
+    }
+    build_env.set_sandbox(config.install_options.sandbox);
+    build_env.set_env_mode(formula.env());
+    if formula.env() == EnvMode::Std {
+        info!("==> Building in the user's environment (stdenv)");
+    }
+    if let Some(arch) = config.install_options.target_arch {
+        build_env.set_target_arch(arch)?;
+        info!("==> Building for {}", arch);
+    }
+    build_env.set_universal(config.install_options.universal);
+    build_env.set_optimization(config.install_options.optimization);
+    if config.install_options.lto && build_env.enable_lto() {
+        info!("==> Building with link-time optimization");
+    }
+    if config.install_options.werror {
+        build_env.enable_werror();
+        info!("==> Building with -Werror; any compiler warning fails the build");
+    }
+    if config.install_options.sanitizers {
+        build_env.enable_sanitizers();
+        info!("==> Building with AddressSanitizer and UndefinedBehaviorSanitizer");
+    }
+    build_env.set_build_docs(config.install_options.build_docs);
+    build_env.set_stop_after(config.install_options.stop_after);
+    build_env.set_dry_run(config.install_options.dry_run);
+    build_env.set_staged_install(!formula.no_destdir());
+    build_env.set_run_checks(formula.make_check());
+    build_env.set_required_tools(formula.build_tools().to_vec());
+    build_env.set_extra_configure_args(formula.configure_args().to_vec());
+    build_env.set_disable_dependency_tracking(!formula.dependency_tracking());
+    build_env.set_phase_timeout(
+        config
+            .install_options
+            .build_timeout
+            .map(std::time::Duration::from_secs),
+    );
 
     // --- Staging Area Setup ---
-    let temp_dir_base = config.cache_dir.join("build-temp");
-    create_dir_all_with_context(&temp_dir_base, "build temp base")?;
+    let temp_dir_base = config.build_root();
+    validate_build_root(&temp_dir_base)?;
     let temp_build_dir = tempfile::Builder::new()
         .prefix(&format!("{}-", formula_name))
         .tempdir_in(&temp_dir_base)
@@ -572,22 +787,152 @@ pub async fn build_from_source(
         })?;
     let build_dir = temp_build_dir.path(); // This is where files will land after stripping
 
-    // --- Extract with calculated strip_components ---
-    info!(
-        "==> Extracting main source {} to {} (strip_components={})",
-        source_path.display(),
-        build_dir.display(),
-        strip_components
-    );
-    // Call the existing extract_archive function (ensure it's in scope, maybe
-    // crate::build::extract::extract_archive)
-    crate::build::extract::extract_archive(
-        source_path,
-        build_dir,
-        strip_components,
-        source_archive_type_str,
-    )?;
-    debug!("==> Extracted main source to {}", build_dir.display());
+    // Commands run in the build dir; the process's working directory is left alone
+    build_env = build_env.in_build_dir(build_dir);
+
+    let tree_epoch = prepare_source(formula, config, source_path, build_dir, &build_env).await?;
+    if config.install_options.reproducible {
+        let epoch = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(tree_epoch);
+        info!("==> Building reproducibly (SOURCE_DATE_EPOCH={})", epoch);
+        build_env.enable_reproducible(epoch, build_dir);
+    }
+
+    // --- Local Build Cache ---
+    // After everything that shapes the build, reproducible mode included. A build that stops
+    // early is for inspecting the build dir, and a dry run for seeing its commands, so both
+    // always run
+    let blob_cache = BlobCache::from_config(config)
+        .filter(|_| config.install_options.stop_after.is_none() && !config.install_options.dry_run)
+        .map(|cache| {
+            (
+                cache,
+                BlobKey::new(formula, config, &build_env, all_installed_paths),
+            )
+        });
+    if let Some((cache, key)) = &blob_cache {
+        if cache.restore(key, &install_dir)? {
+            // The archived keg is this build's output, but the install is a new one
+            if !config.skips_post_install_pass(formula.name(), PostInstallPass::LinkageCheck) {
+                linkage::verify_library_references(&install_dir, config)?;
+            }
+            crate::build::write_receipt(formula, &install_dir, build_env.warnings())?;
+            return Ok(install_dir);
+        }
+    }
+
+    // After the cache key, as ccache doesn't change what gets built
+    if config.install_options.ccache {
+        let ccache_dir = config
+            .install_options
+            .ccache_dir
+            .clone()
+            .unwrap_or_else(|| config.cache_dir.join("ccache"));
+        if build_env.enable_ccache(&ccache_dir)? {
+            info!("==> Compiling through ccache ({})", ccache_dir.display());
+        } else {
+            warn!("ccache is enabled but was not found on PATH; compiling without it");
+        }
+    }
+
+    // The log is a convenience; a build that can't write one goes ahead without
+    let build_log = match BuildLog::create(&config.cache_dir, formula_name) {
+        Ok(log) => Some(Arc::new(log)),
+        Err(e) => {
+            warn!("Could not create a build log for {}: {}", formula_name, e);
+            None
+        }
+    };
+    let displayed_env = build_env.displayed_env();
+    if let Some(log) = &build_log {
+        log.environment(&displayed_env);
+    }
+    if build_env.dry_run() {
+        // As comments, so the printed commands can still be pasted into a shell as they are
+        println!("# Build environment");
+        for (key, value) in &displayed_env {
+            println!("#   {}={}", key, value);
+        }
+    }
+
+    let built = build_log::scope(
+        build_log.clone(),
+        build_staged_source(
+            formula,
+            config,
+            &build_env,
+            build_dir,
+            &install_dir,
+            all_installed_paths,
+        ),
+    )
+    .await;
+    if let Some(log) = &build_log {
+        report_log_warnings(log);
+    }
+    match built {
+        Ok(()) => {
+            info!(
+                "Build completed, temporary directory {} will be cleaned up.",
+                build_dir.display()
+            );
+            if let Some((cache, key)) = &blob_cache {
+                // The keg is installed either way; a cache failure only costs the next rebuild
+                if let Err(e) = cache.store(key, &install_dir) {
+                    warn!("Could not add {} to the build cache: {}", formula_name, e);
+                }
+            }
+            Ok(install_dir)
+        }
+        Err(SapphireError::BuildStopped { phase, .. }) => {
+            let build_dir = temp_build_dir.keep();
+            info!(
+                "==> Stopped after the {} phase; build directory kept at {}",
+                phase,
+                build_dir.display()
+            );
+            Err(SapphireError::BuildStopped { phase, build_dir })
+        }
+        Err(e) => {
+            if let Some(log) = &build_log {
+                log.header(&format!("Build failed: {}", e));
+                error!("==> Full build log: {}", log.path().display());
+            }
+            if config.install_options.keep_build_dir_on_failure {
+                let build_dir = temp_build_dir.keep();
+                error!("==> Build directory kept at {}", build_dir.display());
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Logs how many warnings the build printed, by compiler option, whether it succeeded or not.
+fn report_log_warnings(log: &BuildLog) {
+    match log.scan_warnings() {
+        Ok(warnings) if warnings.total > 0 => info!("==> The build printed {}", warnings),
+        Ok(_) => {}
+        Err(e) => debug!(
+            "Could not scan {} for warnings: {}",
+            log.path().display(),
+            e
+        ),
+    }
+}
+
+/// Builds and installs the source staged in `build_dir` into `install_dir`, stopping early
+/// with [`SapphireError::BuildStopped`] if `stop_after` asks for it.
+async fn build_staged_source(
+    formula: &Formula,
+    config: &Config,
+    build_env: &BuildEnvironment,
+    build_dir: &Path,
+    install_dir: &Path,
+    all_installed_paths: &[PathBuf],
+) -> Result<()> {
+    let formula_name = formula.name();
 
     // --- Resource Handling (remains the same) ---
     let resources = formula.resources()?; // Assume this returns Vec<ResourceSpec>
@@ -608,8 +953,15 @@ pub async fn build_from_source(
             let config_clone = config.clone();
             async move {
                 info!(" --> Downloading resource: {}", resource.name);
-                let path = http_fetch::fetch_resource(&formula_name_clone, resource, &config_clone)
-                    .await?;
+                // Resources are small and fetched mid-build, where a bar would fight the build
+                // output; the log lines above are their progress
+                let path = http_fetch::fetch_resource(
+                    &formula_name_clone,
+                    resource,
+                    &config_clone,
+                    &|_| {},
+                )
+                .await?;
                 Ok::<_, SapphireError>((resource.name.clone(), path))
             }
         });
@@ -625,18 +977,12 @@ pub async fn build_from_source(
                 resource_archive_path.display(),
                 stage_path.display()
             );
-            let resource_archive_type_str = determine_archive_type(
-                &resource_archive_path,
-                &format!("resource '{}'", res_name),
-            )?;
-            // Resources are typically extracted without stripping components
-            crate::build::extract::extract_archive(
-                &resource_archive_path,
-                &stage_path,
-                0, // strip_components = 0 for resources
-                resource_archive_type_str,
-            )?;
-            resource_stage_paths.insert(res_name, stage_path);
+            // Resources are installed from their top-level directory, where their build files
+            // are, or from the stage dir itself for flat archives
+            let source_dir =
+                extract::extract_resource_archive(&resource_archive_path, &stage_path)?;
+            extract::strip_quarantine(&stage_path);
+            resource_stage_paths.insert(res_name, source_dir);
         }
     }
 
@@ -646,46 +992,7 @@ pub async fn build_from_source(
         build_dir.display() // Build happens directly in the temp dir now
     );
 
-    // --- Build Environment Setup (remains the same) ---
-    info!("==> Setting up build environment");
-    let sapphire_prefix = config.prefix();
-    let build_env = BuildEnvironment::new(
-        formula,
-        sapphire_prefix,
-        &config.cellar,
-        all_installed_paths,
-    )?;
-
-    // --- Build Process (with CWD management) ---
-    let original_cwd = std::env::current_dir().map_err(SapphireError::Io)?;
-    info!(
-        "Changing working directory to build dir: {}",
-        build_dir.display()
-    );
-    std::env::set_current_dir(build_dir).map_err(SapphireError::Io)?;
-    // RAII guard ensures CWD is restored even if subsequent steps panic or return Err
-    let _cwd_guard = CurrentWorkingDirectoryGuard::new(original_cwd.clone());
-
-    // --- Autoreconf Check (remains the same) ---
-    if (Path::new("configure.ac").exists() || Path::new("configure.in").exists())
-        && !Path::new("configure").exists()
-    {
-        match which::which_in("autoreconf", build_env.get_path_string(), Path::new(".")) {
-            Ok(autoreconf_path) => {
-                info!("==> Running autoreconf -fvi (as configure script is missing)");
-                let mut cmd = Command::new(autoreconf_path);
-                cmd.args(["-fvi"]);
-                build_env.apply_to_command(&mut cmd);
-                match run_command(&mut cmd, "autoreconf") {
-                    Ok(_) => info!("Autoreconf completed successfully."),
-                    Err(e) => warn!("Autoreconf failed ({}). Continuing build detection...", e),
-                }
-            }
-            Err(_) => {
-                warn!("configure.ac/in found but configure script and autoreconf command are missing.");
-            }
-        }
-    }
+    build_env.checkpoint(BuildPhase::Environment)?;
 
     // --- Install Resources First (remains the same) ---
     if !resources.is_empty() {
@@ -696,12 +1003,7 @@ pub async fn build_from_source(
         for resource in &resources {
             if let Some(stage_path) = resource_stage_paths.get(&resource.name) {
                 info!(" --> Installing resource: {}", resource.name);
-                // install_resource changes CWD, ensure build_dir is restored afterward
-                // let build_dir_cwd = std::env::current_dir().map_err(SapphireError::Io)?; // Not
-                // needed due to guard install_resource needs to be called within
-                // the context of the _cwd_guard
-                install_resource(resource, stage_path, &libexec_path, &build_env)?;
-                // No need to manually restore CWD here, the guard handles it.
+                install_resource(resource, stage_path, &libexec_path, build_env)?;
             } else {
                 warn!(
                     "Could not find stage path for resource '{}'. Skipping installation.",
@@ -716,17 +1018,22 @@ pub async fn build_from_source(
         "==> Detecting build system and building main formula: {}",
         formula_name
     );
-    // CWD is now guaranteed to be the source root directory
-    detect_and_build_in_cwd(
-        formula,
-        &install_dir,
-        &build_env,
+    detect_and_build_in(
+        build_dir,
+        install_dir,
+        build_env,
         all_installed_paths, // Keep passing this for Go build
     )?;
+    if build_env.dry_run() {
+        return Err(SapphireError::DryRun(formula_name.to_string()));
+    }
+    // Backends that build and install in one step only check the earlier phases themselves
+    build_env.checkpoint(BuildPhase::Build)?;
+    build_env.checkpoint(BuildPhase::Install)?;
 
     if !install_dir.exists() {
         info!("Creating installation directory: {}", install_dir.display());
-        fs::create_dir_all(&install_dir).map_err(|e| {
+        fs::create_dir_all(install_dir).map_err(|e| {
             SapphireError::Io(std::io::Error::new(
                 e.kind(),
                 format!("Failed create install dir {}: {}", install_dir.display(), e),
@@ -738,12 +1045,47 @@ pub async fn build_from_source(
             install_dir.display()
         );
     }
-    crate::build::write_receipt(formula, &install_dir)?; // Ensure write_receipt is available
-    info!(
-        "Build completed, temporary directory {} will be cleaned up.",
-        build_dir.display()
-    );
-    Ok(install_dir)
+    if !config.skips_post_install_pass(formula.name(), PostInstallPass::Relocation) {
+        fix_dylib_ids(install_dir, &config.formula_opt_link_path(formula.name()))?;
+        share::relocate_share_paths(
+            formula.name(),
+            formula.skip_clean(),
+            install_dir,
+            &config.formula_opt_link_path(formula.name()),
+            Some(build_dir),
+            !config.skips_post_install_pass(formula.name(), PostInstallPass::Codesign),
+            build_env.warnings(),
+        )?;
+    }
+    // Stale paths in libtool archives break the builds of whatever links against them later
+    if formula.keep_la_files() {
+        rewrite_la_files(install_dir, formula.skip_clean())?;
+    } else {
+        prune_la_files(install_dir, config.prefix(), formula.skip_clean())?;
+    }
+    if !config.skips_post_install_pass(formula.name(), PostInstallPass::ShebangRewrite) {
+        let interpreters = interpreter_map(formula, config)?;
+        rewrite_shebangs(
+            install_dir,
+            &interpreters,
+            &[config.prefix(), config.cellar_path()],
+            formula.skip_clean(),
+        )?;
+    }
+    build_env.checkpoint(BuildPhase::Relocation)?;
+    // Relocation leaves what it changed unsigned, and post-install steps may run it
+    if !config.skips_post_install_pass(formula.name(), PostInstallPass::Codesign) {
+        codesign_install(install_dir)?;
+    }
+    run_post_install(
+        install_dir,
+        &PostInstallEnv::Build(build_env),
+        formula.post_install(),
+    )?;
+    if !config.skips_post_install_pass(formula.name(), PostInstallPass::LinkageCheck) {
+        linkage::verify_library_references(install_dir, config)?;
+    }
+    crate::build::write_receipt(formula, install_dir, build_env.warnings())
 }
 
 fn install_perl_resource(
@@ -751,7 +1093,7 @@ fn install_perl_resource(
     libexec_path: &Path,
     build_env: &BuildEnvironment,
 ) -> Result<()> {
-    // CWD is expected to be the resource stage_path here
+    // Commands run in the resource's stage dir, the build env's build dir here
     let perl_exe =
         which::which_in("perl", build_env.get_path_string(), Path::new(".")).map_err(|_| {
             SapphireError::BuildEnvError(
@@ -784,7 +1126,10 @@ fn install_perl_resource(
     configure_cmd
         .arg("Makefile.PL")
         .arg(format!("INSTALL_BASE={}", libexec_path.display()));
-    configure_cmd.env_clear().envs(&cmd_env); // Apply full env
+    configure_cmd
+        .env_clear()
+        .envs(&cmd_env)
+        .current_dir(build_env.build_dir()); // Apply full env
     run_command(
         &mut configure_cmd,
         &format!("Perl Makefile.PL for resource '{}'", resource.name),
@@ -792,7 +1137,10 @@ fn install_perl_resource(
 
     // Run make
     let mut make_cmd = Command::new(make_exe.clone());
-    make_cmd.env_clear().envs(&cmd_env); // Apply full env
+    make_cmd
+        .env_clear()
+        .envs(&cmd_env)
+        .current_dir(build_env.build_dir()); // Apply full env
     run_command(
         &mut make_cmd,
         &format!("make for Perl resource '{}'", resource.name),
@@ -801,7 +1149,10 @@ fn install_perl_resource(
     // Run make install
     let mut install_cmd = Command::new(make_exe);
     install_cmd.arg("install");
-    install_cmd.env_clear().envs(&cmd_env); // Apply full env
+    install_cmd
+        .env_clear()
+        .envs(&cmd_env)
+        .current_dir(build_env.build_dir()); // Apply full env
     run_command(
         &mut install_cmd,
         &format!("make install for Perl resource '{}'", resource.name),
@@ -819,7 +1170,7 @@ fn install_python_resource(
     libexec_path: &Path,
     build_env: &BuildEnvironment,
 ) -> Result<()> {
-    // CWD is expected to be the resource stage_path here
+    // Commands run in the resource's stage dir, the build env's build dir here
     let python_exe = which::which_in("python3", build_env.get_path_string(), Path::new("."))
         .or_else(|_| which::which_in("python", build_env.get_path_string(), Path::new(".")))
         .map_err(|_| {
@@ -876,7 +1227,10 @@ fn install_python_resource(
         "--prefix={}",
         libexec_path.join("vendor").display()
     )); // Install under vendor prefix
-    install_cmd.env_clear().envs(&cmd_env); // Apply full env
+    install_cmd
+        .env_clear()
+        .envs(&cmd_env)
+        .current_dir(build_env.build_dir()); // Apply full env
     run_command(
         &mut install_cmd,
         &format!("Python setup.py install for resource '{}'", resource.name),
@@ -0,0 +1,173 @@
+// sapphire-core/src/build/formula/source/ninja.rs
+// Builds projects that ship a hand-written build.ninja (no CMake or Meson generating it).
+
+use std::path::Path;
+use std::process::Command;
+
+use tracing::{debug, error, info, warn};
+
+use super::docs::{build_docs, DocTool};
+use super::make::{install_artifacts_fallback, phase_error};
+use crate::build::env::BuildEnvironment;
+use crate::build::process::run_streaming;
+use crate::build::warnings::BuildPhase;
+use crate::utils::error::{Result, SapphireError};
+
+/// Build with a plain `build.ninja` in the CWD (`ninja && ninja install`).
+///
+/// Hand-written ninja files have no standard way to receive the prefix, so it is exported as
+/// `PREFIX` for rules that read it from the environment. If there is no `install` target, or it
+/// fails, the built binary is installed with the same heuristic as the simple Makefile backend.
+pub fn ninja_build(install_dir: &Path, build_env: &BuildEnvironment) -> Result<()> {
+    info!("==> Building with build.ninja");
+    let ninja_exe = which::which_in("ninja", build_env.get_path_string(), Path::new("."))
+        .or_else(|_| which::which("ninja"))
+        .map_err(|_| {
+            SapphireError::BuildEnvError(
+                "ninja command not found in build environment PATH or system PATH.".to_string(),
+            )
+        })?;
+    // A hand-written build.ninja has no configure step
+    build_env.checkpoint(BuildPhase::Configure)?;
+
+    info!("==> Running ninja");
+    let mut cmd_build = Command::new(&ninja_exe);
+    cmd_build.args(build_env.ninja_parallel_args());
+    build_env.apply_to_command(&mut cmd_build);
+    // After the environment, which starts from a cleared one
+    cmd_build.env("PREFIX", install_dir);
+    let output_build = run_streaming(&mut cmd_build, "ninja")?;
+    if !output_build.status.success() {
+        error!("Ninja failed with status: {}", output_build.status);
+        error!(
+            "Ninja stdout:\n{}",
+            String::from_utf8_lossy(&output_build.stdout)
+        );
+        error!(
+            "Ninja stderr:\n{}",
+            String::from_utf8_lossy(&output_build.stderr)
+        );
+        return Err(SapphireError::Generic(format!(
+            "Ninja failed with status: {}",
+            output_build.status
+        )));
+    }
+    debug!("Ninja build completed successfully.");
+    build_env.checkpoint(BuildPhase::Build)?;
+
+    if !has_install_target(&ninja_exe, build_env) {
+        warn!(
+            "build.ninja has no 'install' target. Will check for manually installable artifacts."
+        );
+        return install_artifacts_fallback(
+            install_dir,
+            "ninja",
+            Some(SapphireError::Generic(
+                "build.ninja has no install target, and no artifacts were found to install \
+                 manually"
+                    .to_string(),
+            )),
+            build_env,
+        );
+    }
+
+    info!("==> Running ninja install");
+    let mut cmd_install = Command::new(&ninja_exe);
+    cmd_install.arg("install");
+    build_env.apply_to_command(&mut cmd_install);
+    cmd_install.env("PREFIX", install_dir);
+    let output_install = run_streaming(&mut cmd_install, "ninja install")?;
+    let install_error = if output_install.status.success() {
+        debug!("Ninja install completed successfully.");
+        None
+    } else {
+        warn!(
+            "'ninja install' failed with status {}. Will check for manually installable artifacts.",
+            output_install.status
+        );
+        debug!(
+            "Ninja install stderr:\n{}",
+            String::from_utf8_lossy(&output_install.stderr)
+        );
+        Some(phase_error(
+            BuildPhase::Install,
+            "ninja install",
+            &output_install,
+            &[],
+        ))
+    };
+    install_artifacts_fallback(install_dir, "ninja install", install_error, build_env)?;
+    build_docs(
+        DocTool::Ninja(&ninja_exe),
+        build_env.build_dir(),
+        build_env.build_dir(),
+        install_dir,
+        build_env,
+    )
+}
+
+/// Runs the default target of the generated build graph in `dir`, so a CMake or Meson build is
+/// compiled as its own step before anything is installed.
+pub(super) fn ninja_compile(
+    ninja_exe: &Path,
+    dir: &Path,
+    build_env: &BuildEnvironment,
+    context: &str,
+) -> Result<()> {
+    info!("==> Running ninja in {}", dir.display());
+    let mut cmd = Command::new(ninja_exe);
+    cmd.args(build_env.ninja_parallel_args()).current_dir(dir);
+    build_env.apply_to_command(&mut cmd);
+    let output = run_streaming(&mut cmd, context)?;
+    if !output.status.success() {
+        error!(
+            "{} stderr:\n{}",
+            context,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(SapphireError::Generic(format!(
+            "{} failed with status: {}",
+            context, output.status
+        )));
+    }
+    debug!("{} completed successfully.", context);
+    Ok(())
+}
+
+/// Asks ninja whether the build graph defines a target named `install`.
+fn has_install_target(ninja_exe: &Path, build_env: &BuildEnvironment) -> bool {
+    match ninja_targets(ninja_exe, build_env.build_dir(), build_env) {
+        Some(targets) => targets.iter().any(|t| t == "install"),
+        None => {
+            debug!("Could not list ninja targets; assuming no install target");
+            false
+        }
+    }
+}
+
+/// Lists every target in the build graph in `dir`, or `None` if ninja can't list them.
+pub(super) fn ninja_targets(
+    ninja_exe: &Path,
+    dir: &Path,
+    build_env: &BuildEnvironment,
+) -> Option<Vec<String>> {
+    let mut cmd = Command::new(ninja_exe);
+    cmd.args(["-t", "targets", "all"]).current_dir(dir);
+    build_env.apply_to_command(&mut cmd);
+    match run_streaming(&mut cmd, "ninja -t targets") {
+        Ok(output) if output.status.success() => Some(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.split_once(':').map(|(t, _)| t.trim().to_string()))
+                .collect(),
+        ),
+        Ok(output) => {
+            debug!("ninja -t targets failed with status {}", output.status);
+            None
+        }
+        Err(e) => {
+            debug!("ninja -t targets failed: {}", e);
+            None
+        }
+    }
+}
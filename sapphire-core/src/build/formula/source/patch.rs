@@ -0,0 +1,142 @@
+// sapphire-core/src/build/formula/source/patch.rs
+// Applies a formula's patches to its staged source before the build system is detected, so
+// patches may add or fix build files too.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{debug, info};
+
+use crate::build::env::BuildEnvironment;
+use crate::fetch::http as http_fetch;
+use crate::model::formula::{Formula, ResourceSpec};
+use crate::utils::config::Config;
+use crate::utils::error::{Result, SapphireError};
+
+/// Resolves `formula`'s patches to files on disk, in order. Remote patches are downloaded to the
+/// resource cache and checked against their SHA-256; local ones must exist. Relative local
+/// paths resolve against the directory of the formula's definition, never the process CWD.
+pub async fn fetch_patches(formula: &Formula, config: &Config) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::with_capacity(formula.patches().len());
+    for (index, spec) in formula.patches().iter().enumerate() {
+        if !spec.is_remote() {
+            let path = local_patch_path(formula, &spec.url)?;
+            if !path.is_file() {
+                return Err(SapphireError::NotFound(format!(
+                    "Patch {} of {} not found",
+                    path.display(),
+                    formula.name()
+                )));
+            }
+            paths.push(path);
+            continue;
+        }
+        if spec.sha256.is_empty() {
+            return Err(SapphireError::Generic(format!(
+                "Remote patch {} of {} has no sha256",
+                spec.url,
+                formula.name()
+            )));
+        }
+        info!(" --> Downloading patch: {}", spec.url);
+        let resource = ResourceSpec {
+            name: format!("patch{}", index),
+            url: spec.url.clone(),
+            sha256: spec.sha256.clone(),
+        };
+        paths.push(http_fetch::fetch_resource(formula.name(), &resource, config, &|_| {}).await?);
+    }
+    Ok(paths)
+}
+
+/// `url` of a local patch as a path: absolute paths as they are, relative ones under the
+/// directory of `formula`'s definition.
+fn local_patch_path(formula: &Formula, url: &str) -> Result<PathBuf> {
+    let path = Path::new(url);
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    formula
+        .definition_dir()
+        .map(|dir| dir.join(path))
+        .ok_or_else(|| {
+            SapphireError::Generic(format!(
+                "Patch {} of {} is relative, but the formula wasn't loaded from a tap",
+                url,
+                formula.name()
+            ))
+        })
+}
+
+/// Finds `patch` on `search_path`, falling back to the system PATH.
+pub(crate) fn find_patch_exe(search_path: Option<&str>) -> Result<PathBuf> {
+    which::which_in("patch", search_path, Path::new("."))
+        .or_else(|_| which::which("patch"))
+        .map_err(|_| {
+            SapphireError::BuildEnvError(
+                "patch command not found in build environment PATH or system PATH.".to_string(),
+            )
+        })
+}
+
+/// The `patch -p1` command applying `patch` to the tree in `source_dir`, or with `dry_run`
+/// only checking that it would apply. `patch` should be absolute since the command runs in
+/// `source_dir`.
+pub(crate) fn patch_command(
+    patch_exe: &Path,
+    source_dir: &Path,
+    patch: &Path,
+    dry_run: bool,
+) -> Command {
+    let mut cmd = Command::new(patch_exe);
+    cmd.args(["-p1", "--forward", "--batch", "-i"]).arg(patch);
+    if dry_run {
+        cmd.arg("--dry-run");
+    }
+    cmd.current_dir(source_dir);
+    cmd
+}
+
+/// `patch` made absolute, failing if it can't be read.
+pub(crate) fn canonical_patch(patch: &Path) -> Result<PathBuf> {
+    patch.canonicalize().map_err(|e| {
+        SapphireError::IoError(format!("Cannot read patch {}: {}", patch.display(), e))
+    })
+}
+
+/// Applies `patches` in order to the source tree in `build_dir` with `patch -p1`. Stops at the
+/// first patch that doesn't apply, naming it along with the output of `patch`. In a dry run the
+/// commands are printed instead.
+pub fn apply_patches(
+    build_dir: &Path,
+    patches: &[PathBuf],
+    build_env: &BuildEnvironment,
+) -> Result<()> {
+    if patches.is_empty() {
+        return Ok(());
+    }
+    let patch_exe = find_patch_exe(build_env.get_path_string())?;
+
+    for patch in patches {
+        let patch = canonical_patch(patch)?;
+        info!("==> Applying patch {}", patch.display());
+        let mut cmd = patch_command(&patch_exe, build_dir, &patch, false);
+        build_env.apply_to_command(&mut cmd);
+        let output = build_env.run_or_print(&mut cmd, "patch", |cmd| {
+            cmd.output()
+                .map_err(|e| SapphireError::CommandExecError(format!("Failed to run patch: {}", e)))
+        })?;
+        if !output.status.success() {
+            // patch reports failed hunks on stdout and usage problems on stderr
+            return Err(SapphireError::CommandExecError(format!(
+                "Patch {} failed to apply ({}):\n{}{}",
+                patch.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )));
+        }
+        debug!("{}", String::from_utf8_lossy(&output.stdout).trim_end());
+    }
+    Ok(())
+}
@@ -1,19 +1,21 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::process::Command;
 
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 use crate::build::env::BuildEnvironment;
+use crate::build::process::run_streaming;
+use crate::build::warnings::BuildPhase;
 use crate::utils::error::{Result, SapphireError};
 
-/// Build Perl using its Configure script or Makefile.PL
+/// Build Perl in `source_dir` using its Configure script or Makefile.PL
 pub fn perl_build(
-    _source_dot: &Path, // <--- Renamed and prefixed with underscore
+    source_dir: &Path,
     install_dir: &Path,
     build_env: &BuildEnvironment,
 ) -> Result<()> {
-    let configure_script = PathBuf::from("Configure");
-    let makefile_pl = PathBuf::from("Makefile.PL");
+    let configure_script = source_dir.join("Configure");
+    let makefile_pl = source_dir.join("Makefile.PL");
 
     // Determine which script to use
     if configure_script.exists() {
@@ -33,18 +35,16 @@ pub fn perl_build(
 
         build_env.apply_to_command(&mut cmd);
         info!("Running Perl Configure: {:?}", cmd);
-        let output = cmd.output().map_err(|e| {
-            SapphireError::CommandExecError(format!("Failed to execute Perl Configure: {}", e))
-        })?;
+        let output = run_streaming(&mut cmd, "Perl Configure")?;
 
         if !output.status.success() {
             // (Error handling remains the same)
-            println!("Perl Configure failed with status: {}", output.status);
-            eprintln!(
+            error!("Perl Configure failed with status: {}", output.status);
+            error!(
                 "Perl Configure stdout:\n{}",
                 String::from_utf8_lossy(&output.stdout)
             );
-            eprintln!(
+            error!(
                 "Perl Configure stderr:\n{}",
                 String::from_utf8_lossy(&output.stderr)
             );
@@ -79,18 +79,16 @@ pub fn perl_build(
 
         build_env.apply_to_command(&mut cmd);
         info!("Running perl Makefile.PL: {:?}", cmd);
-        let output = cmd.output().map_err(|e| {
-            SapphireError::CommandExecError(format!("Failed to execute perl Makefile.PL: {}", e))
-        })?;
+        let output = run_streaming(&mut cmd, "perl Makefile.PL")?;
 
         if !output.status.success() {
             // (Error handling)
-            println!("perl Makefile.PL failed with status: {}", output.status);
-            eprintln!(
+            error!("perl Makefile.PL failed with status: {}", output.status);
+            error!(
                 "perl Makefile.PL stdout:\n{}",
                 String::from_utf8_lossy(&output.stdout)
             );
-            eprintln!(
+            error!(
                 "perl Makefile.PL stderr:\n{}",
                 String::from_utf8_lossy(&output.stderr)
             );
@@ -113,6 +111,7 @@ pub fn perl_build(
             "Neither Perl Configure nor Makefile.PL script found in CWD.".to_string(),
         ));
     }
+    build_env.checkpoint(BuildPhase::Configure)?;
 
     // Run make (common step for both Configure and Makefile.PL)
     info!("==> Running make for Perl");
@@ -124,18 +123,16 @@ pub fn perl_build(
         })?;
     let mut make_cmd = Command::new(make_exe.clone());
     build_env.apply_to_command(&mut make_cmd);
-    let output_make = make_cmd.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute make for Perl: {}", e))
-    })?;
+    let output_make = run_streaming(&mut make_cmd, "make for Perl")?;
 
     if !output_make.status.success() {
         // (Error handling remains the same)
-        println!("Perl make failed with status: {}", output_make.status);
-        eprintln!(
+        error!("Perl make failed with status: {}", output_make.status);
+        error!(
             "Perl make stdout:\n{}",
             String::from_utf8_lossy(&output_make.stdout)
         );
-        eprintln!(
+        error!(
             "Perl make stderr:\n{}",
             String::from_utf8_lossy(&output_make.stderr)
         );
@@ -146,27 +143,26 @@ pub fn perl_build(
     } else {
         info!("Perl make completed successfully.");
     }
+    build_env.checkpoint(BuildPhase::Build)?;
 
     // Run make install
     info!("==> Running make install for Perl");
     let mut install_cmd = Command::new(make_exe);
     install_cmd.arg("install");
     build_env.apply_to_command(&mut install_cmd);
-    let output_install = install_cmd.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute make install for Perl: {}", e))
-    })?;
+    let output_install = run_streaming(&mut install_cmd, "make install for Perl")?;
 
     if !output_install.status.success() {
         // (Error handling remains the same)
-        println!(
+        error!(
             "Perl make install failed with status: {}",
             output_install.status
         );
-        eprintln!(
+        error!(
             "Perl make install stdout:\n{}",
             String::from_utf8_lossy(&output_install.stdout)
         );
-        eprintln!(
+        error!(
             "Perl make install stderr:\n{}",
             String::from_utf8_lossy(&output_install.stderr)
         );
@@ -0,0 +1,240 @@
+// sapphire-core/src/build/formula/source/post_install.rs
+// Runs a formula's post-install steps (creating directories, generating caches, fixing
+// permissions) in the keg once a source build has been installed, or a bottle poured, and
+// relocated.
+
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use tracing::info;
+
+use crate::build::env::BuildEnvironment;
+use crate::build::process::run_streaming;
+use crate::build::warnings::{BuildPhase, WarningCode, WarningCollector};
+use crate::model::formula::{PostInstallAction, PostInstallStep};
+use crate::utils::error::{Result, SapphireError};
+
+/// Lines of a failed command's output quoted in the error.
+const FAILURE_TAIL_LINES: usize = 20;
+
+/// The environment post-install commands run in.
+pub enum PostInstallEnv<'a> {
+    /// A source build's, which also collects the warnings.
+    Build(&'a BuildEnvironment),
+    /// A poured bottle has no build environment: commands run in the user's environment with
+    /// `path` as PATH (see [`crate::build::formula::runtime_path`]).
+    Pour {
+        path: OsString,
+        warnings: &'a WarningCollector,
+    },
+}
+
+impl PostInstallEnv<'_> {
+    fn path(&self) -> Option<OsString> {
+        match self {
+            Self::Build(build_env) => build_env.get_path_string().map(OsString::from),
+            Self::Pour { path, .. } => Some(path.clone()),
+        }
+    }
+
+    fn apply_to_command(&self, cmd: &mut Command) {
+        match self {
+            Self::Build(build_env) => build_env.apply_to_command(cmd),
+            Self::Pour { path, .. } => {
+                cmd.env("PATH", path);
+            }
+        }
+    }
+
+    fn warnings(&self) -> &WarningCollector {
+        match self {
+            Self::Build(build_env) => build_env.warnings(),
+            Self::Pour { warnings, .. } => warnings,
+        }
+    }
+}
+
+/// Runs `steps` in order in the keg at `install_dir`. The first failing step fails the install,
+/// with the command output where there is some, unless it is optional: then it is recorded as
+/// a build warning and the remaining steps still run.
+pub fn run_post_install(
+    install_dir: &Path,
+    env: &PostInstallEnv,
+    steps: &[PostInstallStep],
+) -> Result<()> {
+    for step in steps {
+        info!("==> Post-install: {}", step.action);
+        let Err(e) = run_step(install_dir, env, &step.action) else {
+            continue;
+        };
+        if !step.optional {
+            return Err(SapphireError::InstallError(format!(
+                "Post-install step `{}` failed: {}",
+                step.action, e
+            )));
+        }
+        env.warnings().warn(
+            WarningCode::ToolFailure,
+            BuildPhase::Install,
+            format!("Optional post-install step `{}` failed: {}", step.action, e),
+            Some(install_dir),
+        );
+    }
+    Ok(())
+}
+
+fn run_step(install_dir: &Path, env: &PostInstallEnv, action: &PostInstallAction) -> Result<()> {
+    match action {
+        PostInstallAction::RunCommand { args } => run_command(install_dir, env, args),
+        PostInstallAction::CreateDir { path } => {
+            Ok(fs::create_dir_all(keg_path(install_dir, path)?)?)
+        }
+        PostInstallAction::Symlink { target, link } => {
+            // The link itself is replaced, so only the directory it goes in has to be in the keg
+            let (parent, name) = link.rsplit_once('/').unwrap_or((".", link));
+            let link = keg_path(install_dir, parent)?.join(keg_relative(name)?);
+            if let Some(parent) = link.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if link.symlink_metadata().is_ok() {
+                fs::remove_file(&link)?;
+            }
+            Ok(std::os::unix::fs::symlink(target, &link)?)
+        }
+        PostInstallAction::Chmod { path, mode } => {
+            let mode = u32::from_str_radix(mode, 8)
+                .map_err(|_| SapphireError::Generic(format!("invalid octal mode `{}`", mode)))?;
+            let path = keg_path(install_dir, path)?;
+            Ok(fs::set_permissions(path, fs::Permissions::from_mode(mode))?)
+        }
+    }
+}
+
+fn run_command(install_dir: &Path, env: &PostInstallEnv, args: &[String]) -> Result<()> {
+    let Some((program, args)) = args.split_first() else {
+        return Err(SapphireError::Generic("no command given".to_string()));
+    };
+    let program = if program.contains('/') {
+        keg_path(install_dir, program)?
+    } else {
+        which::which_in(program, env.path(), install_dir).map_err(|_| {
+            SapphireError::BuildEnvError(format!("{} not found on the build PATH", program))
+        })?
+    };
+    let prefix = install_dir.to_string_lossy();
+    let mut cmd = Command::new(&program);
+    cmd.args(args.iter().map(|arg| arg.replace("{prefix}", &prefix)))
+        .current_dir(install_dir);
+    env.apply_to_command(&mut cmd);
+    let output = run_streaming(&mut cmd, "post-install")?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let text = if stderr.trim().is_empty() {
+        stdout
+    } else {
+        stderr
+    };
+    let lines: Vec<&str> = text.lines().collect();
+    let tail = lines[lines.len().saturating_sub(FAILURE_TAIL_LINES)..].join("\n");
+    Err(SapphireError::CommandExecError(format!(
+        "{} exited with {}\n{}",
+        program.display(),
+        output.status,
+        tail
+    )))
+}
+
+/// `rel` inside the keg. Absolute paths and `..` are refused so a step can't touch anything
+/// outside it, and so are paths leading out of it through a symlink, such as one an earlier step
+/// created.
+fn keg_path(install_dir: &Path, rel: &str) -> Result<PathBuf> {
+    let path = install_dir.join(keg_relative(rel)?);
+    // What doesn't exist yet can't be a symlink, so the rest resolves inside the keg
+    let existing = path
+        .ancestors()
+        .find(|p| p.symlink_metadata().is_ok())
+        .unwrap_or(install_dir);
+    let inside = match (fs::canonicalize(existing), fs::canonicalize(install_dir)) {
+        (Ok(resolved), Ok(keg)) => resolved.starts_with(keg),
+        _ => false,
+    };
+    if !inside {
+        return Err(SapphireError::Generic(format!(
+            "`{}` leads out of the keg through a symlink",
+            rel
+        )));
+    }
+    Ok(path)
+}
+
+/// `rel` as a relative path, refusing absolute paths and `..`.
+fn keg_relative(rel: &str) -> Result<&Path> {
+    let rel_path = Path::new(rel);
+    if rel_path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(SapphireError::Generic(format!(
+            "`{}` is not a path inside the keg",
+            rel
+        )));
+    }
+    Ok(rel_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_cannot_follow_a_symlink_out_of_the_keg() {
+        let tmp = tempfile::tempdir().unwrap();
+        let keg = tmp.path().join("keg");
+        let outside = tmp.path().join("outside");
+        fs::create_dir_all(keg.join("share")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        let warnings = WarningCollector::new();
+        let env = PostInstallEnv::Pour {
+            path: OsString::new(),
+            warnings: &warnings,
+        };
+        let step = |action| PostInstallStep {
+            action,
+            optional: false,
+        };
+
+        let steps = [
+            step(PostInstallAction::Symlink {
+                target: outside.to_string_lossy().into_owned(),
+                link: "share/out".to_string(),
+            }),
+            step(PostInstallAction::CreateDir {
+                path: "share/out/evil".to_string(),
+            }),
+        ];
+        assert!(run_post_install(&keg, &env, &steps).is_err());
+        assert!(!outside.join("evil").exists());
+
+        // Replacing the link itself is fine, and so are links inside the keg
+        let steps = [
+            step(PostInstallAction::Symlink {
+                target: "../lib".to_string(),
+                link: "share/out".to_string(),
+            }),
+            step(PostInstallAction::CreateDir {
+                path: "lib/foo".to_string(),
+            }),
+            step(PostInstallAction::CreateDir {
+                path: "share/out/bar".to_string(),
+            }),
+        ];
+        run_post_install(&keg, &env, &steps).unwrap();
+        assert!(keg.join("lib/bar").is_dir());
+    }
+}
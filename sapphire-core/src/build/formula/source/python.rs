@@ -1,51 +1,63 @@
 use std::path::Path;
 use std::process::Command;
 
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 use crate::build::env::BuildEnvironment;
+use crate::build::process::run_streaming;
+use crate::build::warnings::BuildPhase;
 use crate::utils::error::{Result, SapphireError};
 
-/// Build with Python setup.py
+/// Build a Python project (`setup.py` or `pyproject.toml`) with
+/// `pip install --prefix=<install_dir> --no-deps --no-build-isolation .`.
+///
+/// Dependencies are separate formulae or resources, so pip neither fetches them nor a fresh
+/// build backend. The scripts pip installs name the interpreter it resolved; the shebang
+/// rewrite after install points them at the Python dependency's stable opt path.
 pub fn python_build(install_dir: &Path, build_env: &BuildEnvironment) -> Result<()> {
-    info!("==> Building with Python setup.py");
+    info!("==> Building with pip");
     let python_exe = which::which_in("python3", build_env.get_path_string(), Path::new("."))
         .or_else(|_| which::which_in("python", build_env.get_path_string(), Path::new(".")))
         .map_err(|_| {
             SapphireError::BuildEnvError(
-                "python3 or python command not found in build environment PATH.".to_string(),
+                "python3 or python command not found in build environment PATH; add python as \
+                 a build dependency."
+                    .to_string(),
             )
         })?;
 
     info!(
-        "==> Running {} setup.py install --prefix={}",
+        "==> Running {} -m pip install --prefix={} --no-deps --no-build-isolation .",
         python_exe.display(),
         install_dir.display()
     );
-    let mut cmd = Command::new(python_exe);
-    cmd.arg("setup.py")
-        .arg("install")
-        .arg(format!("--prefix={}", install_dir.display()));
+    // `pip install` configures, builds and installs in one go
+    build_env.checkpoint(BuildPhase::Configure)?;
+    let mut cmd = Command::new(&python_exe);
+    cmd.args(["-m", "pip", "install"])
+        .arg(format!("--prefix={}", install_dir.display()))
+        .args([
+            "--no-deps",
+            "--no-build-isolation",
+            "--no-warn-script-location",
+            "--disable-pip-version-check",
+            ".",
+        ]);
     build_env.apply_to_command(&mut cmd);
-    let output = cmd.output().map_err(|e| {
-        SapphireError::CommandExecError(format!("Failed to execute python setup.py install: {}", e))
-    })?;
+    let output = run_streaming(&mut cmd, "pip install")?;
 
     if !output.status.success() {
-        println!(
-            "Python setup.py install failed with status: {}",
-            output.status
-        );
-        eprintln!(
+        error!("pip install failed with status: {}", output.status);
+        error!(
             "Python install stdout:\n{}",
             String::from_utf8_lossy(&output.stdout)
         );
-        eprintln!(
+        error!(
             "Python install stderr:\n{}",
             String::from_utf8_lossy(&output.stderr)
         );
         return Err(SapphireError::Generic(format!(
-            "Python setup.py install failed with status: {}",
+            "pip install failed with status: {}",
             output.status
         )));
     } else {
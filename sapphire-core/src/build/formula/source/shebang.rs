@@ -0,0 +1,257 @@
+// sapphire-core/src/build/formula/source/shebang.rs
+// Scripts installed by a build often start with the interpreter the build happened to find,
+// like `#!/usr/bin/python3.11`, which may not exist on the machine the keg ends up on. Known
+// interpreters' shebangs are pointed at the same interpreter (version included) in the
+// sapphire-installed formula the formula depends on, through its stable opt path, or else at
+// whichever one `/usr/bin/env` finds.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info};
+use walkdir::WalkDir;
+
+use crate::build::formula::skips_clean;
+use crate::model::formula::{Formula, PathPattern};
+use crate::utils::config::Config;
+use crate::utils::error::Result;
+
+/// Interpreters whose shebangs are rewritten unless a formula lists its own.
+pub const DEFAULT_INTERPRETERS: &[&str] = &["python", "perl", "ruby"];
+
+/// Where shebangs naming an interpreter are pointed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShebangTarget {
+    /// A `bin` directory holding the interpreter, normally under a dependency's opt path.
+    Dir(PathBuf),
+    /// `/usr/bin/env`, for an interpreter no dependency provides.
+    Env,
+}
+
+impl ShebangTarget {
+    /// The shebang line running `command`, the file name of the interpreter a script named. A
+    /// command missing from a `Dir` target, such as another version, is left to `env`.
+    fn line(&self, command: &str) -> String {
+        match self {
+            Self::Dir(dir) if dir.join(command).is_file() => {
+                format!("#!{}", dir.join(command).display())
+            }
+            _ => format!("#!/usr/bin/env {}", command),
+        }
+    }
+}
+
+/// Interpreter names (`python`, `perl`...) mapped to where their shebangs are pointed. A name
+/// matches an interpreter file name that is either the name itself or the name followed by a
+/// version, so `python` covers `python3` and `python3.11`.
+pub type InterpreterMap = BTreeMap<String, ShebangTarget>;
+
+/// The interpreter map for `formula`: each interpreter it asks to rewrite (by default those in
+/// [`DEFAULT_INTERPRETERS`]) is pointed at the `bin` directory of the first of its dependencies
+/// whose opt path has a matching executable there, or at `/usr/bin/env`.
+pub fn interpreter_map(formula: &Formula, config: &Config) -> Result<InterpreterMap> {
+    let dependencies = formula.dependencies()?;
+    let names: Vec<String> = match formula.rewrite_shebangs() {
+        Some(names) => names.to_vec(),
+        None => DEFAULT_INTERPRETERS.iter().map(|n| n.to_string()).collect(),
+    };
+
+    let mut map = InterpreterMap::new();
+    for name in names {
+        let provided = dependencies.iter().find_map(|dep| {
+            let bin = config.formula_opt_link_path(&dep.name).join("bin");
+            let has_interpreter = fs::read_dir(&bin).ok()?.flatten().any(|e| {
+                names_interpreter(&name, &e.file_name().to_string_lossy()) && e.path().is_file()
+            });
+            has_interpreter.then_some(bin)
+        });
+        map.insert(
+            name,
+            provided.map_or(ShebangTarget::Env, ShebangTarget::Dir),
+        );
+    }
+    Ok(map)
+}
+
+/// Rewrites the shebangs of the scripts in `bin/` and `lib/python*/site-packages` of
+/// `install_dir` that run an interpreter in `interpreter_map` directly, and returns how many
+/// were rewritten.
+///
+/// Binary files, scripts already run through `env`, and scripts whose interpreter is already
+/// inside `install_dir` or one of `managed_roots` (the prefix, the Cellar) are left alone; the
+/// latter covers a formula that installs the interpreter itself. A shebang with arguments
+/// pointed at `env` gets `env -S`, which splits them from the command name. A rewritten script
+/// keeps its permissions.
+pub fn rewrite_shebangs(
+    install_dir: &Path,
+    interpreter_map: &InterpreterMap,
+    managed_roots: &[&Path],
+    skip_clean: &[PathPattern],
+) -> Result<usize> {
+    if interpreter_map.is_empty() {
+        return Ok(0);
+    }
+    let mut roots = vec![install_dir.join("bin")];
+    if let Ok(entries) = fs::read_dir(install_dir.join("lib")) {
+        roots.extend(
+            entries
+                .flatten()
+                .filter(|e| e.file_name().to_string_lossy().starts_with("python"))
+                .map(|e| e.path().join("site-packages")),
+        );
+    }
+
+    let mut rewritten = 0;
+    for root in roots.iter().filter(|r| r.is_dir()) {
+        for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file()
+                && rewrite_shebang(
+                    install_dir,
+                    entry.path(),
+                    interpreter_map,
+                    managed_roots,
+                    skip_clean,
+                )?
+            {
+                rewritten += 1;
+            }
+        }
+    }
+    if rewritten > 0 {
+        info!("==> Rewrote the shebangs of {} script(s)", rewritten);
+    }
+    Ok(rewritten)
+}
+
+/// Rewrites the shebang of the file at `path` if it needs it; returns whether it did.
+fn rewrite_shebang(
+    install_dir: &Path,
+    path: &Path,
+    interpreter_map: &InterpreterMap,
+    managed_roots: &[&Path],
+    skip_clean: &[PathPattern],
+) -> Result<bool> {
+    let Some((interpreter, args)) = read_shebang(path) else {
+        return Ok(false);
+    };
+    if interpreter.starts_with(install_dir)
+        || managed_roots
+            .iter()
+            .any(|root| interpreter.starts_with(root))
+    {
+        return Ok(false);
+    }
+    let Some(command) = interpreter.file_name().map(|n| n.to_string_lossy()) else {
+        return Ok(false);
+    };
+    let Some(target) = target_for(&command, interpreter_map) else {
+        return Ok(false);
+    };
+    if skips_clean(skip_clean, install_dir, path, "shebang rewrite") {
+        return Ok(false);
+    }
+    let target_line = target.line(&command);
+    let line = if args.is_empty() {
+        target_line
+    } else if let Some(env_command) = target_line.strip_prefix("#!/usr/bin/env ") {
+        // The kernel passes everything after the interpreter as one argument
+        format!("#!/usr/bin/env -S {} {}", env_command, args)
+    } else {
+        format!("{} {}", target_line, args)
+    };
+    replace_first_line(path, &line)?;
+    debug!(
+        "Rewrote shebang of {} from {}",
+        path.display(),
+        interpreter.display()
+    );
+    Ok(true)
+}
+
+fn target_for<'a>(command: &str, map: &'a InterpreterMap) -> Option<&'a ShebangTarget> {
+    map.iter()
+        .find(|(name, _)| names_interpreter(name, command))
+        .map(|(_, target)| target)
+}
+
+/// Whether `file_name` is the interpreter `name`, bare or followed by a version.
+fn names_interpreter(name: &str, file_name: &str) -> bool {
+    file_name
+        .strip_prefix(name)
+        .is_some_and(|version| version.chars().all(|c| c.is_ascii_digit() || c == '.'))
+}
+
+/// The interpreter and arguments of `path`'s shebang line, if it is a text file whose shebang
+/// runs an interpreter directly (not via `env`).
+fn read_shebang(path: &Path) -> Option<(PathBuf, String)> {
+    let mut head = [0u8; 512];
+    let n = fs::File::open(path).ok()?.read(&mut head).ok()?;
+    let head = &head[..n];
+    if !head.starts_with(b"#!") || head.contains(&0) {
+        return None;
+    }
+    let line = head[2..].split(|&b| b == b'\n').next()?;
+    let line = std::str::from_utf8(line).ok()?.trim();
+    let (interpreter, args) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(i, a)| (i, a.trim()));
+    let interpreter = PathBuf::from(interpreter);
+    if interpreter.file_name()? == "env" {
+        return None;
+    }
+    Some((interpreter, args.to_string()))
+}
+
+/// Replaces the first line of `path` with `line`, keeping its permissions. Installed scripts are
+/// often read-only, so they are made writable for the write and then restored.
+fn replace_first_line(path: &Path, line: &str) -> Result<()> {
+    let content = fs::read(path)?;
+    let rest = content
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(&[][..], |i| &content[i + 1..]);
+    let mut new_content = Vec::with_capacity(line.len() + 1 + rest.len());
+    new_content.extend_from_slice(line.as_bytes());
+    new_content.push(b'\n');
+    new_content.extend_from_slice(rest);
+
+    let permissions = fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    if mode & 0o200 == 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode | 0o200))?;
+    }
+    let written = fs::write(path, new_content);
+    fs::set_permissions(path, permissions)?;
+    Ok(written?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_targets_split_arguments_with_dash_s() {
+        let keg = tempfile::tempdir().unwrap();
+        let bin = keg.path().join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("tool"), "#!/usr/bin/perl -w\nprint 1;\n").unwrap();
+        fs::write(bin.join("plain"), "#!/usr/bin/python3\nprint(1)\n").unwrap();
+
+        let map = InterpreterMap::from([
+            ("perl".to_string(), ShebangTarget::Env),
+            ("python".to_string(), ShebangTarget::Env),
+        ]);
+        assert_eq!(rewrite_shebangs(keg.path(), &map, &[], &[]).unwrap(), 2);
+        assert_eq!(
+            fs::read_to_string(bin.join("tool")).unwrap(),
+            "#!/usr/bin/env -S perl -w\nprint 1;\n"
+        );
+        assert_eq!(
+            fs::read_to_string(bin.join("plain")).unwrap(),
+            "#!/usr/bin/env python3\nprint(1)\n"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+// sapphire-core/src/build/formula/test.rs
+// Runs a formula's declared smoke test against its installed keg, like `brew test`: each
+// command runs in a fresh temporary directory, which is also its HOME, and must exit with the
+// expected code and print the expected output within a time limit.
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use tracing::{debug, info};
+
+use crate::build::formula::runtime_path;
+use crate::build::process::run_with_timeout;
+use crate::model::formula::{FormulaTest, TestCommand};
+use crate::utils::error::{Result, SapphireError};
+
+/// How long a test command may run before it is killed and fails, as with `brew test`.
+const TEST_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Runs the commands of `test` in order against the keg at `install_dir`, stopping at the first
+/// that fails. Commands find the keg's executables, then those of `dep_opt_paths` (the opt paths
+/// of the formula's dependencies) and `prefix`, first on `PATH`.
+pub fn run_formula_test(
+    install_dir: &Path,
+    test: &FormulaTest,
+    dep_opt_paths: &[PathBuf],
+    prefix: &Path,
+) -> Result<()> {
+    if !install_dir.is_dir() {
+        return Err(SapphireError::NotFound(format!(
+            "No keg at {}",
+            install_dir.display()
+        )));
+    }
+    let path = runtime_path(install_dir, dep_opt_paths, prefix);
+    for command in &test.commands {
+        info!("==> Testing: {}", command.args.join(" "));
+        run_test_command(install_dir, command, &path)?;
+    }
+    Ok(())
+}
+
+fn run_test_command(install_dir: &Path, command: &TestCommand, path: &OsString) -> Result<()> {
+    let Some((program, args)) = command.args.split_first() else {
+        return Err(SapphireError::Generic("test command is empty".to_string()));
+    };
+    let program = test_program(install_dir, program, path)?;
+    let prefix = install_dir.to_string_lossy();
+    let args: Vec<String> = args
+        .iter()
+        .map(|a| a.replace("{prefix}", &prefix))
+        .collect();
+    let command_line = std::iter::once(program.display().to_string())
+        .chain(args.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let test_dir = tempfile::Builder::new()
+        .prefix("sapphire-test-")
+        .tempdir()?;
+    debug!(
+        "Running `{}` in {}",
+        command_line,
+        test_dir.path().display()
+    );
+    let mut cmd = Command::new(&program);
+    cmd.args(&args)
+        .current_dir(test_dir.path())
+        // Keep tests from reading or writing the user's dotfiles
+        .env("HOME", test_dir.path())
+        .env("PATH", path);
+    let output = run_with_timeout(&mut cmd, &command_line, TEST_TIMEOUT)?;
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let exit_code = output.status.code();
+    let reason = if exit_code != Some(command.exit_code) {
+        Some(match exit_code {
+            Some(code) => format!("exited with code {} (expected {})", code, command.exit_code),
+            None => "terminated by a signal".to_string(),
+        })
+    } else {
+        command
+            .expect_output
+            .iter()
+            .find(|expected| !printed.contains(expected.as_str()))
+            .map(|expected| format!("output does not contain `{}`", expected))
+    };
+    match reason {
+        None => Ok(()),
+        Some(reason) => Err(SapphireError::TestFailed {
+            command: command_line,
+            exit_code,
+            reason,
+            output: printed,
+        }),
+    }
+}
+
+/// The executable `program` names: a path, relative to the keg unless absolute, if it has a `/`
+/// in it, else the first on `path`, which starts with the keg's `bin`.
+fn test_program(install_dir: &Path, program: &str, path: &OsStr) -> Result<PathBuf> {
+    if program.contains('/') {
+        let path = install_dir.join(program);
+        return if path.is_file() {
+            Ok(path)
+        } else {
+            Err(SapphireError::NotFound(format!(
+                "{} does not exist",
+                program
+            )))
+        };
+    }
+    which::which_in(program, Some(path), install_dir).map_err(|_| {
+        SapphireError::NotFound(format!("{} not found in the keg or on PATH", program))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn commands_find_the_kegs_and_dependencies_executables() {
+        let tmp = tempfile::tempdir().unwrap();
+        let keg = tmp.path().join("Cellar/foo/1.0");
+        let dep = tmp.path().join("opt/bar");
+        for (bin, script) in [
+            (keg.join("bin"), "#!/bin/sh\nbar-helper\n"),
+            (dep.join("bin"), "#!/bin/sh\necho hello from bar\n"),
+        ] {
+            fs::create_dir_all(&bin).unwrap();
+            let name = if bin.starts_with(&keg) {
+                "foo"
+            } else {
+                "bar-helper"
+            };
+            fs::write(bin.join(name), script).unwrap();
+            fs::set_permissions(bin.join(name), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let test = FormulaTest {
+            commands: vec![TestCommand {
+                args: vec!["foo".to_string()],
+                exit_code: 0,
+                expect_output: vec!["hello from bar".to_string()],
+            }],
+        };
+
+        run_formula_test(&keg, &test, &[dep], tmp.path()).unwrap();
+    }
+}
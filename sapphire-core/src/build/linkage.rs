@@ -0,0 +1,339 @@
+// sapphire-core/src/build/linkage.rs
+// Post-install check that every shared library an installed binary links against can actually
+// be found. Load commands (Mach-O) and DT_NEEDED entries (ELF) are read directly from the
+// files and resolved roughly the way dyld / ld.so would, against the binary's own rpaths, the
+// keg, the Sapphire prefix and the system library directories.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use object::elf::{DT_NEEDED, DT_RPATH, DT_RUNPATH};
+use object::macho::{MachHeader32, MachHeader64, LC_LOAD_DYLIB, LC_REEXPORT_DYLIB, MH_EXECUTE};
+use object::read::elf::{Dyn, ElfFile32, ElfFile64, FileHeader};
+use object::read::macho::{
+    FatArch, LoadCommandVariant, MachHeader, MachOFatFile32, MachOFatFile64,
+};
+use object::{Endianness, FileKind};
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+use crate::utils::config::Config;
+use crate::utils::error::{Result, SapphireError};
+
+/// Library directories searched for bare ELF `DT_NEEDED` names when no rpath provides them.
+const SYSTEM_LIB_DIRS: [&str; 5] = ["/lib", "/lib64", "/usr/lib", "/usr/lib64", "/usr/local/lib"];
+/// macOS system libraries live in the dyld shared cache rather than on disk.
+const DYLD_SHARED_CACHE_PREFIXES: [&str; 2] = ["/usr/lib/", "/System/Library/"];
+
+/// A library reference in an installed binary that doesn't resolve to an existing file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedReference {
+    pub binary: PathBuf,
+    /// The reference as recorded in the binary (install name or `DT_NEEDED` entry).
+    pub missing: String,
+}
+
+/// Libraries a binary links against, and where it asks the loader to look for them.
+#[derive(Debug, Default)]
+struct LinkedLibraries {
+    libraries: Vec<String>,
+    rpaths: Vec<String>,
+    is_executable: bool,
+    is_macho: bool,
+}
+
+/// What [`verify_linkage`] found in a keg.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkageReport {
+    /// Mach-O and ELF files inspected.
+    pub binaries_checked: usize,
+    /// References that resolve to no existing library, in the order found.
+    pub unresolved: Vec<UnresolvedReference>,
+}
+
+impl LinkageReport {
+    /// Whether every library reference resolved.
+    pub fn is_ok(&self) -> bool {
+        self.unresolved.is_empty()
+    }
+
+    /// Fails with [`SapphireError::UnresolvedDylib`] for the first unresolved reference.
+    pub fn into_result(self) -> Result<()> {
+        match self.unresolved.into_iter().next() {
+            Some(first) => Err(SapphireError::UnresolvedDylib {
+                binary: first.binary,
+                missing: first.missing,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Scans every Mach-O and ELF file in `keg_path` and reports the library references that don't
+/// resolve within the keg, the prefix or the system library directories. Whether that fails
+/// the install is up to the caller.
+pub fn verify_linkage(keg_path: &Path, config: &Config) -> Result<LinkageReport> {
+    if !keg_path.is_dir() {
+        return Err(SapphireError::NotFound(format!(
+            "Keg {} does not exist",
+            keg_path.display()
+        )));
+    }
+    let system_dirs = system_library_dirs();
+    let mut report = LinkageReport::default();
+    for entry in WalkDir::new(keg_path).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let Ok(data) = fs::read(path) else {
+            continue;
+        };
+        let Some(linked) = linked_libraries(&data) else {
+            continue;
+        };
+        report.binaries_checked += 1;
+        for library in &linked.libraries {
+            if !resolves(library, path, &linked, keg_path, config, &system_dirs) {
+                report.unresolved.push(UnresolvedReference {
+                    binary: path.to_path_buf(),
+                    missing: library.clone(),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Fails with [`SapphireError::UnresolvedDylib`] for the first unresolved library reference in
+/// `keg_path`. Every unresolved reference is logged.
+pub fn verify_library_references(keg_path: &Path, config: &Config) -> Result<()> {
+    let report = verify_linkage(keg_path, config)?;
+    for reference in &report.unresolved {
+        warn!(
+            "{} links against {}, which cannot be found",
+            reference.binary.display(),
+            reference.missing
+        );
+    }
+    if report.is_ok() {
+        debug!(
+            "All library references of {} binaries in {} resolve",
+            report.binaries_checked,
+            keg_path.display()
+        );
+    }
+    report.into_result()
+}
+
+fn linked_libraries(data: &[u8]) -> Option<LinkedLibraries> {
+    match FileKind::parse(data).ok()? {
+        FileKind::Elf32 => elf_libraries(&ElfFile32::<Endianness>::parse(data).ok()?, data),
+        FileKind::Elf64 => elf_libraries(&ElfFile64::<Endianness>::parse(data).ok()?, data),
+        FileKind::MachO32 => macho_libraries::<MachHeader32<Endianness>>(data),
+        FileKind::MachO64 => macho_libraries::<MachHeader64<Endianness>>(data),
+        FileKind::MachOFat32 => {
+            let fat = MachOFatFile32::parse(data).ok()?;
+            merge_slices(fat.arches().iter().filter_map(|a| a.data(data).ok()))
+        }
+        FileKind::MachOFat64 => {
+            let fat = MachOFatFile64::parse(data).ok()?;
+            merge_slices(fat.arches().iter().filter_map(|a| a.data(data).ok()))
+        }
+        _ => None,
+    }
+}
+
+fn merge_slices<'a>(slices: impl Iterator<Item = &'a [u8]>) -> Option<LinkedLibraries> {
+    let mut merged: Option<LinkedLibraries> = None;
+    for slice in slices {
+        let Some(linked) = linked_libraries(slice) else {
+            continue;
+        };
+        let merged = merged.get_or_insert_with(|| LinkedLibraries {
+            is_executable: linked.is_executable,
+            is_macho: true,
+            ..Default::default()
+        });
+        for library in linked.libraries {
+            if !merged.libraries.contains(&library) {
+                merged.libraries.push(library);
+            }
+        }
+        for rpath in linked.rpaths {
+            if !merged.rpaths.contains(&rpath) {
+                merged.rpaths.push(rpath);
+            }
+        }
+    }
+    merged
+}
+
+fn elf_libraries<Elf: FileHeader<Endian = Endianness>>(
+    file: &object::read::elf::ElfFile<'_, Elf>,
+    data: &[u8],
+) -> Option<LinkedLibraries> {
+    let endian = file.endian();
+    let sections = file.elf_section_table();
+    let (dynamic, link) = sections.dynamic(endian, data).ok()??;
+    let strings = sections.strings(endian, data, link).ok()?;
+    let mut linked = LinkedLibraries {
+        is_executable: file.elf_header().e_type(endian) == object::elf::ET_EXEC,
+        ..Default::default()
+    };
+    for entry in dynamic {
+        let Some(tag) = entry.tag32(endian) else {
+            continue;
+        };
+        if !matches!(tag, DT_NEEDED | DT_RPATH | DT_RUNPATH) {
+            continue;
+        }
+        let Ok(value) = entry.string(endian, strings) else {
+            continue;
+        };
+        let value = String::from_utf8_lossy(value).into_owned();
+        if tag == DT_NEEDED {
+            linked.libraries.push(value);
+        } else {
+            linked.rpaths.extend(value.split(':').map(str::to_string));
+        }
+    }
+    Some(linked)
+}
+
+fn macho_libraries<Mach: MachHeader<Endian = Endianness>>(data: &[u8]) -> Option<LinkedLibraries> {
+    let header = Mach::parse(data, 0).ok()?;
+    let endian = header.endian().ok()?;
+    let mut linked = LinkedLibraries {
+        is_executable: header.filetype(endian) == MH_EXECUTE,
+        is_macho: true,
+        ..Default::default()
+    };
+    let mut commands = header.load_commands(endian, data, 0).ok()?;
+    while let Ok(Some(command)) = commands.next() {
+        match command.variant() {
+            // Weak and upward dylibs may legitimately be absent; LC_ID_DYLIB names the file itself
+            Ok(LoadCommandVariant::Dylib(dylib))
+                if matches!(command.cmd(), LC_LOAD_DYLIB | LC_REEXPORT_DYLIB) =>
+            {
+                if let Ok(name) = command.string(endian, dylib.dylib.name) {
+                    linked
+                        .libraries
+                        .push(String::from_utf8_lossy(name).into_owned());
+                }
+            }
+            Ok(LoadCommandVariant::Rpath(rpath)) => {
+                if let Ok(path) = command.string(endian, rpath.path) {
+                    linked
+                        .rpaths
+                        .push(String::from_utf8_lossy(path).into_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(linked)
+}
+
+fn resolves(
+    library: &str,
+    binary: &Path,
+    linked: &LinkedLibraries,
+    keg_path: &Path,
+    config: &Config,
+    system_dirs: &[PathBuf],
+) -> bool {
+    let origin = binary.parent().unwrap_or(keg_path);
+    if linked.is_macho {
+        if DYLD_SHARED_CACHE_PREFIXES
+            .iter()
+            .any(|p| library.starts_with(p))
+        {
+            return true;
+        }
+        if let Some(rest) = library.strip_prefix("@rpath/") {
+            let rpaths = linked
+                .rpaths
+                .iter()
+                .map(|r| expand_macho_path(r, origin))
+                .map(|dir| dir.join(rest));
+            // A dylib's @rpath is searched with the loading executable's rpaths, which can't be
+            // known here, so also accept the usual library directories
+            let fallbacks = (!linked.is_executable)
+                .then(|| [keg_path.join("lib"), config.prefix().join("lib")])
+                .into_iter()
+                .flatten()
+                .map(|dir| dir.join(rest));
+            return rpaths.chain(fallbacks).any(|p| p.exists());
+        }
+        // Libraries of the keg itself are named by its opt path, which is only linked later
+        let own_opt = keg_path
+            .parent()
+            .and_then(Path::file_name)
+            .map(|name| config.formula_opt_link_path(&name.to_string_lossy()));
+        if let Some(rest) = own_opt.and_then(|opt| {
+            Path::new(library)
+                .strip_prefix(opt)
+                .ok()
+                .map(Path::to_path_buf)
+        }) {
+            return keg_path.join(rest).exists();
+        }
+        return expand_macho_path(library, origin).exists();
+    }
+
+    if library.contains('/') {
+        return origin.join(library).exists();
+    }
+    let mut search: Vec<PathBuf> = linked
+        .rpaths
+        .iter()
+        .map(|r| PathBuf::from(r.replace("$ORIGIN", &origin.to_string_lossy())))
+        .collect();
+    search.push(keg_path.join("lib"));
+    search.push(config.prefix().join("lib"));
+    search.extend(system_dirs.iter().cloned());
+    search.iter().any(|dir| dir.join(library).exists())
+}
+
+/// Expands `@loader_path` / `@executable_path` relative to the binary's directory.
+fn expand_macho_path(path: &str, origin: &Path) -> PathBuf {
+    for token in ["@loader_path", "@executable_path"] {
+        if let Some(rest) = path.strip_prefix(token) {
+            return origin.join(rest.trim_start_matches('/'));
+        }
+    }
+    PathBuf::from(path)
+}
+
+/// Standard library directories, multiarch ones like `/usr/lib/x86_64-linux-gnu`, and any
+/// listed in `/etc/ld.so.conf.d`.
+fn system_library_dirs() -> Vec<PathBuf> {
+    let mut dirs: BTreeSet<PathBuf> = SYSTEM_LIB_DIRS.iter().map(PathBuf::from).collect();
+    if let Ok(entries) = fs::read_dir("/etc/ld.so.conf.d") {
+        for entry in entries.flatten() {
+            let Ok(content) = fs::read_to_string(entry.path()) else {
+                continue;
+            };
+            dirs.extend(
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| l.starts_with('/'))
+                    .map(PathBuf::from),
+            );
+        }
+    }
+    for base in ["/lib", "/usr/lib"] {
+        if let Ok(entries) = fs::read_dir(base) {
+            dirs.extend(
+                entries
+                    .flatten()
+                    .filter(|e| e.file_name().to_string_lossy().contains("-linux-"))
+                    .map(|e| e.path()),
+            );
+        }
+    }
+    dirs.into_iter().collect()
+}
@@ -8,11 +8,19 @@ use crate::model::formula::Formula;
 use crate::utils::config::Config;
 
 // --- Submodules ---
+pub mod blob_cache;
+pub mod build_log;
 pub mod cask;
 pub mod devtools;
 pub mod env;
+pub mod events;
 pub mod extract;
 pub mod formula; // <-- Declare the extract module
+pub mod linkage;
+pub mod process;
+pub mod source_cache;
+pub mod toolchain;
+pub mod warnings;
 
 // --- Re-exports ---
 pub use extract::extract_archive; // <-- Re-export the main function from extract.rs
@@ -0,0 +1,630 @@
+// sapphire-core/src/build/process.rs
+// Spawns build commands and streams their output line by line instead of buffering it until
+// the process exits. This lets us watch for builds that have stopped making progress, and
+// decide per line what is worth showing to the user.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use tracing::{debug, info, warn};
+
+use crate::build::build_log;
+use crate::utils::error::{Result, SapphireError};
+
+/// Env var controlling the output-silence watchdog, e.g. `45m` or `2h`. `0` or `off` disables it.
+pub const SILENCE_TIMEOUT_ENV: &str = "SAPPHIRE_BUILD_SILENCE_TIMEOUT";
+
+/// How long a build may go without printing anything before it is presumed hung.
+const DEFAULT_SILENCE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Returns the output-silence timeout configured via `SAPPHIRE_BUILD_SILENCE_TIMEOUT`,
+/// falling back to the default. `None` means the watchdog is disabled.
+pub fn silence_timeout() -> Option<Duration> {
+    let Ok(value) = std::env::var(SILENCE_TIMEOUT_ENV) else {
+        return Some(DEFAULT_SILENCE_TIMEOUT);
+    };
+    let value = value.trim();
+    if value == "0" || value.eq_ignore_ascii_case("off") {
+        debug!(
+            "Output-silence watchdog disabled via {}",
+            SILENCE_TIMEOUT_ENV
+        );
+        return None;
+    }
+    match humantime::parse_duration(value) {
+        Ok(d) => Some(d),
+        Err(e) => {
+            warn!(
+                "Ignoring invalid {}={} ({}); using default of {}",
+                SILENCE_TIMEOUT_ENV,
+                value,
+                e,
+                humantime::format_duration(DEFAULT_SILENCE_TIMEOUT)
+            );
+            Some(DEFAULT_SILENCE_TIMEOUT)
+        }
+    }
+}
+
+/// Env var selecting how much build output is shown: `quiet`, `normal` or `full`.
+pub const OUTPUT_VERBOSITY_ENV: &str = "SAPPHIRE_BUILD_OUTPUT";
+/// Env var overriding the regex that marks a build output line as a warning.
+pub const WARNING_PATTERN_ENV: &str = "SAPPHIRE_BUILD_OUTPUT_WARNING_PATTERN";
+/// Env var overriding the regex that marks a build output line as an error.
+pub const ERROR_PATTERN_ENV: &str = "SAPPHIRE_BUILD_OUTPUT_ERROR_PATTERN";
+
+const DEFAULT_WARNING_PATTERN: &str = r"(?i)\bwarning\b\s*:|\bdeprecated\b";
+const DEFAULT_ERROR_PATTERN: &str =
+    r"(?i)\b(fatal )?error\b\s*:|undefined reference to|\*\*\* .*(Error|Stop)|^FAILED: ";
+/// Lines marking progress between build phases, as printed by the common build systems.
+const MILESTONE_PATTERN: &str = concat!(
+    r"^(==> |-- (Configuring|Generating) done|-- Build files have been written|",
+    r"config\.status: creating Makefile$|Making (all|install) in |Found ninja|",
+    r"\s*(Compiling|Finished|Installing) )"
+);
+
+/// How much of a build's streamed output is shown, independent of the log level. Lines that
+/// aren't shown are still logged at debug level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputVerbosity {
+    /// Only lines matching the warning or error patterns.
+    Quiet,
+    /// Warnings, errors, and milestones between build phases.
+    #[default]
+    Normal,
+    /// Every line.
+    Full,
+}
+
+impl OutputVerbosity {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "quiet" => Some(Self::Quiet),
+            "normal" => Some(Self::Normal),
+            "full" | "verbose" => Some(Self::Full),
+            _ => None,
+        }
+    }
+}
+
+/// Decides which streamed lines are shown, based on an [`OutputVerbosity`] and the regexes
+/// used to recognise warnings and errors.
+#[derive(Debug, Clone)]
+pub struct OutputFilter {
+    verbosity: OutputVerbosity,
+    warning: Regex,
+    error: Regex,
+    milestone: Regex,
+}
+
+impl OutputFilter {
+    /// A filter using the default warning and error patterns.
+    pub fn new(verbosity: OutputVerbosity) -> Self {
+        Self {
+            verbosity,
+            warning: Regex::new(DEFAULT_WARNING_PATTERN).expect("valid default warning pattern"),
+            error: Regex::new(DEFAULT_ERROR_PATTERN).expect("valid default error pattern"),
+            milestone: Regex::new(MILESTONE_PATTERN).expect("valid milestone pattern"),
+        }
+    }
+
+    pub fn with_verbosity(mut self, verbosity: OutputVerbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Replaces the warning pattern.
+    pub fn with_warning_pattern(mut self, pattern: &str) -> Result<Self> {
+        self.warning = compile_pattern(pattern, "warning")?;
+        Ok(self)
+    }
+
+    /// Replaces the error pattern.
+    pub fn with_error_pattern(mut self, pattern: &str) -> Result<Self> {
+        self.error = compile_pattern(pattern, "error")?;
+        Ok(self)
+    }
+
+    /// Builds the filter from `SAPPHIRE_BUILD_OUTPUT` and the pattern env vars, ignoring (with
+    /// a warning) values that don't parse.
+    pub fn from_env() -> Self {
+        let verbosity = match std::env::var(OUTPUT_VERBOSITY_ENV) {
+            Ok(value) => OutputVerbosity::parse(&value).unwrap_or_else(|| {
+                warn!(
+                    "Ignoring invalid {}={}; expected quiet, normal or full",
+                    OUTPUT_VERBOSITY_ENV, value
+                );
+                OutputVerbosity::default()
+            }),
+            Err(_) => OutputVerbosity::default(),
+        };
+        let mut filter = Self::new(verbosity);
+        if let Ok(pattern) = std::env::var(WARNING_PATTERN_ENV) {
+            match filter.clone().with_warning_pattern(&pattern) {
+                Ok(f) => filter = f,
+                Err(e) => warn!("Ignoring {}: {}", WARNING_PATTERN_ENV, e),
+            }
+        }
+        if let Ok(pattern) = std::env::var(ERROR_PATTERN_ENV) {
+            match filter.clone().with_error_pattern(&pattern) {
+                Ok(f) => filter = f,
+                Err(e) => warn!("Ignoring {}: {}", ERROR_PATTERN_ENV, e),
+            }
+        }
+        filter
+    }
+
+    pub fn verbosity(&self) -> OutputVerbosity {
+        self.verbosity
+    }
+
+    /// Whether `line` should be shown to the user.
+    pub fn shows(&self, line: &str) -> bool {
+        match self.verbosity {
+            OutputVerbosity::Full => true,
+            OutputVerbosity::Normal => {
+                self.is_diagnostic(line) || self.milestone.is_match(line.trim_end())
+            }
+            OutputVerbosity::Quiet => self.is_diagnostic(line),
+        }
+    }
+
+    fn is_diagnostic(&self, line: &str) -> bool {
+        self.error.is_match(line) || self.warning.is_match(line)
+    }
+}
+
+fn compile_pattern(pattern: &str, kind: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|e| {
+        SapphireError::Generic(format!("Invalid {} pattern '{}': {}", kind, pattern, e))
+    })
+}
+
+static OUTPUT_FILTER: OnceCell<OutputFilter> = OnceCell::new();
+
+/// Sets the filter used by every later [`run_streaming`] call, typically from a command line
+/// flag. Only the first call has an effect; without one, [`OutputFilter::from_env`] is used.
+pub fn set_output_filter(filter: OutputFilter) {
+    if OUTPUT_FILTER.set(filter).is_err() {
+        debug!("Build output filter already set; keeping the existing one");
+    }
+}
+
+/// The filter applied to streamed build output.
+pub fn output_filter() -> &'static OutputFilter {
+    OUTPUT_FILTER.get_or_init(OutputFilter::from_env)
+}
+
+/// `cmd` as a line that can be pasted into a shell: a `cd` into its working directory, then
+/// `env` with the variables it sets or removes relative to this process's environment, then
+/// the program and its arguments.
+pub fn shell_command_line(cmd: &Command) -> String {
+    let cwd = cmd
+        .get_current_dir()
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_default();
+    let mut words = vec![
+        "cd".to_string(),
+        shell_quote(&cwd.to_string_lossy()),
+        "&&".to_string(),
+    ];
+
+    // Build commands start from a cleared environment, so what the command sets is all it gets
+    let set: BTreeMap<String, String> = cmd
+        .get_envs()
+        .filter_map(|(key, value)| {
+            Some((
+                key.to_string_lossy().into_owned(),
+                value?.to_string_lossy().into_owned(),
+            ))
+        })
+        .collect();
+    let mut env_words: Vec<String> = std::env::vars_os()
+        .map(|(key, _)| key.to_string_lossy().into_owned())
+        .filter(|key| !set.contains_key(key))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .flat_map(|key| ["-u".to_string(), shell_quote(&key)])
+        .collect();
+    env_words.extend(
+        set.iter()
+            .filter(|(key, value)| std::env::var(key).ok().as_ref() != Some(*value))
+            .map(|(key, value)| shell_quote(&format!("{}={}", key, value))),
+    );
+    if !env_words.is_empty() {
+        words.push("env".to_string());
+        words.extend(env_words);
+    }
+
+    words.push(shell_quote(&cmd.get_program().to_string_lossy()));
+    words.extend(
+        cmd.get_args()
+            .map(|arg| shell_quote(&arg.to_string_lossy())),
+    );
+    words.join(" ")
+}
+
+/// Quotes `word` for a POSIX shell, leaving it bare if nothing in it is special.
+fn shell_quote(word: &str) -> String {
+    let plain = |b: u8| b.is_ascii_alphanumeric() || b"-_./=:,+@%".contains(&b);
+    if !word.is_empty() && word.bytes().all(plain) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Runs `cmd` to completion, streaming its output and using the configured silence timeout.
+///
+/// Like `Command::output()`, a non-zero exit is *not* an error; callers check `status`.
+/// Errors are returned if the command cannot be spawned or is killed by the watchdog.
+pub fn run_streaming(cmd: &mut Command, context: &str) -> Result<Output> {
+    run_streaming_with_silence_timeout(cmd, context, silence_timeout())
+}
+
+/// Like [`run_streaming`], but only the last `tail_lines` lines of each stream are kept in the
+/// returned `Output`. Every line is still logged as it arrives, so this is for long builds whose
+/// full log would otherwise sit in memory only to be shown in part on failure.
+pub fn run_streaming_tail(cmd: &mut Command, context: &str, tail_lines: usize) -> Result<Output> {
+    run_with_watchdog(cmd, context, silence_timeout(), None, Some(tail_lines))
+}
+
+/// Like [`run_streaming`], but the command must also finish within `timeout` in total, output
+/// or not. On expiry the command is killed as the silence watchdog kills it, and
+/// [`SapphireError::BuildTimeout`] is returned.
+pub fn run_with_timeout(cmd: &mut Command, context: &str, timeout: Duration) -> Result<Output> {
+    run_with_watchdog(cmd, context, silence_timeout(), Some(timeout), None)
+}
+
+/// [`run_streaming_tail`] with an optional overall timeout, as in [`run_with_timeout`].
+pub fn run_streaming_tail_with_timeout(
+    cmd: &mut Command,
+    context: &str,
+    tail_lines: usize,
+    timeout: Option<Duration>,
+) -> Result<Output> {
+    run_with_watchdog(cmd, context, silence_timeout(), timeout, Some(tail_lines))
+}
+
+/// Same as [`run_streaming`] with an explicit silence timeout (`None` disables the watchdog).
+///
+/// The timer is reset every time the child writes a line to stdout or stderr. If it expires,
+/// the child's whole process group is killed, which takes down the compilers and sub-makes a
+/// recursive make has spawned too, and `SapphireError::CommandExecError` is returned.
+pub fn run_streaming_with_silence_timeout(
+    cmd: &mut Command,
+    context: &str,
+    silence_timeout: Option<Duration>,
+) -> Result<Output> {
+    run_with_watchdog(cmd, context, silence_timeout, None, None)
+}
+
+/// Output captured from one stream: everything, or only the last `limit` lines.
+struct Captured {
+    lines: VecDeque<Vec<u8>>,
+    limit: Option<usize>,
+}
+
+impl Captured {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            limit,
+        }
+    }
+
+    fn push(&mut self, line: Vec<u8>) {
+        if self.limit.is_some_and(|limit| self.lines.len() >= limit) {
+            self.lines.pop_front();
+        }
+        if self.limit != Some(0) {
+            self.lines.push_back(line);
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.lines.into_iter().flatten().collect()
+    }
+}
+
+fn run_with_watchdog(
+    cmd: &mut Command,
+    context: &str,
+    silence_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    tail_lines: Option<usize>,
+) -> Result<Output> {
+    debug!("Running command ({}): {}", context, shell_command_line(cmd));
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Its own process group lets the watchdog kill everything the command started. That also
+    // takes it out of the terminal's group and away from Ctrl-C; see `kill_running_commands`.
+    cmd.process_group(0);
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let log = build_log::active();
+    if let Some(log) = &log {
+        log.command_started(context, cmd);
+    }
+    let mut child = cmd.spawn().map_err(|e| {
+        SapphireError::CommandExecError(format!("Failed to execute {}: {}", context, e))
+    })?;
+    let _group = RunningGroup::register(child.id());
+
+    let (tx, rx) = mpsc::channel();
+    let readers = [
+        spawn_reader(child.stdout.take(), Stream::Stdout, tx.clone()),
+        spawn_reader(child.stderr.take(), Stream::Stderr, tx),
+    ];
+
+    let filter = output_filter();
+    let mut stdout = Captured::new(tail_lines);
+    let mut stderr = Captured::new(tail_lines);
+    loop {
+        // Checked on every line too, since a command that keeps printing never times out the
+        // receive below
+        if let (Some(timeout), Some(deadline)) = (timeout, deadline) {
+            if Instant::now() >= deadline {
+                warn!(
+                    "{} did not finish within {}; killing it",
+                    context,
+                    humantime::format_duration(timeout)
+                );
+                kill_child(&mut child, context);
+                if let Some(log) = &log {
+                    log.header(&format!(
+                        "{} killed after {}",
+                        context,
+                        humantime::format_duration(timeout)
+                    ));
+                }
+                return Err(SapphireError::BuildTimeout {
+                    step: context.to_string(),
+                    timeout,
+                });
+            }
+        }
+        let until_deadline = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let wait = match (silence_timeout, until_deadline) {
+            (Some(silence), Some(left)) => Some(silence.min(left)),
+            (silence, left) => silence.or(left),
+        };
+        let received = match wait {
+            Some(wait) => rx.recv_timeout(wait),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok((stream, line)) => {
+                let text = String::from_utf8_lossy(&line);
+                if filter.shows(&text) {
+                    info!("[{}] {}", context, text.trim_end());
+                } else {
+                    debug!("[{}] {}", context, text.trim_end());
+                }
+                if let Some(log) = &log {
+                    log.line(&line);
+                }
+                match stream {
+                    Stream::Stdout => stdout.push(line),
+                    Stream::Stderr => stderr.push(line),
+                }
+            }
+            // Both pipes closed: the process is done (or has detached its output)
+            Err(RecvTimeoutError::Disconnected) => break,
+            // The overall deadline is handled at the top of the loop
+            Err(RecvTimeoutError::Timeout) if deadline.is_some_and(|d| Instant::now() >= d) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                let timeout = silence_timeout.unwrap_or_default();
+                warn!(
+                    "{} produced no output for {}; killing it",
+                    context,
+                    humantime::format_duration(timeout)
+                );
+                kill_child(&mut child, context);
+                if let Some(log) = &log {
+                    log.header(&format!(
+                        "{} killed after {} without output",
+                        context,
+                        humantime::format_duration(timeout)
+                    ));
+                }
+                // The reader threads are not joined: grandchildren that inherited the pipes may
+                // keep them open, and we don't want to block on those.
+                return Err(SapphireError::CommandExecError(format!(
+                    "{}: build produced no output for {}, presumed hung",
+                    context,
+                    humantime::format_duration(timeout)
+                )));
+            }
+        }
+    }
+
+    for reader in readers.into_iter().flatten() {
+        let _ = reader.join();
+    }
+    let status = child.wait().map_err(|e| {
+        SapphireError::CommandExecError(format!("Failed to wait for {}: {}", context, e))
+    })?;
+    if let Some(log) = &log {
+        log.command_finished(context, &status);
+    }
+    Ok(Output {
+        status,
+        stdout: stdout.into_bytes(),
+        stderr: stderr.into_bytes(),
+    })
+}
+
+/// Kills every process in `child`'s process group, then reaps it.
+fn kill_child(child: &mut Child, context: &str) {
+    if !kill_group(child.id()) {
+        if let Err(e) = child.kill() {
+            warn!("Failed to kill hung {} process: {}", context, e);
+        }
+    }
+    let _ = child.wait();
+}
+
+/// Sends SIGKILL to the process group `pgid`; returns whether that worked.
+fn kill_group(pgid: u32) -> bool {
+    // Signalling a group needs kill(1) without a libc binding
+    Command::new("kill")
+        .args(["-s", "KILL", "--", &format!("-{}", pgid)])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Process groups of the commands currently running under [`run_with_watchdog`].
+static RUNNING_GROUPS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// Keeps a command's process group in [`RUNNING_GROUPS`] while it runs.
+struct RunningGroup(u32);
+
+impl RunningGroup {
+    fn register(pgid: u32) -> Self {
+        RUNNING_GROUPS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(pgid);
+        Self(pgid)
+    }
+}
+
+impl Drop for RunningGroup {
+    fn drop(&mut self) {
+        RUNNING_GROUPS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.0);
+    }
+}
+
+/// Kills the process groups of all running build commands. Each runs in its own group, so
+/// Ctrl-C in the terminal doesn't reach them; call this when sapphire is interrupted.
+pub fn kill_running_commands() {
+    let groups = std::mem::take(&mut *RUNNING_GROUPS.lock().unwrap_or_else(|e| e.into_inner()));
+    for pgid in groups {
+        debug!("Killing process group {}", pgid);
+        kill_group(pgid);
+    }
+}
+
+/// Forwards each line read from `pipe` to `tx` until EOF.
+fn spawn_reader<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    stream: Stream,
+    tx: Sender<(Stream, Vec<u8>)>,
+) -> Option<JoinHandle<()>> {
+    let pipe = pipe?;
+    Some(thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        loop {
+            let mut line = Vec::new();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if tx.send((stream, line)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    /// Whether `pid` is still running; killed processes nobody has reaped yet count as gone.
+    fn is_alive(pid: &str) -> bool {
+        fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| {
+            !stat
+                .rsplit(')')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .starts_with('Z')
+        })
+    }
+
+    #[test]
+    fn timeout_kills_background_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("bg.pid");
+        let mut cmd = sh(&format!(
+            "sleep 60 & echo $! > '{}'; sleep 60",
+            pid_file.display()
+        ));
+
+        let start = Instant::now();
+        let result = run_with_timeout(&mut cmd, "sleepy", Duration::from_secs(1));
+        assert!(
+            matches!(result, Err(SapphireError::BuildTimeout { .. })),
+            "{result:?}"
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        let pid = pid.trim();
+        let gone_by = Instant::now() + Duration::from_secs(5);
+        while is_alive(pid) && Instant::now() < gone_by {
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(
+            !is_alive(pid),
+            "background sleep {pid} survived the timeout"
+        );
+    }
+
+    #[test]
+    fn silent_command_is_presumed_hung() {
+        let mut cmd = sh("echo hi; sleep 60");
+        let start = Instant::now();
+        let result =
+            run_streaming_with_silence_timeout(&mut cmd, "quiet", Some(Duration::from_secs(1)));
+        match result {
+            Err(SapphireError::CommandExecError(msg)) => {
+                assert!(msg.contains("presumed hung"), "{msg}")
+            }
+            other => panic!("expected a hung command, got {other:?}"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn steady_output_keeps_the_watchdog_away() {
+        let mut cmd = sh("for i in 1 2 3 4; do echo $i; sleep 0.5; done");
+        let output =
+            run_streaming_with_silence_timeout(&mut cmd, "chatty", Some(Duration::from_secs(1)))
+                .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"1\n2\n3\n4\n");
+    }
+}
@@ -0,0 +1,130 @@
+// sapphire-core/src/build/source_cache.rs
+// Local cache of prepared source trees: a formula's source extracted with its patches applied.
+// Trees are archived under a key made of the source URL and checksum (or git commit) and the
+// checksums of the patch files, so reinstalling with unchanged inputs restores the tree instead
+// of extracting and patching again, while a changed patch or source gets a fresh one.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use crate::build::blob_cache::BlobCache;
+use crate::fetch::git;
+use crate::model::formula::Formula;
+use crate::utils::config::Config;
+use crate::utils::error::Result;
+
+/// Directory under the cache dir holding the archives.
+const SOURCE_TREE_DIR: &str = "source-trees";
+
+/// File in a cached tree recording the `SOURCE_DATE_EPOCH` of the tree before it was patched.
+/// Patched files carry the time they were patched, so it can't be recomputed after a restore.
+const EPOCH_FILE: &str = ".sapphire-source-date-epoch";
+
+/// Everything that decides whether a cached tree matches what extracting and patching the
+/// source now would produce.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceTreeKey {
+    pub formula: String,
+    pub url: String,
+    /// SHA-256 of the source archive, or the commit of a git checkout.
+    pub source: String,
+    /// SHA-256 of each patch file, in the order they are applied.
+    pub patches: Vec<String>,
+}
+
+impl SourceTreeKey {
+    /// The key for `formula`'s source at `source_path` with `patches`. `None` if the source
+    /// has no checksum and isn't a git checkout, as its contents can then change unnoticed.
+    pub fn new(formula: &Formula, source_path: &Path, patches: &[PathBuf]) -> Result<Option<Self>> {
+        let source = if !formula.sha256.is_empty() {
+            formula.sha256.clone()
+        } else if source_path.join(".git").exists() {
+            git::head_commit(source_path)?
+        } else {
+            return Ok(None);
+        };
+        let patches = patches
+            .iter()
+            .map(|patch| Ok(hex::encode(Sha256::digest(fs::read(patch)?))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(Self {
+            formula: formula.name().to_string(),
+            url: formula.url.clone(),
+            source,
+            patches,
+        }))
+    }
+
+    /// SHA-256 of the whole key, used as the archive's file name.
+    pub fn digest(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        hex::encode(Sha256::digest(&json))
+    }
+}
+
+/// The source tree cache in `<cache_dir>/source-trees`.
+pub struct SourceTreeCache {
+    archives: BlobCache,
+}
+
+impl SourceTreeCache {
+    /// The cache configured by `source_cache_size`, or `None` if it is disabled or
+    /// `force_fetch` asks for everything to be prepared afresh.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let size_mb = config.install_options.source_cache_size;
+        (size_mb > 0 && !config.install_options.force_fetch).then(|| Self {
+            archives: BlobCache::at(config.cache_dir.join(SOURCE_TREE_DIR), size_mb),
+        })
+    }
+
+    /// Restores the tree cached under `key` into `build_dir` and returns the
+    /// `SOURCE_DATE_EPOCH` recorded with it, or `None` if nothing usable is cached, leaving
+    /// `build_dir` empty.
+    pub fn restore(&self, key: &SourceTreeKey, build_dir: &Path) -> Result<Option<u64>> {
+        let digest = key.digest();
+        if !self.archives.contains(&digest) {
+            debug!("No prepared source of {} for key {}", key.formula, digest);
+            return Ok(None);
+        }
+        info!(
+            "==> Restoring the prepared source of {} from the local cache",
+            key.formula
+        );
+        if !self.archives.restore_dir(&digest, build_dir)? {
+            fs::create_dir_all(build_dir)?;
+            return Ok(None);
+        }
+        let epoch_file = build_dir.join(EPOCH_FILE);
+        let epoch = fs::read_to_string(&epoch_file)
+            .ok()
+            .and_then(|s| s.trim().parse().ok());
+        let _ = fs::remove_file(&epoch_file);
+        if epoch.is_none() {
+            warn!(
+                "Cached source of {} has no timestamp; preparing it again",
+                key.formula
+            );
+            fs::remove_dir_all(build_dir)?;
+            fs::create_dir_all(build_dir)?;
+        }
+        Ok(epoch)
+    }
+
+    /// Archives the prepared tree in `build_dir` under `key`, along with `source_date_epoch`.
+    pub fn store(
+        &self,
+        key: &SourceTreeKey,
+        build_dir: &Path,
+        source_date_epoch: u64,
+    ) -> Result<()> {
+        let epoch_file = build_dir.join(EPOCH_FILE);
+        fs::write(&epoch_file, source_date_epoch.to_string())?;
+        let stored = self.archives.store_dir(&key.digest(), build_dir);
+        fs::remove_file(&epoch_file)?;
+        stored
+    }
+}
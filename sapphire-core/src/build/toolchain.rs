@@ -0,0 +1,282 @@
+// sapphire-core/src/build/toolchain.rs
+// A declarative toolchain definition (compilers, flags, sysroot, target triple) loaded from a
+// TOML file and translated into whatever each build backend understands: environment
+// variables, a CMake toolchain file, a meson cross/native file, or autotools' `--host`.
+//
+// Example:
+//
+//     target = "aarch64-linux-gnu"
+//     cc = "/usr/bin/aarch64-linux-gnu-gcc"
+//     cxx = "/usr/bin/aarch64-linux-gnu-g++"
+//     sysroot = "/opt/sysroots/aarch64"
+//     cflags = ["-O2", "-pipe"]
+//     ldflags = ["-Wl,--as-needed"]
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::build::env::BuildEnvironment;
+use crate::utils::error::{Result, SapphireError};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Toolchain {
+    /// Target triple, e.g. `aarch64-linux-gnu`. Setting it makes the build a cross build.
+    pub target: Option<String>,
+    pub cc: Option<PathBuf>,
+    pub cxx: Option<PathBuf>,
+    pub ar: Option<PathBuf>,
+    pub ranlib: Option<PathBuf>,
+    pub strip: Option<PathBuf>,
+    pub sysroot: Option<PathBuf>,
+    /// Extra flags appended to the environment's CFLAGS (and CXXFLAGS).
+    pub cflags: Vec<String>,
+    /// Extra flags appended to the environment's CXXFLAGS only.
+    pub cxxflags: Vec<String>,
+    pub ldflags: Vec<String>,
+}
+
+impl Toolchain {
+    /// Reads a toolchain definition from a TOML file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            SapphireError::Config(format!(
+                "Failed to read toolchain file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let toolchain: Self = toml::from_str(&content).map_err(|e| {
+            SapphireError::Config(format!("Invalid toolchain file {}: {}", path.display(), e))
+        })?;
+        debug!("Loaded toolchain from {}: {:?}", path.display(), toolchain);
+        Ok(toolchain)
+    }
+
+    pub fn is_cross(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Sets compilers, tools and flags in `env` so every backend sees the same toolchain.
+    /// Flags are appended to the ones the environment already computed.
+    pub fn apply_to_env(&self, env: &mut BuildEnvironment) {
+        let tools = [
+            ("CC", &self.cc),
+            ("CXX", &self.cxx),
+            ("AR", &self.ar),
+            ("RANLIB", &self.ranlib),
+            ("STRIP", &self.strip),
+        ];
+        for (var, tool) in tools {
+            if let Some(tool) = tool {
+                env.set_var(var, tool.to_string_lossy());
+            }
+        }
+
+        let sysroot_flag = self.sysroot_flag();
+        let cflags: Vec<&str> = sysroot_flag
+            .iter()
+            .map(String::as_str)
+            .chain(self.cflags.iter().map(String::as_str))
+            .collect();
+        let cxxflags: Vec<&str> = cflags
+            .iter()
+            .copied()
+            .chain(self.cxxflags.iter().map(String::as_str))
+            .collect();
+        let ldflags: Vec<&str> = sysroot_flag
+            .iter()
+            .map(String::as_str)
+            .chain(self.ldflags.iter().map(String::as_str))
+            .collect();
+        for flag in cflags {
+            env.add_cflag(flag);
+        }
+        for flag in cxxflags {
+            env.add_cxxflag(flag);
+        }
+        for flag in ldflags {
+            env.add_ldflag(flag);
+        }
+    }
+
+    /// Extra `./configure` arguments (`--host` for cross builds).
+    pub fn configure_args(&self) -> Vec<String> {
+        self.target
+            .iter()
+            .map(|t| format!("--host={}", t))
+            .collect()
+    }
+
+    /// Writes a CMake toolchain file into `dir` and returns its path.
+    ///
+    /// Only the target, tools and sysroot go in the file; flags reach CMake through the
+    /// environment set up by [`Toolchain::apply_to_env`].
+    pub fn write_cmake_toolchain_file(&self, dir: &Path) -> Result<PathBuf> {
+        let mut out = String::from("# Generated by Sapphire from a toolchain definition\n");
+        if let Some(target) = &self.target {
+            let (system, processor) = cmake_system(target);
+            let _ = writeln!(out, "set(CMAKE_SYSTEM_NAME {})", system);
+            let _ = writeln!(out, "set(CMAKE_SYSTEM_PROCESSOR {})", processor);
+            let _ = writeln!(out, "set(CMAKE_C_COMPILER_TARGET {})", target);
+            let _ = writeln!(out, "set(CMAKE_CXX_COMPILER_TARGET {})", target);
+        }
+        for (var, tool) in [
+            ("CMAKE_C_COMPILER", &self.cc),
+            ("CMAKE_CXX_COMPILER", &self.cxx),
+            ("CMAKE_AR", &self.ar),
+            ("CMAKE_RANLIB", &self.ranlib),
+            ("CMAKE_STRIP", &self.strip),
+        ] {
+            if let Some(tool) = tool {
+                let _ = writeln!(out, "set({} \"{}\")", var, cmake_escape(tool));
+            }
+        }
+        if let Some(sysroot) = &self.sysroot {
+            let _ = writeln!(out, "set(CMAKE_SYSROOT \"{}\")", cmake_escape(sysroot));
+            let _ = writeln!(
+                out,
+                "set(CMAKE_FIND_ROOT_PATH \"{}\")",
+                cmake_escape(sysroot)
+            );
+            out.push_str("set(CMAKE_FIND_ROOT_PATH_MODE_PROGRAM NEVER)\n");
+            out.push_str("set(CMAKE_FIND_ROOT_PATH_MODE_LIBRARY ONLY)\n");
+            out.push_str("set(CMAKE_FIND_ROOT_PATH_MODE_INCLUDE ONLY)\n");
+            out.push_str("set(CMAKE_FIND_ROOT_PATH_MODE_PACKAGE ONLY)\n");
+        }
+        let path = dir.join("sapphire-toolchain.cmake");
+        fs::write(&path, out)?;
+        debug!("Wrote CMake toolchain file {}", path.display());
+        Ok(path)
+    }
+
+    /// Writes a meson machine file into `dir`. It is a cross file when a target is set and a
+    /// native file otherwise; the returned flag is `true` for a cross file.
+    pub fn write_meson_machine_file(&self, dir: &Path) -> Result<(PathBuf, bool)> {
+        let mut out = String::from("# Generated by Sapphire from a toolchain definition\n");
+        out.push_str("[binaries]\n");
+        for (key, tool) in [
+            ("c", &self.cc),
+            ("cpp", &self.cxx),
+            ("ar", &self.ar),
+            ("strip", &self.strip),
+        ] {
+            if let Some(tool) = tool {
+                let _ = writeln!(out, "{} = {}", key, meson_string(&tool.to_string_lossy()));
+            }
+        }
+
+        let sysroot_flag = self.sysroot_flag();
+        let c_args: Vec<String> = sysroot_flag.iter().chain(&self.cflags).cloned().collect();
+        let cpp_args: Vec<String> = c_args.iter().chain(&self.cxxflags).cloned().collect();
+        let link_args: Vec<String> = sysroot_flag.iter().chain(&self.ldflags).cloned().collect();
+        out.push_str("\n[built-in options]\n");
+        for (key, args) in [
+            ("c_args", &c_args),
+            ("cpp_args", &cpp_args),
+            ("c_link_args", &link_args),
+            ("cpp_link_args", &link_args),
+        ] {
+            if !args.is_empty() {
+                let _ = writeln!(out, "{} = {}", key, meson_array(args));
+            }
+        }
+
+        if let Some(sysroot) = &self.sysroot {
+            out.push_str("\n[properties]\n");
+            let _ = writeln!(
+                out,
+                "sys_root = {}",
+                meson_string(&sysroot.to_string_lossy())
+            );
+        }
+        if let Some(target) = &self.target {
+            let (system, cpu_family, cpu, endian) = meson_host_machine(target);
+            out.push_str("\n[host_machine]\n");
+            let _ = writeln!(out, "system = {}", meson_string(system));
+            let _ = writeln!(out, "cpu_family = {}", meson_string(cpu_family));
+            let _ = writeln!(out, "cpu = {}", meson_string(cpu));
+            let _ = writeln!(out, "endian = {}", meson_string(endian));
+        }
+
+        let is_cross = self.is_cross();
+        let path = dir.join(if is_cross {
+            "sapphire-meson-cross.ini"
+        } else {
+            "sapphire-meson-native.ini"
+        });
+        fs::write(&path, out)?;
+        debug!("Wrote meson machine file {}", path.display());
+        Ok((path, is_cross))
+    }
+
+    fn sysroot_flag(&self) -> Option<String> {
+        self.sysroot
+            .as_ref()
+            .map(|s| format!("--sysroot={}", s.display()))
+    }
+}
+
+/// Maps a target triple to CMake's `CMAKE_SYSTEM_NAME` and `CMAKE_SYSTEM_PROCESSOR`.
+fn cmake_system(target: &str) -> (&'static str, &str) {
+    let arch = target.split('-').next().unwrap_or(target);
+    let system = if target.contains("darwin") || target.contains("apple") {
+        "Darwin"
+    } else if target.contains("windows") || target.contains("mingw") {
+        "Windows"
+    } else if target.contains("freebsd") {
+        "FreeBSD"
+    } else {
+        "Linux"
+    };
+    (system, arch)
+}
+
+/// Maps a target triple to meson's host machine `system`, `cpu_family`, `cpu` and `endian`.
+fn meson_host_machine(target: &str) -> (&'static str, &'static str, &str, &'static str) {
+    let (system, arch) = cmake_system(target);
+    let system = match system {
+        "Darwin" => "darwin",
+        "Windows" => "windows",
+        "FreeBSD" => "freebsd",
+        _ => "linux",
+    };
+    let cpu_family = match arch {
+        "x86_64" | "amd64" => "x86_64",
+        "i386" | "i486" | "i586" | "i686" => "x86",
+        "aarch64" | "arm64" => "aarch64",
+        a if a.starts_with("arm") => "arm",
+        "riscv64" => "riscv64",
+        "powerpc64" | "powerpc64le" | "ppc64" | "ppc64le" => "ppc64",
+        "s390x" => "s390x",
+        a if a.starts_with("mips64") => "mips64",
+        a if a.starts_with("mips") => "mips",
+        _ => "unknown",
+    };
+    let big_endian = matches!(arch, "s390x" | "powerpc64" | "ppc64" | "mips" | "mips64");
+    (
+        system,
+        cpu_family,
+        arch,
+        if big_endian { "big" } else { "little" },
+    )
+}
+
+fn cmake_escape(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
+
+fn meson_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn meson_array(values: &[String]) -> String {
+    let items: Vec<String> = values.iter().map(|v| meson_string(v)).collect();
+    format!("[{}]", items.join(", "))
+}
@@ -0,0 +1,221 @@
+// sapphire-core/src/build/warnings.rs
+// Typed warnings accumulated while building or pouring a formula. Every warning is still
+// logged as it happens; the collector additionally keeps it so it can be summarised at the end
+// of the install and recorded in the keg's receipt.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::build::events::{self, BuildEvent};
+use crate::receipt::read_receipt_field;
+
+/// Stable, machine-readable identifier for a class of build warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningCode {
+    /// A compiler or configure flag that is obsolete or ignored.
+    DeprecatedFlag,
+    /// A library from outside the Sapphire prefix was picked up.
+    ImpureLibrary,
+    /// A variable from the user's environment leaked into the build and was removed.
+    ImpureEnvironment,
+    /// A binary targets a different OS version than the one being built for.
+    DeploymentTargetMismatch,
+    /// A file could not be (fully) relocated and may still reference the build prefix.
+    RelocationHazard,
+    /// The install step produced nothing and artifacts were copied by heuristic.
+    ManualInstallFallback,
+    /// An auxiliary tool (e.g. autoreconf) failed but the build carried on.
+    ToolFailure,
+    /// The project uses a build layout Sapphire only partially supports.
+    UnsupportedBuildSystem,
+    /// Sapphire targets a different CPU architecture than the machine (e.g. under Rosetta).
+    ArchitectureMismatch,
+    /// A requested build feature (e.g. LTO) isn't supported by the toolchain and was left out.
+    UnsupportedFeature,
+}
+
+impl WarningCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DeprecatedFlag => "deprecated-flag",
+            Self::ImpureLibrary => "impure-library",
+            Self::ImpureEnvironment => "impure-environment",
+            Self::DeploymentTargetMismatch => "deployment-target-mismatch",
+            Self::RelocationHazard => "relocation-hazard",
+            Self::ManualInstallFallback => "manual-install-fallback",
+            Self::ToolFailure => "tool-failure",
+            Self::UnsupportedBuildSystem => "unsupported-build-system",
+            Self::ArchitectureMismatch => "architecture-mismatch",
+            Self::UnsupportedFeature => "unsupported-feature",
+        }
+    }
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The part of the install a warning was raised in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildPhase {
+    Environment,
+    /// Generating `./configure` with the project's `autogen.sh` or autoreconf, for trees that
+    /// don't ship one.
+    AutogenBootstrap,
+    Configure,
+    Build,
+    /// The project's own test suite, run between building and installing when asked for.
+    Test,
+    Install,
+    Relocation,
+}
+
+impl fmt::Display for BuildPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Environment => "environment",
+            Self::AutogenBootstrap => "autogen-bootstrap",
+            Self::Configure => "configure",
+            Self::Build => "build",
+            Self::Test => "test",
+            Self::Install => "install",
+            Self::Relocation => "relocation",
+        })
+    }
+}
+
+impl BuildPhase {
+    /// Every phase, in the order a source build goes through them. [`Self::AutogenBootstrap`]
+    /// and [`Self::Test`] are left out: only some builds go through them, so a build can't stop
+    /// after them.
+    pub const ALL: [BuildPhase; 5] = [
+        Self::Environment,
+        Self::Configure,
+        Self::Build,
+        Self::Install,
+        Self::Relocation,
+    ];
+
+    /// Parses a phase name as printed by `Display` (case-insensitive).
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|phase| phase.to_string().eq_ignore_ascii_case(s.trim()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildWarning {
+    pub code: WarningCode,
+    pub phase: BuildPhase,
+    pub message: String,
+    /// The file the warning is about, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<PathBuf>,
+}
+
+impl fmt::Display for BuildWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.code, self.message)?;
+        if let Some(location) = &self.location {
+            write!(f, " ({})", location.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared, append-only list of warnings for one formula's install.
+///
+/// Clones share the same list, so a collector can be handed to every phase of a build.
+#[derive(Debug, Clone, Default)]
+pub struct WarningCollector {
+    warnings: Arc<Mutex<Vec<BuildWarning>>>,
+    /// Formula the warnings belong to; when set, each warning is also published as an event.
+    formula: Option<String>,
+}
+
+impl WarningCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A collector for `formula`'s install, which also publishes its warnings as
+    /// [`BuildEvent::Warning`].
+    pub fn for_formula(formula: &str) -> Self {
+        Self {
+            formula: Some(formula.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Logs the warning, records it and publishes it if the collector belongs to a formula.
+    pub fn warn(
+        &self,
+        code: WarningCode,
+        phase: BuildPhase,
+        message: impl Into<String>,
+        location: Option<&Path>,
+    ) {
+        let warning = BuildWarning {
+            code,
+            phase,
+            message: message.into(),
+            location: location.map(Path::to_path_buf),
+        };
+        warn!("{}", warning);
+        if let Some(formula) = &self.formula {
+            events::publish(BuildEvent::Warning {
+                formula: formula.clone(),
+                warning: warning.clone(),
+            });
+        }
+        self.lock().push(warning);
+    }
+
+    /// All warnings recorded so far, in the order they were raised.
+    pub fn warnings(&self) -> Vec<BuildWarning> {
+        self.lock().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Recorded warnings grouped by code.
+    pub fn by_code(&self) -> BTreeMap<WarningCode, Vec<BuildWarning>> {
+        group_by_code(self.warnings())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<BuildWarning>> {
+        // A panic while holding the lock can't leave a Vec half-pushed, so poisoning is harmless
+        self.warnings.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Groups `warnings` by code, preserving their order within each group.
+pub fn group_by_code(warnings: Vec<BuildWarning>) -> BTreeMap<WarningCode, Vec<BuildWarning>> {
+    let mut grouped: BTreeMap<WarningCode, Vec<BuildWarning>> = BTreeMap::new();
+    for warning in warnings {
+        grouped.entry(warning.code).or_default().push(warning);
+    }
+    grouped
+}
+
+/// Reads the warnings recorded in a keg's INSTALL_RECEIPT.json. Missing or unreadable receipts
+/// yield an empty list.
+pub fn read_receipt_warnings(keg_path: &Path) -> Vec<BuildWarning> {
+    read_receipt_field(keg_path, "warnings").unwrap_or_default()
+}
@@ -4,7 +4,7 @@ pub mod resolver;
 
 // Re-export key types for easier access
 pub use definition::{Dependency, DependencyExt, DependencyTag}; // Updated source module
-pub use requirement::Requirement;
+pub use requirement::{CompilerRequirement, CompilerVendor, Requirement};
 pub use resolver::{
     DependencyResolver, ResolutionContext, ResolutionStatus, ResolvedDependency, ResolvedGraph,
 };
@@ -25,3 +25,65 @@ impl fmt::Display for Requirement {
         }
     }
 }
+
+/// A compiler family, as reported by its `--version` output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompilerVendor {
+    /// Upstream LLVM clang, such as the `llvm` formula's.
+    #[serde(alias = "llvm")]
+    Clang,
+    /// The clang shipped with Xcode, which has its own version numbering.
+    AppleClang,
+    Gcc,
+}
+
+impl fmt::Display for CompilerVendor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Clang => "clang",
+            Self::AppleClang => "Apple clang",
+            Self::Gcc => "GCC",
+        })
+    }
+}
+
+/// The compiler a formula needs, when the system's default `cc` won't do. Either field may be
+/// left out; an empty requirement accepts any compiler.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CompilerRequirement {
+    #[serde(default)]
+    pub vendor: Option<CompilerVendor>,
+    /// Lowest acceptable major version, in the vendor's own numbering.
+    #[serde(default)]
+    pub min_version: Option<u32>,
+}
+
+impl CompilerRequirement {
+    /// Whether a compiler from `vendor` with major version `version` satisfies this.
+    ///
+    /// Versions aren't comparable across vendors (Apple clang 15 is roughly LLVM 16, and GCC
+    /// counts on its own), so a minimum version without a vendor is taken in the numbering of
+    /// `default_vendor`, the system compiler's, and only accepts compilers from it.
+    pub fn accepts(
+        &self,
+        vendor: CompilerVendor,
+        version: u32,
+        default_vendor: Option<CompilerVendor>,
+    ) -> bool {
+        let required_vendor = self.vendor.or(self.min_version.and(default_vendor));
+        required_vendor.is_none_or(|v| v == vendor)
+            && self.min_version.is_none_or(|min| version >= min)
+    }
+}
+
+impl fmt::Display for CompilerRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.vendor, self.min_version) {
+            (Some(vendor), Some(min)) => write!(f, "{} >= {}", vendor, min),
+            (Some(vendor), None) => write!(f, "{}", vendor),
+            (None, Some(min)) => write!(f, "a compiler >= {}", min),
+            (None, None) => f.write_str("any compiler"),
+        }
+    }
+}
@@ -51,6 +51,9 @@ pub struct ResolutionContext<'a> {
     pub include_test: bool,
     pub skip_recommended: bool,
     pub force_build: bool,
+    /// Treat the requested formulae as not installed, so they are installed again. Their
+    /// dependencies are left alone.
+    pub reinstall: bool,
 }
 
 /// Resolves the dependency graph for a given set of target formulas.
@@ -255,7 +258,8 @@ impl<'a> DependencyResolver<'a> {
             };
 
             // Check if installed
-            let installed_keg = if self.context.force_build {
+            let installed_keg = if self.context.force_build || (is_target && self.context.reinstall)
+            {
                 None
             } else {
                 self.context.keg_registry.get_installed_keg(name)?
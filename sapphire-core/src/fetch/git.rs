@@ -0,0 +1,169 @@
+// sapphire-core/src/fetch/git.rs
+// Fetches formula sources from git repositories, for builds of a formula's development version
+// (`--HEAD`) rather than a release tarball. Uses the git command line, which handles shallow
+// fetches of arbitrary refs and submodules.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{debug, info};
+
+use crate::utils::error::{Result, SapphireError};
+
+/// Checks out `url` into `dest`, with its submodules. A checkout already at `dest` (from an
+/// earlier `--HEAD` build) is fetched into, so only new objects are downloaded; anything else
+/// there is replaced. Either way the working tree ends up exactly at the fetched commit.
+///
+/// With `git_ref` (a branch, tag or commit) only that revision is fetched, at depth 1. Without
+/// one the default branch is fetched with its full history and tags, which build scripts that
+/// compute their version from `git describe` need. Returns `dest`.
+///
+/// Runs git and blocks until it is done, so async callers should use `spawn_blocking`.
+pub fn fetch_git_source(url: &str, git_ref: Option<&str>, dest: &Path) -> Result<PathBuf> {
+    if dest.join(".git").is_dir() {
+        debug!("Reusing the checkout at {}", dest.display());
+        git(dest, &["remote", "set-url", "--", "origin", url])?;
+    } else {
+        if dest.exists() {
+            fs::remove_dir_all(dest)?;
+        }
+        fs::create_dir_all(dest)?;
+        git(dest, &["init", "--quiet"])?;
+        git(dest, &["remote", "add", "--", "origin", url])?;
+    }
+
+    // Fetching the ref itself works for commits too, where `clone --branch` does not. `--`
+    // keeps a URL or ref starting with `-` from being read as an option.
+    match git_ref {
+        Some(git_ref) => {
+            info!("==> Fetching {} from {}", git_ref, url);
+            git(
+                dest,
+                &["fetch", "--quiet", "--depth", "1", "--", "origin", git_ref],
+            )?;
+        }
+        None => {
+            info!("==> Fetching {}", url);
+            let mut args = vec!["fetch", "--quiet", "--tags"];
+            // An earlier fetch of a single ref left a shallow history
+            if git(dest, &["rev-parse", "--is-shallow-repository"])? == "true" {
+                args.push("--unshallow");
+            }
+            args.extend(["--", "origin", "HEAD"]);
+            git(dest, &args)?;
+        }
+    }
+    git(
+        dest,
+        &["checkout", "--quiet", "--force", "--detach", "FETCH_HEAD"],
+    )?;
+    git(dest, &["clean", "-ffdxq"])?;
+
+    if dest.join(".gitmodules").is_file() {
+        info!("==> Checking out submodules");
+        let mut args = vec![
+            "submodule",
+            "update",
+            "--init",
+            "--recursive",
+            "--force",
+            "--quiet",
+        ];
+        if git_ref.is_some() {
+            args.extend(["--depth", "1"]);
+        }
+        git(dest, &args)?;
+    }
+    Ok(dest.to_path_buf())
+}
+
+/// The abbreviated hash of the commit checked out in `repo`.
+pub fn head_commit(repo: &Path) -> Result<String> {
+    git(repo, &["rev-parse", "--short", "HEAD"])
+}
+
+/// The commit time of the commit checked out in `repo`, in seconds since the Unix epoch.
+pub fn head_commit_time(repo: &Path) -> Result<u64> {
+    let time = git(repo, &["log", "-1", "--format=%ct", "HEAD"])?;
+    time.parse().map_err(|_| {
+        SapphireError::CommandExecError(format!("Unexpected commit time from git: {}", time))
+    })
+}
+
+/// Runs `git <args>` in `dir` and returns its trimmed stdout. Fails with git's stderr.
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let git = which::which("git").map_err(|_| {
+        SapphireError::CommandExecError(
+            "git not found on PATH; it is needed to build from a repository".to_string(),
+        )
+    })?;
+    debug!("Running git {} in {}", args.join(" "), dir.display());
+    let output = Command::new(git)
+        .args(args)
+        .current_dir(dir)
+        // Never stop to ask for credentials on a private or mistyped URL
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .map_err(|e| SapphireError::CommandExecError(format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(SapphireError::CommandExecError(format!(
+            "git {} failed ({}): {}",
+            args.first().unwrap_or(&""),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(repo: &Path, file: &str) {
+        fs::write(repo.join(file), file).unwrap();
+        git(repo, &["add", file]).unwrap();
+        git(
+            repo,
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                file,
+            ],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn later_fetches_update_the_existing_checkout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let upstream = tmp.path().join("upstream");
+        fs::create_dir_all(&upstream).unwrap();
+        git(&upstream, &["init", "--quiet"]).unwrap();
+        commit(&upstream, "a");
+        let url = upstream.to_string_lossy();
+        let dest = tmp.path().join("checkout");
+
+        fetch_git_source(&url, Some("HEAD"), &dest).unwrap();
+        assert!(dest.join("a").is_file());
+        fs::write(dest.join(".git/kept"), "").unwrap();
+        fs::write(dest.join("build-output"), "").unwrap();
+
+        commit(&upstream, "b");
+        fetch_git_source(&url, None, &dest).unwrap();
+        assert!(dest.join(".git/kept").is_file(), "checkout was recreated");
+        assert!(dest.join("b").is_file());
+        assert!(!dest.join("build-output").exists());
+        assert_eq!(
+            git(&dest, &["rev-parse", "--is-shallow-repository"]).unwrap(),
+            "false"
+        );
+        assert_eq!(head_commit(&dest).unwrap(), head_commit(&upstream).unwrap());
+    }
+}
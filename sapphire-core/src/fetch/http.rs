@@ -3,38 +3,54 @@
 
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use reqwest::header::{HeaderMap, ACCEPT, USER_AGENT};
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_RANGE, RANGE, USER_AGENT};
 use reqwest::{Client, StatusCode}; // Use async Client
 use sha2::{Digest, Sha256};
-use tokio::fs::File as TokioFile; // Use tokio's async File
 use tokio::io::AsyncWriteExt;
 use tracing::{error, warn};
 
+use crate::build::extract::{reassemble_split_archive, split_archive_part_index};
+use crate::fetch::segmented;
 use crate::model::formula::ResourceSpec;
 use crate::utils::config::Config;
 use crate::utils::error::{Result, SapphireError}; // For async write operations
 
 const DOWNLOAD_TIMEOUT_SECS: u64 = 300;
 const CONNECT_TIMEOUT_SECS: u64 = 30;
+/// Tries a download gets before a transient failure is reported.
+const DOWNLOAD_ATTEMPTS: u32 = 4;
+/// Wait before the first retry of a download; doubled for each retry after it.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
 const USER_AGENT_STRING: &str =
     "Sapphire Package Manager (Rust; +https://github.com/your/sapphire)";
 
-/// Fetches a formula's primary source or bottle asynchronously.
+/// Fetches a formula's primary source or bottle asynchronously. `url` is tried first, then each
+/// of `mirrors` in order, until one yields a file matching `sha256_expected`. If every URL fails
+/// the error lists each with why it failed. `on_progress` follows whichever URL is being tried.
+///
+/// A `url` naming a part of a split archive (`foo.tar.gz.part0`) fetches every part and returns
+/// the reassembled `foo.tar.gz`, which `sha256_expected` is the checksum of; see
+/// [`download_split_archive`].
 pub async fn fetch_formula_source_or_bottle(
     formula_name: &str,
     url: &str,
     sha256_expected: &str,
     mirrors: &[String],
     config: &Config,
+    on_progress: &ProgressCallback,
 ) -> Result<PathBuf> {
     let filename = url
         .split('/')
         .next_back() // Use next_back()
         .map(|s| s.to_string())
         .unwrap_or_else(|| format!("{}-download", formula_name));
-    let cache_path = config.cache_dir.join(&filename);
+    let split_base = split_archive_part_index(Path::new(&filename)).map(|(base, _)| base);
+    let cache_path = config
+        .cache_dir
+        .join(split_base.as_deref().unwrap_or(&filename));
 
     tracing::debug!(
         "Preparing to fetch main resource for '{}' from URL: {}",
@@ -45,39 +61,10 @@ pub async fn fetch_formula_source_or_bottle(
     tracing::debug!("Expected SHA256: {}", sha256_expected);
 
     // Check cache first (blocking IO is okay for quick checks)
-    if cache_path.is_file() {
-        tracing::debug!("File exists in cache: {}", cache_path.display());
-        if !sha256_expected.is_empty() {
-            match verify_checksum(&cache_path, sha256_expected) {
-                // Checksum verification is sync
-                Ok(_) => {
-                    tracing::debug!("Using valid cached file: {}", cache_path.display());
-                    return Ok(cache_path);
-                }
-                Err(e) => {
-                    warn!(
-                        "Cached file checksum mismatch ({}): {}. Redownloading.",
-                        cache_path.display(),
-                        e
-                    );
-                    if let Err(remove_err) = fs::remove_file(&cache_path) {
-                        warn!(
-                            "Failed to remove corrupted cached file {}: {}",
-                            cache_path.display(),
-                            remove_err
-                        );
-                    }
-                }
-            }
-        } else {
-            tracing::debug!(
-                "Using cached file (no checksum provided): {}",
-                cache_path.display()
-            );
-            return Ok(cache_path);
-        }
-    } else {
-        tracing::debug!("File not found in cache.");
+    discard_if_forced(&cache_path, config)?;
+    let cached = check_cached_entry(&cache_path, sha256_expected);
+    if cached == CachedEntry::Valid {
+        return Ok(cache_path);
     }
 
     // Create cache dir (sync is fine)
@@ -91,31 +78,68 @@ pub async fn fetch_formula_source_or_bottle(
 
     let client = build_http_client()?; // Builds async client
 
-    let urls_to_try = std::iter::once(url).chain(mirrors.iter().map(|s| s.as_str()));
-    let mut last_error: Option<SapphireError> = None;
+    let mut urls_to_try: Vec<&str> = Vec::new();
+    for candidate in std::iter::once(url).chain(mirrors.iter().map(|s| s.as_str())) {
+        if !urls_to_try.contains(&candidate) {
+            urls_to_try.push(candidate);
+        }
+    }
+    let mut failures: Vec<(String, SapphireError)> = Vec::new();
 
     for current_url in urls_to_try {
         tracing::debug!("Attempting download from: {}", current_url);
-        match download_and_verify(&client, current_url, &cache_path, sha256_expected).await {
+        let connections = config.install_options.download_connections;
+        let result = if split_base.is_some() {
+            download_split_archive(
+                &client,
+                current_url,
+                &cache_path,
+                sha256_expected,
+                connections,
+                on_progress,
+            )
+            .await
+        } else {
+            download_and_verify(
+                &client,
+                current_url,
+                &cache_path,
+                sha256_expected,
+                connections,
+                on_progress,
+            )
+            .await
+        };
+        match result {
             // Await async download
             Ok(path) => {
+                if current_url != url {
+                    tracing::info!("Downloaded {} from mirror {}", filename, current_url);
+                }
                 tracing::debug!("Successfully downloaded and verified: {}", path.display());
                 return Ok(path);
             }
             Err(e) => {
                 error!("Download attempt failed from {}: {}", current_url, e);
-                last_error = Some(e);
+                failures.push((
+                    current_url.to_string(),
+                    checksum_failure(e, current_url, cached),
+                ));
             }
         }
     }
 
-    Err(last_error.unwrap_or_else(|| {
-        SapphireError::DownloadError(
-            formula_name.to_string(),
-            url.to_string(),
-            "All download attempts failed.".to_string(),
-        )
-    }))
+    // With a single URL its own error says it all, and keeps its type
+    if failures.len() == 1 {
+        return Err(failures.remove(0).1);
+    }
+    Err(SapphireError::AllDownloadsFailed {
+        name: formula_name.to_string(),
+        failures: failures
+            .into_iter()
+            .map(|(url, e)| (url, e.to_string()))
+            .collect(),
+    })
 }
 
 /// Fetches a formula's resource dependency asynchronously.
@@ -123,6 +147,7 @@ pub async fn fetch_resource(
     formula_name: &str,
     resource: &ResourceSpec,
     config: &Config,
+    on_progress: &ProgressCallback,
 ) -> Result<PathBuf> {
     let resource_cache_dir = config.cache_dir.join("resources");
     fs::create_dir_all(&resource_cache_dir).map_err(|e| {
@@ -152,35 +177,23 @@ pub async fn fetch_resource(
     tracing::debug!("Expected SHA256: {}", resource.sha256);
 
     // Check resource cache (sync is fine)
-    if cache_path.is_file() {
-        tracing::debug!("Resource exists in cache: {}", cache_path.display());
-        match verify_checksum(&cache_path, &resource.sha256) {
-            // Checksum is sync
-            Ok(_) => {
-                tracing::debug!("Using cached resource: {}", cache_path.display());
-                return Ok(cache_path);
-            }
-            Err(e) => {
-                warn!(
-                    "Cached resource checksum mismatch ({}): {}. Redownloading.",
-                    cache_path.display(),
-                    e
-                );
-                if let Err(remove_err) = fs::remove_file(&cache_path) {
-                    warn!(
-                        "Failed to remove corrupted cached resource file {}: {}",
-                        cache_path.display(),
-                        remove_err
-                    );
-                }
-            }
-        }
-    } else {
-        tracing::debug!("Resource not found in cache.");
+    discard_if_forced(&cache_path, config)?;
+    let cached = check_cached_entry(&cache_path, &resource.sha256);
+    if cached == CachedEntry::Valid {
+        return Ok(cache_path);
     }
 
     let client = build_http_client()?;
-    match download_and_verify(&client, &resource.url, &cache_path, &resource.sha256).await {
+    match download_and_verify(
+        &client,
+        &resource.url,
+        &cache_path,
+        &resource.sha256,
+        config.install_options.download_connections,
+        on_progress,
+    )
+    .await
+    {
         // Await async download
         Ok(path) => {
             tracing::debug!(
@@ -189,6 +202,11 @@ pub async fn fetch_resource(
             );
             Ok(path)
         }
+        Err(e @ SapphireError::ChecksumMismatch { .. }) => {
+            error!("Resource download failed from {}: {}", resource.url, e);
+            let _ = fs::remove_file(&cache_path); // Attempt cleanup
+            Err(checksum_failure(e, &resource.url, cached))
+        }
         Err(e) => {
             error!("Resource download failed from {}: {}", resource.url, e);
             let _ = fs::remove_file(&cache_path); // Attempt cleanup
@@ -201,10 +219,85 @@ pub async fn fetch_resource(
     }
 }
 
+/// State of a download's cache entry, checked before going to the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachedEntry {
+    /// A cached copy exists and matches its checksum (or there is no checksum to check).
+    Valid,
+    /// Nothing is cached yet.
+    Missing,
+    /// A cached copy failed its checksum (partial write, disk error, ...) and was deleted.
+    Discarded,
+}
+
+/// Removes the cached download at `cache_path` when `force_fetch` asks for a fresh one.
+fn discard_if_forced(cache_path: &Path, config: &Config) -> Result<()> {
+    if config.install_options.force_fetch && cache_path.exists() {
+        tracing::debug!("Discarding cached {}", cache_path.display());
+        fs::remove_file(cache_path)?;
+    }
+    Ok(())
+}
+
+/// Checks the cached copy at `cache_path` against `sha256_expected`. A corrupt copy is deleted
+/// so the caller downloads it again instead of failing on it later.
+pub fn check_cached_entry(cache_path: &Path, sha256_expected: &str) -> CachedEntry {
+    if !cache_path.is_file() {
+        tracing::debug!("Not found in cache: {}", cache_path.display());
+        return CachedEntry::Missing;
+    }
+    if sha256_expected.is_empty() {
+        tracing::debug!(
+            "Using cached file (no checksum provided): {}",
+            cache_path.display()
+        );
+        return CachedEntry::Valid;
+    }
+    match verify_checksum(cache_path, sha256_expected) {
+        Ok(()) => {
+            tracing::debug!("Using valid cached file: {}", cache_path.display());
+            CachedEntry::Valid
+        }
+        Err(e) => {
+            warn!(
+                "Cached file {} is corrupt ({}); deleting it and downloading it again",
+                cache_path.display(),
+                e
+            );
+            if let Err(remove_err) = fs::remove_file(cache_path) {
+                warn!(
+                    "Failed to remove corrupted cached file {}: {}",
+                    cache_path.display(),
+                    remove_err
+                );
+            }
+            CachedEntry::Discarded
+        }
+    }
+}
+
+/// Rewords a checksum failure of a fresh download from `url` so it says whether a corrupt
+/// cached copy was replaced first. Either way the fresh download itself doesn't match, so the
+/// upstream file or the expected checksum is wrong. Other errors are returned unchanged.
+pub fn checksum_failure(error: SapphireError, url: &str, cached: CachedEntry) -> SapphireError {
+    if !matches!(error, SapphireError::ChecksumMismatch { .. }) {
+        return error;
+    }
+    let context = if cached == CachedEntry::Discarded {
+        "the cached copy was corrupt and was re-downloaded, but"
+    } else {
+        "the freshly downloaded file"
+    };
+    SapphireError::ChecksumError(format!(
+        "Upstream checksum mismatch from {}: {} does not match the expected checksum ({})",
+        url, context, error
+    ))
+}
+
 // --- Internal Helpers ---
 
 // Builds the async reqwest::Client
-fn build_http_client() -> Result<Client> {
+pub(crate) fn build_http_client() -> Result<Client> {
     let mut headers = HeaderMap::new();
     headers.insert(USER_AGENT, USER_AGENT_STRING.parse().unwrap());
     headers.insert(ACCEPT, "*/*".parse().unwrap());
@@ -217,12 +310,107 @@ fn build_http_client() -> Result<Client> {
         .map_err(|e| SapphireError::HttpError(format!("Failed to build HTTP client: {}", e)))
 }
 
-// Performs download and verification asynchronously
+// Performs download and verification asynchronously. With more than one connection the file is
+// fetched in segments when the server allows it; the checksum covers the reassembled file.
 async fn download_and_verify(
     client: &Client,
     url: &str,
     final_path: &Path,
     sha256_expected: &str,
+    connections: usize,
+    on_progress: &ProgressCallback,
+) -> Result<PathBuf> {
+    let temp_path = download_to_temp(client, url, final_path, connections, on_progress).await?;
+
+    // Checksum verification is synchronous (CPU bound)
+    if !sha256_expected.is_empty() {
+        verify_checksum(&temp_path, sha256_expected)?;
+        tracing::debug!(
+            "Checksum verified for temporary file: {}",
+            temp_path.display()
+        );
+    } else {
+        tracing::warn!(
+            "Skipping checksum verification for {} - none provided.",
+            temp_path.display()
+        );
+    }
+
+    move_into_place(&temp_path, final_path)?;
+    Ok(final_path.to_path_buf())
+}
+
+/// The URL of part `index` of the split archive whose part `url` names, which is `url` with its
+/// `.partN` suffix replaced.
+fn split_part_url(url: &str, index: u32) -> Option<String> {
+    let (prefix, _) = url.rsplit_once(".part")?;
+    Some(format!("{}.part{}", prefix, index))
+}
+
+/// Downloads the split archive whose first part is at `first_part_url` and reassembles it into
+/// `final_path`, which must match `sha256_expected`. Parts are numbered on from the first until
+/// the server answers 404 for the next one; a missing last part therefore shows up as a
+/// checksum mismatch. Parts are only kept in the cache until they are joined.
+async fn download_split_archive(
+    client: &Client,
+    first_part_url: &str,
+    final_path: &Path,
+    sha256_expected: &str,
+    connections: usize,
+    on_progress: &ProgressCallback,
+) -> Result<PathBuf> {
+    let file_name = first_part_url.rsplit('/').next().unwrap_or(first_part_url);
+    let Some((base, first_index)) = split_archive_part_index(Path::new(file_name)) else {
+        return Err(SapphireError::Generic(format!(
+            "{} is not part of a split archive",
+            first_part_url
+        )));
+    };
+    let mut parts = Vec::new();
+    for index in first_index.. {
+        let part_url = split_part_url(first_part_url, index).unwrap_or_default();
+        if index > first_index {
+            let probe = client.head(&part_url).send().await.map_err(|e| {
+                SapphireError::HttpError(format!("HTTP request failed for {}: {}", part_url, e))
+            })?;
+            if matches!(probe.status(), StatusCode::NOT_FOUND | StatusCode::GONE) {
+                break;
+            }
+        }
+        tracing::debug!("Downloading split archive part {}", part_url);
+        let part_path = final_path.with_file_name(format!("{}.part{}", base, index));
+        let temp_path =
+            download_to_temp(client, &part_url, &part_path, connections, on_progress).await?;
+        move_into_place(&temp_path, &part_path)?;
+        parts.push(part_path);
+    }
+
+    tracing::info!(
+        "Reassembling {} split archive parts into {}",
+        parts.len(),
+        final_path.display()
+    );
+    let reassembled = reassemble_split_archive(&parts, final_path, Some(sha256_expected));
+    for part in &parts {
+        let _ = fs::remove_file(part);
+    }
+    reassembled?;
+    if sha256_expected.is_empty() {
+        tracing::warn!(
+            "Skipping checksum verification for {} - none provided.",
+            final_path.display()
+        );
+    }
+    Ok(final_path.to_path_buf())
+}
+
+/// Downloads `url` to a temporary file next to `final_path` and returns the temporary file.
+async fn download_to_temp(
+    client: &Client,
+    url: &str,
+    final_path: &Path,
+    connections: usize,
+    on_progress: &ProgressCallback,
 ) -> Result<PathBuf> {
     let temp_filename = format!(
         ".{}.download",
@@ -240,141 +428,360 @@ async fn download_and_verify(
         }
     }
 
-    let response = client
-        .get(url)
-        .send()
-        .await // Await send
-        .map_err(|e| SapphireError::HttpError(format!("HTTP request failed for {}: {}", url, e)))?;
+    if !segmented::download_segmented(|| client.get(url), &temp_path, connections, on_progress)
+        .await?
+    {
+        download_resumable(client, url, &temp_path, DOWNLOAD_ATTEMPTS, on_progress).await?;
+    }
+    Ok(temp_path)
+}
+
+/// Moves a finished download from `temp_path` to `final_path`.
+fn move_into_place(temp_path: &Path, final_path: &Path) -> Result<()> {
+    // Rename is synchronous
+    fs::rename(temp_path, final_path).map_err(|e| {
+        SapphireError::IoError(format!(
+            "Failed to move temp file {} to {}: {}",
+            temp_path.display(),
+            final_path.display(),
+            e
+        ))
+    })?;
+    tracing::debug!(
+        "Moved downloaded file to final location: {}",
+        final_path.display()
+    );
+    Ok(())
+}
+
+/// How far a download has got, as passed to a progress callback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownloadProgress {
+    /// Bytes of the file received so far, including any kept from an earlier try.
+    pub downloaded: u64,
+    /// Size of the whole file, from `Content-Length`. `None` when the server doesn't send one
+    /// (chunked responses), and progress is indeterminate.
+    pub total: Option<u64>,
+    /// Average speed since the download started, in bytes per second.
+    pub bytes_per_sec: f64,
+}
+
+/// Receives the progress of a download. It is shared by the connections of a segmented
+/// download, which report concurrently.
+pub type ProgressCallback = dyn Fn(DownloadProgress) + Send + Sync;
+
+impl DownloadProgress {
+    /// Progress of a download begun at `started`, which has itself received `received` of the
+    /// `downloaded` bytes so far.
+    pub(crate) fn measure(
+        downloaded: u64,
+        total: Option<u64>,
+        received: u64,
+        started: Instant,
+    ) -> Self {
+        let elapsed = started.elapsed().as_secs_f64();
+        Self {
+            downloaded,
+            total,
+            bytes_per_sec: if elapsed > 0.0 {
+                received as f64 / elapsed
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// How much of the file is done, from 0 to 100, or `None` if the size is unknown.
+    pub fn percent(&self) -> Option<f64> {
+        self.total
+            .filter(|&total| total > 0)
+            .map(|total| (self.downloaded as f64 / total as f64 * 100.0).min(100.0))
+    }
+}
+
+/// Turns the bytes written by each try of a download into [`DownloadProgress`] reports.
+struct ProgressTracker<F> {
+    started: Instant,
+    /// Bytes received by this download, not counting what a resumed file already held.
+    received: u64,
+    on_progress: F,
+}
+
+impl<F: FnMut(DownloadProgress)> ProgressTracker<F> {
+    fn report(&mut self, downloaded: u64, total: Option<u64>) {
+        (self.on_progress)(DownloadProgress::measure(
+            downloaded,
+            total,
+            self.received,
+            self.started,
+        ));
+    }
+}
+
+/// Downloads `url` to `dest`, retrying connection failures and 5xx responses with exponential
+/// backoff, `max_attempts` tries in all. A retry resumes from the bytes already in `dest` with a
+/// range request where the server supports one. Other 4xx responses (404, 403...) fail at once.
+///
+/// `on_progress` is called as each chunk is written, so it should be cheap; a frontend drawing
+/// a progress bar throttles its own redraws.
+pub async fn download_resumable(
+    client: &Client,
+    url: &str,
+    dest: &Path,
+    max_attempts: u32,
+    on_progress: impl FnMut(DownloadProgress),
+) -> Result<()> {
+    let mut progress = ProgressTracker {
+        started: Instant::now(),
+        received: 0,
+        on_progress,
+    };
+    let mut attempt = 1;
+    loop {
+        match download_attempt(client, url, dest, &mut progress).await {
+            Ok(()) => return Ok(()),
+            Err(AttemptError::Fatal(e)) => return Err(e),
+            Err(AttemptError::Transient(e)) if attempt >= max_attempts => return Err(e),
+            Err(AttemptError::Transient(e)) => {
+                let delay = RETRY_BASE_DELAY
+                    .saturating_mul(1 << (attempt - 1).min(16))
+                    .min(RETRY_MAX_DELAY);
+                warn!(
+                    "Download of {} failed: {}; retrying in {}s (attempt {}/{})",
+                    url,
+                    e,
+                    delay.as_secs(),
+                    attempt + 1,
+                    max_attempts
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Why one download attempt failed, and so whether another is worth making.
+enum AttemptError {
+    Transient(SapphireError),
+    Fatal(SapphireError),
+}
+
+// One request for `url`, appended to what `dest` already holds when the server honours the range
+async fn download_attempt<F: FnMut(DownloadProgress)>(
+    client: &Client,
+    url: &str,
+    dest: &Path,
+    progress: &mut ProgressTracker<F>,
+) -> std::result::Result<(), AttemptError> {
+    let have = tokio::fs::metadata(dest)
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let mut request = client.get(url);
+    if have > 0 {
+        tracing::debug!("Resuming {} from byte {}", url, have);
+        request = request.header(RANGE, format!("bytes={}-", have));
+    }
+    let mut response = request.send().await.map_err(|e| {
+        let error = SapphireError::HttpError(format!("HTTP request failed for {}: {}", url, e));
+        if e.is_builder() {
+            AttemptError::Fatal(error)
+        } else {
+            AttemptError::Transient(error)
+        }
+    })?;
     let status = response.status();
     tracing::debug!("Received HTTP status: {} for {}", status, url);
 
+    if status == StatusCode::RANGE_NOT_SATISFIABLE && have > 0 {
+        // The partial file doesn't fit what the server has now; start over
+        let _ = tokio::fs::remove_file(dest).await;
+        return Err(AttemptError::Transient(SapphireError::HttpError(format!(
+            "Server rejected resuming {} at byte {}",
+            url, have
+        ))));
+    }
     if !status.is_success() {
         let body_text = response
             .text()
             .await
-            .unwrap_or_else(|_| "Failed to read response body".to_string()); // Await text
-        tracing::error!("HTTP error {} for URL {}: {}", status, url, body_text);
-        return match status {
-            StatusCode::NOT_FOUND => Err(SapphireError::DownloadError(
-                final_path
-                    .file_name()
-                    .map(|s| s.to_string_lossy().to_string())
-                    .unwrap_or_default(),
+            .unwrap_or_else(|_| "Failed to read response body".to_string());
+        let file_name = url.rsplit('/').next().unwrap_or(url).to_string();
+        return Err(match status {
+            StatusCode::NOT_FOUND => AttemptError::Fatal(SapphireError::DownloadError(
+                file_name,
                 url.to_string(),
                 "Resource not found (404)".to_string(),
             )),
-            StatusCode::FORBIDDEN => Err(SapphireError::DownloadError(
-                final_path
-                    .file_name()
-                    .map(|s| s.to_string_lossy().to_string())
-                    .unwrap_or_default(),
+            StatusCode::FORBIDDEN => AttemptError::Fatal(SapphireError::DownloadError(
+                file_name,
                 url.to_string(),
                 "Access forbidden (403)".to_string(),
             )),
-            _ => Err(SapphireError::HttpError(format!(
-                "HTTP error {} for URL {}: {}",
-                status, url, body_text
-            ))),
-        };
+            _ => {
+                let error = SapphireError::HttpError(format!(
+                    "HTTP error {} for URL {}: {}",
+                    status, url, body_text
+                ));
+                if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                    AttemptError::Transient(error)
+                } else {
+                    AttemptError::Fatal(error)
+                }
+            }
+        });
     }
 
-    // Use tokio async file operations
-    let mut temp_file = TokioFile::create(&temp_path).await.map_err(|e| {
-        SapphireError::IoError(format!(
-            "Failed to create temp file {}: {}",
-            temp_path.display(),
-            e
-        ))
-    })?;
-    let content = response
-        .bytes()
-        .await // Await bytes
+    // A plain 200 means the server ignored the range and is sending the whole file
+    let resume = status == StatusCode::PARTIAL_CONTENT;
+    if resume && !resumes_at(response.headers(), have) {
+        // Appending a range that starts anywhere else would corrupt the file; start over
+        let _ = tokio::fs::remove_file(dest).await;
+        return Err(AttemptError::Transient(SapphireError::HttpError(format!(
+            "Server answered the request to resume {} at byte {} with another range",
+            url, have
+        ))));
+    }
+    let mut downloaded = if resume { have } else { 0 };
+    let total = response.content_length().map(|len| downloaded + len);
+    progress.report(downloaded, total);
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resume)
+        .truncate(!resume)
+        .open(dest)
+        .await
         .map_err(|e| {
-            SapphireError::HttpError(format!("Failed to read response body bytes: {}", e))
+            AttemptError::Fatal(SapphireError::IoError(format!(
+                "Failed to open {}: {}",
+                dest.display(),
+                e
+            )))
         })?;
-    temp_file
-        .write_all(&content)
-        .await // Await write
-        .map_err(|e| {
-            SapphireError::IoError(format!(
+    // Written as it arrives, so a dropped connection leaves something to resume from
+    while let Some(chunk) = response.chunk().await.map_err(|e| {
+        AttemptError::Transient(SapphireError::HttpError(format!(
+            "Failed to read response body from {}: {}",
+            url, e
+        )))
+    })? {
+        file.write_all(&chunk).await.map_err(|e| {
+            AttemptError::Fatal(SapphireError::IoError(format!(
                 "Failed to write download stream to {}: {}",
-                temp_path.display(),
+                dest.display(),
                 e
-            ))
+            )))
         })?;
-    drop(temp_file); // Close file
-    tracing::debug!("Finished writing download stream to temp file.");
-
-    // Checksum verification is synchronous (CPU bound)
-    if !sha256_expected.is_empty() {
-        verify_checksum(&temp_path, sha256_expected)?;
-        tracing::debug!(
-            "Checksum verified for temporary file: {}",
-            temp_path.display()
-        );
-    } else {
-        tracing::warn!(
-            "Skipping checksum verification for {} - none provided.",
-            temp_path.display()
-        );
+        downloaded += chunk.len() as u64;
+        progress.received += chunk.len() as u64;
+        progress.report(downloaded, total);
     }
+    file.flush().await.map_err(|e| {
+        AttemptError::Fatal(SapphireError::IoError(format!(
+            "Failed to write {}: {}",
+            dest.display(),
+            e
+        )))
+    })?;
+    tracing::debug!("Finished writing download stream to {}", dest.display());
+    Ok(())
+}
 
-    // Rename is synchronous
-    fs::rename(&temp_path, final_path).map_err(|e| {
+/// Whether a 206 response's `Content-Range` (`bytes 1000-1999/2000`) starts at byte `have`.
+fn resumes_at(headers: &HeaderMap, have: u64) -> bool {
+    headers
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|range| range.starts_with(&format!("bytes {}-", have)))
+}
+
+/// Streams the file at `file_path` through SHA-256 and returns the lowercase hex digest.
+pub fn sha256_file(file_path: &Path) -> Result<String> {
+    let mut file = fs::File::open(file_path).map_err(|e| {
         SapphireError::IoError(format!(
-            "Failed to move temp file {} to {}: {}",
-            temp_path.display(),
-            final_path.display(),
+            "Failed to open file for checksum {}: {}",
+            file_path.display(),
             e
         ))
     })?;
-    tracing::debug!(
-        "Moved verified file to final location: {}",
-        final_path.display()
-    );
-    Ok(final_path.to_path_buf())
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| {
+        SapphireError::IoError(format!(
+            "Failed read file for checksum {}: {}",
+            file_path.display(),
+            e
+        ))
+    })?;
+    Ok(hex::encode(hasher.finalize()))
 }
 
 // verify_checksum remains synchronous
+/// Checks the file at `file_path` against `expected_sha256` (hex, any case, surrounding
+/// whitespace ignored), failing with [`SapphireError::ChecksumMismatch`] if they differ.
 pub fn verify_checksum(file_path: &Path, expected_sha256: &str) -> Result<()> {
     tracing::debug!("Verifying checksum for: {}", file_path.display());
-    let mut file = match fs::File::open(file_path) {
-        Ok(f) => f,
-        Err(e) => {
-            return Err(SapphireError::IoError(format!(
-                "Failed to open file for checksum {}: {}",
-                file_path.display(),
-                e
-            )))
-        }
-    };
-    let mut hasher = Sha256::new();
-    let bytes_copied = match std::io::copy(&mut file, &mut hasher) {
-        Ok(b) => b,
-        Err(e) => {
-            return Err(SapphireError::IoError(format!(
-                "Failed read file for checksum {}: {}",
-                file_path.display(),
-                e
-            )))
-        }
-    };
-    let hash_bytes = hasher.finalize();
-    let actual_sha256 = hex::encode(hash_bytes);
-    tracing::debug!(
-        "Calculated SHA256: {} ({} bytes read)",
-        actual_sha256,
-        bytes_copied
-    );
+    let expected_sha256 = expected_sha256.trim();
+    let actual_sha256 = sha256_file(file_path)?;
+    tracing::debug!("Calculated SHA256: {}", actual_sha256);
     tracing::debug!("Expected SHA256:   {}", expected_sha256);
     if actual_sha256.eq_ignore_ascii_case(expected_sha256) {
         Ok(())
     } else {
-        Err(SapphireError::ChecksumError(format!(
-            "Checksum mismatch for {}: expected {}, got {}",
-            file_path.display(),
-            expected_sha256,
-            actual_sha256
-        )))
+        Err(SapphireError::ChecksumMismatch {
+            expected: expected_sha256.to_string(),
+            actual: actual_sha256,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_SHA256: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    #[test]
+    fn split_part_urls_replace_the_part_number() {
+        assert_eq!(
+            split_part_url("https://example.com/v1.2/model.tar.gz.part0", 3).as_deref(),
+            Some("https://example.com/v1.2/model.tar.gz.part3")
+        );
+        assert_eq!(split_part_url("https://example.com/model.tar.gz", 1), None);
+    }
+
+    #[test]
+    fn partial_responses_must_resume_where_the_file_stops() {
+        let mut headers = HeaderMap::new();
+        assert!(!resumes_at(&headers, 1000));
+        headers.insert(CONTENT_RANGE, "bytes 1000-1999/2000".parse().unwrap());
+        assert!(resumes_at(&headers, 1000));
+        assert!(!resumes_at(&headers, 100));
+        headers.insert(CONTENT_RANGE, "bytes 0-1999/2000".parse().unwrap());
+        assert!(!resumes_at(&headers, 1000));
+    }
+
+    #[test]
+    fn checksum_matches_fixture_in_any_case() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("hello.txt");
+        fs::write(&file, "hello\n").unwrap();
+
+        assert_eq!(sha256_file(&file).unwrap(), HELLO_SHA256);
+        verify_checksum(&file, HELLO_SHA256).unwrap();
+        verify_checksum(&file, &format!(" {}\n", HELLO_SHA256.to_uppercase())).unwrap();
+
+        match verify_checksum(&file, &"0".repeat(64)).unwrap_err() {
+            SapphireError::ChecksumMismatch { expected, actual } => {
+                assert_eq!(expected, "0".repeat(64));
+                assert_eq!(actual, HELLO_SHA256);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+        assert!(matches!(
+            verify_checksum(&dir.path().join("missing"), HELLO_SHA256),
+            Err(SapphireError::IoError(_))
+        ));
     }
 }
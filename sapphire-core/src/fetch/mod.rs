@@ -1,6 +1,9 @@
 pub mod api;
+pub mod git;
 pub mod http;
 pub mod oci;
+pub mod prefetch;
+pub mod segmented;
 
 // Re-export
 pub use api::*;
@@ -3,17 +3,19 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::path::Path;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::StreamExt;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 use reqwest::header::{ACCEPT, AUTHORIZATION};
-use reqwest::{Client, Response, StatusCode};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, warn};
 use url::Url;
 
+use crate::fetch::http::{DownloadProgress, ProgressCallback};
+use crate::fetch::segmented;
 use crate::utils::config::Config;
 use crate::utils::error::{Result, SapphireError};
 // ────────────────────────────────────────────────────────────────────────────────
@@ -98,6 +100,7 @@ pub async fn download_oci_blob(
     destination_path: &Path,
     config: &Config,
     client: &Client,
+    on_progress: &ProgressCallback,
 ) -> Result<()> {
     debug!("Downloading OCI blob: {}", blob_url);
     let url = Url::parse(blob_url)
@@ -106,19 +109,36 @@ pub async fn download_oci_blob(
     let repo_path = extract_repo_path_from_url(&url).unwrap_or("");
 
     let auth = determine_auth(config, client, registry_domain, repo_path).await?;
-    let resp = execute_oci_request(client, blob_url, OCI_LAYER_V1_TYPE, &auth).await?;
 
     // Write to a temporary file, then rename
     let tmp = destination_path.with_file_name(format!(
         ".{}.download",
         destination_path.file_name().unwrap().to_string_lossy()
     ));
-    let mut out = File::create(&tmp).map_err(SapphireError::Io)?;
+    let segmented = segmented::download_segmented(
+        || oci_request(client, blob_url, OCI_LAYER_V1_TYPE, &auth),
+        &tmp,
+        config.install_options.download_connections,
+        on_progress,
+    )
+    .await?;
+    if !segmented {
+        let resp = execute_oci_request(client, blob_url, OCI_LAYER_V1_TYPE, &auth).await?;
+        let total = resp.content_length();
+        let mut out = File::create(&tmp).map_err(SapphireError::Io)?;
 
-    let mut stream = resp.bytes_stream();
-    while let Some(chunk) = stream.next().await {
-        let b = chunk.map_err(SapphireError::Http)?;
-        std::io::Write::write_all(&mut out, &b).map_err(SapphireError::Io)?;
+        let started = Instant::now();
+        let mut downloaded = 0;
+        on_progress(DownloadProgress::measure(0, total, 0, started));
+        let mut stream = resp.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let b = chunk.map_err(SapphireError::Http)?;
+            std::io::Write::write_all(&mut out, &b).map_err(SapphireError::Io)?;
+            downloaded += b.len() as u64;
+            on_progress(DownloadProgress::measure(
+                downloaded, total, downloaded, started,
+            ));
+        }
     }
     std::fs::rename(&tmp, destination_path).map_err(SapphireError::Io)?;
 
@@ -251,27 +271,33 @@ async fn fetch_anonymous_token(
     )))
 }
 
-async fn execute_oci_request(
-    client: &Client,
-    url: &str,
-    accept: &str,
-    auth: &OciAuth,
-) -> Result<Response> {
-    debug!("OCI request → {} (Accept: {})", url, accept);
-    let mut req = client.get(url).header(ACCEPT, accept);
+/// Builds an authenticated GET request for `url`.
+fn oci_request(client: &Client, url: &str, accept: &str, auth: &OciAuth) -> RequestBuilder {
+    let req = client.get(url).header(ACCEPT, accept);
     match auth {
         OciAuth::AnonymousBearer { token } | OciAuth::ExplicitBearer { token }
             if !token.is_empty() =>
         {
-            req = req.header(AUTHORIZATION, format!("Bearer {}", token))
+            req.header(AUTHORIZATION, format!("Bearer {}", token))
         }
         OciAuth::Basic { encoded } if !encoded.is_empty() => {
-            req = req.header(AUTHORIZATION, format!("Basic {}", encoded))
+            req.header(AUTHORIZATION, format!("Basic {}", encoded))
         }
-        _ => {}
+        _ => req,
     }
+}
 
-    let resp = req.send().await.map_err(SapphireError::Http)?;
+async fn execute_oci_request(
+    client: &Client,
+    url: &str,
+    accept: &str,
+    auth: &OciAuth,
+) -> Result<Response> {
+    debug!("OCI request → {} (Accept: {})", url, accept);
+    let resp = oci_request(client, url, accept, auth)
+        .send()
+        .await
+        .map_err(SapphireError::Http)?;
     let status = resp.status();
     if status.is_success() {
         Ok(resp)
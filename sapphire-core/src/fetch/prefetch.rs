@@ -0,0 +1,149 @@
+// sapphire-core/src/fetch/prefetch.rs
+// Background downloads of the bottles/sources later formulae in an install plan will need, so
+// network time overlaps with the builds running in the meantime. Each formula is downloaded at
+// most once; a build asking for its artifact joins the prefetch instead of starting a second
+// download into the same cache path.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+use reqwest::Client;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::build::formula::{bottle, has_bottle_for_current_platform, source};
+use crate::fetch::http::{DownloadProgress, ProgressCallback};
+use crate::model::formula::Formula;
+use crate::utils::config::Config;
+use crate::utils::error::Result;
+
+// Errors are kept as strings so the shared result can be cloned to every waiter
+type SharedDownload = Shared<BoxFuture<'static, std::result::Result<PathBuf, String>>>;
+
+/// Receives the name of a formula and how far the download of its artifact has got.
+pub type ArtifactProgress = Arc<dyn Fn(&str, DownloadProgress) + Send + Sync>;
+
+/// Bounded pool of background downloads, keyed by formula name. Clones share the same pool.
+#[derive(Clone)]
+pub struct Prefetcher {
+    config: Config,
+    client: Arc<Client>,
+    permits: Arc<Semaphore>,
+    downloads: Arc<Mutex<HashMap<String, SharedDownload>>>,
+    on_progress: Option<ArtifactProgress>,
+}
+
+impl Prefetcher {
+    /// Creates a prefetcher running at most `max_concurrent` background downloads at once.
+    pub fn new(config: Config, client: Arc<Client>, max_concurrent: usize) -> Self {
+        Self {
+            config,
+            client,
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            downloads: Arc::new(Mutex::new(HashMap::new())),
+            on_progress: None,
+        }
+    }
+
+    /// Reports the progress of every download, prefetched or not, to `on_progress`.
+    pub fn with_progress(mut self, on_progress: ArtifactProgress) -> Self {
+        self.on_progress = Some(on_progress);
+        self
+    }
+
+    /// Starts downloading the artifact `formula` will be installed from (its bottle, or its
+    /// source when building). Does nothing if a download for it was already started.
+    pub fn prefetch(&self, formula: Arc<Formula>, build_from_source: bool) {
+        let mut downloads = self.lock();
+        if downloads.contains_key(formula.name()) {
+            return;
+        }
+
+        let name = formula.name().to_string();
+        let config = self.config.clone();
+        let client = Arc::clone(&self.client);
+        let permits = Arc::clone(&self.permits);
+        let on_progress = self.on_progress.clone();
+        let download = async move {
+            let _permit = permits.acquire_owned().await.map_err(|e| e.to_string())?;
+            debug!("Prefetching artifact for {}", formula.name());
+            let report = progress_for(on_progress, formula.name());
+            download_artifact(&formula, build_from_source, &config, &client, &report)
+                .await
+                .map_err(|e| e.to_string())
+        }
+        .boxed()
+        .shared();
+
+        downloads.insert(name, download.clone());
+        // Drive the download even if nothing ends up waiting on it
+        tokio::spawn(download);
+    }
+
+    /// Returns the artifact for `formula`, joining its prefetch if one was started.
+    ///
+    /// A failed prefetch is not an error by itself: the download is retried here, so only a
+    /// failure at the point the artifact is actually needed is reported.
+    pub async fn fetch(&self, formula: &Formula, build_from_source: bool) -> Result<PathBuf> {
+        let pending = self.lock().get(formula.name()).cloned();
+        if let Some(pending) = pending {
+            match pending.await {
+                Ok(path) if path.exists() => return Ok(path),
+                Ok(path) => debug!(
+                    "Prefetched artifact {} for {} is gone, downloading again",
+                    path.display(),
+                    formula.name()
+                ),
+                Err(e) => warn!(
+                    "Prefetch for {} failed ({}), retrying download",
+                    formula.name(),
+                    e
+                ),
+            }
+        }
+        let report = progress_for(self.on_progress.clone(), formula.name());
+        download_artifact(
+            formula,
+            build_from_source,
+            &self.config,
+            &self.client,
+            &report,
+        )
+        .await
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, SharedDownload>> {
+        self.downloads.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A download progress callback passing `name` along to `on_progress`, if there is one.
+fn progress_for(
+    on_progress: Option<ArtifactProgress>,
+    name: &str,
+) -> impl Fn(DownloadProgress) + Send + Sync + 'static {
+    let name = name.to_string();
+    move |progress| {
+        if let Some(on_progress) = &on_progress {
+            on_progress(&name, progress);
+        }
+    }
+}
+
+/// Downloads the bottle for `formula`, or its source if building from source or no bottle
+/// exists for this platform.
+pub async fn download_artifact(
+    formula: &Formula,
+    build_from_source: bool,
+    config: &Config,
+    client: &Client,
+    on_progress: &ProgressCallback,
+) -> Result<PathBuf> {
+    if build_from_source || !has_bottle_for_current_platform(formula) {
+        source::download_source(formula, config, on_progress).await
+    } else {
+        bottle::download_bottle(formula, config, client, on_progress).await
+    }
+}
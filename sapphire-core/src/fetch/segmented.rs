@@ -0,0 +1,208 @@
+// sapphire-core/src/fetch/segmented.rs
+// Segmented downloads: a file is split into byte ranges that are fetched over several
+// connections at once and written straight into place. On high-latency links a single TCP
+// connection rarely fills the pipe, so this can be much faster for large bottles and sources.
+
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use futures::future::try_join_all;
+use futures::StreamExt;
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::{RequestBuilder, StatusCode};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tracing::{debug, warn};
+
+use crate::fetch::http::{DownloadProgress, ProgressCallback};
+use crate::utils::error::{Result, SapphireError};
+
+/// Files smaller than this are not worth splitting up.
+const MIN_SEGMENTED_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Most connections opened for one download, whatever `download_connections` asks for.
+pub const MAX_CONNECTIONS: usize = 16;
+
+/// Downloads into `dest` with `connections` (at most [`MAX_CONNECTIONS`]) concurrent range
+/// requests. `request` builds a GET request for the file, with whatever headers it needs (auth,
+/// accept); it is called once per range. `on_progress` gets the bytes received over all
+/// connections together.
+///
+/// Returns `Ok(false)` if the server doesn't support range requests, the file is too small to
+/// split or a range fails part-way, in which case `dest` is removed and the caller should
+/// download the file the usual way.
+pub async fn download_segmented<F>(
+    request: F,
+    dest: &Path,
+    connections: usize,
+    on_progress: &ProgressCallback,
+) -> Result<bool>
+where
+    F: Fn() -> RequestBuilder,
+{
+    if connections < 2 {
+        return Ok(false);
+    }
+    let connections = connections.min(MAX_CONNECTIONS);
+    let Some(total) = probe_length(&request).await? else {
+        debug!("Server does not support range requests; using a single connection");
+        return Ok(false);
+    };
+    if total < MIN_SEGMENTED_SIZE {
+        debug!(
+            "{} bytes is too small to split; using a single connection",
+            total
+        );
+        return Ok(false);
+    }
+
+    let file = File::create(dest).await.map_err(|e| {
+        SapphireError::IoError(format!("Failed to create {}: {}", dest.display(), e))
+    })?;
+    file.set_len(total).await?;
+    drop(file);
+
+    let ranges = split_ranges(total, connections);
+    debug!(
+        "Downloading {} bytes in {} segments to {}",
+        total,
+        ranges.len(),
+        dest.display()
+    );
+    let progress = SegmentProgress {
+        total,
+        received: AtomicU64::new(0),
+        started: Instant::now(),
+        on_progress,
+    };
+    progress.add(0);
+    let fetched = try_join_all(
+        ranges
+            .into_iter()
+            .map(|(start, end)| fetch_range(&request, dest, start, end, &progress)),
+    )
+    .await;
+    if let Err(e) = fetched {
+        warn!(
+            "Segmented download failed ({}); retrying over a single connection",
+            e
+        );
+        tokio::fs::remove_file(dest).await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Bytes received by all the segments of one download.
+struct SegmentProgress<'a> {
+    total: u64,
+    received: AtomicU64,
+    started: Instant,
+    on_progress: &'a ProgressCallback,
+}
+
+impl SegmentProgress<'_> {
+    fn add(&self, bytes: u64) {
+        let received = self.received.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        (self.on_progress)(DownloadProgress::measure(
+            received,
+            Some(self.total),
+            received,
+            self.started,
+        ));
+    }
+}
+
+/// Asks for the first byte to find out whether ranges are supported and how big the file is.
+async fn probe_length<F>(request: &F) -> Result<Option<u64>>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let response = request()
+        .header(RANGE, "bytes=0-0")
+        .send()
+        .await
+        .map_err(|e| SapphireError::HttpError(format!("Range probe failed: {}", e)))?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Ok(None);
+    }
+    Ok(response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit_once('/'))
+        .and_then(|(_, total)| total.parse().ok()))
+}
+
+/// Splits `0..total` into up to `connections` inclusive ranges of roughly equal size.
+fn split_ranges(total: u64, connections: usize) -> Vec<(u64, u64)> {
+    let segment = total.div_ceil(connections as u64);
+    (0..total)
+        .step_by(segment as usize)
+        .map(|start| (start, (start + segment).min(total) - 1))
+        .collect()
+}
+
+/// Fetches bytes `start..=end` into their place in `dest`, through a handle of its own.
+async fn fetch_range<F>(
+    request: &F,
+    dest: &Path,
+    start: u64,
+    end: u64,
+    progress: &SegmentProgress<'_>,
+) -> Result<()>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let response = request()
+        .header(RANGE, format!("bytes={}-{}", start, end))
+        .send()
+        .await
+        .map_err(|e| {
+            SapphireError::HttpError(format!("Request for bytes {}-{} failed: {}", start, end, e))
+        })?;
+    // A server that ignores the range would send the whole file here
+    let expected_range = format!("bytes {}-{}/", start, end);
+    let range_matches = response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with(&expected_range));
+    if response.status() != StatusCode::PARTIAL_CONTENT || !range_matches {
+        return Err(SapphireError::HttpError(format!(
+            "Server did not honour the request for bytes {}-{} (HTTP {})",
+            start,
+            end,
+            response.status()
+        )));
+    }
+
+    let mut file = OpenOptions::new().write(true).open(dest).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut offset = start;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(SapphireError::Http)?;
+        if offset + chunk.len() as u64 > end + 1 {
+            return Err(SapphireError::HttpError(format!(
+                "Server sent more than the requested bytes {}-{}",
+                start, end
+            )));
+        }
+        file.write_all(&chunk).await?;
+        offset += chunk.len() as u64;
+        progress.add(chunk.len() as u64);
+    }
+    file.flush().await?;
+    if offset != end + 1 {
+        return Err(SapphireError::HttpError(format!(
+            "Segment {}-{} ended early after {} bytes",
+            start,
+            end,
+            offset - start
+        )));
+    }
+    Ok(())
+}
@@ -1,9 +1,12 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use chrono::{DateTime, NaiveDate, Utc};
 use semver::Version; // Changed from crate::model::version::Version
+use tracing::{debug, info, warn};
 
-use crate::utils::config::Config;
+use crate::receipt::read_receipt_field;
+use crate::utils::config::{Config, KegLayout};
 use crate::utils::error::{Result, SapphireError};
 
 /// Represents information about an installed package (Keg).
@@ -11,10 +14,124 @@ use crate::utils::error::{Result, SapphireError};
 pub struct InstalledKeg {
     pub name: String,
     pub version: Version, // Use semver::Version
-    pub path: PathBuf,    // Path to the versioned installation directory (e.g., Cellar/foo/1.2.3)
+    pub path: PathBuf,    // Path to the installation directory (e.g., Cellar/foo/1.2.3)
     pub revision: u32,    // Store revision separately
 }
 
+impl InstalledKeg {
+    /// Returns when this keg was installed, read from the `time` field of its
+    /// INSTALL_RECEIPT.json. Falls back to the keg directory's mtime for kegs without a
+    /// readable receipt.
+    pub fn installed_at(&self) -> Option<DateTime<Utc>> {
+        let from_receipt = read_receipt_field::<String>(&self.path, "time")
+            .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+            .map(|t| t.with_timezone(&Utc));
+        if from_receipt.is_some() {
+            return from_receipt;
+        }
+        debug!(
+            "No usable receipt timestamp for {}, using directory mtime",
+            self.path.display()
+        );
+        fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok()
+            .map(DateTime::<Utc>::from)
+    }
+
+    /// Returns `true` if the formula's opt link currently points at this keg.
+    pub fn is_linked(&self, config: &Config) -> bool {
+        let opt_link = config.formula_opt_link_path(&self.name);
+        match (fs::canonicalize(&opt_link), fs::canonicalize(&self.path)) {
+            (Ok(target), Ok(keg)) => target == keg,
+            _ => false,
+        }
+    }
+}
+
+/// A formula name and the installed version (directory name, including any `_revision`)
+/// that owns a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormulaRef {
+    pub name: String,
+    pub version: String,
+}
+
+impl std::fmt::Display for FormulaRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name, self.version)
+    }
+}
+
+/// Selects kegs by install time. Both bounds are optional; a keg must satisfy every bound set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InstallTimeFilter {
+    /// Only match kegs installed after this instant.
+    pub newer_than: Option<DateTime<Utc>>,
+    /// Only match kegs installed before this instant.
+    pub older_than: Option<DateTime<Utc>>,
+}
+
+impl InstallTimeFilter {
+    pub fn is_empty(&self) -> bool {
+        self.newer_than.is_none() && self.older_than.is_none()
+    }
+
+    /// Checks a keg against the filter. Kegs with an unknown install time only match an
+    /// empty filter.
+    pub fn matches(&self, keg: &InstalledKeg) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let Some(installed_at) = keg.installed_at() else {
+            return false;
+        };
+        self.newer_than.is_none_or(|t| installed_at > t)
+            && self.older_than.is_none_or(|t| installed_at < t)
+    }
+}
+
+/// Parses a time cutoff for `--newer-than`/`--older-than`.
+///
+/// Accepts an RFC 3339 timestamp (`2024-05-01T12:00:00Z`), a plain date (`2024-05-01`,
+/// midnight UTC) or a duration before now (`30d`, `2weeks`, `12h`).
+pub fn parse_time_cutoff(s: &str) -> Result<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        if let Some(t) = d.and_hms_opt(0, 0, 0) {
+            return Ok(t.and_utc());
+        }
+    }
+    if let Ok(age) = humantime::parse_duration(s) {
+        let age = chrono::Duration::from_std(age)
+            .map_err(|e| SapphireError::ParseError("time cutoff", format!("{}: {}", s, e)))?;
+        return Utc::now().checked_sub_signed(age).ok_or_else(|| {
+            SapphireError::ParseError("time cutoff", format!("{} is too far in the past", s))
+        });
+    }
+    Err(SapphireError::ParseError(
+        "time cutoff",
+        format!(
+            "'{}' is not a date (YYYY-MM-DD), RFC 3339 timestamp or duration (e.g. 30d)",
+            s
+        ),
+    ))
+}
+
+/// Options for [`KegRegistry::cleanup`].
+#[derive(Debug, Clone, Default)]
+pub struct CleanupOptions {
+    /// Only consider kegs matching this install-time filter.
+    pub time_filter: InstallTimeFilter,
+    /// Always keep this many of the newest versions of each formula.
+    pub keep_latest: usize,
+    /// Report what would be removed without touching the Cellar.
+    pub dry_run: bool,
+}
+
 /// Manages querying installed packages in the Cellar.
 #[derive(Debug)]
 pub struct KegRegistry {
@@ -28,13 +145,13 @@ impl KegRegistry {
 
     /// Gets the path to the directory containing all versions for a formula.
     fn formula_cellar_path(&self, name: &str) -> PathBuf {
-        self.config.cellar.join(name)
+        self.config.formula_cellar_dir(name)
     }
 
     /// Calculates the conventional 'opt' path for a formula (e.g., /opt/homebrew/opt/foo).
     /// This path typically points to the currently linked/active version.
     pub fn get_opt_path(&self, name: &str) -> PathBuf {
-        self.config.prefix.join("opt").join(name)
+        self.config.formula_opt_link_path(name)
     }
 
     /// Checks if a formula is installed and returns its Keg info if it is.
@@ -46,62 +163,10 @@ impl KegRegistry {
             return Ok(None);
         }
 
-        let mut latest_keg: Option<InstalledKeg> = None;
-
-        for entry_result in fs::read_dir(&formula_dir).map_err(SapphireError::Io)? {
-            let entry = entry_result.map_err(SapphireError::Io)?;
-            let path = entry.path();
-
-            if path.is_dir() {
-                if let Some(version_str_full) = path.file_name().and_then(|n| n.to_str()) {
-                    // Separate version and revision
-                    let mut parts = version_str_full.splitn(2, '_');
-                    let version_part = parts.next().unwrap_or(version_str_full);
-                    let revision = parts
-                        .next()
-                        .and_then(|s| s.parse::<u32>().ok())
-                        .unwrap_or(0);
-
-                    // Attempt to parse the version part (pad if necessary)
-                    let version_str_padded = if version_part.split('.').count() < 3 {
-                        let v_parts: Vec<&str> = version_part.split('.').collect();
-                        match v_parts.len() {
-                            1 => format!("{}.0.0", v_parts[0]),
-                            2 => format!("{}.{}.0", v_parts[0], v_parts[1]),
-                            _ => version_part.to_string(),
-                        }
-                    } else {
-                        version_part.to_string()
-                    };
-
-                    if let Ok(version) = Version::parse(&version_str_padded) {
-                        let current_keg = InstalledKeg {
-                            name: name.to_string(),
-                            version: version.clone(),
-                            revision,
-                            path: path.clone(),
-                        };
-
-                        // Compare with the latest found so far
-                        match latest_keg {
-                            Some(ref latest) => {
-                                if version > latest.version
-                                    || (version == latest.version && revision > latest.revision)
-                                {
-                                    latest_keg = Some(current_keg);
-                                }
-                            }
-                            None => {
-                                latest_keg = Some(current_keg);
-                            }
-                        }
-                    }
-                    // else: Ignore directories that don't parse as versions
-                }
-            }
-        }
-
-        Ok(latest_keg)
+        Ok(self
+            .kegs_in(name, &formula_dir)?
+            .into_iter()
+            .max_by(|a, b| (&a.version, a.revision).cmp(&(&b.version, b.revision))))
     }
 
     /// Lists all installed kegs.
@@ -122,46 +187,11 @@ impl KegRegistry {
 
             if formula_path.is_dir() {
                 if let Some(formula_name) = formula_path.file_name().and_then(|n| n.to_str()) {
-                    // Iterate over version directories within the formula dir
-                    for version_entry_res in
-                        fs::read_dir(&formula_path).map_err(SapphireError::Io)?
-                    {
-                        let version_entry = version_entry_res.map_err(SapphireError::Io)?;
-                        let version_path = version_entry.path();
-
-                        if version_path.is_dir() {
-                            if let Some(version_str_full) =
-                                version_path.file_name().and_then(|n| n.to_str())
-                            {
-                                // Parse version and revision
-                                let mut parts = version_str_full.splitn(2, '_');
-                                let version_part = parts.next().unwrap_or(version_str_full);
-                                let revision = parts
-                                    .next()
-                                    .and_then(|s| s.parse::<u32>().ok())
-                                    .unwrap_or(0);
-                                let version_str_padded = if version_part.split('.').count() < 3 {
-                                    let v_parts: Vec<&str> = version_part.split('.').collect();
-                                    match v_parts.len() {
-                                        1 => format!("{}.0.0", v_parts[0]),
-                                        2 => format!("{}.{}.0", v_parts[0], v_parts[1]),
-                                        _ => version_part.to_string(),
-                                    }
-                                } else {
-                                    version_part.to_string()
-                                };
-
-                                if let Ok(version) = Version::parse(&version_str_padded) {
-                                    installed_kegs.push(InstalledKeg {
-                                        name: formula_name.to_string(),
-                                        version,
-                                        revision,
-                                        path: version_path.clone(),
-                                    });
-                                }
-                            }
-                        }
+                    // Hidden entries are reinstalls in progress, not formulae
+                    if formula_name.starts_with('.') {
+                        continue;
                     }
+                    installed_kegs.extend(self.kegs_in(formula_name, &formula_path)?);
                 }
             }
         }
@@ -169,6 +199,232 @@ impl KegRegistry {
         Ok(installed_kegs)
     }
 
+    /// The kegs in a formula's Cellar directory. Versioned layouts have one keg per version
+    /// directory; a flat layout's formula directory is itself the keg, versioned by its
+    /// receipt.
+    fn kegs_in(&self, name: &str, formula_dir: &Path) -> Result<Vec<InstalledKeg>> {
+        let mut kegs = Vec::new();
+        match self.config.layout.keg_layout {
+            KegLayout::Versioned => {
+                // Iterate over version directories within the formula dir
+                for entry_result in fs::read_dir(formula_dir).map_err(SapphireError::Io)? {
+                    let entry = entry_result.map_err(SapphireError::Io)?;
+                    let path = entry.path();
+                    if !path.is_dir() {
+                        continue;
+                    }
+                    let Some(version_str_full) = path.file_name().and_then(|n| n.to_str()) else {
+                        continue;
+                    };
+                    // Ignore directories that don't parse as versions
+                    if let Some((version, revision)) = parse_keg_version(version_str_full) {
+                        kegs.push(InstalledKeg {
+                            name: name.to_string(),
+                            version,
+                            revision,
+                            path,
+                        });
+                    }
+                }
+            }
+            KegLayout::Flat => match flat_keg_version(formula_dir) {
+                Some(version_str_full) => {
+                    if let Some((version, revision)) = parse_keg_version(&version_str_full) {
+                        kegs.push(InstalledKeg {
+                            name: name.to_string(),
+                            version,
+                            revision,
+                            path: formula_dir.to_path_buf(),
+                        });
+                    }
+                }
+                None => debug!("No receipt version in {}, not a keg", formula_dir.display()),
+            },
+        }
+        Ok(kegs)
+    }
+
+    /// Lists installed kegs whose install time matches `filter`.
+    pub fn list_installed_kegs_filtered(
+        &self,
+        filter: &InstallTimeFilter,
+    ) -> Result<Vec<InstalledKeg>> {
+        let kegs = self.list_installed_kegs()?;
+        Ok(kegs.into_iter().filter(|k| filter.matches(k)).collect())
+    }
+
+    /// Removes old keg versions from the Cellar and returns the kegs removed (or, for a dry
+    /// run, the kegs that would be removed).
+    ///
+    /// A keg is only removed if it matches the time filter, is not among the newest
+    /// `keep_latest` versions of its formula, and is not the currently linked version.
+    pub fn cleanup(&self, options: &CleanupOptions) -> Result<Vec<InstalledKeg>> {
+        let mut by_formula: std::collections::BTreeMap<String, Vec<InstalledKeg>> =
+            std::collections::BTreeMap::new();
+        for keg in self.list_installed_kegs()? {
+            by_formula.entry(keg.name.clone()).or_default().push(keg);
+        }
+
+        let mut candidates = Vec::new();
+        for (_, mut kegs) in by_formula {
+            // Newest first so the retained versions are the first `keep_latest`
+            kegs.sort_by(|a, b| (&b.version, b.revision).cmp(&(&a.version, a.revision)));
+            for keg in kegs.into_iter().skip(options.keep_latest) {
+                if keg.is_linked(&self.config) {
+                    debug!(
+                        "Keeping {} {}: currently linked",
+                        keg.name,
+                        keg.path.display()
+                    );
+                    continue;
+                }
+                if !options.time_filter.matches(&keg) {
+                    debug!(
+                        "Keeping {} {}: outside the install-time filter",
+                        keg.name,
+                        keg.path.display()
+                    );
+                    continue;
+                }
+                candidates.push(keg);
+            }
+        }
+
+        if options.dry_run {
+            return Ok(candidates);
+        }
+
+        let mut removed = Vec::with_capacity(candidates.len());
+        for keg in candidates {
+            info!("Removing {}", keg.path.display());
+            match fs::remove_dir_all(&keg.path) {
+                Ok(()) => removed.push(keg),
+                Err(e) => warn!("Failed to remove {}: {}", keg.path.display(), e),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Directory holding one marker per pinned formula (`var/sapphire/pinned/<name>`).
+    fn pinned_dir(&self) -> PathBuf {
+        self.config
+            .prefix
+            .join("var")
+            .join("sapphire")
+            .join("pinned")
+    }
+
+    /// Returns `true` if the formula is pinned and should be left alone by upgrades.
+    pub fn is_pinned(&self, name: &str) -> bool {
+        fs::symlink_metadata(self.pinned_dir().join(name)).is_ok()
+    }
+
+    /// Pins an installed formula to its current keg. The marker is a symlink to the keg so the
+    /// pinned version stays visible on disk.
+    pub fn pin(&self, name: &str) -> Result<()> {
+        let keg = self.get_installed_keg(name)?.ok_or_else(|| {
+            SapphireError::NotFound(format!("Formula '{}' is not installed", name))
+        })?;
+        let dir = self.pinned_dir();
+        fs::create_dir_all(&dir)?;
+        let marker = dir.join(name);
+        if fs::symlink_metadata(&marker).is_ok() {
+            fs::remove_file(&marker)?;
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&keg.path, &marker)?;
+        #[cfg(not(unix))]
+        fs::write(&marker, keg.path.to_string_lossy().as_bytes())?;
+        debug!("Pinned {} at {}", name, keg.path.display());
+        Ok(())
+    }
+
+    /// Removes a formula's pin. Unpinning a formula that isn't pinned is not an error.
+    pub fn unpin(&self, name: &str) -> Result<()> {
+        match fs::remove_file(self.pinned_dir().join(name)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(SapphireError::Io(e)),
+        }
+    }
+
+    /// Names of all pinned formulae, sorted.
+    pub fn pinned_formulae(&self) -> Result<Vec<String>> {
+        let dir = self.pinned_dir();
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Finds the keg that owns `path`, a file in the Cellar or the prefix. Relative paths are
+    /// taken relative to the prefix.
+    ///
+    /// Paths inside a keg belong to it directly. Otherwise the kegs' INSTALL_MANIFEST.json
+    /// files are consulted, so links into a linked directory (e.g. `share/foo/x` when
+    /// `share/foo` is a link) are found too; the linked keg wins if several claim the path.
+    /// As a last resort a symlink is followed into the Cellar.
+    pub fn owner_of(&self, path: &Path) -> Result<Option<FormulaRef>> {
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.config.prefix.join(path)
+        };
+
+        if let Some(owner) = self.cellar_owner(&path) {
+            return Ok(Some(owner));
+        }
+
+        let mut claims: Vec<InstalledKeg> = Vec::new();
+        for keg in self.list_installed_kegs()? {
+            let Ok(content) = fs::read_to_string(keg.path.join("INSTALL_MANIFEST.json")) else {
+                continue;
+            };
+            let Ok(links) = serde_json::from_str::<Vec<String>>(&content) else {
+                debug!("Unreadable install manifest in {}", keg.path.display());
+                continue;
+            };
+            if links.iter().any(|link| path.starts_with(link)) {
+                claims.push(keg);
+            }
+        }
+        let claim = match claims.iter().position(|k| k.is_linked(&self.config)) {
+            Some(i) => Some(claims.swap_remove(i)),
+            None => claims.pop(),
+        };
+        if let Some(keg) = claim {
+            return Ok(self.cellar_owner(&keg.path));
+        }
+
+        Ok(fs::canonicalize(&path)
+            .ok()
+            .and_then(|resolved| self.cellar_owner(&resolved)))
+    }
+
+    /// The keg containing `path`, if `path` is inside the Cellar.
+    fn cellar_owner(&self, path: &Path) -> Option<FormulaRef> {
+        let mut components = path.strip_prefix(&self.config.cellar).ok()?.components();
+        let name = components
+            .next()?
+            .as_os_str()
+            .to_string_lossy()
+            .into_owned();
+        let version = match self.config.layout.keg_layout {
+            KegLayout::Versioned => components
+                .next()?
+                .as_os_str()
+                .to_string_lossy()
+                .into_owned(),
+            KegLayout::Flat => flat_keg_version(&self.formula_cellar_path(&name))?,
+        };
+        Some(FormulaRef { name, version })
+    }
+
     /// Returns the root path of the Cellar.
     pub fn cellar_path(&self) -> &Path {
         &self.config.cellar
@@ -182,6 +438,38 @@ impl KegRegistry {
         } else {
             version.to_string()
         };
-        self.formula_cellar_path(name).join(version_string)
+        self.config.formula_keg_path(name, &version_string)
     }
 }
+
+/// Parses a keg version like `1.2_1` into its version and revision. Versions with fewer than
+/// three components are padded so they parse as semver.
+fn parse_keg_version(version_str_full: &str) -> Option<(Version, u32)> {
+    // Separate version and revision
+    let mut parts = version_str_full.splitn(2, '_');
+    let version_part = parts.next().unwrap_or(version_str_full);
+    let revision = parts
+        .next()
+        .and_then(|s| s.parse::<u32>().ok())
+        .unwrap_or(0);
+
+    let version_str_padded = if version_part.split('.').count() < 3 {
+        let v_parts: Vec<&str> = version_part.split('.').collect();
+        match v_parts.len() {
+            1 => format!("{}.0.0", v_parts[0]),
+            2 => format!("{}.{}.0", v_parts[0], v_parts[1]),
+            _ => version_part.to_string(),
+        }
+    } else {
+        version_part.to_string()
+    };
+
+    Version::parse(&version_str_padded)
+        .ok()
+        .map(|version| (version, revision))
+}
+
+/// The version recorded in a flat-layout keg's INSTALL_RECEIPT.json.
+fn flat_keg_version(keg_path: &Path) -> Option<String> {
+    read_receipt_field(keg_path, "version")
+}
@@ -4,12 +4,15 @@
 
 // Declare the top-level modules within the library crate
 // These are directories with their own mod.rs files
+pub mod audit;
 pub mod build;
 pub mod dependency;
 pub mod fetch;
 pub mod formulary;
 pub mod keg;
 pub mod model;
+pub mod outdated;
+pub mod receipt;
 pub mod tap;
 pub mod utils;
 
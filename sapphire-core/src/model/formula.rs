@@ -2,7 +2,7 @@
 // *** Corrected: Removed derive Deserialize from ResourceSpec, removed unused SapphireError import,
 // added ResourceSpec struct and parsing ***
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 
 use semver::Version;
@@ -10,7 +10,8 @@ use serde::{de, Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use tracing::{debug, error, warn};
 
-use crate::dependency::{Dependency, DependencyTag, Requirement};
+use crate::build::env::EnvMode;
+use crate::dependency::{CompilerRequirement, Dependency, DependencyTag, Requirement};
 use crate::utils::error::Result; // <-- Import only Result // Use log crate imports
 
 // --- Resource Spec Struct ---
@@ -51,6 +52,132 @@ pub struct FormulaVersions {
     pub bottle: bool,
 }
 
+/// A keg-relative path or glob (`lib/*.a`, `share/foo`) naming files the post-install clean-up
+/// passes must leave alone. A pattern that matches a directory covers everything beneath it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct PathPattern(String);
+
+impl PathPattern {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self(pattern.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether `rel`, a path relative to the keg, or one of its parent directories matches.
+    pub fn matches(&self, rel: &Path) -> bool {
+        let pattern = self.0.trim_start_matches("./").trim_end_matches('/');
+        let Ok(pattern) = glob::Pattern::new(pattern) else {
+            return false;
+        };
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        rel.ancestors()
+            .filter(|a| !a.as_os_str().is_empty())
+            .any(|a| pattern.matches_path_with(a, options))
+    }
+}
+
+/// The repository a formula's development version is built from with `--HEAD`, from the
+/// `urls.head` entry of the formula JSON. `branch` is the ref to build; without one the default
+/// branch is cloned with its full history.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HeadSpec {
+    pub url: String,
+    #[serde(default)]
+    pub branch: Option<String>,
+}
+
+/// A patch applied to the source before it is built. `url` is either an `http(s)` URL, whose
+/// download is checked against `sha256`, or a path to a local patch file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PatchSpec {
+    pub url: String,
+    #[serde(default)]
+    pub sha256: String,
+}
+
+impl PatchSpec {
+    pub fn is_remote(&self) -> bool {
+        self.url.starts_with("http://") || self.url.starts_with("https://")
+    }
+}
+
+/// A step run in the keg once it is built or poured and relocated, e.g. for fontconfig:
+/// `{"type": "run_command", "args": ["bin/fc-cache", "-f"]}`. Paths are relative to the keg. A
+/// failing step fails the install unless it is `optional`, in which case it is only warned
+/// about.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PostInstallStep {
+    #[serde(flatten)]
+    pub action: PostInstallAction,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+/// What a [`PostInstallStep`] does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PostInstallAction {
+    /// Runs a command in the keg with the build environment. A program with a `/` in it is
+    /// keg-relative, any other is looked up on the build PATH; `{prefix}` in arguments is
+    /// replaced with the keg path.
+    RunCommand {
+        args: Vec<String>,
+    },
+    CreateDir {
+        path: String,
+    },
+    /// Creates `link` pointing at `target`, which is used as given (so may be relative to the
+    /// link's directory).
+    Symlink {
+        target: String,
+        link: String,
+    },
+    /// Sets the permissions of `path` to `mode`, in octal (`"755"`).
+    Chmod {
+        path: String,
+        mode: String,
+    },
+}
+
+impl std::fmt::Display for PostInstallAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RunCommand { args } => write!(f, "run {}", args.join(" ")),
+            Self::CreateDir { path } => write!(f, "create directory {}", path),
+            Self::Symlink { target, link } => write!(f, "link {} -> {}", link, target),
+            Self::Chmod { path, mode } => write!(f, "chmod {} {}", mode, path),
+        }
+    }
+}
+
+/// A smoke test of the installed keg, the counterpart of Homebrew's `test do` block, e.g.
+/// `{"commands": [{"args": ["bin/foo", "--version"], "expect_output": ["foo 1.2"]}]}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FormulaTest {
+    pub commands: Vec<TestCommand>,
+}
+
+/// One command of a [`FormulaTest`]. A program with a `/` in it is a path, relative to the keg
+/// unless absolute; any other is looked up in the keg's `bin` and then on `PATH`. `{prefix}` in
+/// arguments is replaced with the keg path.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TestCommand {
+    pub args: Vec<String>,
+    /// Exit code the command must return.
+    #[serde(default)]
+    pub exit_code: i32,
+    /// Substrings its combined stdout and stderr must contain.
+    #[serde(default)]
+    pub expect_output: Vec<String>,
+}
+
 // --- Main Formula Struct ---
 // *** Added 'resources' field ***
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
@@ -73,6 +200,69 @@ pub struct Formula {
     pub mirrors: Vec<String>,
     #[serde(default)]
     pub bottle: BottleSpec,
+    /// Where to fetch the development version from, if the formula has one.
+    #[serde(default)]
+    pub head: Option<HeadSpec>,
+    /// Keg-only formulae are not linked into the prefix; dependents reach them via their opt path.
+    #[serde(default)]
+    pub keg_only: bool,
+    /// Environment variables the installed executables need at runtime. Values may use
+    /// `{prefix}` for the keg directory; see [`crate::build::formula::link::install_wrapper`].
+    #[serde(default)]
+    pub runtime_env: BTreeMap<String, String>,
+    /// Keg paths that relocation and shebang rewriting must not touch, for files that are
+    /// checksummed, signed or otherwise break when modified.
+    #[serde(default)]
+    pub skip_clean: Vec<PathPattern>,
+    /// Interpreters (`python`, `perl`, `ruby`...) whose shebangs in installed scripts are
+    /// pointed at the installed interpreter. Unset means python, perl and ruby; an empty list
+    /// leaves every shebang as the build wrote it.
+    #[serde(default)]
+    pub rewrite_shebangs: Option<Vec<String>>,
+    /// The formula's build breaks with parallel make, so it always builds with one job.
+    #[serde(default)]
+    pub deparallelize: bool,
+    /// Keep the libtool `.la` archives the build installs, with their paths rewritten to the
+    /// keg, instead of deleting them.
+    #[serde(default)]
+    pub keep_la_files: bool,
+    /// The formula's `make install` ignores or mishandles `DESTDIR`, so it installs straight
+    /// into the keg instead of through a staging directory.
+    #[serde(default)]
+    pub no_destdir: bool,
+    /// Run the project's own test suite (`make check`, or `make test`) between building and
+    /// installing; a failing suite fails the install.
+    #[serde(default)]
+    pub make_check: bool,
+    /// The environment the build runs in: `super`, controlled by sapphire, or `std`, the
+    /// user's environment with sapphire's settings on top.
+    #[serde(default)]
+    pub env: EnvMode,
+    /// Steps run in the keg after it is built or poured and relocated.
+    #[serde(default)]
+    pub post_install: Vec<PostInstallStep>,
+    /// Commands the build runs (`autoconf`, `pkg-config`...), looked for on the build PATH
+    /// before the build starts so that any missing are reported together up front.
+    #[serde(default)]
+    pub build_tools: Vec<String>,
+    /// Extra arguments for an Autotools `configure` (`--enable-foo`, `--with-bar=...`),
+    /// passed after the standard ones so they can override them.
+    #[serde(default)]
+    pub configure_args: Vec<String>,
+    /// Leave Automake's dependency tracking on: `configure` doesn't get
+    /// `--disable-dependency-tracking`, for builds that rely on the generated dependency files.
+    #[serde(default)]
+    pub dependency_tracking: bool,
+    /// Patches applied in order, with `patch -p1`, before the source is built.
+    #[serde(default)]
+    pub patches: Vec<PatchSpec>,
+    /// The compiler the build needs, if the system default isn't good enough. A matching LLVM
+    /// or GCC among the formula's dependencies is preferred over the system compiler.
+    #[serde(default)]
+    pub compiler: Option<CompilerRequirement>,
+    /// Smoke test run against the installed keg by `sapphire test`.
+    #[serde(default)]
+    pub test: Option<FormulaTest>,
     #[serde(skip_deserializing)]
     pub dependencies: Vec<Dependency>,
     #[serde(default, deserialize_with = "deserialize_requirements")]
@@ -81,6 +271,10 @@ pub struct Formula {
     pub resources: Vec<ResourceSpec>, // Stores parsed resources
     #[serde(skip)]
     install_keg_path: Option<PathBuf>,
+    /// The directory holding the formula's definition file, for definitions loaded from a
+    /// tap. Relative local patch paths resolve against it.
+    #[serde(skip)]
+    definition_dir: Option<PathBuf>,
 }
 
 // Custom deserialization logic for Formula
@@ -108,6 +302,40 @@ impl<'de> Deserialize<'de> for Formula {
             #[serde(default)]
             bottle: BottleSpec,
             #[serde(default)]
+            head: Option<HeadSpec>,
+            #[serde(default)]
+            keg_only: bool,
+            #[serde(default)]
+            runtime_env: BTreeMap<String, String>,
+            #[serde(default)]
+            skip_clean: Vec<PathPattern>,
+            #[serde(default)]
+            rewrite_shebangs: Option<Vec<String>>,
+            #[serde(default)]
+            deparallelize: bool,
+            #[serde(default)]
+            keep_la_files: bool,
+            #[serde(default)]
+            no_destdir: bool,
+            #[serde(default)]
+            make_check: bool,
+            #[serde(default)]
+            env: EnvMode,
+            #[serde(default)]
+            post_install: Vec<PostInstallStep>,
+            #[serde(default)]
+            build_tools: Vec<String>,
+            #[serde(default)]
+            configure_args: Vec<String>,
+            #[serde(default)]
+            dependency_tracking: bool,
+            #[serde(default)]
+            patches: Vec<PatchSpec>,
+            #[serde(default)]
+            compiler: Option<CompilerRequirement>,
+            #[serde(default)]
+            test: Option<FormulaTest>,
+            #[serde(default)]
             dependencies: Vec<String>,
             #[serde(default)]
             build_dependencies: Vec<String>,
@@ -191,6 +419,13 @@ impl<'de> Deserialize<'de> for Formula {
         // --- URL/SHA256 Logic (Original logic) ---
         let mut final_url = raw.url;
         let mut final_sha256 = raw.sha256;
+        // A serialized Formula carries `head` directly; API JSON has it under `urls.head`
+        let head = raw.head.or_else(|| {
+            raw.urls
+                .as_ref()?
+                .get("head")
+                .and_then(|h| HeadSpec::deserialize(h).ok())
+        });
         if final_url.is_empty() {
             if let Some(Value::Object(urls_map)) = raw.urls {
                 if let Some(Value::Object(stable_url_info)) = urls_map.get("stable") {
@@ -290,10 +525,28 @@ impl<'de> Deserialize<'de> for Formula {
             sha256: final_sha256,
             mirrors: raw.mirrors,
             bottle: raw.bottle,
+            head,
+            keg_only: raw.keg_only,
+            runtime_env: raw.runtime_env,
+            skip_clean: raw.skip_clean,
+            rewrite_shebangs: raw.rewrite_shebangs,
+            deparallelize: raw.deparallelize,
+            keep_la_files: raw.keep_la_files,
+            no_destdir: raw.no_destdir,
+            make_check: raw.make_check,
+            env: raw.env,
+            post_install: raw.post_install,
+            build_tools: raw.build_tools,
+            configure_args: raw.configure_args,
+            dependency_tracking: raw.dependency_tracking,
+            patches: raw.patches,
+            compiler: raw.compiler,
+            test: raw.test,
             dependencies: combined_dependencies,
             requirements: raw.requirements,
             resources: combined_resources, // Assign parsed resources
             install_keg_path: None,
+            definition_dir: None,
         })
     }
 }
@@ -317,6 +570,12 @@ impl Formula {
     pub fn set_keg_path(&mut self, path: PathBuf) {
         self.install_keg_path = Some(path);
     }
+    pub fn set_definition_dir(&mut self, dir: PathBuf) {
+        self.definition_dir = Some(dir);
+    }
+    pub fn definition_dir(&self) -> Option<&Path> {
+        self.definition_dir.as_deref()
+    }
     pub fn version_str_full(&self) -> String {
         if self.revision > 0 {
             format!("{}_{}", self.stable_version_str, self.revision)
@@ -336,6 +595,65 @@ impl Formula {
     pub fn source_sha256(&self) -> &str {
         &self.sha256
     }
+    pub fn head(&self) -> Option<&HeadSpec> {
+        self.head.as_ref()
+    }
+    /// Switches this formula to its development version checked out at `commit`, so it installs
+    /// into a `HEAD-<commit>` keg instead of the stable version's.
+    pub fn use_head_version(&mut self, commit: &str) {
+        if let Some(head) = &self.head {
+            self.url = head.url.clone();
+        }
+        // A checkout has no archive to verify
+        self.sha256.clear();
+        self.stable_version_str = format!("HEAD-{}", commit);
+        self.revision = 0;
+    }
+    pub fn is_keg_only(&self) -> bool {
+        self.keg_only
+    }
+    pub fn runtime_env(&self) -> &BTreeMap<String, String> {
+        &self.runtime_env
+    }
+    pub fn skip_clean(&self) -> &[PathPattern] {
+        &self.skip_clean
+    }
+    pub fn rewrite_shebangs(&self) -> Option<&[String]> {
+        self.rewrite_shebangs.as_deref()
+    }
+    pub fn deparallelize(&self) -> bool {
+        self.deparallelize
+    }
+    pub fn keep_la_files(&self) -> bool {
+        self.keep_la_files
+    }
+    pub fn no_destdir(&self) -> bool {
+        self.no_destdir
+    }
+    pub fn make_check(&self) -> bool {
+        self.make_check
+    }
+    pub fn post_install(&self) -> &[PostInstallStep] {
+        &self.post_install
+    }
+    pub fn env(&self) -> EnvMode {
+        self.env
+    }
+    pub fn build_tools(&self) -> &[String] {
+        &self.build_tools
+    }
+    pub fn configure_args(&self) -> &[String] {
+        &self.configure_args
+    }
+    pub fn dependency_tracking(&self) -> bool {
+        self.dependency_tracking
+    }
+    pub fn patches(&self) -> &[PatchSpec] {
+        &self.patches
+    }
+    pub fn compiler(&self) -> Option<&CompilerRequirement> {
+        self.compiler.as_ref()
+    }
     pub fn get_bottle_spec(&self, bottle_tag: &str) -> Option<&BottleFileSpec> {
         self.bottle.stable.as_ref()?.files.get(bottle_tag)
     }
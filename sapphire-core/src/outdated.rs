@@ -0,0 +1,170 @@
+// sapphire-core/src/outdated.rs
+// Compares installed formulae against the latest definitions (API cache first, then installed
+// taps) and reports the ones with a newer version available. This is read-only; upgrading is
+// left to the caller.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+
+use tracing::{debug, warn};
+
+use crate::build::formula::has_bottle_for_current_platform;
+use crate::formulary::Formulary;
+use crate::keg::{InstalledKeg, KegRegistry};
+use crate::model::formula::Formula;
+use crate::model::version::Version;
+use crate::receipt::read_receipt_field;
+use crate::tap::manifest;
+use crate::utils::config::Config;
+use crate::utils::error::Result;
+
+/// An installed formula for which a newer version is available.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutdatedFormula {
+    pub name: String,
+    /// Version (including any `_revision`) of the installed keg.
+    pub installed_version: String,
+    /// Latest version (including any `_revision`) known to the API cache or a tap.
+    pub available_version: String,
+    /// Whether the available version has a bottle for this platform.
+    pub bottle_available: bool,
+    pub pinned: bool,
+}
+
+impl OutdatedFormula {
+    /// Pinned formulae are reported as outdated but must not be upgraded.
+    pub fn is_upgradeable(&self) -> bool {
+        !self.pinned
+    }
+}
+
+/// Lists installed formulae whose latest available version is newer than the installed one,
+/// sorted by name. Formulae that can't be found in the API cache or any installed tap are
+/// skipped.
+pub fn outdated(config: &Config) -> Result<Vec<OutdatedFormula>> {
+    let registry = KegRegistry::new(config.clone());
+    let formulary = Formulary::new(config.clone());
+
+    // Listing kegs walks the whole Cellar, so it is done once and grouped by formula
+    let mut kegs_by_name: BTreeMap<String, Vec<InstalledKeg>> = BTreeMap::new();
+    for keg in registry.list_installed_kegs()? {
+        kegs_by_name.entry(keg.name.clone()).or_default().push(keg);
+    }
+
+    let mut outdated = Vec::new();
+    for (name, kegs) in kegs_by_name {
+        let Some(keg) = current_keg(kegs, config) else {
+            continue;
+        };
+        let installed_version = receipt_version(&keg);
+
+        let latest = match formulary.load_formula(&name) {
+            Ok(formula) => formula,
+            Err(e) => match load_from_taps(config, &name) {
+                Some(formula) => formula,
+                None => {
+                    debug!("Skipping {}: no definition available ({})", name, e);
+                    continue;
+                }
+            },
+        };
+        let available_version = latest.version_str_full();
+
+        if compare_versions(&available_version, &installed_version) != Ordering::Greater {
+            continue;
+        }
+
+        outdated.push(OutdatedFormula {
+            pinned: registry.is_pinned(&name),
+            bottle_available: has_bottle_for_current_platform(&latest),
+            name,
+            installed_version,
+            available_version,
+        });
+    }
+    Ok(outdated)
+}
+
+/// Of a formula's installed `kegs`, the one an upgrade would replace: the linked one, else the
+/// newest.
+fn current_keg(kegs: Vec<InstalledKeg>, config: &Config) -> Option<InstalledKeg> {
+    if let Some(linked) = kegs.iter().position(|k| k.is_linked(config)) {
+        return kegs.into_iter().nth(linked);
+    }
+    kegs.into_iter()
+        .max_by(|a, b| (&a.version, a.revision).cmp(&(&b.version, b.revision)))
+}
+
+/// The version recorded in the keg's receipt, falling back to the keg directory name.
+fn receipt_version(keg: &InstalledKeg) -> String {
+    read_receipt_field(&keg.path, "version")
+        .or_else(|| {
+            keg.path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| keg.version.to_string())
+}
+
+/// Looks for a JSON definition of `name` in every installed tap. Ruby formulae can't be
+/// evaluated and are ignored.
+fn load_from_taps(config: &Config, name: &str) -> Option<Formula> {
+    let users = fs::read_dir(&config.taps_dir).ok()?;
+    for user in users.flatten() {
+        let Ok(repos) = fs::read_dir(user.path()) else {
+            continue;
+        };
+        for repo in repos.flatten() {
+            let repo_name = repo.file_name().to_string_lossy().into_owned();
+            let Some(tap) = repo_name.strip_prefix("homebrew-") else {
+                continue;
+            };
+            let tap_name = format!("{}/{}", user.file_name().to_string_lossy(), tap);
+            let Some(path) = config.get_formula_path_from_tap(&tap_name, name) else {
+                continue;
+            };
+            if path.extension().is_some_and(|ext| ext != "json") {
+                debug!("Ignoring non-JSON formula {} in {}", name, tap_name);
+                continue;
+            }
+            if let Err(e) = manifest::verify_tap_file(config, &repo.path(), &path) {
+                warn!("Not loading {} from {}: {}", name, tap_name, e);
+                continue;
+            }
+            match fs::read_to_string(&path)
+                .ok()
+                .and_then(|s| serde_json::from_str::<Formula>(&s).ok())
+            {
+                Some(mut formula) => {
+                    debug!("Found {} in tap {}", name, tap_name);
+                    if let Some(dir) = path.parent() {
+                        formula.set_definition_dir(dir.to_path_buf());
+                    }
+                    return Some(formula);
+                }
+                None => debug!("Failed to parse {}", path.display()),
+            }
+        }
+    }
+    None
+}
+
+/// Compares two `version[_revision]` strings. When either side doesn't parse, differing
+/// strings count as `a` being newer since `a` is the definition's version.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let split = |s: &str| -> (String, u32) {
+        s.rsplit_once('_')
+            .and_then(|(v, r)| r.parse().ok().map(|r| (v.to_string(), r)))
+            .unwrap_or_else(|| (s.to_string(), 0))
+    };
+    let (a_version, a_revision) = split(a);
+    let (b_version, b_revision) = split(b);
+    match (Version::parse(&a_version), Version::parse(&b_version)) {
+        (Ok(a_parsed), Ok(b_parsed)) => a_parsed
+            .cmp(&b_parsed)
+            .then_with(|| a_revision.cmp(&b_revision)),
+        _ if a == b => Ordering::Equal,
+        _ => Ordering::Greater,
+    }
+}
@@ -0,0 +1,92 @@
+// sapphire-core/src/receipt.rs
+// Reads and updates the INSTALL_RECEIPT.json that `build::formula::write_receipt` leaves in
+// every installed keg, recording how it was installed (version, time, keg-only, runtime env,
+// warnings, link filter...).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::utils::error::{Result, SapphireError};
+
+/// File name of the install receipt inside a keg.
+pub const RECEIPT_FILE_NAME: &str = "INSTALL_RECEIPT.json";
+
+/// The install receipt's path in the keg at `keg_path`.
+pub fn receipt_path(keg_path: &Path) -> PathBuf {
+    keg_path.join(RECEIPT_FILE_NAME)
+}
+
+/// Reads the install receipt of the keg at `keg_path`. Missing or unparsable receipts yield
+/// `None`.
+pub fn read_receipt(keg_path: &Path) -> Option<Value> {
+    let content = fs::read_to_string(receipt_path(keg_path)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Reads the `field` of the install receipt of the keg at `keg_path`, or `None` if the receipt
+/// is missing or the field is absent or not a `T`.
+pub fn read_receipt_field<T: DeserializeOwned>(keg_path: &Path, field: &str) -> Option<T> {
+    let value = read_receipt(keg_path)?.get(field)?.clone();
+    serde_json::from_value(value).ok()
+}
+
+/// Sets the `field` of the install receipt of the keg at `keg_path` to `value`, or removes it
+/// for `None`. A missing receipt is created, unless there is nothing to set.
+pub fn set_receipt_field(keg_path: &Path, field: &str, value: Option<Value>) -> Result<()> {
+    let path = receipt_path(keg_path);
+    let mut receipt = match fs::read_to_string(&path) {
+        Ok(s) => serde_json::from_str::<Value>(&s)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && value.is_none() => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::json!({}),
+        Err(e) => return Err(SapphireError::Io(e)),
+    };
+    let Some(fields) = receipt.as_object_mut() else {
+        return Err(SapphireError::Generic(format!(
+            "Install receipt {} is not a JSON object",
+            path.display()
+        )));
+    };
+    match value {
+        Some(value) => fields.insert(field.to_string(), value),
+        None => fields.remove(field),
+    };
+    fs::write(&path, serde_json::to_string_pretty(&receipt)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_read_set_and_removed() {
+        let keg = tempfile::tempdir().unwrap();
+        assert_eq!(read_receipt_field::<bool>(keg.path(), "keg_only"), None);
+
+        set_receipt_field(keg.path(), "keg_only", None).unwrap();
+        assert!(!receipt_path(keg.path()).exists());
+
+        fs::write(
+            receipt_path(keg.path()),
+            r#"{"version": "1.2.3", "keg_only": true}"#,
+        )
+        .unwrap();
+        assert_eq!(read_receipt_field(keg.path(), "keg_only"), Some(true));
+        assert_eq!(read_receipt_field::<bool>(keg.path(), "version"), None);
+
+        set_receipt_field(keg.path(), "link_filter", Some(serde_json::json!(["foo"]))).unwrap();
+        set_receipt_field(keg.path(), "keg_only", None).unwrap();
+        assert_eq!(
+            read_receipt_field::<Vec<String>>(keg.path(), "link_filter"),
+            Some(vec!["foo".to_string()])
+        );
+        assert_eq!(read_receipt_field::<bool>(keg.path(), "keg_only"), None);
+        assert_eq!(
+            read_receipt_field::<String>(keg.path(), "version").as_deref(),
+            Some("1.2.3")
+        );
+    }
+}
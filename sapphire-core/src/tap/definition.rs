@@ -1,7 +1,8 @@
 // tap/tap.rs - Basic tap functionality // Should probably be in model module
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use super::manifest::{self, IntegrityViolation};
 use crate::utils::error::{Result, SapphireError};
 
 /// Represents a source of packages (formulas and casks)
@@ -120,6 +121,11 @@ impl Tap {
         format!("{}/{}", self.user, self.repo)
     }
 
+    /// Verifies the tap's files against its signed manifest. See [`manifest::verify_tap`].
+    pub fn verify(&self, trusted_keys: &Path) -> Result<Vec<IntegrityViolation>> {
+        manifest::verify_tap(&self.path, trusted_keys)
+    }
+
     /// Check if this tap is installed locally
     pub fn is_installed(&self) -> bool {
         self.path.exists()
@@ -0,0 +1,413 @@
+// sapphire-core/src/tap/manifest.rs
+// Whole-tap integrity checks for locked-down deployments. A tap may carry a manifest at its
+// root listing the SHA-256 of every formula and cask file, signed with an SSH key
+// (`ssh-keygen -Y sign -n sapphire-tap`). When trusted signers are configured, the signature
+// is checked against them and every file is compared with the manifest before it is used.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+use crate::fetch::http::sha256_file;
+use crate::utils::config::Config;
+use crate::utils::error::{Result, SapphireError};
+
+/// Manifest file at the tap root.
+pub const MANIFEST_FILE: &str = "sapphire-manifest.json";
+/// Detached SSH signature of the manifest.
+pub const SIGNATURE_FILE: &str = "sapphire-manifest.json.sig";
+/// Namespace the manifest must be signed under, so signatures made for other purposes with the
+/// same key can't be replayed here.
+pub const SIGNATURE_NAMESPACE: &str = "sapphire-tap";
+/// Tap directories whose files the manifest covers.
+const COVERED_DIRS: [&str; 2] = ["Formula", "Casks"];
+
+/// The SHA-256 of each covered file, keyed by its path relative to the tap root.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TapManifest {
+    pub files: BTreeMap<String, String>,
+}
+
+/// A file in a tap that doesn't match its manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityViolation {
+    /// The file's content differs from the hash in the manifest.
+    Modified {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    /// The file exists but the manifest doesn't list it.
+    Unlisted { path: PathBuf },
+    /// The manifest lists a file that doesn't exist.
+    Missing { path: PathBuf },
+    /// The file is a symlink. What it points at can change without the manifest noticing, so
+    /// symlinks are never trusted.
+    Symlink { path: PathBuf },
+}
+
+impl std::fmt::Display for IntegrityViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Modified {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} does not match the tap manifest (expected {}, got {})",
+                path.display(),
+                expected,
+                actual
+            ),
+            Self::Unlisted { path } => {
+                write!(f, "{} is not listed in the tap manifest", path.display())
+            }
+            Self::Missing { path } => {
+                write!(
+                    f,
+                    "{} is listed in the tap manifest but missing",
+                    path.display()
+                )
+            }
+            Self::Symlink { path } => {
+                write!(
+                    f,
+                    "{} is a symlink, which the tap manifest can't cover",
+                    path.display()
+                )
+            }
+        }
+    }
+}
+
+impl TapManifest {
+    /// Reads the manifest at the root of `tap_path` and checks its signature against the
+    /// signers in `trusted_keys` (an OpenSSH `allowed_signers` file).
+    pub fn load_verified(tap_path: &Path, trusted_keys: &Path) -> Result<Self> {
+        let manifest_path = tap_path.join(MANIFEST_FILE);
+        let content = fs::read(&manifest_path).map_err(|e| {
+            SapphireError::TapIntegrity(format!(
+                "{} has no readable {}: {}",
+                tap_path.display(),
+                MANIFEST_FILE,
+                e
+            ))
+        })?;
+        verify_signature(&content, &tap_path.join(SIGNATURE_FILE), trusted_keys)?;
+        serde_json::from_slice(&content).map_err(|e| {
+            SapphireError::TapIntegrity(format!("Invalid {}: {}", manifest_path.display(), e))
+        })
+    }
+
+    /// Compares every covered file in `tap_path` with the manifest. Symlinks are reported
+    /// rather than followed.
+    pub fn check(&self, tap_path: &Path) -> Vec<IntegrityViolation> {
+        let mut violations = Vec::new();
+        let mut seen = Vec::new();
+        for dir in COVERED_DIRS {
+            for entry in WalkDir::new(tap_path.join(dir))
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| !e.file_type().is_dir())
+            {
+                let Ok(rel) = entry.path().strip_prefix(tap_path) else {
+                    continue;
+                };
+                let key = manifest_key(rel);
+                if let Some(violation) = self.check_file(tap_path, &key) {
+                    violations.push(violation);
+                }
+                seen.push(key);
+            }
+        }
+        for listed in self.files.keys() {
+            if !seen.contains(listed) {
+                violations.push(IntegrityViolation::Missing {
+                    path: tap_path.join(listed),
+                });
+            }
+        }
+        violations
+    }
+
+    /// Compares one file, given relative to the tap root, with the manifest.
+    fn check_file(&self, tap_path: &Path, key: &str) -> Option<IntegrityViolation> {
+        let path = tap_path.join(key);
+        if path.is_symlink() {
+            return Some(IntegrityViolation::Symlink { path });
+        }
+        let Some(expected) = self.files.get(key) else {
+            return Some(IntegrityViolation::Unlisted { path });
+        };
+        let actual = match sha256_file(&path) {
+            Ok(actual) => actual,
+            Err(_) => return Some(IntegrityViolation::Missing { path }),
+        };
+        (!actual.eq_ignore_ascii_case(expected)).then(|| IntegrityViolation::Modified {
+            path,
+            expected: expected.clone(),
+            actual,
+        })
+    }
+}
+
+/// Verifies a whole tap against its signed manifest. Returns every file that doesn't match;
+/// a missing or badly signed manifest is an error.
+pub fn verify_tap(tap_path: &Path, trusted_keys: &Path) -> Result<Vec<IntegrityViolation>> {
+    let manifest = TapManifest::load_verified(tap_path, trusted_keys)?;
+    let violations = manifest.check(tap_path);
+    for violation in &violations {
+        warn!("{}", violation);
+    }
+    Ok(violations)
+}
+
+/// Checks a single file of a tap before it is loaded. Does nothing unless trusted tap signers
+/// are configured (`SAPPHIRE_TAP_TRUSTED_KEYS`).
+///
+/// So far only `outdated` runs this, on the formulae it loads from taps; other readers of tap
+/// files don't. The whole-tap check, `Tap::verify`, has no callers yet.
+pub fn verify_tap_file(config: &Config, tap_path: &Path, file: &Path) -> Result<()> {
+    let Some(trusted_keys) = &config.tap_trusted_keys else {
+        return Ok(());
+    };
+    let manifest = TapManifest::load_verified(tap_path, trusted_keys)?;
+    let rel = file.strip_prefix(tap_path).map_err(|_| {
+        SapphireError::TapIntegrity(format!(
+            "{} is not inside tap {}",
+            file.display(),
+            tap_path.display()
+        ))
+    })?;
+    match manifest.check_file(tap_path, &manifest_key(rel)) {
+        Some(violation) => Err(SapphireError::TapIntegrity(violation.to_string())),
+        None => {
+            debug!("{} matches the tap manifest", file.display());
+            Ok(())
+        }
+    }
+}
+
+/// Manifest keys always use `/`, whatever the platform.
+fn manifest_key(rel: &Path) -> String {
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Checks `signature` over `content` with `ssh-keygen -Y`, accepting any principal listed in
+/// the `trusted_keys` allowed-signers file.
+fn verify_signature(content: &[u8], signature: &Path, trusted_keys: &Path) -> Result<()> {
+    if !signature.is_file() {
+        return Err(SapphireError::TapIntegrity(format!(
+            "Missing manifest signature {}",
+            signature.display()
+        )));
+    }
+    let ssh_keygen = which::which("ssh-keygen").map_err(|_| {
+        SapphireError::TapIntegrity("ssh-keygen is needed to verify tap manifests".to_string())
+    })?;
+
+    let principals = Command::new(&ssh_keygen)
+        .args(["-Y", "find-principals", "-s"])
+        .arg(signature)
+        .arg("-f")
+        .arg(trusted_keys)
+        .output()?;
+    let principals = String::from_utf8_lossy(&principals.stdout).into_owned();
+    let Some(principal) = principals.lines().map(str::trim).find(|l| !l.is_empty()) else {
+        return Err(SapphireError::TapIntegrity(format!(
+            "{} is not signed by a trusted key",
+            signature.display()
+        )));
+    };
+
+    let mut child = Command::new(&ssh_keygen)
+        .args([
+            "-Y",
+            "verify",
+            "-n",
+            SIGNATURE_NAMESPACE,
+            "-I",
+            principal,
+            "-s",
+        ])
+        .arg(signature)
+        .arg("-f")
+        .arg(trusted_keys)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content)?;
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        debug!("Tap manifest signed by {}", principal);
+        Ok(())
+    } else {
+        Err(SapphireError::TapIntegrity(format!(
+            "Manifest signature {} does not verify: {}",
+            signature.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tap with one formula and one cask, and a manifest listing both as they are.
+    fn tap_fixture(root: &Path) -> TapManifest {
+        fs::create_dir_all(root.join("Formula")).unwrap();
+        fs::create_dir_all(root.join("Casks")).unwrap();
+        fs::write(root.join("Formula/foo.rb"), "class Foo < Formula\nend\n").unwrap();
+        fs::write(root.join("Casks/bar.rb"), "cask \"bar\" do\nend\n").unwrap();
+        let mut manifest = TapManifest::default();
+        for key in ["Formula/foo.rb", "Casks/bar.rb"] {
+            let hash = sha256_file(&root.join(key)).unwrap();
+            manifest.files.insert(key.to_string(), hash);
+        }
+        manifest
+    }
+
+    #[test]
+    fn check_reports_each_kind_of_violation() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let manifest = tap_fixture(root);
+        assert!(manifest.check(root).is_empty());
+
+        fs::write(
+            root.join("Formula/foo.rb"),
+            "class Foo < Formula\n  evil\nend\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("Formula/extra.rb"),
+            "class Extra < Formula\nend\n",
+        )
+        .unwrap();
+        fs::remove_file(root.join("Casks/bar.rb")).unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", root.join("Casks/link.rb")).unwrap();
+
+        let violations = manifest.check(root);
+        assert_eq!(violations.len(), 4, "{violations:?}");
+        assert!(violations.iter().any(|v| matches!(v,
+            IntegrityViolation::Modified { path, .. } if path.ends_with("Formula/foo.rb"))));
+        assert!(violations.iter().any(|v| matches!(v,
+            IntegrityViolation::Unlisted { path } if path.ends_with("Formula/extra.rb"))));
+        assert!(violations.iter().any(|v| matches!(v,
+            IntegrityViolation::Missing { path } if path.ends_with("Casks/bar.rb"))));
+        assert!(violations.iter().any(|v| matches!(v,
+            IntegrityViolation::Symlink { path } if path.ends_with("Casks/link.rb"))));
+    }
+
+    #[test]
+    fn listed_symlink_is_still_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let manifest = tap_fixture(root);
+        let target = root.join("elsewhere.rb");
+        fs::rename(root.join("Formula/foo.rb"), &target).unwrap();
+        std::os::unix::fs::symlink(&target, root.join("Formula/foo.rb")).unwrap();
+
+        assert_eq!(
+            manifest.check(root),
+            vec![IntegrityViolation::Symlink {
+                path: root.join("Formula/foo.rb")
+            }]
+        );
+    }
+
+    #[test]
+    fn unconfigured_signers_skip_file_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        tap_fixture(root);
+        let mut config = Config::load().unwrap();
+        config.tap_trusted_keys = None;
+
+        // No manifest at all, which would fail if checks were on.
+        assert!(verify_tap_file(&config, root, &root.join("Formula/foo.rb")).is_ok());
+    }
+
+    fn ssh_keygen(args: &[&str], dir: &Path) -> bool {
+        Command::new("ssh-keygen")
+            .args(args)
+            .current_dir(dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    }
+
+    #[test]
+    fn manifest_signature_is_enforced() {
+        if which::which("ssh-keygen").is_err() {
+            eprintln!("skipping: ssh-keygen not found");
+            return;
+        }
+        let keys = tempfile::tempdir().unwrap();
+        let tap = tempfile::tempdir().unwrap();
+        let root = tap.path();
+        let manifest = tap_fixture(root);
+        fs::write(
+            root.join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+
+        assert!(ssh_keygen(
+            &["-q", "-t", "ed25519", "-N", "", "-f", "trusted"],
+            keys.path()
+        ));
+        assert!(ssh_keygen(
+            &["-q", "-t", "ed25519", "-N", "", "-f", "stranger"],
+            keys.path()
+        ));
+        let public = fs::read_to_string(keys.path().join("trusted.pub")).unwrap();
+        let allowed = keys.path().join("allowed_signers");
+        fs::write(&allowed, format!("tap@example.com {public}")).unwrap();
+        let sign = |key: &str, namespace: &str| {
+            let _ = fs::remove_file(root.join(SIGNATURE_FILE));
+            let key = keys.path().join(key);
+            let key = key.to_str().unwrap();
+            assert!(ssh_keygen(
+                &["-Y", "sign", "-f", key, "-n", namespace, MANIFEST_FILE],
+                root
+            ));
+        };
+
+        assert!(matches!(
+            TapManifest::load_verified(root, &allowed),
+            Err(SapphireError::TapIntegrity(_))
+        ));
+
+        sign("trusted", SIGNATURE_NAMESPACE);
+        assert_eq!(
+            TapManifest::load_verified(root, &allowed).unwrap(),
+            manifest
+        );
+
+        sign("stranger", SIGNATURE_NAMESPACE);
+        assert!(TapManifest::load_verified(root, &allowed).is_err());
+
+        sign("trusted", "file");
+        assert!(TapManifest::load_verified(root, &allowed).is_err());
+
+        sign("trusted", SIGNATURE_NAMESPACE);
+        fs::write(root.join(MANIFEST_FILE), b"{\"files\":{}}").unwrap();
+        assert!(TapManifest::load_verified(root, &allowed).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod definition; // Renamed from 'tap'
+pub mod manifest;
 
 // Re-export
 pub use definition::*;
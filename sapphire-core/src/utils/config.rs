@@ -1,12 +1,18 @@
 // ===== sapphire-core/src/utils/config.rs =====
-use std::env;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fmt};
 
 use dirs;
-use tracing::debug;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
 
+use crate::build::devtools::TargetArch;
+use crate::build::env::OptimizationLevel;
+use crate::build::warnings::BuildPhase;
 use crate::utils::cache;
-use crate::utils::error::Result; // for home directory lookup
+use crate::utils::error::{Result, SapphireError}; // for home directory lookup
 
 /// Default installation prefixes
 const DEFAULT_LINUX_PREFIX: &str = "/home/linuxbrew/.linuxbrew";
@@ -37,10 +43,836 @@ fn determine_prefix() -> PathBuf {
     PathBuf::from(default_prefix)
 }
 
+/// Env var pointing at the allowed-signers file used to verify tap manifests.
+const TAP_TRUSTED_KEYS_ENV: &str = "SAPPHIRE_TAP_TRUSTED_KEYS";
+
+/// How kegs are arranged inside the Cellar.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KegLayout {
+    /// `<cellar>/<name>/<version>`, allowing several versions side by side (Homebrew's layout).
+    #[default]
+    Versioned,
+    /// `<cellar>/<name>`, one installed version per formula. The version is read from the
+    /// keg's receipt.
+    Flat,
+}
+
+/// Names of the directories under the prefix that hold kegs and their stable `opt` links.
+/// The default matches Homebrew: `Cellar`, `opt`, versioned kegs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixLayout {
+    pub cellar_dir_name: String,
+    pub opt_dir_name: String,
+    pub keg_layout: KegLayout,
+}
+
+impl Default for PrefixLayout {
+    fn default() -> Self {
+        Self {
+            cellar_dir_name: "Cellar".to_string(),
+            opt_dir_name: "opt".to_string(),
+            keg_layout: KegLayout::Versioned,
+        }
+    }
+}
+
+/// Env var holding the comma-separated list of post-install passes to skip for every formula.
+const SKIP_POST_INSTALL_ENV: &str = "SAPPHIRE_SKIP_POST_INSTALL";
+/// Prefix for per-formula skip lists, e.g. `SAPPHIRE_SKIP_POST_INSTALL_OPENSSL_3=codesign`.
+const SKIP_POST_INSTALL_FORMULA_PREFIX: &str = "SAPPHIRE_SKIP_POST_INSTALL_";
+
+/// The individual transformations applied to a keg after it has been poured or built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PostInstallPass {
+    Relocation,
+    Codesign,
+    ShebangRewrite,
+    LinkageCheck,
+}
+
+impl PostInstallPass {
+    /// Parses a pass name as accepted in the skip env vars.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "relocation" | "relocate" => Some(Self::Relocation),
+            "codesign" | "sign" => Some(Self::Codesign),
+            "shebang" | "shebangs" | "shebang-rewrite" => Some(Self::ShebangRewrite),
+            "linkage" | "linkage-check" => Some(Self::LinkageCheck),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PostInstallPass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Relocation => "relocation",
+            Self::Codesign => "codesign",
+            Self::ShebangRewrite => "shebang-rewrite",
+            Self::LinkageCheck => "linkage-check",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Set of post-install passes that should not run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PostInstallSkips {
+    pub relocation: bool,
+    pub codesign: bool,
+    pub shebang_rewrite: bool,
+    pub linkage_check: bool,
+}
+
+impl PostInstallSkips {
+    /// Parses a comma-separated list like `relocation,codesign`. `all` skips every pass.
+    /// Unknown entries are logged and ignored.
+    pub fn parse(list: &str) -> Self {
+        let mut skips = Self::default();
+        for item in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            if item.eq_ignore_ascii_case("all") {
+                return Self {
+                    relocation: true,
+                    codesign: true,
+                    shebang_rewrite: true,
+                    linkage_check: true,
+                };
+            }
+            match PostInstallPass::parse(item) {
+                Some(pass) => skips.set(pass, true),
+                None => warn!("Ignoring unknown post-install pass '{}' in skip list", item),
+            }
+        }
+        skips
+    }
+
+    pub fn contains(&self, pass: PostInstallPass) -> bool {
+        match pass {
+            PostInstallPass::Relocation => self.relocation,
+            PostInstallPass::Codesign => self.codesign,
+            PostInstallPass::ShebangRewrite => self.shebang_rewrite,
+            PostInstallPass::LinkageCheck => self.linkage_check,
+        }
+    }
+
+    pub fn set(&mut self, pass: PostInstallPass, skip: bool) {
+        match pass {
+            PostInstallPass::Relocation => self.relocation = skip,
+            PostInstallPass::Codesign => self.codesign = skip,
+            PostInstallPass::ShebangRewrite => self.shebang_rewrite = skip,
+            PostInstallPass::LinkageCheck => self.linkage_check = skip,
+        }
+    }
+
+    /// Combines two skip sets; a pass skipped in either is skipped in the result.
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            relocation: self.relocation || other.relocation,
+            codesign: self.codesign || other.codesign,
+            shebang_rewrite: self.shebang_rewrite || other.shebang_rewrite,
+            linkage_check: self.linkage_check || other.linkage_check,
+        }
+    }
+}
+
+/// Normalizes a formula name into an env-var-safe key (like the `@@HOMEBREW_OPT_*@@` placeholders),
+/// so `SAPPHIRE_SKIP_POST_INSTALL_OPENSSL_3` matches `openssl@3`.
+fn formula_env_key(formula_name: &str) -> String {
+    formula_name
+        .to_uppercase()
+        .replace(['-', '+', '.', '@'], "_")
+}
+
+/// Env var overriding the location of the persisted config file.
+const CONFIG_FILE_ENV: &str = "SAPPHIRE_CONFIG_FILE";
+
+/// Returns the path of the persisted config file.
+/// `SAPPHIRE_CONFIG_FILE` wins, otherwise `<config dir>/sapphire/config.toml`.
+fn determine_config_file() -> PathBuf {
+    if let Ok(path) = env::var(CONFIG_FILE_ENV) {
+        debug!("Using config file from {}: {}", CONFIG_FILE_ENV, path);
+        return PathBuf::from(path);
+    }
+    dirs::config_dir()
+        .or_else(|| dirs::home_dir().map(|h| h.join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("sapphire")
+        .join("config.toml")
+}
+
+/// Parses boolean-ish env var values ("1", "true", "yes", "on" and their negatives).
+fn parse_env_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// Reads the first of `names` that is set in the environment.
+fn first_env_var(names: &[&str]) -> Option<(String, String)> {
+    names
+        .iter()
+        .find_map(|name| env::var(name).ok().map(|v| (name.to_string(), v)))
+}
+
+/// Default options used by `install`.
+///
+/// Values are resolved with the following precedence, highest first:
+/// 1. Command-line flags passed to `install`
+/// 2. Environment variables (`SAPPHIRE_MAKE_JOBS`/`HOMEBREW_MAKE_JOBS`,
+///    `SAPPHIRE_BUILD_FROM_SOURCE`/`HOMEBREW_BUILD_FROM_SOURCE`, `SAPPHIRE_SANITIZERS`,
+///    `SAPPHIRE_SANDBOX`, `SAPPHIRE_VERIFY_RUNTIME_DEPS`, `SAPPHIRE_TOOLCHAIN_FILE`,
+///    `SAPPHIRE_BUILD_DOCS`, `SAPPHIRE_STOP_AFTER`, `SAPPHIRE_DOWNLOAD_CONNECTIONS`,
+///    `SAPPHIRE_BLOB_CACHE_SIZE`, `SAPPHIRE_CCACHE`, `SAPPHIRE_CCACHE_DIR`, `SAPPHIRE_UNIVERSAL`,
+///    `SAPPHIRE_DEPLOYMENT_TARGET`, `SAPPHIRE_BUILD_TIMEOUT`,
+///    `SAPPHIRE_OPTIMIZATION_LEVEL`/`HOMEBREW_OPTIMIZATION_LEVEL`, `SAPPHIRE_LTO`,
+///    `SAPPHIRE_WERROR`, `SAPPHIRE_TARGET_ARCH`, `SAPPHIRE_BUILD_HEAD`, `SAPPHIRE_REPRODUCIBLE`,
+///    `SAPPHIRE_KEEP_BUILD_DIR`, `SAPPHIRE_BUILD_ROOT`/`HOMEBREW_TEMP`, `SAPPHIRE_SDKROOT`,
+///    `SAPPHIRE_SOURCE_CACHE_SIZE`, `SAPPHIRE_MAX_LOAD`)
+/// 3. The `[install]` table of the config file (see [`Config::save`])
+/// 4. The built-in defaults below
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstallOptions {
+    /// Parallel jobs for source builds. `None` means one job per CPU.
+    pub jobs: Option<usize>,
+    /// Always build from source, even when a bottle is available.
+    pub build_from_source: bool,
+    /// Compile source builds with AddressSanitizer and UndefinedBehaviorSanitizer.
+    pub sanitizers: bool,
+    /// Run source builds inside the sandbox.
+    pub sandbox: bool,
+    /// Refuse to pour a bottle whose recorded runtime dependencies aren't installed.
+    pub verify_runtime_deps: bool,
+    /// Toolchain definition applied to every source build (see `build::toolchain`).
+    pub toolchain: Option<PathBuf>,
+    /// Also build and install documentation targets (slow, so off by default).
+    pub build_docs: bool,
+    /// Stop source builds once this phase has finished, keeping the build directory for
+    /// inspection. Implies building from source.
+    pub stop_after: Option<BuildPhase>,
+    /// Print the commands of source builds instead of running them. Implies building from
+    /// source. Only ever set for one run by `install --dry-run`, never saved or read from the
+    /// environment, so that a forgotten setting can't silently stop every install.
+    #[serde(skip)]
+    pub dry_run: bool,
+    /// Build formulae from the latest commit of their git repository instead of their stable
+    /// release, where they have one. Implies building from source.
+    pub head: bool,
+    /// Connections used per download. Above 1, large files are fetched as that many byte ranges
+    /// in parallel where the server supports it, up to
+    /// [`crate::fetch::segmented::MAX_CONNECTIONS`].
+    pub download_connections: usize,
+    /// Size limit in MiB of the local cache of kegs built from source (see
+    /// `build::blob_cache`). 0 disables the cache.
+    pub blob_cache_size: u64,
+    /// Compile C and C++ through ccache in source builds, when ccache is installed.
+    pub ccache: bool,
+    /// Where ccache keeps its cache. `None` means `<cache_dir>/ccache`.
+    pub ccache_dir: Option<PathBuf>,
+    /// Build universal (x86_64 + arm64) binaries from source. macOS only.
+    pub universal: bool,
+    /// Oldest macOS version (`major.minor`) source builds target. `None` means the running one.
+    pub deployment_target: Option<String>,
+    /// Seconds a single configure, build or install step may run before it is killed.
+    /// `None` means no limit beyond the output-silence watchdog.
+    pub build_timeout: Option<u64>,
+    /// Compiler optimization level of source builds.
+    pub optimization: OptimizationLevel,
+    /// Build from source with link-time optimization, where the compiler supports it.
+    pub lto: bool,
+    /// Compile source builds with `-Werror`, failing them on any compiler warning. For auditing
+    /// formulae; many builds that are fine otherwise fail with it.
+    pub werror: bool,
+    /// Architecture source builds target. `None` means the one sapphire was built for.
+    pub target_arch: Option<TargetArch>,
+    /// Ask source builds to be reproducible: fixed timestamps and no build paths in the output.
+    pub reproducible: bool,
+    /// Keep the build directory of a failed source build for debugging instead of deleting it.
+    pub keep_build_dir_on_failure: bool,
+    /// Directory source builds are extracted and run in. `None` means `$TMPDIR` if set, else
+    /// `<cache_dir>/build-temp` (see [`Config::build_root`]).
+    pub build_root: Option<PathBuf>,
+    /// macOS SDK source builds compile against. `None` means the one `xcrun` reports.
+    pub sdk_path: Option<PathBuf>,
+    /// Size limit in MiB of the local cache of extracted and patched source trees (see
+    /// `build::source_cache`). 0 disables the cache.
+    pub source_cache_size: u64,
+    /// Download sources and bottles again and prepare source trees afresh, ignoring what is
+    /// cached. Only ever set for one run by `install --force-fetch`, never saved or read from
+    /// the environment, as a forgotten setting would make every install download everything.
+    #[serde(skip)]
+    pub force_fetch: bool,
+    /// Load average above which make starts no new jobs (`-l`), so big parallel builds back off
+    /// when the machine is busy. `None` means no limit.
+    pub max_load: Option<u32>,
+}
+
+impl Default for InstallOptions {
+    fn default() -> Self {
+        Self {
+            jobs: None,
+            build_from_source: false,
+            sanitizers: false,
+            sandbox: false,
+            verify_runtime_deps: true,
+            toolchain: None,
+            build_docs: false,
+            stop_after: None,
+            dry_run: false,
+            head: false,
+            download_connections: 1,
+            blob_cache_size: 0,
+            ccache: false,
+            ccache_dir: None,
+            universal: false,
+            deployment_target: None,
+            build_timeout: None,
+            optimization: OptimizationLevel::default(),
+            lto: false,
+            werror: false,
+            target_arch: None,
+            reproducible: false,
+            keep_build_dir_on_failure: false,
+            build_root: None,
+            sdk_path: None,
+            source_cache_size: 0,
+            force_fetch: false,
+            max_load: None,
+        }
+    }
+}
+
+impl InstallOptions {
+    /// Keys accepted by [`InstallOptions::get`] and [`InstallOptions::set`].
+    pub const KEYS: &'static [&'static str] = &[
+        "jobs",
+        "build_from_source",
+        "sanitizers",
+        "sandbox",
+        "verify_runtime_deps",
+        "toolchain",
+        "build_docs",
+        "stop_after",
+        "head",
+        "download_connections",
+        "blob_cache_size",
+        "ccache",
+        "ccache_dir",
+        "universal",
+        "deployment_target",
+        "build_timeout",
+        "optimization",
+        "lto",
+        "werror",
+        "target_arch",
+        "reproducible",
+        "keep_build_dir_on_failure",
+        "build_root",
+        "sdk_path",
+        "source_cache_size",
+        "max_load",
+    ];
+
+    /// Returns the value for `key` formatted for display, or `None` for an unknown key.
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "jobs" => Some(
+                self.jobs
+                    .map(|j| j.to_string())
+                    .unwrap_or_else(|| "auto".to_string()),
+            ),
+            "build_from_source" => Some(self.build_from_source.to_string()),
+            "sanitizers" => Some(self.sanitizers.to_string()),
+            "sandbox" => Some(self.sandbox.to_string()),
+            "verify_runtime_deps" => Some(self.verify_runtime_deps.to_string()),
+            "toolchain" => Some(
+                self.toolchain
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|| "none".to_string()),
+            ),
+            "build_docs" => Some(self.build_docs.to_string()),
+            "stop_after" => Some(
+                self.stop_after
+                    .map(|p| p.to_string())
+                    .unwrap_or_else(|| "none".to_string()),
+            ),
+            "head" => Some(self.head.to_string()),
+            "download_connections" => Some(self.download_connections.to_string()),
+            "blob_cache_size" => Some(self.blob_cache_size.to_string()),
+            "ccache" => Some(self.ccache.to_string()),
+            "ccache_dir" => Some(
+                self.ccache_dir
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|| "default".to_string()),
+            ),
+            "universal" => Some(self.universal.to_string()),
+            "build_timeout" => Some(match self.build_timeout {
+                Some(secs) => humantime::format_duration(Duration::from_secs(secs)).to_string(),
+                None => "none".to_string(),
+            }),
+            "deployment_target" => Some(
+                self.deployment_target
+                    .clone()
+                    .unwrap_or_else(|| "default".to_string()),
+            ),
+            "optimization" => Some(self.optimization.to_string()),
+            "lto" => Some(self.lto.to_string()),
+            "werror" => Some(self.werror.to_string()),
+            "target_arch" => Some(
+                self.target_arch
+                    .map(|arch| arch.to_string())
+                    .unwrap_or_else(|| "native".to_string()),
+            ),
+            "reproducible" => Some(self.reproducible.to_string()),
+            "keep_build_dir_on_failure" => Some(self.keep_build_dir_on_failure.to_string()),
+            "build_root" => Some(
+                self.build_root
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|| "default".to_string()),
+            ),
+            "sdk_path" => Some(
+                self.sdk_path
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|| "default".to_string()),
+            ),
+            "source_cache_size" => Some(self.source_cache_size.to_string()),
+            "max_load" => Some(
+                self.max_load
+                    .map(|l| l.to_string())
+                    .unwrap_or_else(|| "none".to_string()),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Parses `value` and stores it under `key`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let parse_bool = |v: &str| {
+            parse_env_bool(v).ok_or_else(|| {
+                SapphireError::Config(format!("Invalid boolean value for '{}': {}", key, v))
+            })
+        };
+        match key {
+            "jobs" => {
+                self.jobs = if value.eq_ignore_ascii_case("auto") {
+                    None
+                } else {
+                    match value.parse::<usize>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => {
+                            return Err(SapphireError::Config(format!(
+                            "Invalid value for 'jobs': {} (expected a positive number or 'auto')",
+                            value
+                        )))
+                        }
+                    }
+                };
+            }
+            "build_from_source" => self.build_from_source = parse_bool(value)?,
+            "sanitizers" => self.sanitizers = parse_bool(value)?,
+            "sandbox" => self.sandbox = parse_bool(value)?,
+            "verify_runtime_deps" => self.verify_runtime_deps = parse_bool(value)?,
+            "toolchain" => {
+                self.toolchain = if value.is_empty() || value.eq_ignore_ascii_case("none") {
+                    None
+                } else {
+                    Some(PathBuf::from(value))
+                };
+            }
+            "build_docs" => self.build_docs = parse_bool(value)?,
+            "stop_after" => {
+                self.stop_after = if value.is_empty() || value.eq_ignore_ascii_case("none") {
+                    None
+                } else {
+                    Some(parse_build_phase(value)?)
+                };
+            }
+            "head" => self.head = parse_bool(value)?,
+            "download_connections" => match value.parse::<usize>() {
+                Ok(n) if n > 0 => self.download_connections = n,
+                _ => {
+                    return Err(SapphireError::Config(format!(
+                        "Invalid value for 'download_connections': {} (expected a positive number)",
+                        value
+                    )))
+                }
+            },
+            "blob_cache_size" => {
+                self.blob_cache_size = value.parse::<u64>().map_err(|_| {
+                    SapphireError::Config(format!(
+                        "Invalid value for 'blob_cache_size': {} (expected a size in MiB, 0 to disable)",
+                        value
+                    ))
+                })?;
+            }
+            "ccache" => self.ccache = parse_bool(value)?,
+            "ccache_dir" => {
+                self.ccache_dir = if value.is_empty() || value.eq_ignore_ascii_case("default") {
+                    None
+                } else {
+                    Some(PathBuf::from(value))
+                };
+            }
+            "universal" => self.universal = parse_bool(value)?,
+            "deployment_target" => {
+                self.deployment_target = parse_deployment_target(value)?;
+            }
+            "build_timeout" => self.build_timeout = parse_build_timeout(value)?,
+            "optimization" => {
+                self.optimization = OptimizationLevel::parse(value).ok_or_else(|| {
+                    SapphireError::Config(format!(
+                        "Invalid value for 'optimization': {} (expected O2, O3 or Os)",
+                        value
+                    ))
+                })?;
+            }
+            "lto" => self.lto = parse_bool(value)?,
+            "werror" => self.werror = parse_bool(value)?,
+            "target_arch" => self.target_arch = parse_target_arch(value)?,
+            "reproducible" => self.reproducible = parse_bool(value)?,
+            "keep_build_dir_on_failure" => self.keep_build_dir_on_failure = parse_bool(value)?,
+            "build_root" => {
+                self.build_root = if value.is_empty() || value.eq_ignore_ascii_case("default") {
+                    None
+                } else {
+                    Some(PathBuf::from(value))
+                };
+            }
+            "sdk_path" => {
+                self.sdk_path = if value.is_empty() || value.eq_ignore_ascii_case("default") {
+                    None
+                } else {
+                    let sdk = PathBuf::from(value);
+                    if !sdk.is_dir() {
+                        return Err(SapphireError::Config(format!(
+                            "Invalid value for 'sdk_path': {} does not exist",
+                            value
+                        )));
+                    }
+                    Some(sdk)
+                };
+            }
+            "source_cache_size" => {
+                self.source_cache_size = value.parse::<u64>().map_err(|_| {
+                    SapphireError::Config(format!(
+                        "Invalid value for 'source_cache_size': {} (expected a size in MiB, 0 to disable)",
+                        value
+                    ))
+                })?;
+            }
+            "max_load" => self.max_load = parse_max_load(value)?,
+            _ => return Err(unknown_install_option(key)),
+        }
+        Ok(())
+    }
+
+    /// Resets `key` to its built-in default.
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        let defaults = Self::default();
+        match key {
+            "jobs" => self.jobs = defaults.jobs,
+            "build_from_source" => self.build_from_source = defaults.build_from_source,
+            "sanitizers" => self.sanitizers = defaults.sanitizers,
+            "sandbox" => self.sandbox = defaults.sandbox,
+            "verify_runtime_deps" => self.verify_runtime_deps = defaults.verify_runtime_deps,
+            "toolchain" => self.toolchain = defaults.toolchain,
+            "build_docs" => self.build_docs = defaults.build_docs,
+            "stop_after" => self.stop_after = defaults.stop_after,
+            "head" => self.head = defaults.head,
+            "download_connections" => self.download_connections = defaults.download_connections,
+            "blob_cache_size" => self.blob_cache_size = defaults.blob_cache_size,
+            "ccache" => self.ccache = defaults.ccache,
+            "ccache_dir" => self.ccache_dir = defaults.ccache_dir,
+            "universal" => self.universal = defaults.universal,
+            "deployment_target" => self.deployment_target = defaults.deployment_target,
+            "build_timeout" => self.build_timeout = defaults.build_timeout,
+            "optimization" => self.optimization = defaults.optimization,
+            "lto" => self.lto = defaults.lto,
+            "werror" => self.werror = defaults.werror,
+            "target_arch" => self.target_arch = defaults.target_arch,
+            "reproducible" => self.reproducible = defaults.reproducible,
+            "keep_build_dir_on_failure" => {
+                self.keep_build_dir_on_failure = defaults.keep_build_dir_on_failure
+            }
+            "build_root" => self.build_root = defaults.build_root,
+            "sdk_path" => self.sdk_path = defaults.sdk_path,
+            "source_cache_size" => self.source_cache_size = defaults.source_cache_size,
+            "max_load" => self.max_load = defaults.max_load,
+            _ => return Err(unknown_install_option(key)),
+        }
+        Ok(())
+    }
+
+    /// Applies environment variable overrides on top of these options.
+    fn with_env_overrides(mut self) -> Self {
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_MAKE_JOBS", "HOMEBREW_MAKE_JOBS"]) {
+            match value.trim().parse::<usize>() {
+                Ok(n) if n > 0 => {
+                    debug!("Loaded {}={}", name, n);
+                    self.jobs = Some(n);
+                }
+                _ => warn!("Ignoring invalid {}={}", name, value),
+            }
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_DOWNLOAD_CONNECTIONS"]) {
+            match value.trim().parse::<usize>() {
+                Ok(n) if n > 0 => {
+                    debug!("Loaded {}={}", name, n);
+                    self.download_connections = n;
+                }
+                _ => warn!("Ignoring invalid {}={}", name, value),
+            }
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_BLOB_CACHE_SIZE"]) {
+            match value.trim().parse::<u64>() {
+                Ok(n) => {
+                    debug!("Loaded {}={}", name, n);
+                    self.blob_cache_size = n;
+                }
+                Err(_) => warn!("Ignoring invalid {}={}", name, value),
+            }
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_SOURCE_CACHE_SIZE"]) {
+            match value.trim().parse::<u64>() {
+                Ok(n) => {
+                    debug!("Loaded {}={}", name, n);
+                    self.source_cache_size = n;
+                }
+                Err(_) => warn!("Ignoring invalid {}={}", name, value),
+            }
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_TOOLCHAIN_FILE"]) {
+            debug!("Loaded {}={}", name, value);
+            self.toolchain = (!value.is_empty()).then(|| PathBuf::from(value));
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_BUILD_TIMEOUT"]) {
+            match parse_build_timeout(&value) {
+                Ok(timeout) => {
+                    debug!("Loaded {}={}", name, value);
+                    self.build_timeout = timeout;
+                }
+                Err(e) => warn!("Ignoring {}: {}", name, e),
+            }
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_MAX_LOAD"]) {
+            match parse_max_load(&value) {
+                Ok(load) => {
+                    debug!("Loaded {}={}", name, value);
+                    self.max_load = load;
+                }
+                Err(e) => warn!("Ignoring {}: {}", name, e),
+            }
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_DEPLOYMENT_TARGET"]) {
+            match parse_deployment_target(&value) {
+                Ok(target) => {
+                    debug!("Loaded {}={}", name, value);
+                    self.deployment_target = target;
+                }
+                Err(e) => warn!("Ignoring {}: {}", name, e),
+            }
+        }
+        if let Some((name, value)) =
+            first_env_var(&["SAPPHIRE_OPTIMIZATION_LEVEL", "HOMEBREW_OPTIMIZATION_LEVEL"])
+        {
+            match OptimizationLevel::parse(&value) {
+                Some(level) => {
+                    debug!("Loaded {}={}", name, level);
+                    self.optimization = level;
+                }
+                None => warn!("Ignoring invalid {}={}", name, value),
+            }
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_TARGET_ARCH"]) {
+            match parse_target_arch(&value) {
+                Ok(arch) => {
+                    debug!("Loaded {}={}", name, value);
+                    self.target_arch = arch;
+                }
+                Err(e) => warn!("Ignoring {}: {}", name, e),
+            }
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_CCACHE_DIR"]) {
+            debug!("Loaded {}={}", name, value);
+            self.ccache_dir = (!value.is_empty()).then(|| PathBuf::from(value));
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_BUILD_ROOT", "HOMEBREW_TEMP"]) {
+            debug!("Loaded {}={}", name, value);
+            self.build_root = (!value.is_empty()).then(|| PathBuf::from(value));
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_SDKROOT"]) {
+            debug!("Loaded {}={}", name, value);
+            self.sdk_path = (!value.is_empty()).then(|| PathBuf::from(value));
+        }
+        if let Some((name, value)) = first_env_var(&["SAPPHIRE_STOP_AFTER"]) {
+            match parse_build_phase(&value) {
+                Ok(phase) => {
+                    debug!("Loaded {}={}", name, phase);
+                    self.stop_after = Some(phase);
+                }
+                Err(_) => warn!("Ignoring invalid {}={}", name, value),
+            }
+        }
+        let bool_overrides: [(&[&str], &mut bool); 12] = [
+            (
+                &["SAPPHIRE_BUILD_FROM_SOURCE", "HOMEBREW_BUILD_FROM_SOURCE"],
+                &mut self.build_from_source,
+            ),
+            (&["SAPPHIRE_SANITIZERS"], &mut self.sanitizers),
+            (&["SAPPHIRE_SANDBOX"], &mut self.sandbox),
+            (
+                &["SAPPHIRE_VERIFY_RUNTIME_DEPS"],
+                &mut self.verify_runtime_deps,
+            ),
+            (&["SAPPHIRE_BUILD_DOCS"], &mut self.build_docs),
+            (&["SAPPHIRE_CCACHE"], &mut self.ccache),
+            (&["SAPPHIRE_UNIVERSAL"], &mut self.universal),
+            (&["SAPPHIRE_LTO"], &mut self.lto),
+            (&["SAPPHIRE_WERROR"], &mut self.werror),
+            (&["SAPPHIRE_BUILD_HEAD"], &mut self.head),
+            (&["SAPPHIRE_REPRODUCIBLE"], &mut self.reproducible),
+            (
+                &["SAPPHIRE_KEEP_BUILD_DIR"],
+                &mut self.keep_build_dir_on_failure,
+            ),
+        ];
+        for (names, field) in bool_overrides {
+            if let Some((name, value)) = first_env_var(names) {
+                match parse_env_bool(&value) {
+                    Some(b) => {
+                        debug!("Loaded {}={}", name, b);
+                        *field = b;
+                    }
+                    None => warn!("Ignoring invalid {}={}", name, value),
+                }
+            }
+        }
+        self
+    }
+}
+
+/// Parses `target_arch`; empty or `native` means building for the native architecture.
+fn parse_target_arch(value: &str) -> Result<Option<TargetArch>> {
+    if value.is_empty() || value.eq_ignore_ascii_case("native") {
+        return Ok(None);
+    }
+    TargetArch::parse(value).map(Some).ok_or_else(|| {
+        SapphireError::Config(format!(
+            "Invalid value for 'target_arch': {} (expected x86_64, arm64 or native)",
+            value
+        ))
+    })
+}
+
+/// Parses a build phase name for `stop_after`.
+pub fn parse_build_phase(value: &str) -> Result<BuildPhase> {
+    BuildPhase::parse(value).ok_or_else(|| {
+        SapphireError::Config(format!(
+            "Invalid build phase '{}' (expected one of: {})",
+            value,
+            BuildPhase::ALL.map(|p| p.to_string()).join(", ")
+        ))
+    })
+}
+
+/// Parses a step timeout such as `90m` or `2h` into seconds. `none`, `off` or `0` disable it.
+fn parse_build_timeout(value: &str) -> Result<Option<u64>> {
+    let value = value.trim();
+    if value.is_empty()
+        || value == "0"
+        || value.eq_ignore_ascii_case("none")
+        || value.eq_ignore_ascii_case("off")
+    {
+        return Ok(None);
+    }
+    let timeout = humantime::parse_duration(value).map_err(|e| {
+        SapphireError::Config(format!(
+            "Invalid build timeout '{}' (expected a duration such as 90m): {}",
+            value, e
+        ))
+    })?;
+    Ok(Some(timeout.as_secs().max(1)))
+}
+
+/// Parses a maximum load average for make. Empty, `0` or `none` means no limit.
+fn parse_max_load(value: &str) -> Result<Option<u32>> {
+    let value = value.trim();
+    if value.is_empty() || value == "0" || value.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    value.parse::<u32>().map(Some).map_err(|_| {
+        SapphireError::Config(format!(
+            "Invalid value for 'max_load': {} (expected a whole number, or none)",
+            value
+        ))
+    })
+}
+
+/// Parses a macOS deployment target such as `11.0` or `13`. Empty or `default` means the
+/// running macOS version.
+fn parse_deployment_target(value: &str) -> Result<Option<String>> {
+    let value = value.trim();
+    if value.is_empty() || value.eq_ignore_ascii_case("default") {
+        return Ok(None);
+    }
+    let valid = value.split('.').count() <= 2
+        && value
+            .split('.')
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    if !valid {
+        return Err(SapphireError::Config(format!(
+            "Invalid deployment target '{}' (expected a macOS version such as 11.0)",
+            value
+        )));
+    }
+    Ok(Some(value.to_string()))
+}
+
+/// The error for an install option `key` that isn't one of [`InstallOptions::KEYS`].
+pub fn unknown_install_option(key: &str) -> SapphireError {
+    SapphireError::Config(format!(
+        "Unknown install option '{}'. Valid options: {}",
+        key,
+        InstallOptions::KEYS.join(", ")
+    ))
+}
+
+/// On-disk layout of the config file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    install: InstallOptions,
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile> {
+    if !path.is_file() {
+        debug!("No config file at {}, using defaults.", path.display());
+        return Ok(ConfigFile::default());
+    }
+    debug!("Reading config file {}", path.display());
+    let content = std::fs::read_to_string(path)?;
+    toml::from_str(&content).map_err(|e| {
+        SapphireError::Config(format!(
+            "Failed to parse config file {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub prefix: PathBuf,
+    /// `<prefix>/<layout.cellar_dir_name>`; use [`Config::set_layout`] to change both.
     pub cellar: PathBuf,
+    /// Directory names and keg arrangement under the prefix.
+    pub layout: PrefixLayout,
     pub taps_dir: PathBuf,
     pub cache_dir: PathBuf,
     pub api_base_url: String,
@@ -48,13 +880,28 @@ pub struct Config {
     pub docker_registry_token: Option<String>,
     pub docker_registry_basic_auth: Option<String>,
     pub github_api_token: Option<String>,
+    /// OpenSSH `allowed_signers` file with the keys trusted to sign tap manifests. When set,
+    /// formulae are only loaded from taps whose signed manifest matches their content.
+    pub tap_trusted_keys: Option<PathBuf>,
+    /// Post-install passes skipped for every formula.
+    pub post_install_skips: PostInstallSkips,
+    /// Post-install passes skipped for specific formulae, keyed by normalized formula name.
+    pub formula_post_install_skips: HashMap<String, PostInstallSkips>,
+    /// Path of the persisted config file read by `load()` and written by `save()`.
+    pub config_file: PathBuf,
+    /// Install options as stored in the config file. This is what `save()` writes.
+    pub persisted_install_options: InstallOptions,
+    /// Effective install options: the config file with env var overrides applied.
+    /// Commands apply their own flags on top of this.
+    pub install_options: InstallOptions,
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
         debug!("Loading Sapphire configuration...");
         let prefix = determine_prefix();
-        let cellar = prefix.join("Cellar");
+        let layout = PrefixLayout::default();
+        let cellar = prefix.join(&layout.cellar_dir_name);
         let taps_dir = prefix.join("Library/Taps");
         let cache_dir = cache::get_cache_dir()?;
         let api_base_url = "https://formulae.brew.sh/api".to_string();
@@ -63,6 +910,7 @@ impl Config {
         let docker_registry_token = env::var("HOMEBREW_DOCKER_REGISTRY_TOKEN").ok();
         let docker_registry_basic_auth = env::var("HOMEBREW_DOCKER_REGISTRY_BASIC_AUTH_TOKEN").ok();
         let github_api_token = env::var("HOMEBREW_GITHUB_API_TOKEN").ok();
+        let tap_trusted_keys = env::var_os(TAP_TRUSTED_KEYS_ENV).map(PathBuf::from);
 
         if artifact_domain.is_some() {
             debug!("Loaded HOMEBREW_ARTIFACT_DOMAIN");
@@ -76,11 +924,47 @@ impl Config {
         if github_api_token.is_some() {
             debug!("Loaded HOMEBREW_GITHUB_API_TOKEN");
         }
+        if let Some(keys) = &tap_trusted_keys {
+            debug!("Verifying taps against signers in {}", keys.display());
+        }
+
+        let post_install_skips = env::var(SKIP_POST_INSTALL_ENV)
+            .map(|v| PostInstallSkips::parse(&v))
+            .unwrap_or_default();
+        if post_install_skips != PostInstallSkips::default() {
+            debug!("Loaded {}: {:?}", SKIP_POST_INSTALL_ENV, post_install_skips);
+        }
+        let formula_post_install_skips: HashMap<String, PostInstallSkips> = env::vars()
+            .filter_map(|(key, value)| {
+                key.strip_prefix(SKIP_POST_INSTALL_FORMULA_PREFIX)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| (name.to_string(), PostInstallSkips::parse(&value)))
+            })
+            .collect();
+        for (name, skips) in &formula_post_install_skips {
+            debug!(
+                "Loaded per-formula post-install skips for {}: {:?}",
+                name, skips
+            );
+        }
+
+        let config_file = determine_config_file();
+        // A broken file mustn't lock the user out of every command, `config` included
+        let persisted_install_options = match read_config_file(&config_file) {
+            Ok(file) => file.install,
+            Err(e) => {
+                warn!("{}; using the default install options", e);
+                InstallOptions::default()
+            }
+        };
+        let install_options = persisted_install_options.clone().with_env_overrides();
+        debug!("Effective install options: {:?}", install_options);
 
         debug!("Configuration loaded successfully.");
         Ok(Self {
             prefix,
             cellar,
+            layout,
             taps_dir,
             cache_dir,
             api_base_url,
@@ -88,9 +972,45 @@ impl Config {
             docker_registry_token,
             docker_registry_basic_auth,
             github_api_token,
+            tap_trusted_keys,
+            post_install_skips,
+            formula_post_install_skips,
+            config_file,
+            persisted_install_options,
+            install_options,
         })
     }
 
+    /// Writes the persisted settings back to the config file, creating its directory if needed.
+    /// Environment overrides are not persisted. A config file that doesn't parse is kept with a
+    /// `.bak` suffix rather than overwritten.
+    pub fn save(&self) -> Result<()> {
+        if self.config_file.is_file() && read_config_file(&self.config_file).is_err() {
+            let mut backup = self.config_file.clone().into_os_string();
+            backup.push(".bak");
+            let backup = PathBuf::from(backup);
+            std::fs::rename(&self.config_file, &backup)?;
+            warn!("Moved the unparsable config file to {}", backup.display());
+        }
+        let file = ConfigFile {
+            install: self.persisted_install_options.clone(),
+        };
+        let content = toml::to_string_pretty(&file)
+            .map_err(|e| SapphireError::Config(format!("Failed to serialize config: {}", e)))?;
+        if let Some(parent) = self.config_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.config_file, content)?;
+        debug!("Saved config to {}", self.config_file.display());
+        Ok(())
+    }
+
+    /// Switches to a different prefix layout, keeping `cellar` in sync with it.
+    pub fn set_layout(&mut self, layout: PrefixLayout) {
+        self.cellar = self.prefix.join(&layout.cellar_dir_name);
+        self.layout = layout;
+    }
+
     // --- Start: New Path Methods ---
 
     pub fn prefix(&self) -> &Path {
@@ -101,12 +1021,26 @@ impl Config {
         &self.cellar
     }
 
+    /// Where source builds create their build directories: the `build_root` install option,
+    /// else `$TMPDIR`, else `<cache_dir>/build-temp`.
+    pub fn build_root(&self) -> PathBuf {
+        self.install_options
+            .build_root
+            .clone()
+            .or_else(|| {
+                std::env::var_os("TMPDIR")
+                    .filter(|dir| !dir.is_empty())
+                    .map(PathBuf::from)
+            })
+            .unwrap_or_else(|| self.cache_dir.join("build-temp"))
+    }
+
     pub fn caskroom_dir(&self) -> PathBuf {
         self.prefix.join("Caskroom")
     }
 
     pub fn opt_dir(&self) -> PathBuf {
-        self.prefix.join("opt")
+        self.prefix.join(&self.layout.opt_dir_name)
     }
 
     pub fn bin_dir(&self) -> PathBuf {
@@ -125,8 +1059,13 @@ impl Config {
         self.cellar_path().join(formula_name)
     }
 
+    /// The keg directory for a formula version. With [`KegLayout::Flat`] the version is not
+    /// part of the path.
     pub fn formula_keg_path(&self, formula_name: &str, version_str: &str) -> PathBuf {
-        self.formula_cellar_dir(formula_name).join(version_str)
+        match self.layout.keg_layout {
+            KegLayout::Versioned => self.formula_cellar_dir(formula_name).join(version_str),
+            KegLayout::Flat => self.formula_cellar_dir(formula_name),
+        }
     }
 
     pub fn formula_opt_link_path(&self, formula_name: &str) -> PathBuf {
@@ -153,6 +1092,41 @@ impl Config {
 
     // --- End: New Path Methods ---
 
+    /// Returns the effective set of skipped post-install passes for a formula
+    /// (global skips combined with the formula's own).
+    pub fn post_install_skips_for(&self, formula_name: &str) -> PostInstallSkips {
+        let own = self
+            .formula_post_install_skips
+            .get(&formula_env_key(formula_name))
+            .copied()
+            .unwrap_or_default();
+        self.post_install_skips.union(own)
+    }
+
+    /// Returns `true` if `pass` should be skipped for `formula_name`, logging the skip.
+    pub fn skips_post_install_pass(&self, formula_name: &str, pass: PostInstallPass) -> bool {
+        if self.post_install_skips.contains(pass) {
+            info!(
+                "Skipping {} pass for {} (disabled globally via {})",
+                pass, formula_name, SKIP_POST_INSTALL_ENV
+            );
+            return true;
+        }
+        let key = formula_env_key(formula_name);
+        if self
+            .formula_post_install_skips
+            .get(&key)
+            .is_some_and(|s| s.contains(pass))
+        {
+            info!(
+                "Skipping {} pass for {} (disabled via {}{})",
+                pass, formula_name, SKIP_POST_INSTALL_FORMULA_PREFIX, key
+            );
+            return true;
+        }
+        false
+    }
+
     pub fn get_tap_path(&self, name: &str) -> Option<PathBuf> {
         let parts: Vec<&str> = name.split('/').collect();
         if parts.len() == 2 {
@@ -3,6 +3,8 @@
 
 use thiserror::Error;
 
+use crate::build::warnings::BuildPhase;
+
 // Define a top-level error enum for the application using thiserror
 #[derive(Error, Debug)]
 pub enum SapphireError {
@@ -31,6 +33,17 @@ pub enum SapphireError {
     #[error("DownloadError: Failed to download '{0}' from '{1}': {2}")]
     DownloadError(String, String, String), // name, url, reason
 
+    #[error(
+        "Failed to download {name} from any of {} URLs:{}",
+        .failures.len(),
+        .failures.iter().map(|(url, reason)| format!("\n  {}: {}", url, reason)).collect::<String>()
+    )]
+    AllDownloadsFailed {
+        name: String,
+        /// Each URL tried, in order, with why it failed.
+        failures: Vec<(String, String)>,
+    },
+
     #[error("Cache Error: {0}")]
     Cache(String),
 
@@ -47,8 +60,8 @@ pub enum SapphireError {
     #[error("HttpError: {0}")]
     HttpError(String),
 
-    #[error("Checksum Mismatch: {0}")]
-    ChecksumMismatch(String), // Keep if used distinctly from ChecksumError
+    #[error("Checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
 
     #[error("Checksum Error: {0}")]
     ChecksumError(String),
@@ -62,6 +75,67 @@ pub enum SapphireError {
     #[error("Dependency Error: {0}")]
     DependencyError(String),
 
+    #[error(
+        "Missing runtime dependency: {formula} requires {dependency} {required}, but {}",
+        .found.as_ref().map_or("it is not installed".to_string(), |v| format!("{} is installed", v))
+    )]
+    MissingRuntimeDependency {
+        formula: String,
+        dependency: String,
+        /// Version the bottle was built against.
+        required: String,
+        /// Installed version, if any.
+        found: Option<String>,
+    },
+
+    #[error("Tap integrity check failed: {0}")]
+    TapIntegrity(String),
+
+    #[error("Unresolved library reference in {}: {missing}", .binary.display())]
+    UnresolvedDylib {
+        binary: std::path::PathBuf,
+        /// The install name or `DT_NEEDED` entry that could not be found.
+        missing: String,
+    },
+
+    #[error("Build stopped after the {phase} phase as requested; build directory kept at {}", .build_dir.display())]
+    BuildStopped {
+        phase: String,
+        build_dir: std::path::PathBuf,
+    },
+
+    #[error("Dry run of {0} finished; nothing was built or installed")]
+    DryRun(String),
+
+    #[error("{step} did not finish within {}", humantime::format_duration(*.timeout))]
+    BuildTimeout {
+        step: String,
+        timeout: std::time::Duration,
+    },
+
+    #[error(
+        "Build failed in the {phase} phase ({})",
+        .exit_code.map_or("terminated by a signal".to_string(), |c| format!("exit code {}", c))
+    )]
+    BuildPhaseFailed {
+        phase: BuildPhase,
+        /// `None` when the command was killed by a signal.
+        exit_code: Option<i32>,
+        /// The last lines the failed command printed, and of its own log (`config.log`) if any.
+        log_tail: String,
+    },
+
+    #[error("Test `{command}` failed: {reason}\n{output}")]
+    TestFailed {
+        command: String,
+        /// `None` when the command was killed by a signal.
+        exit_code: Option<i32>,
+        /// What was wrong: the exit code, or output that was missing.
+        reason: String,
+        /// Everything the command printed, stdout then stderr.
+        output: String,
+    },
+
     #[error("Build environment setup failed: {0}")]
     BuildEnvError(String),
 